@@ -0,0 +1,83 @@
+//! Measures how the I/O and decompression buffer sizes of `OpenOptions` affect
+//! the time it takes to read every file in an archive.
+//!
+//! Run with `cargo bench --bench buffer_sizes`. Set `BSA_BENCH_DIR` to put the
+//! archive on a different drive, e.g. a network share, where larger I/O
+//! buffers make a much bigger difference than on a local SSD.
+
+use std::error::Error;
+use std::time::{Duration, Instant};
+use std::{env, fs, io, path};
+
+const FILES: u32 = 2000;
+const ROUNDS: u32 = 5;
+
+fn build_archive(path: &path::Path) -> Result<(), Box<dyn Error>> {
+    let mut builder = bsa::BsaBuilder::new(bsa::Version::SKYRIM);
+    builder.compress(cfg!(feature = "zlib"));
+    for i in 0..FILES {
+        // sizes from a few bytes to ~64 KiB, compressible but not trivially
+        let len = (i as usize * 7919) % (64 * 1024) + 16;
+        let data = (0..len).map(|j| ((j / 7) as u32 ^ i) as u8).collect();
+        builder.add_file(&format!(r"bench\{:03}\file{}.bin", i % 50, i), data)?;
+    }
+    builder.write(&mut io::BufWriter::new(fs::File::create(path)?))?;
+    Ok(())
+}
+
+fn read_all(
+    path: &path::Path,
+    io_buffer_size: Option<usize>,
+    decompression_buffer_size: usize,
+) -> Result<Duration, Box<dyn Error>> {
+    let mut options = bsa::OpenOptions::new();
+    options.decompression_buffer_size(decompression_buffer_size);
+    if let Some(size) = io_buffer_size {
+        options.io_buffer_size(size);
+    }
+    let mut best = Duration::MAX;
+    for _ in 0..ROUNDS {
+        let start = Instant::now();
+        let mut bsa = options.open(path)?;
+        let files: Vec<_> = bsa
+            .files_by_offset()
+            .into_iter()
+            .map(|(_, file)| file.clone())
+            .collect();
+        for file in files {
+            io::copy(&mut file.read_contents(&mut bsa)?, &mut io::sink())?;
+        }
+        best = best.min(start.elapsed());
+    }
+    Ok(best)
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let dir = env::var_os("BSA_BENCH_DIR").map_or_else(env::temp_dir, path::PathBuf::from);
+    let path = dir.join(format!("bsa-bench-{}.bsa", std::process::id()));
+    build_archive(&path)?;
+    println!(
+        "{} files, {} bytes, best of {} rounds",
+        FILES,
+        fs::metadata(&path)?.len(),
+        ROUNDS
+    );
+    println!("{:>10} {:>14} {:>10}", "io buffer", "decompression", "time");
+    let kib = |size: usize| format!("{} KiB", size / 1024);
+    let result = (|| -> Result<(), Box<dyn Error>> {
+        for &io_buffer_size in &[None, Some(8 * 1024), Some(64 * 1024), Some(1 << 20)] {
+            for &decompression_buffer_size in &[8 * 1024, 64 * 1024, 1 << 20] {
+                let time = read_all(&path, io_buffer_size, decompression_buffer_size)?;
+                println!(
+                    "{:>10} {:>14} {:>8.2?}",
+                    io_buffer_size.map_or_else(|| "none".to_string(), kib),
+                    kib(decompression_buffer_size),
+                    time
+                );
+            }
+        }
+        Ok(())
+    })();
+    fs::remove_file(&path)?;
+    result
+}
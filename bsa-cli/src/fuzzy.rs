@@ -0,0 +1,81 @@
+/// Returns the smallest number of single-character edits needed to turn
+/// `needle` into some substring of `haystack`
+fn substring_distance(needle: &[char], haystack: &[char]) -> usize {
+    // row `i` holds the distances between `needle[..i]` and the best
+    // substrings of `haystack` ending at each position
+    let mut row = vec![0; haystack.len() + 1];
+    for (i, &n) in needle.iter().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &h) in haystack.iter().enumerate() {
+            let substitution = diagonal + usize::from(n != h);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j + 1] + 1).min(row[j] + 1);
+        }
+    }
+    row.into_iter().min().unwrap_or(needle.len())
+}
+
+/// Scores how well a path inside a BSA matches a search query, lower is
+/// better. Each word of the query must occur somewhere in the path, allowing
+/// one typo per four characters. Returns `None` if the path doesn't match.
+///
+/// The score is the number of typos, followed by the number of typos when
+/// only matching against the file name, so that matches in the file name are
+/// ranked before matches in folder names.
+pub(crate) fn score(query: &str, path: &str) -> Option<(usize, usize)> {
+    let path: Vec<char> = path
+        .chars()
+        .map(|ch| {
+            if ch == '/' {
+                '\\'
+            } else {
+                ch.to_ascii_lowercase()
+            }
+        })
+        .collect();
+    let file_name = match path.iter().rposition(|&ch| ch == '\\') {
+        Some(i) => &path[i + 1..],
+        None => &path[..],
+    };
+    let mut res = (0, 0);
+    for word in query.split_whitespace() {
+        let word: Vec<char> = word.chars().map(|ch| ch.to_ascii_lowercase()).collect();
+        let distance = substring_distance(&word, &path);
+        if distance > word.len() / 4 {
+            return None;
+        }
+        res.0 += distance;
+        res.1 += substring_distance(&word, file_name);
+    }
+    Some(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{score, substring_distance};
+
+    #[test]
+    fn test_substring_distance() {
+        let distance = |a: &str, b: &str| {
+            substring_distance(
+                &a.chars().collect::<Vec<_>>(),
+                &b.chars().collect::<Vec<_>>(),
+            )
+        };
+        assert_eq!(distance("plate", "breastplate"), 0);
+        assert_eq!(distance("brestplate", "steelbreastplate"), 1);
+        assert_eq!(distance("abc", "xyz"), 3);
+        assert_eq!(distance("", "xyz"), 0);
+    }
+
+    #[test]
+    fn test_score() {
+        let path = r"meshes\armor\steel\breastplate_1.nif";
+        assert_eq!(score("brestplate", path), Some((1, 1)));
+        assert_eq!(score("Steel BreastPlate", path), Some((0, 2)));
+        assert_eq!(score("armor", path), Some((0, 4)));
+        assert_eq!(score("helmet", path), None);
+        assert!(score("breastplate", path) < score("armor", path));
+    }
+}
@@ -0,0 +1,55 @@
+#![allow(dead_code)]
+
+/// Returns whether a path inside a BSA matches the given glob pattern.
+///
+/// `*` matches any sequence of characters except path separators, `**`
+/// matches any sequence of characters including separators, and `?` matches a
+/// single character. Matching is case-insensitive, and forward slashes and
+/// backslashes are treated the same.
+pub(crate) fn matches(pattern: &str, path: &str) -> bool {
+    let normalize = |s: &str| -> Vec<char> {
+        s.chars()
+            .map(|ch| {
+                if ch == '/' {
+                    '\\'
+                } else {
+                    ch.to_ascii_lowercase()
+                }
+            })
+            .collect()
+    };
+    matches_chars(&normalize(pattern), &normalize(path))
+}
+
+fn matches_chars(pattern: &[char], path: &[char]) -> bool {
+    match pattern {
+        [] => path.is_empty(),
+        ['*', '*', '\\', rest @ ..] => {
+            // `**\` can also match zero folders
+            matches_chars(rest, path) || (0..path.len()).any(|i| matches_chars(rest, &path[i..]))
+        }
+        ['*', '*', rest @ ..] => (0..=path.len()).any(|i| matches_chars(rest, &path[i..])),
+        ['*', rest @ ..] => (0..=path.len())
+            .take_while(|&i| i == 0 || path[i - 1] != '\\')
+            .any(|i| matches_chars(rest, &path[i..])),
+        ['?', rest @ ..] => !path.is_empty() && path[0] != '\\' && matches_chars(rest, &path[1..]),
+        [ch, rest @ ..] => path.first() == Some(ch) && matches_chars(rest, &path[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::matches;
+
+    #[test]
+    fn test_glob() {
+        assert!(matches("textures/**", r"textures\foo\bar.dds"));
+        assert!(matches("**/*.dds", r"textures\foo\bar.dds"));
+        assert!(matches("**/*.dds", "bar.dds"));
+        assert!(matches("textures/*/bar.DDS", r"Textures\foo\bar.dds"));
+        assert!(!matches("textures/*.dds", r"textures\foo\bar.dds"));
+        assert!(matches("*female*", "femalenord"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", r"a\c"));
+    }
+}
@@ -0,0 +1,373 @@
+//! A SQLite database of the files in many archives, so that conflict and
+//! duplicate analyses don't need to read the archives again.
+
+use crate::{fuzzy, glob, Res};
+use std::convert::TryFrom;
+use std::{fs, io, path, time};
+
+const SCHEMA: &str = "
+    PRAGMA foreign_keys = ON;
+    CREATE TABLE IF NOT EXISTS archives (
+        id INTEGER PRIMARY KEY,
+        path TEXT NOT NULL UNIQUE,
+        size INTEGER NOT NULL,
+        modified INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS files (
+        archive INTEGER NOT NULL REFERENCES archives(id) ON DELETE CASCADE,
+        path TEXT,
+        folder_hash TEXT NOT NULL,
+        name_hash TEXT NOT NULL,
+        size INTEGER NOT NULL,
+        stored_size INTEGER NOT NULL,
+        checksum TEXT NOT NULL
+    );
+    CREATE INDEX IF NOT EXISTS files_path ON files(path);
+    CREATE INDEX IF NOT EXISTS files_checksum ON files(checksum);
+";
+
+fn open_db(db: &path::Path) -> Res<rusqlite::Connection> {
+    init_db(rusqlite::Connection::open(db)?)
+}
+
+fn init_db(conn: rusqlite::Connection) -> Res<rusqlite::Connection> {
+    conn.execute_batch(SCHEMA)?;
+    Ok(conn)
+}
+
+/// Opens a database for querying, which unlike indexing requires it to exist
+fn open_existing_db(db: &path::Path) -> Res<rusqlite::Connection> {
+    if !db.exists() {
+        return Err(format!("Database {} does not exist", db.to_string_lossy()).into());
+    }
+    open_db(db)
+}
+
+/// Returns the size and modification time (in nanoseconds since the Unix
+/// epoch) that are stored to detect changed archives
+fn file_stamp(metadata: &fs::Metadata) -> (i64, Option<i64>) {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(time::UNIX_EPOCH).ok())
+        .and_then(|since| i64::try_from(since.as_nanos()).ok());
+    (metadata.len() as i64, modified)
+}
+
+/// Adds the files in each archive to the database, replacing older entries of
+/// the same archive. Archives with the same size and modification time as when
+/// they were last indexed are skipped, unless `force` is set.
+pub(crate) fn index(bsa_files: &[path::PathBuf], db: &path::Path, force: bool) -> Res<()> {
+    index_into(&mut open_db(db)?, bsa_files, force)
+}
+
+fn index_into(
+    conn: &mut rusqlite::Connection,
+    bsa_files: &[path::PathBuf],
+    force: bool,
+) -> Res<()> {
+    let (mut indexed, mut unchanged) = (0, 0);
+    for bsa_file in bsa_files {
+        let archive_path = fs::canonicalize(bsa_file)?;
+        let archive_path = archive_path.to_string_lossy();
+        let (size, modified) = file_stamp(&fs::metadata(bsa_file)?);
+        let known: Option<(i64, Option<i64>)> =
+            rusqlite::OptionalExtension::optional(conn.query_row(
+                "SELECT size, modified FROM archives WHERE path = ?1",
+                [&archive_path],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            ))?;
+        if !force && known == Some((size, modified)) {
+            unchanged += 1;
+            continue;
+        }
+
+        let mut bsa = bsa::open(bsa_file)?;
+        let files: Vec<_> = bsa
+            .files_by_offset()
+            .into_iter()
+            .map(|(folder, file)| {
+                (
+                    file.full_path(folder).map(|path| path.to_string()),
+                    file.clone(),
+                )
+            })
+            .collect();
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM archives WHERE path = ?1", [&archive_path])?;
+        tx.execute(
+            "INSERT INTO archives (path, size, modified) VALUES (?1, ?2, ?3)",
+            rusqlite::params![archive_path, size, modified],
+        )?;
+        let archive = tx.last_insert_rowid();
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO files (archive, path, folder_hash, name_hash, size, stored_size, checksum)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?;
+            for (path, file) in &files {
+                insert.execute(rusqlite::params![
+                    archive,
+                    path,
+                    format!("{:016x}", file.folder_hash()),
+                    format!("{:016x}", file.name_hash()),
+                    file.uncompressed_size() as i64,
+                    file.stored_size() as i64,
+                    file.checksum(&mut bsa)?.to_string(),
+                ])?;
+            }
+        }
+        tx.commit()?;
+        eprintln!(
+            "{}: indexed {} files",
+            bsa_file.to_string_lossy(),
+            files.len()
+        );
+        indexed += 1;
+    }
+    eprintln!(
+        "Indexed {} archives ({} unchanged)",
+        indexed + unchanged,
+        unchanged
+    );
+    Ok(())
+}
+
+/// Prints the archives that contain files matching the given glob pattern
+pub(crate) fn find(db: &path::Path, pattern: &str) -> Res<()> {
+    find_in(&open_existing_db(db)?, pattern, &mut io::stdout().lock())
+}
+
+fn find_in(conn: &rusqlite::Connection, pattern: &str, out: &mut impl io::Write) -> Res<()> {
+    let mut stmt = conn.prepare(
+        "SELECT files.path, archives.path, files.size, files.checksum
+         FROM files JOIN archives ON files.archive = archives.id
+         WHERE files.path IS NOT NULL AND (?1 IS NULL OR files.path = ?1)
+         ORDER BY files.path, archives.path",
+    )?;
+    // paths without wildcards can use the index
+    let exact = if pattern.contains(['*', '?']) {
+        None
+    } else {
+        Some(pattern.replace('/', "\\").to_lowercase())
+    };
+    let mut rows = stmt.query([exact])?;
+    while let Some(row) = rows.next()? {
+        let path: String = row.get(0)?;
+        if !glob::matches(pattern, &path) {
+            continue;
+        }
+        let archive: String = row.get(1)?;
+        let size: i64 = row.get(2)?;
+        let checksum: String = row.get(3)?;
+        writeln!(out, "{}\t{}\t{}\t{}", path, archive, size, checksum)?;
+    }
+    Ok(())
+}
+
+/// Prints the files that are in more than one archive, and whether their
+/// contents differ
+pub(crate) fn conflicts(db: &path::Path) -> Res<()> {
+    conflicts_in(&open_existing_db(db)?, &mut io::stdout().lock())
+}
+
+fn conflicts_in(conn: &rusqlite::Connection, out: &mut impl io::Write) -> Res<()> {
+    let mut stmt = conn.prepare(
+        "SELECT files.path, COUNT(DISTINCT files.checksum), GROUP_CONCAT(archives.path, char(10))
+         FROM files JOIN archives ON files.archive = archives.id
+         WHERE files.path IS NOT NULL
+         GROUP BY files.path HAVING COUNT(*) > 1
+         ORDER BY files.path",
+    )?;
+    let mut rows = stmt.query([])?;
+    let (mut count, mut different) = (0, 0);
+    while let Some(row) = rows.next()? {
+        let path: String = row.get(0)?;
+        let checksums: i64 = row.get(1)?;
+        let archives: String = row.get(2)?;
+        let contents = if checksums > 1 {
+            different += 1;
+            "different"
+        } else {
+            "identical"
+        };
+        writeln!(out, "{} ({} contents)", path, contents)?;
+        for archive in archives.lines() {
+            writeln!(out, "  {}", archive)?;
+        }
+        count += 1;
+    }
+    eprintln!(
+        "{} files are in more than one archive ({} with different contents)",
+        count, different
+    );
+    Ok(())
+}
+
+/// Prints the contents that are stored more than once, largest waste first
+pub(crate) fn duplicates(db: &path::Path) -> Res<()> {
+    duplicates_in(&open_existing_db(db)?, &mut io::stdout().lock())
+}
+
+fn duplicates_in(conn: &rusqlite::Connection, out: &mut impl io::Write) -> Res<()> {
+    let mut stmt = conn.prepare(
+        "SELECT files.checksum, MAX(files.size), COUNT(*),
+                GROUP_CONCAT(archives.path || ': ' || COALESCE(files.path, '?'), char(10))
+         FROM files JOIN archives ON files.archive = archives.id
+         GROUP BY files.checksum HAVING COUNT(*) > 1
+         ORDER BY MAX(files.size) * (COUNT(*) - 1) DESC, files.checksum",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut wasted = 0;
+    while let Some(row) = rows.next()? {
+        let checksum: String = row.get(0)?;
+        let size: i64 = row.get(1)?;
+        let count: i64 = row.get(2)?;
+        let paths: String = row.get(3)?;
+        writeln!(out, "{} ({} bytes, {} copies)", checksum, size, count)?;
+        for path in paths.lines() {
+            writeln!(out, "  {}", path)?;
+        }
+        wasted += size * (count - 1);
+    }
+    eprintln!("{} bytes are stored more than once", wasted);
+    Ok(())
+}
+
+/// Prints the paths that best match a search query, allowing typos (see
+/// [`fuzzy::score`]), with the archives that contain them
+pub(crate) fn search(db: &path::Path, query: &str, limit: usize) -> Res<()> {
+    search_in(
+        &open_existing_db(db)?,
+        query,
+        limit,
+        &mut io::stdout().lock(),
+    )
+}
+
+fn search_in(
+    conn: &rusqlite::Connection,
+    query: &str,
+    limit: usize,
+    out: &mut impl io::Write,
+) -> Res<()> {
+    let mut stmt = conn.prepare(
+        "SELECT files.path, GROUP_CONCAT(archives.path, char(10))
+         FROM files JOIN archives ON files.archive = archives.id
+         WHERE files.path IS NOT NULL
+         GROUP BY files.path",
+    )?;
+    let mut rows = stmt.query([])?;
+    let mut matches = vec![];
+    while let Some(row) = rows.next()? {
+        let path: String = row.get(0)?;
+        if let Some(score) = fuzzy::score(query, &path) {
+            let archives: String = row.get(1)?;
+            matches.push((score, path, archives));
+        }
+    }
+    matches.sort_by(|(a_score, a_path, _), (b_score, b_path, _)| {
+        (a_score, a_path.len(), a_path).cmp(&(b_score, b_path.len(), b_path))
+    });
+    for (_, path, archives) in matches.iter().take(limit) {
+        writeln!(out, "{}", path)?;
+        for archive in archives.lines() {
+            writeln!(out, "  {}", archive)?;
+        }
+    }
+    if matches.len() > limit {
+        eprintln!("{} more matches not shown", matches.len() - limit);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{conflicts_in, duplicates_in, find_in, index_into, init_db, search_in};
+    use crate::tests::{temp_dir, write_archive};
+    use std::fs;
+
+    #[test]
+    fn test_index() {
+        let dir = temp_dir("index");
+        let archives = [dir.join("a.bsa"), dir.join("b.bsa")];
+        write_archive(
+            &archives[0],
+            &[(r"meshes\a.nif", b"same"), (r"textures\b.dds", b"first")],
+        );
+        write_archive(
+            &archives[1],
+            &[
+                (r"meshes\a.nif", b"same"),
+                (r"meshes\c.nif", b"same"),
+                (r"textures\b.dds", b"last"),
+            ],
+        );
+        let a = fs::canonicalize(&archives[0]).unwrap();
+        let b = fs::canonicalize(&archives[1]).unwrap();
+        let (a, b) = (a.to_string_lossy(), b.to_string_lossy());
+
+        let mut conn = init_db(rusqlite::Connection::open_in_memory().unwrap()).unwrap();
+        let counts = |conn: &rusqlite::Connection| -> (i64, i64) {
+            let count = |table| {
+                conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |row| {
+                    row.get(0)
+                })
+                .unwrap()
+            };
+            (count("archives"), count("files"))
+        };
+        index_into(&mut conn, &archives, false).unwrap();
+        assert_eq!(counts(&conn), (2, 5));
+        // unchanged archives are skipped, and forced ones replace their files
+        index_into(&mut conn, &archives, false).unwrap();
+        assert_eq!(counts(&conn), (2, 5));
+        index_into(&mut conn, &archives[1..], true).unwrap();
+        assert_eq!(counts(&conn), (2, 5));
+
+        let output = |write: &dyn Fn(&mut Vec<u8>)| {
+            let mut out = vec![];
+            write(&mut out);
+            String::from_utf8(out).unwrap()
+        };
+        let found = output(&|out| find_in(&conn, "meshes/*.nif", out).unwrap());
+        let found: Vec<(&str, &str)> = found
+            .lines()
+            .map(|line| {
+                let fields: Vec<&str> = line.split('\t').collect();
+                (fields[0], fields[1])
+            })
+            .collect();
+        assert_eq!(
+            found,
+            [
+                (r"meshes\a.nif", &*a),
+                (r"meshes\a.nif", &*b),
+                (r"meshes\c.nif", &*b)
+            ]
+        );
+        let found = output(&|out| find_in(&conn, "Meshes/C.nif", out).unwrap());
+        assert_eq!(found.lines().count(), 1);
+
+        assert_eq!(
+            output(&|out| conflicts_in(&conn, out).unwrap()),
+            format!(
+                "meshes\\a.nif (identical contents)\n  {a}\n  {b}\n\
+                 textures\\b.dds (different contents)\n  {a}\n  {b}\n",
+                a = a,
+                b = b
+            )
+        );
+        let duplicates = output(&|out| duplicates_in(&conn, out).unwrap());
+        assert!(duplicates
+            .lines()
+            .next()
+            .unwrap()
+            .ends_with(" (4 bytes, 3 copies)"));
+        assert_eq!(duplicates.lines().count(), 4);
+
+        let found = output(&|out| search_in(&conn, "mesh a.nf", 1, out).unwrap());
+        assert_eq!(found, format!("meshes\\a.nif\n  {}\n  {}\n", a, b));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,3395 @@
+use std::{collections, error, fmt, fs, io, path, process, time};
+
+#[cfg(any(feature = "sqlite", test))]
+mod fuzzy;
+mod glob;
+#[cfg(feature = "sqlite")]
+mod index;
+mod rewrite;
+
+type Res<T> = Result<T, Box<dyn error::Error + Send + Sync + 'static>>;
+
+fn setup_logger(verbose: bool) {
+    let level = if verbose {
+        log::LevelFilter::max()
+    } else {
+        log::LevelFilter::Off
+    };
+    pretty_env_logger::formatted_builder()
+        .filter(None, level)
+        .init();
+}
+
+fn ls_entry(bsa: &mut bsa::Bsa, path: &str, file: &bsa::File, long: bool) -> Res<()> {
+    if long {
+        println!(
+            "{:>10} {:<7} {}",
+            file.uncompressed_size(),
+            file.kind(bsa)?,
+            path
+        );
+    } else {
+        println!("{}", path);
+    }
+    Ok(())
+}
+
+/// How `ls` orders and prints paths
+#[derive(Debug, Clone, Copy)]
+struct ListOptions {
+    by_offset: bool,
+    sorted: bool,
+    relative: bool,
+    long: bool,
+}
+
+/// Options for selecting a subset of the files in a BSA
+#[derive(clap::Args, Debug, Default)]
+struct Filter {
+    /// Only include files of the given type (meshes, textures, menus, sounds, voices, shaders, trees, fonts, misc)
+    #[arg(long = "type")]
+    types: Vec<bsa::FileType>,
+    /// Only include files matching the given glob pattern (patterns without a folder are matched against the file name)
+    #[arg(long)]
+    include: Vec<String>,
+}
+
+impl Filter {
+    fn matches_archive(&self, bsa: &bsa::Bsa) -> bool {
+        self.types.is_empty()
+            || self
+                .types
+                .iter()
+                .any(|&file_type| bsa.file_flags().contains(file_type))
+    }
+
+    fn matches(&self, folder_name: &str, file_name: &str) -> bool {
+        let path = format!("{}\\{}", folder_name, file_name);
+        (self.types.is_empty() || self.types.contains(&bsa::FileType::from_path(&path)))
+            && (self.include.is_empty()
+                || self.include.iter().any(|pattern| {
+                    if pattern.contains(['/', '\\']) {
+                        glob::matches(pattern, &path)
+                    } else {
+                        glob::matches(pattern, file_name)
+                    }
+                }))
+    }
+}
+
+/// Opens a BSA file, replacing control characters in names so that they can
+/// be safely printed and used as file names
+fn open_sanitized(file: &path::Path) -> Res<bsa::Bsa> {
+    Ok(bsa::OpenOptions::new()
+        .control_characters(bsa::ControlCharacters::Replace)
+        .open(file)?)
+}
+
+fn ls(file: &path::Path, options: ListOptions, filter: &Filter) -> Res<()> {
+    let mut bsa = open_sanitized(file)?;
+    if !filter.matches_archive(&bsa) {
+        return Ok(());
+    }
+    let mut entries = vec![];
+    if options.by_offset {
+        for (folder, file) in bsa.files_by_offset() {
+            entries.push((folder.name().map(str::to_string), file.clone()));
+        }
+    } else {
+        for folder in bsa.folders() {
+            for file in folder.files() {
+                entries.push((folder.name().map(str::to_string), file.clone()));
+            }
+        }
+    }
+    let mut paths = vec![];
+    for (folder_name, file) in entries {
+        if let (Some(folder_name), Some(file_name)) = (folder_name, file.name()) {
+            if filter.matches(&folder_name, file_name) {
+                paths.push((format!("{}\\{}", folder_name, file_name), file));
+            }
+        }
+    }
+    if options.sorted {
+        let mut files: collections::HashMap<String, bsa::File> = collections::HashMap::new();
+        for (path, file) in paths {
+            files.entry(path.to_lowercase()).or_insert(file);
+        }
+        paths = bsa
+            .canonical_listing()
+            .lines()
+            .filter_map(|path| Some((path.to_string(), files.remove(path)?)))
+            .collect();
+    }
+    for (mut path, file) in paths {
+        if options.relative {
+            path = path.replace('\\', "/");
+        }
+        ls_entry(&mut bsa, &path, &file, options.long)?;
+    }
+    Ok(())
+}
+
+fn find_file(bsa: &bsa::Bsa, path: &str) -> Option<bsa::File> {
+    let path = path.replace('/', "\\");
+    for folder in bsa.folders() {
+        for file in folder.files() {
+            if let Some(full_path) = file.full_path(&folder) {
+                if path.eq_ignore_ascii_case(full_path.as_str()) {
+                    return Some(file.clone());
+                }
+            }
+        }
+    }
+    None
+}
+
+fn named_files_by_offset(bsa: &bsa::Bsa) -> Vec<(String, bsa::File)> {
+    bsa.files_by_offset()
+        .into_iter()
+        .filter_map(|(folder, file)| Some((file.full_path(folder)?.to_string(), file.clone())))
+        .collect()
+}
+
+fn cat(bsa_file: &path::Path, path: &str) -> Res<()> {
+    let mut bsa = bsa::open(bsa_file)?;
+    match find_file(&bsa, path) {
+        Some(file) => {
+            file.send_to(&mut bsa, &mut io::stdout().lock())?;
+        }
+        None => eprintln!(
+            "File {} does not exist in {}",
+            path,
+            bsa_file.to_string_lossy()
+        ),
+    }
+    Ok(())
+}
+
+/// The encoding of the strings printed by the strings command
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum StringsCharset {
+    Detect,
+    Utf8,
+    Cp1252,
+}
+
+fn strings(
+    bsa_file: &path::Path,
+    path: Option<&str>,
+    charset: StringsCharset,
+    json: bool,
+) -> Res<()> {
+    let mut bsa = bsa::open(bsa_file)?;
+    let path = match path {
+        Some(path) => path,
+        None => {
+            for (kind, entry) in bsa.strings_files() {
+                if let Some(path) = entry.path() {
+                    println!("{}\t{}", path, kind.extension());
+                }
+            }
+            return Ok(());
+        }
+    };
+    let kind = bsa::StringsKind::from_path(path)
+        .ok_or_else(|| format!("{} is not a .strings, .dlstrings or .ilstrings file", path))?;
+    let file = find_file(&bsa, path)
+        .ok_or_else(|| format!("File {} does not exist in {}", path, bsa_file.display()))?;
+    let encoding = match charset {
+        StringsCharset::Detect => bsa::StringsEncoding::default(),
+        StringsCharset::Utf8 => bsa::StringsEncoding::Utf8,
+        StringsCharset::Cp1252 => bsa::StringsEncoding::Legacy(bsa::Encoding::Windows1252),
+    };
+    let strings = file.read_strings(&mut bsa, kind, encoding)?;
+    if json {
+        let strings: Vec<_> = strings
+            .iter()
+            .map(|(id, string)| serde_json::json!({ "id": id, "string": string }))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&strings)?);
+        return Ok(());
+    }
+    for (id, string) in &strings {
+        println!("{:08x}\t{}", id, string.escape_debug());
+    }
+    Ok(())
+}
+
+fn hexdump(bsa_file: &path::Path, path: &str, length: u64) -> Res<()> {
+    let mut bsa = bsa::open(bsa_file)?;
+    let file = find_file(&bsa, path)
+        .ok_or_else(|| format!("File {} does not exist in {}", path, bsa_file.display()))?;
+    let mut data = vec![];
+    io::Read::read_to_end(
+        &mut io::Read::take(file.read_contents(&mut bsa)?, length),
+        &mut data,
+    )?;
+    for (i, line) in data.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for j in 0..16 {
+            if j == 8 {
+                hex.push(' ');
+            }
+            match line.get(j) {
+                Some(byte) => hex.push_str(&format!("{:02x} ", byte)),
+                None => hex.push_str("   "),
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!("{:08x}  {} |{}|", i * 16, hex, ascii);
+    }
+    Ok(())
+}
+
+/// Options for extracting files from a BSA
+#[derive(clap::Args, Debug, Default)]
+struct ExtractOptions {
+    /// Extract files folder by folder, instead of in the order their contents are stored in the BSA
+    #[arg(long)]
+    folder_order: bool,
+    /// Skip files that have already been extracted (with matching size and checksum), same as
+    /// '--overwrite if-different'
+    #[arg(long, conflicts_with = "overwrite")]
+    resume: bool,
+    /// What to do with files that already exist (defaults to always replacing them)
+    #[arg(long, value_enum)]
+    overwrite: Option<OverwriteKind>,
+    /// Extract files without names as '_unknown/IDX_HASH.bin' instead of skipping them
+    #[arg(long)]
+    include_nameless: bool,
+    /// Print the files that would be extracted, without writing anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Don't use extended-length paths on Windows (which allow paths longer than 260 characters)
+    #[arg(long)]
+    no_long_paths: bool,
+    /// Link files with identical contents to the first extracted copy instead of writing them again
+    #[arg(long, value_enum)]
+    link_duplicates: Option<LinkKind>,
+    /// Delete the files extracted from an archive if extracting it fails
+    #[arg(long)]
+    rollback_on_error: bool,
+    /// Try other codecs (or no compression) for compressed files that fail to decompress
+    #[arg(long)]
+    recover: bool,
+    /// Print a summary of the files written from each archive, by extension
+    #[arg(long)]
+    stats: bool,
+    /// Convert extracted XWM and FUZ audio into playable files with ffmpeg, writing the lip sync
+    /// data of FUZ files to .lip files
+    #[arg(long, value_enum)]
+    convert_audio: Option<AudioKind>,
+    /// Path of the ffmpeg executable used by '--convert-audio'
+    #[arg(long, requires = "convert_audio")]
+    ffmpeg: Option<path::PathBuf>,
+    /// Permissions of extracted files, in octal (e.g. 644)
+    #[cfg(unix)]
+    #[arg(long, value_parser = parse_mode)]
+    file_mode: Option<u32>,
+    /// Permissions of created directories, in octal (e.g. 755)
+    #[cfg(unix)]
+    #[arg(long, value_parser = parse_mode)]
+    dir_mode: Option<u32>,
+}
+
+#[cfg(unix)]
+fn parse_mode(s: &str) -> Result<u32, String> {
+    u32::from_str_radix(s.trim_start_matches("0o"), 8)
+        .ok()
+        .filter(|&mode| mode <= 0o7777)
+        .ok_or_else(|| format!("invalid octal mode: {}", s))
+}
+
+fn print_extract_stats(stats: &bsa::ExtractStats) {
+    eprintln!(
+        "Wrote {} files ({} bytes) in {:.2}s ({:.1} MB/s), linked {}, skipped {}",
+        stats.files_written(),
+        stats.bytes_written(),
+        stats.elapsed().as_secs_f64(),
+        stats.throughput() / 1_000_000.0,
+        stats.files_linked(),
+        stats.files_skipped()
+    );
+    for (extension, count) in stats.by_extension() {
+        let extension = if extension.is_empty() {
+            "(none)".to_string()
+        } else {
+            format!(".{}", extension)
+        };
+        eprintln!(
+            "    {:<10} {:>8} files {:>14} bytes",
+            extension,
+            count.files(),
+            count.bytes()
+        );
+    }
+}
+
+/// The format of audio converted by `extract --convert-audio`
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum AudioKind {
+    Wav,
+    Ogg,
+}
+
+/// Replaces extracted XWM and FUZ files with converted audio (and .lip files
+/// for the lip sync data in FUZ files)
+#[cfg(feature = "audio")]
+fn convert_audio(paths: &[path::PathBuf], options: &ExtractOptions) -> Res<()> {
+    let format = match options.convert_audio {
+        Some(AudioKind::Wav) => bsa::AudioFormat::Wav,
+        Some(AudioKind::Ogg) => bsa::AudioFormat::Ogg,
+        None => return Ok(()),
+    };
+    let mut converter = bsa::AudioConverter::new();
+    if let Some(ffmpeg) = &options.ffmpeg {
+        converter.program(ffmpeg);
+    }
+    for path in paths {
+        let extension = path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+        let data = match extension.as_deref() {
+            Some("fuz") | Some("xwm") => fs::read(path)?,
+            _ => continue,
+        };
+        let audio = if extension.as_deref() == Some("fuz") {
+            match bsa::Fuz::parse(&data) {
+                Ok(fuz) => {
+                    if !fuz.lip().is_empty() {
+                        fs::write(path.with_extension("lip"), fuz.lip())?;
+                    }
+                    fuz.audio()
+                }
+                Err(e) => {
+                    eprintln!("warning: {:?}: {}", path, error_chain(&e));
+                    continue;
+                }
+            }
+        } else {
+            &data[..]
+        };
+        let dest = path.with_extension(format.extension());
+        println!("Converting {:?} to {:?}", path, dest);
+        match converter.convert(audio, format) {
+            Ok(converted) => {
+                fs::write(&dest, converted)?;
+                fs::remove_file(path)?;
+            }
+            Err(e @ bsa::AudioError::ConverterNotFound(_)) => {
+                return Err(format!("{} (use --ffmpeg to set its path)", e).into())
+            }
+            Err(e) => eprintln!("warning: {:?}: {}", path, error_chain(&e)),
+        }
+    }
+    Ok(())
+}
+
+/// The kind of link created by `extract --link-duplicates`
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum LinkKind {
+    Hard,
+    Sym,
+}
+
+/// When `extract --overwrite` replaces existing files
+#[derive(clap::ValueEnum, Debug, Clone, Copy)]
+enum OverwriteKind {
+    Always,
+    Never,
+    IfNewer,
+    IfDifferent,
+}
+
+fn extract_bsa(
+    bsa: &mut bsa::Bsa,
+    into: &path::Path,
+    options: &ExtractOptions,
+    filter: &Filter,
+) -> Res<()> {
+    if !filter.matches_archive(bsa) {
+        return Ok(());
+    }
+    if cfg!(not(feature = "audio")) && options.convert_audio.is_some() {
+        return Err("Converting audio requires the `audio` feature".into());
+    }
+    let mut files = vec![];
+    if options.folder_order {
+        for folder in bsa.folders() {
+            for file in folder.files() {
+                files.push((folder.name().map(str::to_string), file.clone()));
+            }
+        }
+    } else {
+        for (folder, file) in bsa.files_by_offset() {
+            files.push((folder.name().map(str::to_string), file.clone()));
+        }
+    }
+    let mut plan = bsa::ExtractPlan::default();
+    plan.long_paths(!options.no_long_paths)
+        .link_duplicates(options.link_duplicates.map(|kind| match kind {
+            LinkKind::Hard => bsa::LinkMode::HardLink,
+            LinkKind::Sym => bsa::LinkMode::SymLink,
+        }))
+        .rollback_on_error(options.rollback_on_error)
+        .recover(options.recover)
+        .overwrite(match options.overwrite {
+            _ if options.resume => bsa::Overwrite::IfDifferent,
+            None | Some(OverwriteKind::Always) => bsa::Overwrite::Always,
+            Some(OverwriteKind::Never) => bsa::Overwrite::Never,
+            Some(OverwriteKind::IfNewer) => bsa::Overwrite::IfNewer,
+            Some(OverwriteKind::IfDifferent) => bsa::Overwrite::IfDifferent,
+        });
+    #[cfg(unix)]
+    plan.file_mode(options.file_mode).dir_mode(options.dir_mode);
+    let mut nameless_count = 0;
+    for (folder_name, file) in files {
+        let (folder_name, file_name) = match (folder_name, file.name()) {
+            (Some(folder_name), Some(file_name)) => (folder_name, file_name.to_string()),
+            _ => {
+                nameless_count += 1;
+                if !options.include_nameless {
+                    continue;
+                }
+                let file_name = format!("{}_{:016x}.bin", nameless_count - 1, file.name_hash());
+                ("_unknown".to_string(), file_name)
+            }
+        };
+        if !filter.matches(&folder_name, &file_name) {
+            continue;
+        }
+        let bsa_path = format!("{}\\{}", folder_name, file_name);
+        match bsa::path::output_path(into, &bsa_path) {
+            Ok(file_path) => {
+                plan.push(file_path, file);
+            }
+            Err(e) => eprintln!("warning: skipping {}: {}", bsa_path, e),
+        }
+    }
+    if options.dry_run {
+        for entry in plan.entries() {
+            let file = entry.file();
+            println!(
+                "Would create {:?} ({} bytes{})",
+                entry.path(),
+                file.uncompressed_size(),
+                if file.is_compressed() {
+                    ", compressed"
+                } else {
+                    ""
+                }
+            );
+        }
+    } else {
+        let mut written = vec![];
+        let stats = plan.execute_with(bsa, |entry, action| {
+            match action {
+                bsa::ExtractAction::Create => println!("Creating {:?}", entry.path()),
+                bsa::ExtractAction::Link(original) => {
+                    println!("Linking {:?} to {:?}", entry.path(), original)
+                }
+                bsa::ExtractAction::Skip => {
+                    println!("Skipping {:?}", entry.path());
+                    return;
+                }
+            }
+            written.push(entry.path().to_path_buf());
+        })?;
+        #[cfg(feature = "audio")]
+        convert_audio(&written, options)?;
+        #[cfg(not(feature = "audio"))]
+        let _ = written;
+        for (path, codec) in stats.recovered() {
+            let codec = match codec {
+                bsa::Codec::None => "uncompressed".to_string(),
+                codec => format!("{} compressed", codec),
+            };
+            eprintln!(
+                "warning: recovered {:?} with mislabeled compression as {} data",
+                path, codec
+            );
+        }
+        if options.stats {
+            print_extract_stats(&stats);
+        }
+    }
+    if nameless_count > 0 && !options.include_nameless {
+        eprintln!(
+            "warning: skipped {} files without names (use --include-nameless to extract them)",
+            nameless_count
+        );
+    }
+    Ok(())
+}
+
+fn extract(
+    bsa_files: &[path::PathBuf],
+    into: Option<&path::Path>,
+    options: &ExtractOptions,
+    filter: &Filter,
+) -> Res<()> {
+    let base_extract_dir = if let Some(into) = into {
+        path::PathBuf::from(into)
+    } else {
+        path::PathBuf::new()
+    };
+    for bsa_file in bsa_files {
+        let mut bsa = open_sanitized(bsa_file)?;
+        let mut concat_folder = path::PathBuf::new();
+        for part in &base_extract_dir {
+            if part == "-" {
+                if let Some(file_stem) = bsa_file.file_stem() {
+                    concat_folder.push(file_stem);
+                } else {
+                    concat_folder.push(part);
+                }
+            } else {
+                concat_folder.push(part);
+            }
+        }
+        extract_bsa(&mut bsa, &concat_folder, options, filter)?;
+    }
+    Ok(())
+}
+
+#[derive(clap::ValueEnum, Debug, Copy, Clone, PartialEq, Eq)]
+enum ConflictPolicy {
+    First,
+    Last,
+    Error,
+}
+
+fn print_build_plan(builder: &bsa::BsaBuilder, output: &path::Path) -> Res<()> {
+    let plan = builder.plan()?;
+    for entry in plan.entries() {
+        println!(
+            "Would write {} ({} bytes{})",
+            entry.path(),
+            entry.size(),
+            if entry.is_compressed() {
+                ", compressed"
+            } else {
+                ""
+            }
+        );
+    }
+    println!(
+        "Would create {:?} with {} files ({} bytes before compression)",
+        output,
+        plan.entries().len(),
+        plan.total_size()
+    );
+    Ok(())
+}
+
+fn merge(
+    bsa_files: &[path::PathBuf],
+    output: &path::Path,
+    conflict: ConflictPolicy,
+    align: Option<u32>,
+    ignore_game_limits: bool,
+    dry_run: bool,
+) -> Res<()> {
+    let mut builder = None;
+    for bsa_file in bsa_files {
+        let mut bsa = bsa::open(bsa_file)?;
+        let builder = builder.get_or_insert_with(|| {
+            let mut builder = bsa::BsaBuilder::for_game(bsa.game());
+            builder.ignore_game_limits(ignore_game_limits);
+            builder.compress(bsa.archive_flags().compressed_archive());
+            builder.embed_file_names(bsa.archive_flags().embed_file_names());
+            builder.alignment(align.unwrap_or(bsa.data_alignment() as u32));
+            builder
+        });
+        for folder in bsa.folders() {
+            if let Some(folder_name) = folder.name() {
+                for file in folder.files() {
+                    if let Some(file_name) = file.name() {
+                        let path = format!("{}\\{}", folder_name, file_name);
+                        if builder.contains(&path) {
+                            match conflict {
+                                ConflictPolicy::First => continue,
+                                ConflictPolicy::Last => (),
+                                ConflictPolicy::Error => {
+                                    return Err(format!(
+                                        "{} exists in multiple archives (found again in {})",
+                                        path,
+                                        bsa_file.to_string_lossy()
+                                    )
+                                    .into())
+                                }
+                            }
+                        }
+                        // copy the stored bytes if they don't need to be recompressed
+                        let raw = file.read_raw(&mut bsa)?;
+                        if raw.codec() == builder.codec() {
+                            builder.add_raw_file(&path, raw)?;
+                        } else {
+                            builder.add_file(&path, file.read_to_vec(&mut bsa)?)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    if let Some(builder) = builder {
+        if dry_run {
+            print_build_plan(&builder, output)?;
+        } else {
+            let tmp_output = sidecar_path(output, "tmp");
+            builder.write(&mut io::BufWriter::new(fs::File::create(&tmp_output)?))?;
+            fs::rename(&tmp_output, output)?;
+        }
+    }
+    Ok(())
+}
+
+fn recompress(
+    bsa_file: &path::Path,
+    output: Option<&path::Path>,
+    only: &str,
+    codec: &str,
+    align: Option<u32>,
+    ignore_game_limits: bool,
+    dry_run: bool,
+) -> Res<usize> {
+    let mut bsa = bsa::open(bsa_file)?;
+    let version = bsa.version();
+    let compress = match codec {
+        "none" => false,
+        "lz4" | "lz4-block" if version == bsa::Version::SKYRIM_SPECIAL_EDITION => true,
+        "zstd" => true,
+        "zlib" if version != bsa::Version::SKYRIM_SPECIAL_EDITION => true,
+        _ => {
+            return Err(format!("The {} codec is not supported by this BSA version", codec).into())
+        }
+    };
+    let mut builder = bsa::BsaBuilder::for_game(bsa.game());
+    builder
+        .ignore_game_limits(ignore_game_limits)
+        .compress(bsa.archive_flags().compressed_archive())
+        .lz4_block(codec == "lz4-block")
+        .zstd(codec == "zstd" || (codec == "none" && bsa.archive_flags().zstd_codec()))
+        .embed_file_names(bsa.archive_flags().embed_file_names())
+        .keep_original_case(true)
+        .alignment(align.unwrap_or(bsa.data_alignment() as u32));
+    let mut count = 0;
+    for (path, file) in named_files_by_offset(&bsa) {
+        if glob::matches(only, &path) {
+            let contents = file.read_to_vec(&mut bsa)?;
+            builder.add_file_with_compression(&path, contents, compress)?;
+            count += 1;
+        } else {
+            match builder.add_raw_file(&path, file.read_raw(&mut bsa)?) {
+                // switching to or from zstd requires recompressing every file
+                Err(bsa::WriteError::CodecMismatch(_, _)) => {
+                    let contents = file.read_to_vec(&mut bsa)?;
+                    builder.add_file_with_compression(&path, contents, true)?;
+                }
+                res => {
+                    res?;
+                }
+            }
+        }
+    }
+    let output = output.map_or_else(|| bsa_file.to_path_buf(), path::Path::to_path_buf);
+    if dry_run {
+        print_build_plan(&builder, &output)?;
+        return Ok(count);
+    }
+    let tmp_output = sidecar_path(&output, "tmp");
+    builder.write(&mut io::BufWriter::new(fs::File::create(&tmp_output)?))?;
+    drop(bsa);
+    fs::rename(&tmp_output, &output)?;
+    Ok(count)
+}
+
+/// Rewrites a BSA with the files renamed by the given rules, copying their
+/// contents without recompressing them. Returns the number of renamed files,
+/// and doesn't write anything if no files are renamed.
+fn rewrite_archive(
+    bsa_file: &path::Path,
+    rewrite: &rewrite::Rewrite,
+    output: Option<&path::Path>,
+    dry_run: bool,
+) -> Res<usize> {
+    let mut bsa = bsa::open(bsa_file)?;
+    let mut builder = bsa::BsaBuilder::for_game(bsa.game());
+    builder
+        .compress(bsa.archive_flags().compressed_archive())
+        .zstd(bsa.archive_flags().zstd_codec())
+        .embed_file_names(bsa.archive_flags().embed_file_names())
+        .keep_original_case(true)
+        .alignment(bsa.data_alignment() as u32);
+    let files: Vec<_> = named_files_by_offset(&bsa)
+        .into_iter()
+        .map(|(path, file)| (rewrite.apply(&path), path, file))
+        .collect();
+    let count = files
+        .iter()
+        .filter(|(renamed, _, _)| renamed.is_some())
+        .count();
+    if count == 0 {
+        return Ok(0);
+    }
+    // the original paths of the files that would end up at each path
+    let mut sources = collections::BTreeMap::<_, Vec<_>>::new();
+    for (renamed, path, _) in &files {
+        let new_path = renamed.as_ref().unwrap_or(path);
+        sources
+            .entry(new_path.to_lowercase())
+            .or_default()
+            .push((new_path, path));
+    }
+    let collisions: Vec<_> = sources.values().filter(|paths| paths.len() > 1).collect();
+    if !collisions.is_empty() {
+        for paths in &collisions {
+            eprintln!("{} would be written by:", paths[0].0);
+            for (_, path) in paths.iter() {
+                eprintln!("    {}", path);
+            }
+        }
+        return Err(format!("{} paths would be written more than once", collisions.len()).into());
+    }
+    for (renamed, path, file) in &files {
+        let raw = file.read_raw(&mut bsa)?;
+        builder.add_raw_file(renamed.as_ref().unwrap_or(path), raw)?;
+    }
+    let output = output.map_or_else(|| bsa_file.to_path_buf(), path::Path::to_path_buf);
+    if dry_run {
+        print_build_plan(&builder, &output)?;
+        return Ok(count);
+    }
+    let tmp_output = sidecar_path(&output, "tmp");
+    builder.write(&mut io::BufWriter::new(fs::File::create(&tmp_output)?))?;
+    drop(bsa);
+    fs::rename(&tmp_output, &output)?;
+    Ok(count)
+}
+
+fn repair(bsa_file: &path::Path, output: Option<&path::Path>, dry_run: bool) -> Res<()> {
+    let diagnostics = bsa::open(bsa_file)?.diagnostics().to_vec();
+    if diagnostics.is_empty() {
+        eprintln!("Nothing to repair");
+        return Ok(());
+    }
+    for diagnostic in &diagnostics {
+        eprintln!("{}", diagnostic);
+    }
+    // rewriting the archive copies every file as it was read, which
+    // corrects the problems detected while reading it
+    recompress(bsa_file, output, "", "none", None, false, dry_run)?;
+    if !dry_run {
+        eprintln!("Repaired {}", bsa_file.to_string_lossy());
+    }
+    Ok(())
+}
+
+fn split_script_line(line: &str) -> Res<Vec<String>> {
+    let mut words = vec![];
+    let mut word = None::<String>;
+    let mut quoted = false;
+    for ch in line.chars() {
+        match ch {
+            '"' => {
+                quoted = !quoted;
+                word.get_or_insert_with(String::new);
+            }
+            '#' if !quoted && word.is_none() => break,
+            ch if ch.is_whitespace() && !quoted => words.extend(word.take()),
+            ch => word.get_or_insert_with(String::new).push(ch),
+        }
+    }
+    if quoted {
+        return Err("Unterminated quote".into());
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+fn open_cached<'a>(
+    archives: &'a mut collections::HashMap<path::PathBuf, bsa::Bsa>,
+    bsa_file: &str,
+) -> Res<&'a mut bsa::Bsa> {
+    Ok(match archives.entry(path::PathBuf::from(bsa_file)) {
+        collections::hash_map::Entry::Occupied(entry) => entry.into_mut(),
+        collections::hash_map::Entry::Vacant(entry) => {
+            let bsa = bsa::open(entry.key())?;
+            entry.insert(bsa)
+        }
+    })
+}
+
+fn exec_line(
+    words: &[String],
+    archives: &mut collections::HashMap<path::PathBuf, bsa::Bsa>,
+    builders: &mut collections::BTreeMap<path::PathBuf, bsa::BsaBuilder>,
+) -> Res<()> {
+    let words: Vec<&str> = words.iter().map(String::as_str).collect();
+    match words.as_slice() {
+        ["extract", bsa_file] => extract_bsa(
+            open_cached(archives, bsa_file)?,
+            path::Path::new(""),
+            &ExtractOptions::default(),
+            &Filter::default(),
+        )?,
+        ["extract", bsa_file, into] => extract_bsa(
+            open_cached(archives, bsa_file)?,
+            path::Path::new(into),
+            &ExtractOptions::default(),
+            &Filter::default(),
+        )?,
+        ["extract", bsa_file, path, dest] => {
+            let bsa = open_cached(archives, bsa_file)?;
+            let file = find_file(bsa, path)
+                .ok_or_else(|| format!("File {} does not exist in {}", path, bsa_file))?;
+            if let Some(parent) = path::Path::new(dest).parent() {
+                fs::create_dir_all(parent)?;
+            }
+            io::copy(&mut file.read_contents(bsa)?, &mut fs::File::create(dest)?)?;
+        }
+        ["cat", bsa_file, path] => {
+            let bsa = open_cached(archives, bsa_file)?;
+            let file = find_file(bsa, path)
+                .ok_or_else(|| format!("File {} does not exist in {}", path, bsa_file))?;
+            io::copy(&mut file.read_contents(bsa)?, &mut io::stdout().lock())?;
+        }
+        ["create", bsa_file, version, options @ ..] => {
+            let version: bsa::Version = version.parse()?;
+            let mut builder = bsa::BsaBuilder::new(version);
+            for &option in options {
+                match option {
+                    "compress" => builder.compress(true),
+                    "embed-names" => builder.embed_file_names(true),
+                    "keep-case" => builder.keep_original_case(true),
+                    other => return Err(format!("Unknown option {}", other).into()),
+                };
+            }
+            builders.insert(path::PathBuf::from(bsa_file), builder);
+        }
+        ["add", bsa_file, source, path] => {
+            builders
+                .get_mut(path::Path::new(bsa_file))
+                .ok_or_else(|| format!("{} must be created with `create` first", bsa_file))?
+                .add_file(path, fs::read(source)?)?;
+        }
+        _ => return Err(format!("Invalid command: {}", words.join(" ")).into()),
+    }
+    Ok(())
+}
+
+fn exec(script_file: &path::Path) -> Res<()> {
+    let script = fs::read_to_string(script_file)?;
+    let mut archives = collections::HashMap::new();
+    let mut builders = collections::BTreeMap::new();
+    for (idx, line) in script.lines().enumerate() {
+        let words = split_script_line(line)?;
+        if words.is_empty() {
+            continue;
+        }
+        if let Err(e) = exec_line(&words, &mut archives, &mut builders) {
+            return Err(format!("Line {}: {}", idx + 1, error_chain(e.as_ref())).into());
+        }
+    }
+    for (bsa_file, builder) in builders {
+        println!("Creating {:?}", &bsa_file);
+        builder.write(&mut io::BufWriter::new(fs::File::create(&bsa_file)?))?;
+    }
+    Ok(())
+}
+
+fn dedup_export(bsa_files: &[path::PathBuf], store: &path::Path) -> Res<()> {
+    use io::Write;
+    fs::create_dir_all(store)?;
+    let mut mapping = io::BufWriter::new(fs::File::create(store.join("mapping.tsv"))?);
+    let mut total = 0;
+    let mut unique = 0;
+    for bsa_file in bsa_files {
+        let mut bsa = bsa::open(bsa_file)?;
+        for folder in bsa.folders() {
+            if let Some(folder_name) = folder.name() {
+                for file in folder.files() {
+                    if let Some(file_name) = file.name() {
+                        let (key, added) = store_object(&mut bsa, file, store)?;
+                        if added {
+                            unique += 1;
+                        }
+                        total += 1;
+                        writeln!(
+                            mapping,
+                            "{}\t{}\\{}\t{}",
+                            bsa_file.to_string_lossy(),
+                            folder_name,
+                            file_name,
+                            key
+                        )?;
+                    }
+                }
+            }
+        }
+    }
+    mapping.flush()?;
+    eprintln!("Exported {} files ({} unique)", total, unique);
+    Ok(())
+}
+
+/// Adds the contents of a file to a content-addressed store, returning their
+/// checksum and whether they weren't in the store yet. Objects are written to
+/// a temporary file first, so an interrupted export never leaves a truncated
+/// object under a valid name.
+fn store_object(bsa: &mut bsa::Bsa, file: &bsa::File, store: &path::Path) -> Res<(String, bool)> {
+    let key = bsa::Checksum::from_reader(&mut file.read_contents(bsa)?)?.to_string();
+    let key_dir = store.join(&key[..2]);
+    let key_path = key_dir.join(&key);
+    if key_path.exists() {
+        return Ok((key, false));
+    }
+    fs::create_dir_all(&key_dir)?;
+    let tmp_path = sidecar_path(&key_path, "tmp");
+    let mut output = io::BufWriter::new(fs::File::create(&tmp_path)?);
+    io::copy(&mut file.read_contents(bsa)?, &mut output)?;
+    io::Write::flush(&mut output)?;
+    drop(output);
+    fs::rename(&tmp_path, &key_path)?;
+    Ok((key, true))
+}
+
+#[cfg(feature = "thumbnail")]
+fn thumbs(bsa_files: &[path::PathBuf], out: &path::Path, size: u32, filter: &Filter) -> Res<()> {
+    let (mut written, mut failed) = (0, 0);
+    for bsa_file in bsa_files {
+        let mut bsa = open_sanitized(bsa_file)?;
+        if !filter.matches_archive(&bsa) {
+            continue;
+        }
+        let files: Vec<_> = bsa
+            .files_by_offset()
+            .into_iter()
+            .filter_map(|(folder, file)| {
+                let folder_name = folder.name()?.to_string();
+                let file_name = file.name()?.to_string();
+                Some((folder_name, file_name, file.clone()))
+            })
+            .collect();
+        for (folder_name, file_name, file) in files {
+            let stem = match file_name.rsplit_once('.') {
+                Some((stem, extension)) if extension.eq_ignore_ascii_case("dds") => stem,
+                _ => continue,
+            };
+            if !filter.matches(&folder_name, &file_name) {
+                continue;
+            }
+            let png_path = format!("{}\\{}.png", folder_name, stem);
+            let dest = match bsa::path::output_path(out, &png_path) {
+                Ok(dest) => dest,
+                Err(e) => {
+                    eprintln!("warning: skipping {}: {}", png_path, e);
+                    failed += 1;
+                    continue;
+                }
+            };
+            match file.thumbnail(&mut bsa, size) {
+                Ok(png) => {
+                    fs::create_dir_all(dest.parent().unwrap_or(out))?;
+                    fs::write(&dest, png)?;
+                    written += 1;
+                }
+                Err(e) => {
+                    eprintln!(
+                        "warning: {}\\{}: {}",
+                        folder_name,
+                        file_name,
+                        error_chain(&e)
+                    );
+                    failed += 1;
+                }
+            }
+        }
+    }
+    eprintln!("Wrote {} thumbnails ({} failed)", written, failed);
+    Ok(())
+}
+
+fn sidecar_path(bsa_file: &path::Path, extension: &str) -> path::PathBuf {
+    let mut path = bsa_file.as_os_str().to_os_string();
+    path.push(".");
+    path.push(extension);
+    path::PathBuf::from(path)
+}
+
+#[cfg(feature = "signature")]
+fn read_key_file(key_file: &path::Path) -> Res<[u8; 32]> {
+    let contents = fs::read_to_string(key_file)?;
+    let bytes = bsa::decode_hex(contents.trim()).ok_or("Invalid key file")?;
+    Ok(std::convert::TryInto::try_into(bytes.as_slice()).map_err(|_| "Invalid key file")?)
+}
+
+fn sign(bsa_file: &path::Path, key_file: Option<&path::Path>) -> Res<()> {
+    #[cfg(not(feature = "signature"))]
+    if key_file.is_some() {
+        return Err("Signing requires the `signature` feature".into());
+    }
+    let mut bsa = bsa::open(bsa_file)?;
+    let manifest = bsa::Manifest::from_bsa(&mut bsa)?;
+    fs::write(sidecar_path(bsa_file, "manifest"), manifest.to_string())?;
+    #[cfg(feature = "signature")]
+    if let Some(key_file) = key_file {
+        let key = ed25519_dalek::SigningKey::from_bytes(&read_key_file(key_file)?);
+        let signature = manifest.sign(&key);
+        let signature: String = signature
+            .to_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        fs::write(sidecar_path(bsa_file, "sig"), signature)?;
+        let public_key: String = key
+            .verifying_key()
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        eprintln!("Signed with public key {}", public_key);
+    }
+    Ok(())
+}
+
+fn verify(bsa_file: &path::Path, public_key_file: Option<&path::Path>) -> Res<()> {
+    let manifest: bsa::Manifest =
+        fs::read_to_string(sidecar_path(bsa_file, "manifest"))?.parse()?;
+    if let Some(public_key_file) = public_key_file {
+        #[cfg(feature = "signature")]
+        {
+            let key = ed25519_dalek::VerifyingKey::from_bytes(&read_key_file(public_key_file)?)?;
+            let signature = fs::read_to_string(sidecar_path(bsa_file, "sig"))?;
+            let signature = bsa::decode_hex(signature.trim()).ok_or("Invalid signature")?;
+            let signature = ed25519_dalek::Signature::from_slice(&signature)?;
+            if !manifest.verify_signature(&key, &signature) {
+                return Err("Manifest signature is invalid".into());
+            }
+        }
+        #[cfg(not(feature = "signature"))]
+        {
+            let _ = public_key_file;
+            return Err("Verifying signatures requires the `signature` feature".into());
+        }
+    }
+    let mut bsa = bsa::open(bsa_file)?;
+    let mismatches = manifest.verify(&mut bsa)?;
+    for mismatch in &mismatches {
+        eprintln!("{}", mismatch);
+    }
+    if !mismatches.is_empty() {
+        return Err(format!("{} files do not match the manifest", mismatches.len()).into());
+    }
+    eprintln!("{}: OK", bsa_file.to_string_lossy());
+    Ok(())
+}
+
+/// The result of validating a single BSA file
+#[derive(Debug, Default)]
+struct Validation {
+    diagnostics: Vec<bsa::Diagnostic>,
+    verified: usize,
+    size_mismatches: usize,
+    corrupt: usize,
+    // paths of files that failed verification
+    failures: Vec<(String, bsa::FileVerification)>,
+    // paths of files rejected by the asset validators, with the reason
+    invalid_assets: Vec<(String, String)>,
+    // zero-byte files and folders without files, which some tools choke on
+    empty_files: Vec<String>,
+    empty_folders: Vec<String>,
+}
+
+impl Validation {
+    fn ok(&self) -> bool {
+        self.failures.is_empty() && self.invalid_assets.is_empty()
+    }
+
+    fn warnings(&self) -> Vec<String> {
+        let diagnostics = self.diagnostics.iter().map(ToString::to_string);
+        let empty_files = self
+            .empty_files
+            .iter()
+            .map(|path| format!("Empty file: {}", path));
+        let empty_folders = self
+            .empty_folders
+            .iter()
+            .map(|path| format!("Empty folder: {}", path));
+        diagnostics
+            .chain(empty_files)
+            .chain(empty_folders)
+            .collect()
+    }
+}
+
+fn validate_file(bsa_file: &path::Path, fast: u8, deep_assets: bool) -> Res<Validation> {
+    let mut buf = [0; 16];
+    let mut bsa = bsa::open(bsa_file)?;
+    let mut validation = Validation::default();
+    for folder in bsa.folders() {
+        if folder.is_empty() {
+            let name = folder
+                .name()
+                .map_or_else(|| format!("{:016x}", folder.name_hash()), str::to_string);
+            validation.empty_folders.push(name);
+        }
+        for file in folder.files() {
+            if file.uncompressed_size() == 0 {
+                let path = file
+                    .full_path(&folder)
+                    .map_or_else(|| "?".to_string(), |path| path.to_string());
+                validation.empty_files.push(path);
+            }
+            if fast == 0 {
+                let verification = file.verify(&mut bsa)?;
+                validation.verified += 1;
+                match verification {
+                    bsa::FileVerification::Ok => continue,
+                    bsa::FileVerification::SizeMismatch { .. } => validation.size_mismatches += 1,
+                    _ => validation.corrupt += 1,
+                }
+                let path = file
+                    .full_path(&folder)
+                    .map_or_else(|| "?".to_string(), |path| path.to_string());
+                validation.failures.push((path, verification));
+            } else if fast == 1 {
+                let mut reader = file.read_contents(&mut bsa)?;
+                let _ = io::Read::read(&mut reader, &mut buf)?;
+            }
+        }
+    }
+    if deep_assets {
+        validation.invalid_assets = bsa
+            .validate_assets(&bsa::builtin_validators())?
+            .into_iter()
+            .map(|(path, problem)| (path.to_string(), problem))
+            .collect();
+    }
+    validation.diagnostics = bsa.diagnostics().to_vec();
+    Ok(validation)
+}
+
+fn validate(
+    bsa_files: &[path::PathBuf],
+    fast: u8,
+    deep_assets: bool,
+    quiet: bool,
+    json: bool,
+) -> Res<()> {
+    let mut failed = 0;
+    let mut report = vec![];
+    for bsa_file in bsa_files {
+        let path = bsa_file.to_string_lossy();
+        let result = validate_file(bsa_file, fast, deep_assets);
+        if json {
+            report.push(match &result {
+                Ok(validation) => serde_json::json!({
+                    "path": path,
+                    "ok": validation.ok(),
+                    "warnings": validation.warnings(),
+                    "empty_files": validation.empty_files,
+                    "empty_folders": validation.empty_folders,
+                    "files": {
+                        "verified": validation.verified,
+                        "size_mismatches": validation.size_mismatches,
+                        "corrupt": validation.corrupt,
+                    },
+                    "failures": validation
+                        .failures
+                        .iter()
+                        .map(|(path, verification)| serde_json::json!({
+                            "path": path,
+                            "error": verification.to_string(),
+                        }))
+                        .collect::<Vec<_>>(),
+                    "invalid_assets": validation
+                        .invalid_assets
+                        .iter()
+                        .map(|(path, problem)| serde_json::json!({
+                            "path": path,
+                            "error": problem,
+                        }))
+                        .collect::<Vec<_>>(),
+                }),
+                Err(e) => serde_json::json!({
+                    "path": path,
+                    "ok": false,
+                    "error": error_chain(e.as_ref()).to_string(),
+                }),
+            });
+        }
+        match result {
+            Ok(validation) if validation.ok() => {
+                if !quiet && !json {
+                    eprintln!("{}: OK", path);
+                    for warning in validation.warnings() {
+                        eprintln!("    warning: {}", warning);
+                    }
+                }
+            }
+            Ok(validation) => {
+                failed += 1;
+                if !json {
+                    if !validation.failures.is_empty() {
+                        eprintln!(
+                            "{}: {} of {} files failed verification ({} size mismatches, {} corrupt)",
+                            path,
+                            validation.failures.len(),
+                            validation.verified,
+                            validation.size_mismatches,
+                            validation.corrupt
+                        );
+                    }
+                    if !validation.invalid_assets.is_empty() {
+                        eprintln!(
+                            "{}: {} invalid assets",
+                            path,
+                            validation.invalid_assets.len()
+                        );
+                    }
+                    for (path, verification) in &validation.failures {
+                        eprintln!("    {}: {}", path, verification);
+                    }
+                    for (path, problem) in &validation.invalid_assets {
+                        eprintln!("    {}: {}", path, problem);
+                    }
+                    for warning in validation.warnings() {
+                        eprintln!("    warning: {}", warning);
+                    }
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                if !json {
+                    eprintln!("{}: {}", path, error_chain(e.as_ref()));
+                }
+            }
+        }
+    }
+    if json {
+        let report = serde_json::json!({
+            "archives": report,
+            "failed": failed,
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    if failed > 0 {
+        return Err(format!(
+            "{} of {} archives failed validation",
+            failed,
+            bsa_files.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// Rewrites an archive to fix the fixable lint findings
+fn lint_fix(bsa_file: &path::Path, mut bsa: bsa::Bsa, findings: &[bsa::Finding]) -> Res<()> {
+    let fixed = |rule| {
+        findings
+            .iter()
+            .any(|finding| finding.rule() == rule && finding.is_fixable())
+    };
+    let flags = bsa.archive_flags();
+    let mut builder = bsa::BsaBuilder::for_game(bsa.game());
+    builder
+        .compress(flags.compressed_archive())
+        .zstd(flags.zstd_codec())
+        .embed_file_names(flags.embed_file_names())
+        .keep_original_case(!fixed(bsa::LintRule::NameCase))
+        .alignment(bsa.data_alignment() as u32)
+        .ignore_game_limits(true);
+    let uncompressed: collections::HashSet<_> = findings
+        .iter()
+        .filter(|finding| finding.rule() == bsa::LintRule::CompressedMedia)
+        .filter_map(|finding| finding.path())
+        .collect();
+    let files = named_files_by_offset(&bsa);
+    if files.len() != bsa.entries().count() {
+        return Err("Archives without file and folder names can't be fixed".into());
+    }
+    for (path, file) in files {
+        if uncompressed.contains(path.as_str()) {
+            let contents = file.read_to_vec(&mut bsa)?;
+            builder.add_file_with_compression(&path, contents, false)?;
+        } else {
+            builder.add_raw_file(&path, file.read_raw(&mut bsa)?)?;
+        }
+    }
+    let tmp_output = sidecar_path(bsa_file, "tmp");
+    builder.write(&mut io::BufWriter::new(fs::File::create(&tmp_output)?))?;
+    drop(bsa);
+    fs::rename(&tmp_output, bsa_file)?;
+    Ok(())
+}
+
+fn lint(
+    bsa_files: &[path::PathBuf],
+    allow: &[bsa::LintRule],
+    only: &[bsa::LintRule],
+    fix: bool,
+    json: bool,
+) -> Res<()> {
+    let mut linter = bsa::Linter::new();
+    if !only.is_empty() {
+        linter.only(only);
+    }
+    for &rule in allow {
+        linter.allow(rule);
+    }
+    let mut failed = 0;
+    let mut report = vec![];
+    for bsa_file in bsa_files {
+        let path = bsa_file.to_string_lossy();
+        let result: Res<_> = bsa::OpenOptions::new()
+            .verify_hashes(bsa::HashVerification::None)
+            .open(bsa_file)
+            .map_err(Box::from)
+            .and_then(|bsa| {
+                let findings = linter.check(&bsa);
+                if fix && findings.iter().any(bsa::Finding::is_fixable) {
+                    lint_fix(bsa_file, bsa, &findings)?;
+                    Ok((findings, true))
+                } else {
+                    Ok((findings, false))
+                }
+            });
+        if json {
+            report.push(match &result {
+                Ok((findings, fixed)) => serde_json::json!({
+                    "path": path,
+                    "findings": findings
+                        .iter()
+                        .map(|finding| serde_json::json!({
+                            "rule": finding.rule().to_string(),
+                            "path": finding.path(),
+                            "message": finding.message(),
+                            "fixable": finding.is_fixable(),
+                            "fixed": *fixed && finding.is_fixable(),
+                        }))
+                        .collect::<Vec<_>>(),
+                }),
+                Err(e) => serde_json::json!({
+                    "path": path,
+                    "error": error_chain(e.as_ref()).to_string(),
+                }),
+            });
+        }
+        match result {
+            Ok((findings, fixed)) => {
+                let remaining = findings
+                    .iter()
+                    .filter(|finding| !fixed || !finding.is_fixable())
+                    .count();
+                if remaining > 0 {
+                    failed += 1;
+                }
+                if json {
+                    continue;
+                }
+                if findings.is_empty() {
+                    eprintln!("{}: OK", path);
+                }
+                for finding in &findings {
+                    if fixed && finding.is_fixable() {
+                        eprintln!("{}: fixed: {}", path, finding);
+                    } else {
+                        eprintln!("{}: {}", path, finding);
+                    }
+                }
+                if fixed {
+                    eprintln!(
+                        "{}: fixed {} of {} findings",
+                        path,
+                        findings.len() - remaining,
+                        findings.len()
+                    );
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                if !json {
+                    eprintln!("{}: {}", path, error_chain(e.as_ref()));
+                }
+            }
+        }
+    }
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    }
+    if failed > 0 {
+        return Err(format!(
+            "{} of {} archives have lint findings",
+            failed,
+            bsa_files.len()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+fn probe_file(file: &path::Path) -> Res<String> {
+    let mut magic = [0; 4];
+    let len = io::Read::read(&mut fs::File::open(file)?, &mut magic)?;
+    Ok(match &magic[..len] {
+        b"BSA\0" => {
+            let info = bsa::probe(file)?;
+            let version = match info.version() {
+                bsa::Version::OBLIVION => "v103 (Oblivion)",
+                bsa::Version::SKYRIM => "v104 (Fallout 3, Fallout: New Vegas, Skyrim)",
+                _ => "v105 (Skyrim Special Edition)",
+            };
+            format!(
+                "BSA {}, {} folders, {} files{}",
+                version,
+                info.folder_count(),
+                info.file_count(),
+                if info.archive_flags().compressed_archive() {
+                    ", compressed"
+                } else {
+                    ""
+                }
+            )
+        }
+        b"BTDX" => "BA2 (Fallout 4, Starfield)".to_string(),
+        [0x00, 0x01, 0x00, 0x00] => "BSA (Morrowind)".to_string(),
+        _ => "not an archive".to_string(),
+    })
+}
+
+fn probe(files: &[path::PathBuf]) {
+    for file in files {
+        match probe_file(file) {
+            Ok(description) => println!("{}: {}", file.to_string_lossy(), description),
+            Err(e) => println!("{}: {}", file.to_string_lossy(), error_chain(e.as_ref())),
+        }
+    }
+}
+
+fn flag_names(flags: bsa::ArchiveFlags) -> [(bool, &'static str); 11] {
+    [
+        (flags.include_directory_names(), "include directory names"),
+        (flags.include_file_names(), "include file names"),
+        (flags.compressed_archive(), "compressed"),
+        (flags.retain_directory_names(), "retain directory names"),
+        (flags.retain_file_names(), "retain file names"),
+        (flags.retain_file_name_offsets(), "retain file name offsets"),
+        (flags.xbox360_archive(), "Xbox 360"),
+        (flags.retain_strings(), "retain strings"),
+        (flags.embed_file_names(), "embed file names"),
+        (flags.xmem_codec(), "XMem codec"),
+        (flags.zstd_codec(), "zstd"),
+    ]
+}
+
+fn info(file: &path::Path, compare_flags: bool) -> Res<()> {
+    let bsa = bsa::open(file)?;
+    let flags: Vec<_> = flag_names(bsa.archive_flags())
+        .iter()
+        .filter(|(set, _)| *set)
+        .map(|(_, name)| *name)
+        .collect();
+    let folders = bsa.folders_slice();
+    println!("Version:   {}", u32::from(bsa.version()));
+    println!("Game:      {}", bsa.game());
+    println!("Flags:     {}", flags.join(", "));
+    println!("Folders:   {}", folders.len());
+    println!(
+        "Files:     {}",
+        folders
+            .iter()
+            .map(|folder| folder.files().count())
+            .sum::<usize>()
+    );
+    println!("Alignment: {}", bsa.data_alignment());
+    let guess = bsa.packer_guess();
+    match guess.packer() {
+        Some(packer) => println!("Packer:    probably {}", packer),
+        None => println!("Packer:    unknown"),
+    }
+    for evidence in guess.evidence() {
+        println!("  - {}", evidence);
+    }
+    if compare_flags {
+        println!();
+        println!("{:<26} {:<8} Recommended", "Flag", "Actual");
+        let actual = flag_names(bsa.archive_flags());
+        let recommended = flag_names(bsa::ArchiveFlags::recommended());
+        for ((set, name), (recommended, _)) in actual.iter().zip(&recommended) {
+            let yes_no = |set: bool| if set { "yes" } else { "no" };
+            // these flags don't affect whether the game can load the archive
+            let harmless = [
+                "compressed",
+                "retain directory names",
+                "retain file names",
+                "retain strings",
+                "embed file names",
+            ];
+            let recommended = if harmless.contains(name) {
+                "either"
+            } else {
+                yes_no(*recommended)
+            };
+            println!("{:<26} {:<8} {}", name, yes_no(*set), recommended);
+        }
+        let lints = bsa.lint_flags();
+        if lints.is_empty() {
+            println!("No problems found for {}", bsa.game());
+        }
+        for lint in lints {
+            eprintln!("warning: {}", lint);
+        }
+    }
+    Ok(())
+}
+
+/// Returns the BSA and BA2 archives in the given files and directories in load
+/// order. If a plugins.txt file is given, archives belonging to a plugin (e.g.
+/// 'Mod.bsa' or 'Mod - Textures.bsa' for 'Mod.esp') load in the order of their
+/// plugins, after all other archives.
+fn load_order(inputs: &[path::PathBuf], plugins: Option<&path::Path>) -> Res<Vec<path::PathBuf>> {
+    let mut archives = vec![];
+    for input in inputs {
+        if !input.is_dir() {
+            archives.push(input.clone());
+            continue;
+        }
+        let mut found = vec![];
+        for entry in fs::read_dir(input)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| {
+                extension.eq_ignore_ascii_case("bsa") || extension.eq_ignore_ascii_case("ba2")
+            }) {
+                found.push(path);
+            }
+        }
+        found.sort();
+        archives.extend(found);
+    }
+    let plugins = match plugins {
+        Some(plugins) => fs::read_to_string(plugins)?,
+        None => return Ok(archives),
+    };
+    let lines: Vec<&str> = plugins
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .collect();
+    // newer games list inactive plugins too, and mark active ones with '*'
+    let marked = lines.iter().any(|line| line.starts_with('*'));
+    let plugins: Vec<String> = lines
+        .iter()
+        .filter(|line| !marked || line.starts_with('*'))
+        .map(|line| {
+            let line = line.trim_start_matches('*');
+            let stem = line.rsplit_once('.').map_or(line, |(stem, _)| stem);
+            stem.to_lowercase()
+        })
+        .collect();
+    archives.sort_by_key(|archive| {
+        let stem = archive
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        plugins
+            .iter()
+            .position(|plugin| *plugin == stem || stem.starts_with(&format!("{} - ", plugin)))
+    });
+    Ok(archives)
+}
+
+fn which(path: &str, inputs: &[path::PathBuf], plugins: Option<&path::Path>) -> Res<()> {
+    let mut providers = vec![];
+    for archive in load_order(inputs, plugins)? {
+        let is_ba2 = archive
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("ba2"));
+        let found = if is_ba2 {
+            bsa::Ba2Fs::open(&archive).map(|ba2| bsa::FileSystem::exists(&ba2, path))
+        } else {
+            bsa::probe_path(&archive, path)
+        };
+        match found {
+            Ok(true) => providers.push(archive),
+            Ok(false) => (),
+            Err(e) => eprintln!("warning: {}: {}", archive.display(), error_chain(&e)),
+        }
+    }
+    let winner = match providers.pop() {
+        Some(winner) => winner,
+        None => return Err(format!("{} is not in any archive", path).into()),
+    };
+    for archive in providers {
+        println!("{}", archive.display());
+    }
+    println!("{} (wins)", winner.display());
+    Ok(())
+}
+
+fn conflicts(inputs: &[path::PathBuf], order: Option<&path::Path>, json: bool) -> Res<()> {
+    let archives = load_order(inputs, order)?;
+    let mut vfs = bsa::Vfs::new();
+    for archive in &archives {
+        vfs.push_archive(archive)
+            .map_err(|e| format!("{}: {}", archive.display(), e))?;
+    }
+    let conflicts = vfs.conflicts();
+    if json {
+        let report = serde_json::json!({
+            "archives": archives,
+            "conflicts": conflicts
+                .iter()
+                .map(|conflict| serde_json::json!({
+                    "path": conflict.path(),
+                    "archives": conflict
+                        .layers()
+                        .iter()
+                        .map(|&layer| &archives[layer])
+                        .collect::<Vec<_>>(),
+                    "winner": archives[conflict.winner()],
+                }))
+                .collect::<Vec<_>>(),
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    for conflict in &conflicts {
+        println!("{}", conflict.path());
+        for &layer in conflict.layers() {
+            if layer == conflict.winner() {
+                println!("    {} (wins)", archives[layer].display());
+            } else {
+                println!("    {}", archives[layer].display());
+            }
+        }
+    }
+    eprintln!(
+        "{} files are provided by more than one of {} archives",
+        conflicts.len(),
+        archives.len()
+    );
+    Ok(())
+}
+
+fn scripts(inputs: &[path::PathBuf], order: Option<&path::Path>, json: bool) -> Res<()> {
+    let archives = load_order(inputs, order)?;
+    let mut vfs = bsa::Vfs::new();
+    for archive in &archives {
+        vfs.push_archive(archive)
+            .map_err(|e| format!("{}: {}", archive.display(), e))?;
+    }
+    let unpaired = vfs.unpaired_scripts();
+    let (compiled, sources): (Vec<_>, Vec<_>) =
+        unpaired.iter().partition(|script| script.is_compiled());
+    if json {
+        let report = |scripts: &[&bsa::UnpairedScript]| {
+            scripts
+                .iter()
+                .map(|script| {
+                    serde_json::json!({
+                        "path": script.path(),
+                        "archive": archives[script.layer()],
+                    })
+                })
+                .collect::<Vec<_>>()
+        };
+        let report = serde_json::json!({
+            "archives": archives,
+            "missing_source": report(&compiled),
+            "missing_compiled": report(&sources),
+        });
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+    for (scripts, problem) in [(&compiled, "no source"), (&sources, "not compiled")] {
+        for script in scripts {
+            println!(
+                "{} ({})\n    {}",
+                script.path(),
+                problem,
+                archives[script.layer()].display()
+            );
+        }
+    }
+    eprintln!(
+        "{} compiled scripts without a source, {} sources without a compiled script",
+        compiled.len(),
+        sources.len()
+    );
+    Ok(())
+}
+
+/// Reads a list of form IDs, one hexadecimal ID per line, ignoring empty
+/// lines and comments starting with '#'
+fn read_form_ids(path: &path::Path) -> Res<Vec<u32>> {
+    let mut res = vec![];
+    for (i, line) in fs::read_to_string(path)?.lines().enumerate() {
+        let line = line.split('#').next().unwrap_or("").trim();
+        if line.is_empty() {
+            continue;
+        }
+        let hex = line
+            .strip_prefix("0x")
+            .or_else(|| line.strip_prefix("0X"))
+            .unwrap_or(line);
+        let form_id = u32::from_str_radix(hex, 16)
+            .map_err(|_| format!("{}:{}: invalid form ID {:?}", path.display(), i + 1, line))?;
+        res.push(form_id);
+    }
+    Ok(res)
+}
+
+fn facegen_check(
+    archives: &[path::PathBuf],
+    plugin: &path::Path,
+    form_ids: Option<&path::Path>,
+) -> Res<()> {
+    let plugin_name = plugin
+        .file_name()
+        .ok_or_else(|| format!("{} is not a plugin file", plugin.display()))?
+        .to_string_lossy();
+    let form_ids = match form_ids {
+        Some(form_ids) => read_form_ids(form_ids)?,
+        None => bsa::PluginNpcs::parse(&fs::read(plugin)?)
+            .map_err(|e| format!("{}: {}", plugin.display(), error_chain(&e)))?
+            .defined_npcs(),
+    };
+    let mut vfs = bsa::Vfs::new();
+    for archive in archives {
+        vfs.push_archive(archive)
+            .map_err(|e| format!("{}: {}", archive.display(), e))?;
+    }
+    let missing = bsa::missing_facegen(&vfs, &plugin_name, &form_ids);
+    for npc in &missing {
+        println!("{:08X}", npc.form_id());
+        for path in npc.missing() {
+            println!("    missing {}", path);
+        }
+    }
+    if !missing.is_empty() {
+        return Err(format!(
+            "{} of {} NPCs are missing facegen files",
+            missing.len(),
+            form_ids.len()
+        )
+        .into());
+    }
+    eprintln!("{}: {} NPCs OK", plugin_name, form_ids.len());
+    Ok(())
+}
+
+fn map(file: &path::Path) -> Res<()> {
+    let bsa = bsa::open(file)?;
+    let len = fs::metadata(file)?.len();
+    let print = |start: u64, end: u64, description: &str| {
+        println!(
+            "{:>10} {:>10} {:>10}  {}",
+            start,
+            end,
+            end.saturating_sub(start),
+            description
+        )
+    };
+    println!("{:>10} {:>10} {:>10}  Contents", "Start", "End", "Size");
+    let mut pos = 0;
+    for region in bsa.layout() {
+        if region.start() > pos {
+            print(pos, region.start(), "(unused)");
+        } else if region.start() < pos {
+            eprintln!(
+                "warning: region at {} overlaps the previous region",
+                region.start()
+            );
+        }
+        let owner = match region.kind() {
+            bsa::RegionKind::FileRecordBlock(folder_index) => {
+                let folder = &bsa.folders_slice()[folder_index];
+                Some(match folder.name() {
+                    Some(name) => name.to_string(),
+                    None => format!("{:016x}", folder.name_hash()),
+                })
+            }
+            bsa::RegionKind::FileData(folder_index, file_index) => {
+                let entry = bsa.entry(folder_index, file_index).unwrap();
+                Some(match entry.path() {
+                    Some(path) => path.to_string(),
+                    None => format!("{:016x}", entry.file().name_hash()),
+                })
+            }
+            _ => None,
+        };
+        match owner {
+            Some(owner) => print(
+                region.start(),
+                region.end(),
+                &format!("{} ({})", region.kind(), owner),
+            ),
+            None => print(region.start(), region.end(), &region.kind().to_string()),
+        }
+        pos = pos.max(region.end());
+    }
+    if len > pos {
+        print(pos, len, "(unused)");
+    }
+    for diagnostic in bsa.diagnostics() {
+        eprintln!("warning: {}", diagnostic);
+    }
+    Ok(())
+}
+
+/// A codec and compression level to estimate with `analyze --what-if`
+#[derive(Debug, Clone)]
+struct WhatIf {
+    name: String,
+    codec: bsa::Codec,
+    level: Option<u32>,
+}
+
+fn parse_what_if(s: &str) -> Result<WhatIf, String> {
+    let (codec, level) = match s.split_once(':') {
+        Some((codec, level)) => {
+            let level = level
+                .parse()
+                .map_err(|_| format!("invalid compression level: {}", level))?;
+            (codec, Some(level))
+        }
+        None => (s, None),
+    };
+    let codec = match codec {
+        "none" => bsa::Codec::None,
+        "zlib" => bsa::Codec::Zlib,
+        "lz4" => bsa::Codec::Lz4,
+        "lz4-block" => bsa::Codec::Lz4Block,
+        "zstd" => bsa::Codec::Zstd,
+        _ => return Err(format!("unknown codec: {}", codec)),
+    };
+    Ok(WhatIf {
+        name: s.to_string(),
+        codec,
+        level,
+    })
+}
+
+fn analyze(file: &path::Path, what_ifs: &[WhatIf], sample: Option<usize>) -> Res<()> {
+    let mut bsa = bsa::open(file)?;
+    let files: Vec<bsa::File> = bsa
+        .files_by_offset()
+        .into_iter()
+        .map(|(_, file)| file.clone())
+        .collect();
+    let uncompressed: u64 = files.iter().map(bsa::File::uncompressed_size).sum();
+    let stored: u64 = files.iter().map(bsa::File::stored_size).sum();
+    let percent = |size: u64| {
+        if uncompressed == 0 {
+            100.0
+        } else {
+            size as f64 * 100.0 / uncompressed as f64
+        }
+    };
+    println!(
+        "{} files, {} bytes uncompressed, {} bytes stored ({:.1}%)",
+        files.len(),
+        uncompressed,
+        stored,
+        percent(stored)
+    );
+    // spread the sample evenly over the archive
+    let sample = sample.unwrap_or(files.len()).min(files.len());
+    let sampled: Vec<&bsa::File> = (0..sample)
+        .map(|i| &files[i * files.len() / sample])
+        .collect();
+    let sampled_size: u64 = sampled.iter().map(|file| file.uncompressed_size()).sum();
+    if sample < files.len() {
+        println!(
+            "Estimating from {} files ({} bytes uncompressed)",
+            sample, sampled_size
+        );
+    }
+    let mut totals = vec![0; what_ifs.len()];
+    let mut elapsed = vec![time::Duration::default(); what_ifs.len()];
+    let mut contents = vec![];
+    for file in sampled {
+        bsa.read_into(file, &mut contents)?;
+        for (i, what_if) in what_ifs.iter().enumerate() {
+            let start = time::Instant::now();
+            totals[i] += what_if.codec.compress(&contents, what_if.level)?.len() as u64;
+            elapsed[i] += start.elapsed();
+        }
+    }
+    println!(
+        "{:<12} {:>14} {:>8} {:>10}",
+        "What if", "Stored bytes", "Ratio", "Time"
+    );
+    for (i, what_if) in what_ifs.iter().enumerate() {
+        let estimate = if sampled_size == 0 {
+            0
+        } else {
+            (totals[i] as f64 * uncompressed as f64 / sampled_size as f64) as u64
+        };
+        println!(
+            "{:<12} {:>14} {:>7.1}% {:>9.2}s",
+            what_if.name,
+            estimate,
+            percent(estimate),
+            elapsed[i].as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+fn run() -> Res<()> {
+    let args = <Cli as clap::Parser>::parse();
+    match args {
+        Cli::Ls {
+            file,
+            by_offset,
+            sorted,
+            relative,
+            long,
+            filter,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            let options = ListOptions {
+                by_offset,
+                sorted,
+                relative,
+                long,
+            };
+            ls(&file, options, &filter)?
+        }
+        Cli::Cat {
+            file,
+            path,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            cat(&file, &path)?
+        }
+        Cli::Strings {
+            file,
+            path,
+            encoding,
+            json,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            strings(&file, path.as_deref(), encoding, json)?
+        }
+        Cli::Hexdump {
+            file,
+            path,
+            length,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            hexdump(&file, &path, length)?
+        }
+        Cli::Extract {
+            files,
+            into,
+            options,
+            filter,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            extract(&files, into.as_deref(), &options, &filter)?;
+        }
+        Cli::Validate {
+            files,
+            verbose,
+            fast,
+            deep_assets,
+            quiet,
+            json,
+        } => {
+            if fast > 2 {
+                let mut command = <Cli as clap::CommandFactory>::command();
+                command.build();
+                command
+                    .find_subcommand_mut("validate")
+                    .unwrap()
+                    .error(
+                        clap::error::ErrorKind::TooManyValues,
+                        "--fast can be specified at most twice",
+                    )
+                    .exit();
+            }
+            setup_logger(verbose);
+            validate(&files, fast, deep_assets, quiet, json)?;
+        }
+        Cli::Lint {
+            files,
+            allow,
+            only,
+            fix,
+            json,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            lint(&files, &allow, &only, fix, json)?;
+        }
+        Cli::Probe { files, verbose } => {
+            setup_logger(verbose);
+            probe(&files);
+        }
+        Cli::Info {
+            file,
+            compare_flags,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            info(&file, compare_flags)?;
+        }
+        Cli::Which {
+            path,
+            files,
+            plugins,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            which(&path, &files, plugins.as_deref())?;
+        }
+        Cli::Conflicts {
+            files,
+            order,
+            json,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            conflicts(&files, order.as_deref(), json)?;
+        }
+        Cli::Scripts {
+            files,
+            order,
+            json,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            scripts(&files, order.as_deref(), json)?;
+        }
+        Cli::FacegenCheck {
+            files,
+            plugin,
+            form_ids,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            facegen_check(&files, &plugin, form_ids.as_deref())?;
+        }
+        Cli::Map { file, verbose } => {
+            setup_logger(verbose);
+            map(&file)?;
+        }
+        Cli::Analyze {
+            file,
+            what_if,
+            sample,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            analyze(&file, &what_if, sample)?;
+        }
+        Cli::Exec { script, verbose } => {
+            setup_logger(verbose);
+            exec(&script)?;
+        }
+        Cli::Merge {
+            files,
+            output,
+            conflict,
+            align,
+            ignore_game_limits,
+            dry_run,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            merge(
+                &files,
+                &output,
+                conflict,
+                align,
+                ignore_game_limits,
+                dry_run,
+            )?;
+        }
+        Cli::Recompress {
+            file,
+            output,
+            only,
+            codec,
+            align,
+            ignore_game_limits,
+            dry_run,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            let count = recompress(
+                &file,
+                output.as_deref(),
+                &only,
+                &codec,
+                align,
+                ignore_game_limits,
+                dry_run,
+            )?;
+            if dry_run {
+                eprintln!("Would recompress {} files", count);
+            } else {
+                eprintln!("Recompressed {} files", count);
+            }
+        }
+        Cli::RenamePrefix {
+            file,
+            from,
+            to,
+            output,
+            dry_run,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            let mut rewrite = rewrite::Rewrite::default();
+            rewrite.prefix(&from, &to);
+            let count = rewrite_archive(&file, &rewrite, output.as_deref(), dry_run)?;
+            if count == 0 {
+                return Err(format!("No files are in {}", from).into());
+            }
+            if dry_run {
+                eprintln!("Would rename {} files", count);
+            } else {
+                eprintln!("Renamed {} files", count);
+            }
+        }
+        Cli::Rewrite {
+            file,
+            map,
+            regex,
+            replace,
+            output,
+            dry_run,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            let mut rewrite = rewrite::Rewrite::default();
+            if let Some(map) = map {
+                rewrite.read_map(&map)?;
+            }
+            if let (Some(regex), Some(replace)) = (regex, replace) {
+                rewrite.regex(&regex, &replace)?;
+            }
+            let count = rewrite_archive(&file, &rewrite, output.as_deref(), dry_run)?;
+            if count == 0 {
+                return Err("No files match the renaming rules".into());
+            }
+            if dry_run {
+                eprintln!("Would rename {} files", count);
+            } else {
+                eprintln!("Renamed {} files", count);
+            }
+        }
+        Cli::Repair {
+            file,
+            output,
+            dry_run,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            repair(&file, output.as_deref(), dry_run)?;
+        }
+        Cli::DedupExport {
+            files,
+            store,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            dedup_export(&files, &store)?;
+        }
+        Cli::Index {
+            files,
+            db,
+            force,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            #[cfg(feature = "sqlite")]
+            index::index(&files, &db, force)?;
+            #[cfg(not(feature = "sqlite"))]
+            {
+                let _ = (files, db, force);
+                return Err("Indexing requires the `sqlite` feature".into());
+            }
+        }
+        Cli::Query { db, query } => {
+            #[cfg(feature = "sqlite")]
+            self::query(&db, &query)?;
+            #[cfg(not(feature = "sqlite"))]
+            {
+                let _ = (db, query);
+                return Err("Querying an index requires the `sqlite` feature".into());
+            }
+        }
+        Cli::Search { query, db, limit } => {
+            #[cfg(feature = "sqlite")]
+            index::search(&db, &query, limit)?;
+            #[cfg(not(feature = "sqlite"))]
+            {
+                let _ = (query, db, limit);
+                return Err("Searching an index requires the `sqlite` feature".into());
+            }
+        }
+        Cli::Thumbs {
+            files,
+            out,
+            size,
+            filter,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            #[cfg(feature = "thumbnail")]
+            thumbs(&files, &out, size, &filter)?;
+            #[cfg(not(feature = "thumbnail"))]
+            {
+                let _ = (files, out, size, filter);
+                return Err("Creating thumbnails requires the `thumbnail` feature".into());
+            }
+        }
+        Cli::Sign { file, key, verbose } => {
+            setup_logger(verbose);
+            sign(&file, key.as_deref())?;
+        }
+        Cli::Verify {
+            file,
+            public_key,
+            verbose,
+        } => {
+            setup_logger(verbose);
+            verify(&file, public_key.as_deref())?;
+        }
+    }
+    Ok(())
+}
+
+fn styles() -> clap::builder::Styles {
+    use clap::builder::styling::AnsiColor;
+    clap::builder::Styles::styled()
+        .header(AnsiColor::Yellow.on_default().bold())
+        .usage(AnsiColor::Yellow.on_default().bold())
+        .literal(AnsiColor::Green.on_default())
+        .placeholder(AnsiColor::Cyan.on_default())
+        .error(AnsiColor::Red.on_default().bold())
+}
+
+#[derive(clap::Parser, Debug)]
+#[command(name = "bsa", version, about, styles = styles())]
+enum Cli {
+    /// List files in a BSA
+    #[command(visible_alias = "l")]
+    Ls {
+        /// Input file
+        file: path::PathBuf,
+        /// List files in the order their contents are stored in the BSA
+        #[arg(long, conflicts_with = "sorted")]
+        by_offset: bool,
+        /// Print lowercase paths in alphabetical order, without duplicates, e.g. to commit the
+        /// listing to version control
+        #[arg(long)]
+        sorted: bool,
+        /// Separate paths with forward slashes, like paths relative to the Data folder
+        #[arg(long)]
+        relative: bool,
+        /// Show the size and type of each file
+        #[arg(short, long)]
+        long: bool,
+        #[command(flatten)]
+        filter: Filter,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Output a file from a BSA
+    Cat {
+        /// Input file
+        file: path::PathBuf,
+        /// Path to file in the BSA
+        path: String,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// List the localized string tables in a BSA, or print the strings in one of them
+    Strings {
+        /// Input file
+        file: path::PathBuf,
+        /// Path to a .strings, .dlstrings or .ilstrings file in the BSA
+        path: Option<String>,
+        /// Encoding of the strings (detect uses UTF-8 if every string is valid UTF-8)
+        #[arg(long, value_enum, default_value_t = StringsCharset::Detect)]
+        encoding: StringsCharset,
+        /// Print the strings as JSON
+        #[arg(long, requires = "path")]
+        json: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Print a hexdump of the start of a file in a BSA
+    Hexdump {
+        /// Input file
+        file: path::PathBuf,
+        /// Path to file in the BSA
+        path: String,
+        /// Number of (decompressed) bytes to print
+        #[arg(short = 'n', long, default_value_t = 256)]
+        length: u64,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Extract all files from a BSA
+    #[command(visible_alias = "x")]
+    Extract {
+        /// Input file(s) to extract
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// Directory to extract into (specify '-' to name the directory based on the BSA file name)
+        #[arg(long)]
+        into: Option<path::PathBuf>,
+        #[command(flatten)]
+        options: ExtractOptions,
+        #[command(flatten)]
+        filter: Filter,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Validate BSA files
+    Validate {
+        /// Input file(s) to validate
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+        /// Skip decompressing every file (specify this option twice to only check the headers)
+        #[arg(long, action = clap::ArgAction::Count)]
+        fast: u8,
+        /// Also check the headers of DDS textures and NIF models
+        #[arg(long)]
+        deep_assets: bool,
+        /// Only print archives that fail validation
+        #[arg(short, long)]
+        quiet: bool,
+        /// Print a JSON report of the results to stdout
+        #[arg(long)]
+        json: bool,
+    },
+    /// Check BSA files for problems with the game or other tools
+    Lint {
+        /// Input file(s) to check
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// Disable a rule: 'flags', 'sorted-records', 'duplicate-hashes', 'hashes', 'name-length',
+        /// 'name-case' or 'compressed-media'
+        #[arg(short = 'A', long)]
+        allow: Vec<bsa::LintRule>,
+        /// Only run the given rules
+        #[arg(long)]
+        only: Vec<bsa::LintRule>,
+        /// Rewrite archives to fix the findings that can be fixed safely (sorting records,
+        /// recomputing hashes, lowercasing names, resetting unused flags and storing media
+        /// uncompressed)
+        #[arg(long)]
+        fix: bool,
+        /// Print a JSON report of the findings to stdout
+        #[arg(long)]
+        json: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Identify the type of archive files
+    Probe {
+        /// Input file(s) to identify
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Print the header of a BSA and guess which tool created it
+    Info {
+        /// Input file
+        file: path::PathBuf,
+        /// Compare the archive flags with the recommended flags, and warn about flags that cause
+        /// problems with the game
+        #[arg(long)]
+        compare_flags: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// List the archives that contain a file, in load order
+    Which {
+        /// Path to the file in the archives (e.g. 'textures\foo\bar.dds')
+        path: String,
+        /// Archives and directories to search (directories are searched for .bsa and .ba2 files,
+        /// sorted by name)
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// A plugins.txt file: archives named after a plugin load in the order of their plugins,
+        /// after all other archives (archives are otherwise loaded in the order given)
+        #[arg(long, alias = "order")]
+        plugins: Option<path::PathBuf>,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// List the files that are provided by more than one archive, and which archive wins
+    Conflicts {
+        /// Archives and directories to check, in load order (directories are searched for .bsa
+        /// and .ba2 files, sorted by name)
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// A plugins.txt file: archives named after a plugin load in the order of their plugins,
+        /// after all other archives
+        #[arg(long, alias = "plugins")]
+        order: Option<path::PathBuf>,
+        /// Print a JSON report to stdout
+        #[arg(long)]
+        json: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// List compiled scripts without a source and sources without a compiled script
+    Scripts {
+        /// Archives and directories to check, in load order (directories are searched for .bsa
+        /// and .ba2 files, sorted by name)
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// A plugins.txt file: archives named after a plugin load in the order of their plugins,
+        /// after all other archives
+        #[arg(long, alias = "plugins")]
+        order: Option<path::PathBuf>,
+        /// Print a JSON report to stdout
+        #[arg(long)]
+        json: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Check that the facegen geometry and tint of each NPC defined by a Skyrim plugin are in
+    /// its archives
+    FacegenCheck {
+        /// The archives of the plugin
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// The plugin (.esm, .esp or .esl) that defines the NPCs
+        plugin: path::PathBuf,
+        /// A file with the form IDs of the NPCs to check, one hexadecimal ID per line, instead
+        /// of reading them from the plugin (which then doesn't need to exist)
+        #[arg(long)]
+        form_ids: Option<path::PathBuf>,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Print the byte layout of a BSA
+    Map {
+        /// Input file
+        file: path::PathBuf,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Estimate the size of a BSA when compressed with other codecs or levels
+    Analyze {
+        /// Input file
+        file: path::PathBuf,
+        /// Codec to estimate the size with: 'none', 'zlib', 'lz4', 'lz4-block' or 'zstd',
+        /// optionally followed by a compression level (e.g. 'zlib:9')
+        #[arg(long, required = true, value_delimiter = ',', value_parser = parse_what_if)]
+        what_if: Vec<WhatIf>,
+        /// Only compress this many files, spread evenly over the archive, and extrapolate
+        #[arg(long)]
+        sample: Option<usize>,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Run a script of BSA operations
+    ///
+    /// Each line of the script contains one of the following commands (archives are only
+    /// opened once, and new archives are written after the script has finished):
+    ///
+    ///     extract ARCHIVE [DIR]           extract all files from ARCHIVE
+    ///     extract ARCHIVE PATH DEST       extract a single file from ARCHIVE to DEST
+    ///     cat ARCHIVE PATH                output a file from ARCHIVE
+    ///     create ARCHIVE VERSION [compress] [embed-names] [keep-case]
+    ///                                     create a new archive with the given version (103-105)
+    ///     add ARCHIVE SOURCE PATH         add the file SOURCE as PATH to an archive created earlier
+    #[command(verbatim_doc_comment)]
+    Exec {
+        /// Script file
+        script: path::PathBuf,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Merge multiple BSA files into one
+    Merge {
+        /// Input files to merge
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// Output file (uses the version and compression settings of the first input file)
+        #[arg(short, long)]
+        output: path::PathBuf,
+        /// What to do when a file exists in multiple archives: keep the 'first' or 'last' copy,
+        /// or report an 'error'
+        #[arg(long, value_enum, default_value_t = ConflictPolicy::Last)]
+        conflict: ConflictPolicy,
+        /// Align the data of each file to a multiple of this many bytes (defaults to the
+        /// alignment of the first input file)
+        #[arg(long)]
+        align: Option<u32>,
+        /// Write the archive even if it would break the game (e.g. because it is too large)
+        #[arg(long)]
+        ignore_game_limits: bool,
+        /// Print the files that would be written, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Recompress selected files in a BSA, copying all other files unchanged
+    Recompress {
+        /// Input file
+        file: path::PathBuf,
+        /// Output file (defaults to overwriting the input file)
+        #[arg(short, long)]
+        output: Option<path::PathBuf>,
+        /// Only recompress files matching this pattern (e.g. 'textures/**')
+        #[arg(long, default_value = "**")]
+        only: String,
+        /// Compression to use: 'lz4' (Skyrim Special Edition), 'zlib' (Oblivion and Skyrim)
+        /// or 'none'. 'lz4-block' writes LZ4 blocks without frame headers, which some
+        /// third-party tools expect but the game can't read. 'zstd' writes an extended
+        /// archive that only this tool can read (requires the zstd feature).
+        #[arg(long, value_parser = ["lz4", "lz4-block", "zlib", "zstd", "none"])]
+        codec: String,
+        /// Align the data of each file to a multiple of this many bytes (defaults to the
+        /// alignment of the input file)
+        #[arg(long)]
+        align: Option<u32>,
+        /// Write the archive even if it would break the game (e.g. because it is too large)
+        #[arg(long)]
+        ignore_game_limits: bool,
+        /// Print the files that would be written, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Move the files in a folder of a BSA to another folder, e.g. to rename a mod
+    ///
+    /// The contents of the files are copied without recompressing them.
+    RenamePrefix {
+        /// Input file
+        file: path::PathBuf,
+        /// Folder to rename (e.g. 'textures\oldmod'), including its subfolders
+        from: String,
+        /// New name of the folder (e.g. 'textures\newmod')
+        to: String,
+        /// Output file (defaults to overwriting the input file)
+        #[arg(short, long)]
+        output: Option<path::PathBuf>,
+        /// Print the files that would be written, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Rename files in a BSA using a mapping file or a regular expression
+    ///
+    /// The contents of the files are copied without recompressing them. Renaming fails if
+    /// more than one file would end up at the same path.
+    #[command(group(clap::ArgGroup::new("rules").required(true).multiple(true)))]
+    Rewrite {
+        /// Input file
+        file: path::PathBuf,
+        /// A file with one 'old,new' pair of paths per line. Paths ending in a slash are
+        /// folders, which move every file inside them. Lines starting with '#' are ignored.
+        #[arg(long, group = "rules")]
+        map: Option<path::PathBuf>,
+        /// Rename paths matching this regular expression (matched case-insensitively against
+        /// paths like 'textures\\foo\\bar.dds'), for files not renamed by '--map'
+        #[arg(long, group = "rules", requires = "replace")]
+        regex: Option<String>,
+        /// Replacement for '--regex', which can refer to groups as '$1' or '${name}'
+        #[arg(long, requires = "regex")]
+        replace: Option<String>,
+        /// Output file (defaults to overwriting the input file)
+        #[arg(short, long)]
+        output: Option<path::PathBuf>,
+        /// Print the files that would be written, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Rewrite a BSA, fixing problems detected while reading it
+    Repair {
+        /// Input file
+        file: path::PathBuf,
+        /// Output file (defaults to overwriting the input file)
+        #[arg(short, long)]
+        output: Option<path::PathBuf>,
+        /// Print the files that would be written, without writing anything
+        #[arg(long)]
+        dry_run: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Extract the unique contents of BSA files into a content-addressed store
+    DedupExport {
+        /// Input file(s) to export
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// Directory to store file contents in, named by their SHA-256 checksums
+        /// (a `mapping.tsv` file listing the checksum of each archive path is also created)
+        #[arg(long)]
+        store: path::PathBuf,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Store the paths, hashes, sizes and checksums of the files in BSA files in a SQLite database
+    Index {
+        /// Input file(s) to index
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// Database file (created if it doesn't exist)
+        #[arg(long)]
+        db: path::PathBuf,
+        /// Index archives again even if they haven't changed since they were last indexed
+        #[arg(long)]
+        force: bool,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Query a database created with the index command
+    Query {
+        /// Database file
+        #[arg(long)]
+        db: path::PathBuf,
+        #[command(subcommand)]
+        query: Query,
+    },
+    /// Search the paths in a database created with the index command, allowing typos
+    Search {
+        /// Words to search for (e.g. 'steel brestplate')
+        query: String,
+        /// Database file
+        #[arg(long)]
+        db: path::PathBuf,
+        /// Maximum number of results
+        #[arg(short = 'n', long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Write PNG thumbnails of the DDS textures in BSA files, e.g. for asset browsers
+    Thumbs {
+        /// Input file(s)
+        #[arg(required = true)]
+        files: Vec<path::PathBuf>,
+        /// Directory to write thumbnails into, mirroring the folders in the archives
+        #[arg(short, long)]
+        out: path::PathBuf,
+        /// Maximum width and height of the thumbnails, in pixels
+        #[arg(long, default_value_t = 256)]
+        size: u32,
+        #[command(flatten)]
+        filter: Filter,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Write a checksum manifest (and optionally a signature) next to a BSA
+    Sign {
+        /// Input file
+        file: path::PathBuf,
+        /// File containing a hex-encoded ed25519 secret key to sign the manifest with
+        #[arg(long)]
+        key: Option<path::PathBuf>,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+    /// Verify a BSA against its checksum manifest (and optionally its signature)
+    Verify {
+        /// Input file
+        file: path::PathBuf,
+        /// File containing the hex-encoded ed25519 public key to check the signature with
+        #[arg(long)]
+        public_key: Option<path::PathBuf>,
+        /// Enable verbose output
+        #[arg(short, long)]
+        verbose: bool,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Query {
+    /// List the archives that contain files matching a glob pattern
+    Find {
+        /// Path or glob pattern (e.g. 'textures/**/*.dds')
+        pattern: String,
+    },
+    /// List the files that are in more than one archive, and whether their contents differ
+    Conflicts,
+    /// List file contents that are stored more than once
+    Duplicates,
+}
+
+#[cfg(feature = "sqlite")]
+fn query(db: &path::Path, query: &Query) -> Res<()> {
+    match query {
+        Query::Find { pattern } => index::find(db, pattern),
+        Query::Conflicts => index::conflicts(db),
+        Query::Duplicates => index::duplicates(db),
+    }
+}
+
+fn error_chain(mut err: &dyn error::Error) -> impl fmt::Display {
+    let mut s = err.to_string();
+    while let Some(inner) = err.source() {
+        s.push_str(": ");
+        s.push_str(&inner.to_string());
+        err = inner;
+    }
+    s
+}
+
+fn main() {
+    match run() {
+        Ok(()) => (),
+        Err(err) => {
+            eprintln!("{}", error_chain(err.as_ref()));
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Creates an empty temporary folder for a test
+    pub(crate) fn temp_dir(name: &str) -> path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("bsa-cli-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a Skyrim SE archive with the given files to `path`
+    pub(crate) fn write_archive(path: &path::Path, files: &[(&str, &[u8])]) {
+        let mut builder = bsa::BsaBuilder::new(bsa::Version::SKYRIM_SPECIAL_EDITION);
+        for (name, data) in files {
+            builder.add_file(name, data.to_vec()).unwrap();
+        }
+        builder.write(&mut fs::File::create(path).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_dedup_export() {
+        let dir = temp_dir("dedup");
+        let archives = [dir.join("a.bsa")];
+        write_archive(
+            &archives[0],
+            &[
+                (r"meshes\a.nif", b"same"),
+                (r"meshes\b.nif", b"same"),
+                (r"meshes\c.nif", b"other"),
+            ],
+        );
+        let store = dir.join("store");
+        dedup_export(&archives, &store).unwrap();
+
+        let mapping = fs::read_to_string(store.join("mapping.tsv")).unwrap();
+        let keys: Vec<&str> = mapping
+            .lines()
+            .map(|line| line.rsplit('\t').next().unwrap())
+            .collect();
+        assert_eq!(keys.len(), 3);
+        assert_eq!(keys[0], keys[1]);
+        assert_ne!(keys[0], keys[2]);
+        let mut objects = 0;
+        for entry in fs::read_dir(&store).unwrap() {
+            let entry = entry.unwrap();
+            if entry.file_type().unwrap().is_dir() {
+                for object in fs::read_dir(entry.path()).unwrap() {
+                    let object = object.unwrap();
+                    // no temporary files are left behind
+                    let name = object.file_name().into_string().unwrap();
+                    assert!(keys.contains(&name.as_str()), "{}", name);
+                    objects += 1;
+                }
+            }
+        }
+        assert_eq!(objects, 2);
+        let same = store.join(&keys[0][..2]).join(keys[0]);
+        assert_eq!(fs::read(same).unwrap(), b"same");
+
+        // exporting again reuses the existing objects
+        dedup_export(&archives, &store).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(feature = "signature"))]
+    fn test_sign_without_feature() {
+        let dir = temp_dir("sign");
+        let archive = dir.join("a.bsa");
+        write_archive(&archive, &[(r"meshes\a.nif", b"a")]);
+        assert!(sign(&archive, Some(&dir.join("key.txt"))).is_err());
+        // nothing is written if signing isn't possible
+        assert!(!sidecar_path(&archive, "manifest").exists());
+        sign(&archive, None).unwrap();
+        assert!(sidecar_path(&archive, "manifest").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_resume() {
+        let dir = temp_dir("resume");
+        let archive = dir.join("a.bsa");
+        write_archive(
+            &archive,
+            &[(r"a\x.txt", b"x"), (r"a\y.txt", b"y"), (r"a\z.txt", b"z")],
+        );
+        let out = dir.join("out");
+        let mut bsa = bsa::open(&archive).unwrap();
+        extract_bsa(
+            &mut bsa,
+            &out,
+            &ExtractOptions::default(),
+            &Filter::default(),
+        )
+        .unwrap();
+
+        // an interrupted extraction left one file unchanged, one changed and
+        // one missing
+        let unchanged = out.join("a").join("x.txt");
+        let old = time::SystemTime::UNIX_EPOCH + time::Duration::from_secs(1_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&unchanged)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        fs::write(out.join("a").join("y.txt"), b"partial").unwrap();
+        fs::remove_file(out.join("a").join("z.txt")).unwrap();
+
+        let options = ExtractOptions {
+            resume: true,
+            ..ExtractOptions::default()
+        };
+        extract_bsa(&mut bsa, &out, &options, &Filter::default()).unwrap();
+        assert_eq!(fs::metadata(&unchanged).unwrap().modified().unwrap(), old);
+        assert_eq!(fs::read(out.join("a").join("y.txt")).unwrap(), b"y");
+        assert_eq!(fs::read(out.join("a").join("z.txt")).unwrap(), b"z");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_probe_file() {
+        let dir = temp_dir("probe");
+        let archive = dir.join("a.bsa");
+        write_archive(&archive, &[(r"a\x.txt", b"x"), (r"b\y.txt", b"y")]);
+        assert_eq!(
+            probe_file(&archive).unwrap(),
+            "BSA v105 (Skyrim Special Edition), 2 folders, 2 files"
+        );
+        let ba2 = dir.join("a.ba2");
+        fs::write(&ba2, b"BTDX\x01\0\0\0GNRL").unwrap();
+        assert_eq!(probe_file(&ba2).unwrap(), "BA2 (Fallout 4, Starfield)");
+        let morrowind = dir.join("morrowind.bsa");
+        fs::write(&morrowind, [0x00, 0x01, 0x00, 0x00, 0x00]).unwrap();
+        assert_eq!(probe_file(&morrowind).unwrap(), "BSA (Morrowind)");
+        let empty = dir.join("empty.bsa");
+        fs::write(&empty, b"").unwrap();
+        assert_eq!(probe_file(&empty).unwrap(), "not an archive");
+        // a BSA header that is cut off is an error
+        fs::write(&archive, b"BSA\0\x69\0\0\0").unwrap();
+        assert!(probe_file(&archive).is_err());
+        assert!(probe_file(&dir.join("missing.bsa")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate() {
+        let dir = temp_dir("validate");
+        let good = dir.join("good.bsa");
+        write_archive(&good, &[(r"a\x.txt", b"x"), (r"a\empty.txt", b"")]);
+        let validation = validate_file(&good, 0, false).unwrap();
+        assert!(validation.ok());
+        assert_eq!(validation.verified, 2);
+        assert_eq!(validation.warnings(), [r"Empty file: a\empty.txt"]);
+        validate(std::slice::from_ref(&good), 0, false, true, false).unwrap();
+
+        let corrupt = dir.join("corrupt.bsa");
+        let mut builder = bsa::BsaBuilder::new(bsa::Version::SKYRIM_SPECIAL_EDITION);
+        builder.compress(true);
+        builder.add_file(r"a\x.txt", vec![1; 100]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let len = data.len();
+        data[len - 2] ^= 0xff;
+        fs::write(&corrupt, data).unwrap();
+        let validation = validate_file(&corrupt, 0, false).unwrap();
+        assert!(!validation.ok());
+        assert_eq!(validation.corrupt, 1);
+        // the contents aren't read with --fast --fast
+        assert!(validate_file(&corrupt, 2, false).unwrap().ok());
+
+        // any failed archive fails the command, even with --quiet
+        let files = [good, corrupt, dir.join("missing.bsa")];
+        let e = validate(&files, 0, false, true, false).unwrap_err();
+        assert_eq!(e.to_string(), "2 of 3 archives failed validation");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_nameless() {
+        let dir = temp_dir("nameless");
+        let archive = dir.join("a.bsa");
+        write_archive(&archive, &[(r"a\x.txt", b"x")]);
+        // clear the flag for file names, so that only their hashes are read
+        let mut data = fs::read(&archive).unwrap();
+        data[12] &= !0x2;
+        fs::write(&archive, data).unwrap();
+        let mut bsa = bsa::open(&archive).unwrap();
+        let hash = bsa.entries().next().unwrap().file().name_hash();
+
+        let out = dir.join("out");
+        extract_bsa(
+            &mut bsa,
+            &out,
+            &ExtractOptions::default(),
+            &Filter::default(),
+        )
+        .unwrap();
+        assert!(!out.exists());
+
+        let options = ExtractOptions {
+            include_nameless: true,
+            ..ExtractOptions::default()
+        };
+        extract_bsa(&mut bsa, &out, &options, &Filter::default()).unwrap();
+        let path = out.join("_unknown").join(format!("0_{:016x}.bin", hash));
+        assert_eq!(fs::read(path).unwrap(), b"x");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_hexdump_missing_file() {
+        let dir = temp_dir("hexdump");
+        let archive = dir.join("a.bsa");
+        write_archive(&archive, &[(r"meshes\a.nif", b"a")]);
+        hexdump(&archive, "meshes/a.nif", 16).unwrap();
+        assert!(hexdump(&archive, "meshes/b.nif", 16).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_split_script_line() {
+        let words = |line| split_script_line(line).unwrap();
+        assert_eq!(words("  extract a.bsa  out "), ["extract", "a.bsa", "out"]);
+        assert_eq!(
+            words(r#"add "my mods\a.bsa" "C:\some file.nif" meshes\a.nif"#),
+            [
+                "add",
+                r"my mods\a.bsa",
+                r"C:\some file.nif",
+                r"meshes\a.nif"
+            ]
+        );
+        assert_eq!(words(r#"cat a"b c"d x"#), ["cat", "ab cd", "x"]);
+        assert_eq!(words(r#"extract "" out"#), ["extract", "", "out"]);
+        assert!(words("# a comment").is_empty());
+        assert_eq!(words("extract a.bsa # out"), ["extract", "a.bsa"]);
+        // '#' only starts a comment at the start of a word
+        assert_eq!(
+            words(r##"cat a.bsa a#b "# c""##),
+            ["cat", "a.bsa", "a#b", "# c"]
+        );
+        assert!(split_script_line(r#"cat "a.bsa"#).is_err());
+    }
+
+    #[test]
+    fn test_exec_line() {
+        let dir = temp_dir("exec");
+        let archive = dir.join("a.bsa");
+        write_archive(&archive, &[(r"meshes\a.nif", b"contents")]);
+        let source = dir.join("b.nif");
+        fs::write(&source, b"new").unwrap();
+        let (mut archives, mut builders) =
+            (collections::HashMap::new(), collections::BTreeMap::new());
+        let mut exec = |line: String| {
+            let words = split_script_line(&line).unwrap();
+            exec_line(&words, &mut archives, &mut builders)
+        };
+        let new_archive = dir.join("new.bsa");
+        let (archive, new_archive, source) = (
+            archive.display().to_string(),
+            new_archive.display().to_string(),
+            source.display().to_string(),
+        );
+
+        let dest = dir.join("out").join("a.nif");
+        exec(format!(
+            r#"extract "{}" meshes/a.nif "{}""#,
+            archive,
+            dest.display()
+        ))
+        .unwrap();
+        assert_eq!(fs::read(&dest).unwrap(), b"contents");
+        assert!(exec(format!(
+            r#"extract "{}" meshes/b.nif "{}""#,
+            archive,
+            dest.display()
+        ))
+        .is_err());
+
+        // files can only be added to archives created earlier in the script
+        assert!(exec(format!(
+            r#"add "{}" "{}" meshes\b.nif"#,
+            new_archive, source
+        ))
+        .is_err());
+        assert!(exec(format!(r#"create "{}" 105 solid"#, new_archive)).is_err());
+        exec(format!(r#"create "{}" 105 compress"#, new_archive)).unwrap();
+        exec(format!(
+            r#"add "{}" "{}" meshes\b.nif"#,
+            new_archive, source
+        ))
+        .unwrap();
+        assert!(exec("remove a.bsa".to_string()).is_err());
+
+        assert_eq!(builders.len(), 1);
+        let builder = &builders[path::Path::new(&new_archive)];
+        assert!(builder.contains(r"meshes\b.nif"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Returns the contents of every named file in an archive, by path
+    fn read_archive(path: &path::Path) -> collections::BTreeMap<String, Vec<u8>> {
+        let mut bsa = bsa::open(path).unwrap();
+        named_files_by_offset(&bsa)
+            .into_iter()
+            .map(|(path, file)| (path, file.read_to_vec(&mut bsa).unwrap()))
+            .collect()
+    }
+
+    #[test]
+    fn test_merge() {
+        let dir = temp_dir("merge");
+        let inputs = [dir.join("a.bsa"), dir.join("b.bsa")];
+        write_archive(
+            &inputs[0],
+            &[(r"meshes\a.nif", b"a"), (r"meshes\c.nif", b"first")],
+        );
+        write_archive(
+            &inputs[1],
+            &[(r"meshes\b.nif", b"b"), (r"meshes\c.nif", b"last")],
+        );
+        let output = dir.join("merged.bsa");
+
+        merge(&inputs, &output, ConflictPolicy::Error, None, false, false).unwrap_err();
+        merge(&inputs, &output, ConflictPolicy::Last, None, false, true).unwrap();
+        assert!(!output.exists());
+
+        merge(&inputs, &output, ConflictPolicy::First, None, false, false).unwrap();
+        let files = read_archive(&output);
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[r"meshes\c.nif"], b"first");
+        merge(&inputs, &output, ConflictPolicy::Last, None, false, false).unwrap();
+        assert_eq!(read_archive(&output)[r"meshes\c.nif"], b"last");
+        assert!(!sidecar_path(&output, "tmp").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_parse_what_if() {
+        let what_if = parse_what_if("zlib:9").unwrap();
+        assert_eq!(
+            (what_if.name.as_str(), what_if.codec, what_if.level),
+            ("zlib:9", bsa::Codec::Zlib, Some(9))
+        );
+        let what_if = parse_what_if("lz4-block").unwrap();
+        assert_eq!((what_if.codec, what_if.level), (bsa::Codec::Lz4Block, None));
+        assert!(parse_what_if("brotli").is_err());
+        assert!(parse_what_if("zstd:max").is_err());
+        assert!(parse_what_if("zlib:").is_err());
+    }
+
+    #[test]
+    fn test_analyze() {
+        let dir = temp_dir("analyze");
+        let archive = dir.join("a.bsa");
+        write_archive(&archive, &[(r"a\x.txt", &[b'x'; 1000]), (r"a\y.txt", b"y")]);
+        let what_ifs = [
+            parse_what_if("none").unwrap(),
+            parse_what_if("zlib:1").unwrap(),
+            parse_what_if("lz4").unwrap(),
+        ];
+        analyze(&archive, &what_ifs, None).unwrap();
+        analyze(&archive, &what_ifs, Some(1)).unwrap();
+        analyze(&archive, &what_ifs, Some(0)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_order() {
+        let dir = temp_dir("load-order");
+        let names = [
+            "b.bsa",
+            "a.bsa",
+            "Mod.bsa",
+            "Mod - Textures.bsa",
+            "Modded.bsa",
+            "Other.ba2",
+            "readme.txt",
+        ];
+        for name in &names {
+            fs::write(dir.join(name), b"").unwrap();
+        }
+        let names = |archives: Vec<path::PathBuf>| {
+            archives
+                .iter()
+                .map(|archive| archive.file_name().unwrap().to_str().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let archives = load_order(std::slice::from_ref(&dir), None).unwrap();
+        assert_eq!(
+            names(archives),
+            [
+                "Mod - Textures.bsa",
+                "Mod.bsa",
+                "Modded.bsa",
+                "Other.ba2",
+                "a.bsa",
+                "b.bsa"
+            ]
+        );
+
+        // only the plugins marked with '*' are active
+        let plugins = dir.join("plugins.txt");
+        fs::write(&plugins, "# comment\n*Other.esp\n*mod.esp\nModded.esp\n").unwrap();
+        let archives = load_order(std::slice::from_ref(&dir), Some(&plugins)).unwrap();
+        assert_eq!(
+            names(archives),
+            [
+                "Modded.bsa",
+                "a.bsa",
+                "b.bsa",
+                "Other.ba2",
+                "Mod - Textures.bsa",
+                "Mod.bsa"
+            ]
+        );
+        // without markers, all listed plugins are active
+        fs::write(&plugins, "Modded.esp\nMod.esm\n").unwrap();
+        let inputs = [
+            dir.join("b.bsa"),
+            dir.join("Mod.bsa"),
+            dir.join("Modded.bsa"),
+        ];
+        let archives = load_order(&inputs, Some(&plugins)).unwrap();
+        assert_eq!(names(archives), ["b.bsa", "Modded.bsa", "Mod.bsa"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_which() {
+        let dir = temp_dir("which");
+        write_archive(&dir.join("a.bsa"), &[(r"a\x.txt", b"a")]);
+        write_archive(
+            &dir.join("b.bsa"),
+            &[(r"a\x.txt", b"b"), (r"a\y.txt", b"b")],
+        );
+        let inputs = [dir.clone()];
+        which("A/X.txt", &inputs, None).unwrap();
+        which(r"a\y.txt", &inputs, None).unwrap();
+        assert!(which("a/z.txt", &inputs, None).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_lint_fix() {
+        let dir = temp_dir("lint-fix");
+        let archive = dir.join("a.bsa");
+        let mut builder = bsa::BsaBuilder::new(bsa::Version::SKYRIM_SPECIAL_EDITION);
+        builder.compress(true).keep_original_case(true);
+        builder.add_file(r"sound\a.ogg", vec![1; 100]).unwrap();
+        builder.add_file(r"meshes\B.nif", vec![2; 100]).unwrap();
+        builder
+            .write(&mut fs::File::create(&archive).unwrap())
+            .unwrap();
+        let inputs = [archive.clone()];
+
+        assert!(lint(&inputs, &[], &[], false, false).is_err());
+        lint(&inputs, &[], &[], true, false).unwrap();
+        lint(&inputs, &[], &[], false, false).unwrap();
+        assert!(!sidecar_path(&archive, "tmp").exists());
+        let bsa = bsa::open(&archive).unwrap();
+        let compressed: collections::BTreeMap<_, _> = named_files_by_offset(&bsa)
+            .into_iter()
+            .map(|(path, file)| (path, file.is_compressed()))
+            .collect();
+        // media is stored uncompressed, while other files keep their compression
+        assert!(compressed[r"meshes\b.nif"]);
+        assert!(!compressed[r"sound\a.ogg"]);
+        let files = read_archive(&archive);
+        assert_eq!(files[r"sound\a.ogg"], [1; 100]);
+        assert_eq!(files[r"meshes\b.nif"], [2; 100]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_rename_prefix() {
+        let dir = temp_dir("rename-prefix");
+        let archive = dir.join("a.bsa");
+        let mut builder = bsa::BsaBuilder::new(bsa::Version::SKYRIM_SPECIAL_EDITION);
+        builder.compress(true);
+        for (name, data) in &[
+            (r"textures\oldmod\a.dds", b"a"),
+            (r"textures\oldmod\b\c.dds", b"c"),
+            (r"textures\a.dds", b"x"),
+        ] {
+            builder.add_file(name, data.repeat(100)).unwrap();
+        }
+        builder
+            .write(&mut fs::File::create(&archive).unwrap())
+            .unwrap();
+        let rewrite = |from, to| {
+            let mut rewrite = rewrite::Rewrite::default();
+            rewrite.prefix(from, to);
+            rewrite
+        };
+
+        let output = dir.join("out.bsa");
+        let newmod = rewrite("textures/oldmod", r"textures\newmod");
+        assert_eq!(
+            rewrite_archive(&archive, &newmod, Some(&output), true).unwrap(),
+            2
+        );
+        assert!(!output.exists());
+        assert_eq!(
+            rewrite_archive(&archive, &newmod, Some(&output), false).unwrap(),
+            2
+        );
+        let bsa = bsa::open(&output).unwrap();
+        assert!(bsa.entries().all(|entry| entry.file().is_compressed()));
+        let files = read_archive(&output);
+        assert_eq!(
+            files.keys().map(String::as_str).collect::<Vec<_>>(),
+            [
+                r"textures\a.dds",
+                r"textures\newmod\a.dds",
+                r"textures\newmod\b\c.dds"
+            ]
+        );
+        assert_eq!(files[r"textures\newmod\b\c.dds"], b"c".repeat(100));
+
+        // moving the files up a folder would overwrite textures\a.dds
+        let up = rewrite(r"textures\oldmod", "textures");
+        assert!(rewrite_archive(&archive, &up, Some(&output), false).is_err());
+        let other = rewrite(r"textures\other", r"textures\newmod");
+        assert_eq!(rewrite_archive(&archive, &other, None, false).unwrap(), 0);
+        assert_eq!(read_archive(&archive).len(), 3);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_unsafe_names() {
+        let dir = temp_dir("extract-unsafe");
+        let archive = dir.join("a.bsa");
+        write_archive(&archive, &[(r"aa\bb\x\y.txt", b"y"), (r"a\z.txt", b"z")]);
+        // rename the first folder so that it points outside the output folder
+        let mut data = fs::read(&archive).unwrap();
+        let pos = data
+            .windows(7)
+            .position(|name| name == br"aa\bb\x")
+            .unwrap();
+        data[pos..pos + 7].copy_from_slice(br"..\..\x");
+        fs::write(&archive, data).unwrap();
+        let mut bsa = bsa::OpenOptions::new()
+            .verify_hashes(bsa::HashVerification::None)
+            .open(&archive)
+            .unwrap();
+
+        let out = dir.join("out").join("nested");
+        extract_bsa(
+            &mut bsa,
+            &out,
+            &ExtractOptions::default(),
+            &Filter::default(),
+        )
+        .unwrap();
+        assert_eq!(fs::read(out.join("a").join("z.txt")).unwrap(), b"z");
+        assert!(!dir.join("x").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "thumbnail")]
+    fn test_thumbs_unsafe_names() {
+        // a 4x4 DXT1 texture with a single block
+        let mut texture = b"DDS ".to_vec();
+        let mut header = [0u32; 31];
+        header[0] = 124;
+        header[2] = 4;
+        header[3] = 4;
+        header[6] = 1;
+        header[18] = 32;
+        header[19] = 0x4;
+        header[20] = u32::from_le_bytes(*b"DXT1");
+        for value in &header {
+            texture.extend_from_slice(&value.to_le_bytes());
+        }
+        texture.extend_from_slice(&[0; 8]);
+
+        let dir = temp_dir("thumbs-unsafe");
+        let archive = dir.join("a.bsa");
+        write_archive(
+            &archive,
+            &[(r"..\..\x\y.dds", &texture), (r"textures\z.dds", &texture)],
+        );
+        let out = dir.join("out").join("nested");
+        thumbs(&[archive], &out, 4, &Filter::default()).unwrap();
+        assert!(out.join("textures").join("z.png").exists());
+        assert!(!dir.join("x").exists());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 2);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+//! Rules for renaming the files in an archive, read from a mapping file or
+//! given as a regular expression.
+
+use crate::Res;
+use std::{fs, path};
+
+/// A single line of a mapping file
+#[derive(Debug)]
+struct Mapping {
+    from: String,
+    to: String,
+    /// Whether `from` is a folder, which renames everything inside it
+    folder: bool,
+}
+
+/// Renames paths inside an archive. Entries of the mapping file take
+/// priority over the regular expression.
+#[derive(Debug, Default)]
+pub(crate) struct Rewrite {
+    mappings: Vec<Mapping>,
+    regex: Option<(regex::Regex, String)>,
+}
+
+fn normalize(path: &str) -> String {
+    path.replace('/', "\\")
+}
+
+impl Rewrite {
+    /// Reads a mapping file with one `old,new` pair per line. Paths ending in
+    /// a slash are folders, which move every file inside them. Empty lines
+    /// and lines starting with '#' are ignored.
+    pub(crate) fn read_map(&mut self, map: &path::Path) -> Res<&mut Self> {
+        for (i, line) in fs::read_to_string(map)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (from, to) = line
+                .split_once(',')
+                .ok_or_else(|| format!("{}:{}: expected 'old,new'", map.display(), i + 1))?;
+            let (from, to) = (normalize(from.trim()), normalize(to.trim()));
+            let folder = from.ends_with('\\');
+            if folder != to.ends_with('\\') {
+                return Err(format!(
+                    "{}:{}: a folder can only be renamed to another folder",
+                    map.display(),
+                    i + 1
+                )
+                .into());
+            }
+            self.mappings.push(Mapping { from, to, folder });
+        }
+        Ok(self)
+    }
+
+    /// Renames the paths matching a regular expression (case-insensitively),
+    /// where the replacement can refer to groups as `$1` or `${name}`
+    pub(crate) fn regex(&mut self, pattern: &str, replacement: &str) -> Res<&mut Self> {
+        let regex = regex::RegexBuilder::new(pattern)
+            .case_insensitive(true)
+            .build()?;
+        self.regex = Some((regex, replacement.to_string()));
+        Ok(self)
+    }
+
+    /// Moves the files inside the folder `from` to the folder `to`
+    pub(crate) fn prefix(&mut self, from: &str, to: &str) -> &mut Self {
+        self.mappings.push(Mapping {
+            from: normalize(from),
+            to: normalize(to),
+            folder: true,
+        });
+        self
+    }
+
+    /// Returns the new path of a file, or `None` if no rule renames it
+    pub(crate) fn apply(&self, path: &str) -> Option<String> {
+        let mapped = self.mappings.iter().find_map(|mapping| {
+            if mapping.folder {
+                bsa::path::replace_prefix(path, &mapping.from, &mapping.to)
+            } else if normalize(path).eq_ignore_ascii_case(&mapping.from) {
+                Some(mapping.to.clone())
+            } else {
+                None
+            }
+        });
+        mapped.or_else(|| {
+            let (regex, replacement) = self.regex.as_ref()?;
+            if !regex.is_match(path) {
+                return None;
+            }
+            Some(normalize(&regex.replace(path, replacement.as_str())))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Rewrite;
+
+    #[test]
+    fn test_rewrite() {
+        let map = std::env::temp_dir().join(format!("bsa-rewrite-{}.csv", std::process::id()));
+        std::fs::write(
+            &map,
+            "# renames\ntextures/oldmod/, textures/newmod/\nmeshes\\a.nif,meshes\\b.nif\n",
+        )
+        .unwrap();
+        let mut rewrite = Rewrite::default();
+        let res = rewrite.read_map(&map).map(|_| ());
+        std::fs::remove_file(&map).unwrap();
+        res.unwrap();
+        rewrite
+            .regex(r"^meshes\\(\w+)\\", r"meshes\new_$1\")
+            .unwrap();
+
+        assert_eq!(
+            rewrite.apply(r"Textures\OldMod\x.dds").unwrap(),
+            r"textures\newmod\x.dds"
+        );
+        assert_eq!(rewrite.apply(r"meshes\A.nif").unwrap(), r"meshes\b.nif");
+        assert_eq!(
+            rewrite.apply(r"Meshes\Armor\c.nif").unwrap(),
+            r"meshes\new_Armor\c.nif"
+        );
+        assert_eq!(rewrite.apply(r"meshes\c.nif"), None);
+        assert_eq!(rewrite.apply(r"textures\other\x.dds"), None);
+    }
+}
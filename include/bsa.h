@@ -0,0 +1,25 @@
+#ifndef BSA_H
+#define BSA_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+typedef struct BsaHandle BsaHandle;
+
+BsaHandle *bsa_open(const char *path);
+void bsa_close(BsaHandle *handle);
+size_t bsa_entry_count(const BsaHandle *handle);
+intptr_t bsa_entry_find(const BsaHandle *handle, const char *path);
+uint64_t bsa_entry_size(const BsaHandle *handle, size_t index);
+intptr_t bsa_entry_path(const BsaHandle *handle, size_t index, char *buf, size_t buf_len);
+int64_t bsa_entry_read(BsaHandle *handle, size_t index, uint8_t *buf, size_t buf_len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
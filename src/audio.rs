@@ -0,0 +1,241 @@
+//! Converting voice and sound files into formats that common players support.
+
+use std::convert::TryFrom;
+use std::{error, fmt, io, path, process, thread};
+
+/// Represents an error when converting audio
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum AudioError {
+    /// The file is not a valid FUZ file, contains the reason
+    InvalidFuz(&'static str),
+    /// The converter program couldn't be found, contains its path
+    ConverterNotFound(path::PathBuf),
+    /// The converter exited with an error, contains its error output or
+    /// exit status
+    ConverterFailed(String),
+    /// The converter couldn't be run or its output couldn't be read
+    Io(io::Error),
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidFuz(reason) => write!(f, "Invalid FUZ file: {}", reason),
+            Self::ConverterNotFound(program) => {
+                write!(f, "Audio converter {} was not found", program.display())
+            }
+            Self::ConverterFailed(message) => write!(f, "Audio converter failed: {}", message),
+            Self::Io(_) => write!(f, "Error running audio converter"),
+        }
+    }
+}
+
+impl error::Error for AudioError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for AudioError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// A FUZ voice file, which contains lip sync data followed by the actual
+/// audio (XWM in Skyrim)
+#[derive(Debug, Clone, Copy)]
+pub struct Fuz<'a> {
+    lip: &'a [u8],
+    audio: &'a [u8],
+}
+
+impl<'a> Fuz<'a> {
+    /// Splits the contents of a FUZ file into its parts
+    pub fn parse(data: &'a [u8]) -> Result<Self, AudioError> {
+        if !data.starts_with(b"FUZE") {
+            return Err(AudioError::InvalidFuz("missing FUZE magic"));
+        }
+        let lip_size = data
+            .get(8..12)
+            .map(|size| u32::from_le_bytes([size[0], size[1], size[2], size[3]]))
+            .ok_or(AudioError::InvalidFuz("header is truncated"))?;
+        let lip_end = usize::try_from(lip_size)
+            .ok()
+            .and_then(|size| size.checked_add(12))
+            .filter(|&end| end <= data.len())
+            .ok_or(AudioError::InvalidFuz("lip data is truncated"))?;
+        Ok(Self {
+            lip: &data[12..lip_end],
+            audio: &data[lip_end..],
+        })
+    }
+
+    /// Returns the lip sync data, which is empty if the line has none
+    pub fn lip(&self) -> &'a [u8] {
+        self.lip
+    }
+
+    /// Returns the audio data
+    pub fn audio(&self) -> &'a [u8] {
+        self.audio
+    }
+}
+
+/// An audio format that files can be converted into
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AudioFormat {
+    /// Uncompressed WAV (PCM) audio
+    Wav,
+    /// Ogg Vorbis audio
+    Ogg,
+}
+
+impl AudioFormat {
+    /// Returns the usual file extension of this format
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Wav => "wav",
+            Self::Ogg => "ogg",
+        }
+    }
+}
+
+/// Converts audio by running an external `ffmpeg`, which can decode the XWM
+/// (xWMA) files used by the games
+///
+/// ```no_run
+/// use bsa::{AudioConverter, AudioFormat, Fuz};
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let fuz = std::fs::read("line.fuz")?;
+///     let fuz = Fuz::parse(&fuz)?;
+///     let wav = AudioConverter::new().convert(fuz.audio(), AudioFormat::Wav)?;
+///     std::fs::write("line.wav", wav)?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct AudioConverter {
+    program: path::PathBuf,
+}
+
+impl Default for AudioConverter {
+    fn default() -> Self {
+        Self {
+            program: path::PathBuf::from("ffmpeg"),
+        }
+    }
+}
+
+impl AudioConverter {
+    /// Creates a converter that runs `ffmpeg` from the `PATH`
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the path of the `ffmpeg` executable
+    pub fn program<P: Into<path::PathBuf>>(&mut self, program: P) -> &mut Self {
+        self.program = program.into();
+        self
+    }
+
+    /// Converts audio in any format `ffmpeg` understands (e.g. XWM, WAV or
+    /// MP3) into the given format
+    pub fn convert(&self, data: &[u8], format: AudioFormat) -> Result<Vec<u8>, AudioError> {
+        let mut command = process::Command::new(&self.program);
+        command.args(["-hide_banner", "-loglevel", "error", "-i", "pipe:0"]);
+        match format {
+            AudioFormat::Wav => command.args(["-f", "wav"]),
+            AudioFormat::Ogg => command.args(["-c:a", "libvorbis", "-f", "ogg"]),
+        };
+        let mut child = command
+            .arg("pipe:1")
+            .stdin(process::Stdio::piped())
+            .stdout(process::Stdio::piped())
+            .stderr(process::Stdio::piped())
+            .spawn()
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => AudioError::ConverterNotFound(self.program.clone()),
+                _ => AudioError::Io(e),
+            })?;
+        let stdin = child.stdin.take();
+        let output = thread::scope(|s| {
+            // write the input while reading the output, so that neither pipe
+            // fills up and blocks the converter
+            s.spawn(move || {
+                if let Some(mut stdin) = stdin {
+                    // the converter may exit early, e.g. on invalid input
+                    let _ = io::Write::write_all(&mut stdin, data);
+                }
+            });
+            child.wait_with_output()
+        })?;
+        if !output.status.success() {
+            let message = String::from_utf8_lossy(&output.stderr).trim().to_string();
+            return Err(AudioError::ConverterFailed(if message.is_empty() {
+                output.status.to_string()
+            } else {
+                message
+            }));
+        }
+        Ok(output.stdout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AudioConverter, AudioError, AudioFormat, Fuz};
+
+    #[test]
+    fn test_fuz() {
+        let mut data = b"FUZE".to_vec();
+        data.extend_from_slice(&1u32.to_le_bytes());
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(b"lipRIFF");
+        let fuz = Fuz::parse(&data).unwrap();
+        assert_eq!(fuz.lip(), b"lip");
+        assert_eq!(fuz.audio(), b"RIFF");
+
+        data[8] = 100;
+        assert!(matches!(Fuz::parse(&data), Err(AudioError::InvalidFuz(_))));
+        assert!(matches!(
+            Fuz::parse(b"RIFF"),
+            Err(AudioError::InvalidFuz(_))
+        ));
+    }
+
+    #[test]
+    fn test_missing_converter() {
+        let mut converter = AudioConverter::new();
+        converter.program("/nonexistent/ffmpeg");
+        assert!(matches!(
+            converter.convert(b"RIFF", AudioFormat::Wav),
+            Err(AudioError::ConverterNotFound(_))
+        ));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_converter_pipes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        // a fake converter that echoes its input, which must not deadlock
+        // when the data is larger than the pipe buffers
+        let program = std::env::temp_dir().join(format!("bsa-fake-ffmpeg-{}", std::process::id()));
+        std::fs::write(&program, "#!/bin/sh\ncat\n").unwrap();
+        std::fs::set_permissions(&program, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let data: Vec<u8> = (0..1 << 20).map(|i| i as u8).collect();
+        let mut converter = AudioConverter::new();
+        converter.program(&program);
+        let res = converter.convert(&data, AudioFormat::Ogg);
+        std::fs::remove_file(&program).unwrap();
+        assert_eq!(res.unwrap(), data);
+    }
+}
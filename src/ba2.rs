@@ -0,0 +1,407 @@
+use crate::bsa::{ReadError, ReadSeek};
+use crate::encoding::Encoding;
+use crate::vfs::{index_key, FileSystem};
+use std::collections::BTreeMap;
+use std::{fmt, fs, io, path};
+
+/// A chunk of file data in a BA2 archive
+#[derive(Debug, Clone)]
+struct Chunk {
+    offset: u64,
+    packed_size: u32,
+    unpacked_size: u32,
+}
+
+/// The header of a texture in a DX10 archive, used to rebuild its DDS header
+#[derive(Debug, Clone)]
+struct Texture {
+    height: u16,
+    width: u16,
+    mip_count: u8,
+    format: u8,
+    cubemap: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Ba2File {
+    path: String,
+    chunks: Vec<Chunk>,
+    texture: Option<Texture>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Compression {
+    Zlib,
+    Lz4Block,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+fn u64_at(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn read_bytes(reader: &mut impl io::Read, len: usize) -> Result<Vec<u8>, ReadError> {
+    let mut buf = vec![0; len];
+    reader.read_exact(&mut buf)?;
+    Ok(buf)
+}
+
+/// A [`FileSystem`] backed by a BA2 archive, the format that replaced BSA
+/// files in Fallout 4 and Starfield. General (`GNRL`) and texture (`DX10`)
+/// archives are supported; textures are returned as DDS files.
+///
+/// ```no_run
+/// use bsa::FileSystem;
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let mut fs = bsa::Ba2Fs::open("Fallout4 - Meshes.ba2")?;
+///     let mut reader = fs.open("meshes/foo.nif")?;
+///     std::io::copy(&mut reader, &mut std::io::sink())?;
+///     Ok(())
+/// }
+/// ```
+pub struct Ba2Fs {
+    reader: Box<dyn ReadSeek + Send + Sync>,
+    path: Option<path::PathBuf>,
+    version: u32,
+    compression: Compression,
+    files: Vec<Ba2File>,
+    index: BTreeMap<String, usize>,
+}
+
+impl Ba2Fs {
+    /// Opens the BA2 archive at the given path
+    pub fn open<P: AsRef<path::Path>>(path: P) -> Result<Self, ReadError> {
+        let path = path.as_ref();
+        let mut res = Self::read(io::BufReader::new(fs::File::open(path)?))?;
+        res.path = Some(path.to_path_buf());
+        Ok(res)
+    }
+
+    /// Reads a BA2 archive from a reader
+    pub fn read<R: io::Read + io::Seek + Send + Sync + 'static>(
+        mut reader: R,
+    ) -> Result<Self, ReadError> {
+        let header = read_bytes(&mut reader, 24).map_err(|_| ReadError::MissingHeader)?;
+        if &header[..4] != b"BTDX" {
+            return Err(ReadError::MissingHeader);
+        }
+        let version = u32_at(&header, 4);
+        let kind = &header[8..12];
+        let file_count = u32_at(&header, 12);
+        let name_table_offset = u64_at(&header, 16);
+        // Starfield archives have extra header fields, the last of which
+        // selects the codec
+        let compression = match version {
+            1 | 7 | 8 => Compression::Zlib,
+            2 => {
+                read_bytes(&mut reader, 8)?;
+                Compression::Zlib
+            }
+            3 => match u32_at(&read_bytes(&mut reader, 12)?, 8) {
+                0 => Compression::Zlib,
+                3 => Compression::Lz4Block,
+                _ => return Err(ReadError::UnknownCompressionAlgorithm),
+            },
+            _ => return Err(ReadError::UnknownVersion(version)),
+        };
+
+        let mut files = vec![];
+        for _ in 0..file_count {
+            let file = match kind {
+                b"GNRL" => {
+                    let record = read_bytes(&mut reader, 36)?;
+                    Ba2File {
+                        path: String::new(),
+                        chunks: vec![Chunk {
+                            offset: u64_at(&record, 16),
+                            packed_size: u32_at(&record, 24),
+                            unpacked_size: u32_at(&record, 28),
+                        }],
+                        texture: None,
+                    }
+                }
+                b"DX10" => {
+                    let record = read_bytes(&mut reader, 24)?;
+                    let mut chunks = vec![];
+                    for _ in 0..record[13] {
+                        let chunk = read_bytes(&mut reader, 24)?;
+                        chunks.push(Chunk {
+                            offset: u64_at(&chunk, 0),
+                            packed_size: u32_at(&chunk, 8),
+                            unpacked_size: u32_at(&chunk, 12),
+                        });
+                    }
+                    Ba2File {
+                        path: String::new(),
+                        chunks,
+                        texture: Some(Texture {
+                            height: u16_at(&record, 16),
+                            width: u16_at(&record, 18),
+                            mip_count: record[20],
+                            format: record[21],
+                            cubemap: record[22] & 1 != 0,
+                        }),
+                    }
+                }
+                _ => {
+                    return Err(ReadError::CorruptRecord(format!(
+                        "unknown BA2 archive type {:?}",
+                        String::from_utf8_lossy(kind)
+                    )))
+                }
+            };
+            files.push(file);
+        }
+
+        reader.seek(io::SeekFrom::Start(name_table_offset))?;
+        let mut index = BTreeMap::new();
+        for (idx, file) in files.iter_mut().enumerate() {
+            let len = u16_at(&read_bytes(&mut reader, 2)?, 0);
+            let name = read_bytes(&mut reader, len.into())?;
+            file.path = Encoding::Windows1252.decode(&name).replace('/', "\\");
+            index.insert(index_key(&file.path), idx);
+        }
+
+        Ok(Self {
+            reader: Box::new(reader),
+            path: None,
+            version,
+            compression,
+            files,
+            index,
+        })
+    }
+
+    /// Returns the BA2 format version: 1 for Fallout 4, 7 and 8 for the
+    /// next-gen update of Fallout 4, and 2 and 3 for Starfield
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+
+    fn read_chunk(&mut self, chunk: &Chunk, out: &mut Vec<u8>) -> Result<(), ReadError> {
+        self.reader.seek(io::SeekFrom::Start(chunk.offset))?;
+        if chunk.packed_size == 0 {
+            out.extend(read_bytes(&mut self.reader, chunk.unpacked_size as usize)?);
+            return Ok(());
+        }
+        #[cfg_attr(not(all(feature = "zlib", feature = "lz4")), allow(unused_variables))]
+        let packed = read_bytes(&mut self.reader, chunk.packed_size as usize)?;
+        match self.compression {
+            Compression::Zlib => {
+                #[cfg(feature = "zlib")]
+                {
+                    io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(&packed[..]), out)?;
+                    Ok(())
+                }
+                #[cfg(not(feature = "zlib"))]
+                {
+                    Err(ReadError::CompressionFeatureDisabled("zlib"))
+                }
+            }
+            Compression::Lz4Block => {
+                #[cfg(feature = "lz4")]
+                {
+                    let size: i32 = std::convert::TryFrom::try_from(chunk.unpacked_size)
+                        .map_err(|_| ReadError::FileTooLarge(chunk.unpacked_size.into()))?;
+                    out.extend(lz4::block::decompress(&packed, Some(size))?);
+                    Ok(())
+                }
+                #[cfg(not(feature = "lz4"))]
+                {
+                    Err(ReadError::CompressionFeatureDisabled("lz4"))
+                }
+            }
+        }
+    }
+
+    fn read_file(&mut self, idx: usize) -> Result<Vec<u8>, ReadError> {
+        let file = self.files[idx].clone();
+        let mut res = vec![];
+        if let Some(texture) = &file.texture {
+            dds_header(texture, &mut res);
+        }
+        for chunk in &file.chunks {
+            self.read_chunk(chunk, &mut res)?;
+        }
+        Ok(res)
+    }
+}
+
+/// Writes a DDS header with a DX10 extension, which can describe every
+/// format that is stored in BA2 archives
+fn dds_header(texture: &Texture, out: &mut Vec<u8>) {
+    let mut put = |value: u32| out.extend(value.to_le_bytes());
+    let (mut caps, mut caps2) = (0x1000, 0);
+    if texture.mip_count > 1 {
+        caps |= 0x40_0008;
+    }
+    if texture.cubemap {
+        caps |= 0x8;
+        caps2 = 0xfe00;
+    }
+    put(u32::from_le_bytes(*b"DDS "));
+    put(124);
+    put(0xa_1007);
+    put(texture.height.into());
+    put(texture.width.into());
+    put(0);
+    put(0);
+    put(texture.mip_count.into());
+    for _ in 0..11 {
+        put(0);
+    }
+    // pixel format
+    put(32);
+    put(0x4);
+    put(u32::from_le_bytes(*b"DX10"));
+    for _ in 0..5 {
+        put(0);
+    }
+    put(caps);
+    put(caps2);
+    for _ in 0..3 {
+        put(0);
+    }
+    // DX10 header
+    put(texture.format.into());
+    put(3);
+    put(if texture.cubemap { 0x4 } else { 0 });
+    put(1);
+    put(0);
+}
+
+impl FileSystem for Ba2Fs {
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read + '_>> {
+        let &idx = self.index.get(&index_key(path)).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path))
+        })?;
+        Ok(Box::new(io::Cursor::new(self.read_file(idx)?)))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.index.contains_key(&index_key(path))
+    }
+
+    fn list(&self, prefix: &str) -> Vec<String> {
+        let mut prefix = index_key(prefix);
+        if !prefix.is_empty() {
+            prefix.push('\\');
+        }
+        self.index
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, &idx)| self.files[idx].path.clone())
+            .collect()
+    }
+
+    fn source_path(&self) -> Option<&path::Path> {
+        self.path.as_deref()
+    }
+}
+
+impl fmt::Debug for Ba2Fs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Ba2Fs")
+            .field("path", &self.path)
+            .field("version", &self.version)
+            .field("files", &self.files.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ba2Fs;
+    use crate::validator::{DdsValidator, Validator};
+    use crate::vfs::FileSystem;
+    use std::io::{Cursor, Read};
+
+    /// Builds an uncompressed BA2 archive with the given records, where each
+    /// record is followed by its data
+    fn ba2(kind: &[u8; 4], files: &[(&str, &[u8])]) -> Vec<u8> {
+        let record_len = if kind == b"GNRL" { 36 } else { 48 };
+        let data_start = 24 + files.len() * record_len;
+        let mut records = vec![];
+        let mut data: Vec<u8> = vec![];
+        for (_, contents) in files {
+            let offset = (data_start + data.len()) as u64;
+            if kind == b"GNRL" {
+                records.extend([0; 16]);
+                records.extend(offset.to_le_bytes());
+                records.extend(0u32.to_le_bytes());
+                records.extend((contents.len() as u32).to_le_bytes());
+                records.extend(0xbaad_f00du32.to_le_bytes());
+            } else {
+                // one chunk holding a 4x4 BC1 texture without mipmaps
+                records.extend([0; 12]);
+                records.extend([0, 1, 24, 0]);
+                records.extend(4u16.to_le_bytes());
+                records.extend(4u16.to_le_bytes());
+                records.extend([1, 71, 0, 8]);
+                records.extend(offset.to_le_bytes());
+                records.extend(0u32.to_le_bytes());
+                records.extend((contents.len() as u32).to_le_bytes());
+                records.extend([0; 4]);
+                records.extend(0xbaad_f00du32.to_le_bytes());
+            }
+            data.extend(*contents);
+        }
+        let mut res = b"BTDX".to_vec();
+        res.extend(1u32.to_le_bytes());
+        res.extend(kind);
+        res.extend((files.len() as u32).to_le_bytes());
+        res.extend(((data_start + data.len()) as u64).to_le_bytes());
+        res.extend(records);
+        res.extend(data);
+        for (path, _) in files {
+            res.extend((path.len() as u16).to_le_bytes());
+            res.extend(path.as_bytes());
+        }
+        res
+    }
+
+    fn read(fs: &mut Ba2Fs, path: &str) -> Vec<u8> {
+        let mut contents = vec![];
+        fs.open(path).unwrap().read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_general_archive() {
+        let data = ba2(
+            b"GNRL",
+            &[(r"meshes\a.nif", b"hello"), ("Scripts/B.pex", b"world")],
+        );
+        let mut fs = Ba2Fs::read(Cursor::new(data)).unwrap();
+        assert_eq!(fs.version(), 1);
+        assert_eq!(fs.list(""), vec![r"meshes\a.nif", r"Scripts\B.pex"]);
+        assert!(fs.exists("scripts/b.pex"));
+        assert_eq!(read(&mut fs, "Meshes/A.nif"), b"hello");
+        assert_eq!(read(&mut fs, r"scripts\b.pex"), b"world");
+        assert!(fs.open("meshes/missing.nif").is_err());
+    }
+
+    #[test]
+    fn test_texture_archive() {
+        let data = ba2(b"DX10", &[(r"textures\a.dds", &[7; 8])]);
+        let mut fs = Ba2Fs::read(Cursor::new(data)).unwrap();
+        let dds = read(&mut fs, r"textures\a.dds");
+        assert_eq!(dds.len(), 148 + 8);
+        assert_eq!(DdsValidator.validate("dds", &mut &dds[..]), Ok(()));
+        assert_eq!(&dds[128..132], &71u32.to_le_bytes());
+        assert_eq!(&dds[148..], &[7; 8]);
+    }
+}
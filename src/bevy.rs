@@ -0,0 +1,91 @@
+use crate::vfs::{FileSystem, Vfs};
+use bevy_asset::io::{AssetReader, AssetReaderError, PathStream, Reader, VecReader};
+use std::{io, path, sync::Mutex};
+
+/// A Bevy [`AssetReader`] that loads assets from one or more BSA files.
+///
+/// ```ignore
+/// let mut vfs = bsa::Vfs::new();
+/// vfs.push(bsa::BsaFs::new(bsa::open("Skyrim - Meshes0.bsa")?));
+/// vfs.push(bsa::BsaFs::new(bsa::open("Skyrim - Textures0.bsa")?));
+/// let reader = bsa::BsaAssetReader::new(vfs);
+/// app.register_asset_source(
+///     "bsa",
+///     bevy::asset::io::AssetSource::build().with_reader(move || Box::new(reader.clone())),
+/// );
+/// ```
+#[derive(Debug, Clone)]
+pub struct BsaAssetReader {
+    vfs: std::sync::Arc<Mutex<Vfs>>,
+}
+
+impl BsaAssetReader {
+    /// Creates a new asset reader from the given virtual file system
+    pub fn new(vfs: Vfs) -> Self {
+        Self {
+            vfs: std::sync::Arc::new(Mutex::new(vfs)),
+        }
+    }
+
+    fn bsa_path(path: &path::Path) -> String {
+        path.to_string_lossy().replace('/', "\\")
+    }
+
+    fn read_bytes(&self, path: &path::Path) -> Result<Vec<u8>, AssetReaderError> {
+        let bsa_path = Self::bsa_path(path);
+        let mut vfs = self.vfs.lock().unwrap();
+        if !vfs.exists(&bsa_path) {
+            return Err(AssetReaderError::NotFound(path.to_path_buf()));
+        }
+        let mut res = vec![];
+        io::Read::read_to_end(&mut vfs.open(&bsa_path)?, &mut res)?;
+        Ok(res)
+    }
+}
+
+impl AssetReader for BsaAssetReader {
+    async fn read<'a>(
+        &'a self,
+        path: &'a path::Path,
+    ) -> Result<impl Reader + 'a, AssetReaderError> {
+        Ok(VecReader::new(self.read_bytes(path)?))
+    }
+
+    async fn read_meta<'a>(
+        &'a self,
+        path: &'a path::Path,
+    ) -> Result<impl Reader + 'a, AssetReaderError> {
+        Err::<VecReader, _>(AssetReaderError::NotFound(path.to_path_buf()))
+    }
+
+    async fn read_directory<'a>(
+        &'a self,
+        path: &'a path::Path,
+    ) -> Result<Box<PathStream>, AssetReaderError> {
+        let prefix = Self::bsa_path(path);
+        let prefix_len = crate::write::normalize_path(&prefix).len();
+        let mut children = vec![];
+        for file in self.vfs.lock().unwrap().list(&prefix) {
+            // only return direct children of this directory
+            let relative = file[prefix_len..].trim_start_matches('\\');
+            let child = relative.split('\\').next().unwrap_or(relative);
+            let child = path.join(child);
+            if !children.contains(&child) {
+                children.push(child);
+            }
+        }
+        if children.is_empty() {
+            return Err(AssetReaderError::NotFound(path.to_path_buf()));
+        }
+        Ok(Box::new(futures_lite::stream::iter(children)))
+    }
+
+    async fn is_directory<'a>(&'a self, path: &'a path::Path) -> Result<bool, AssetReaderError> {
+        Ok(!self
+            .vfs
+            .lock()
+            .unwrap()
+            .list(&Self::bsa_path(path))
+            .is_empty())
+    }
+}
@@ -1,10 +1,19 @@
-#![allow(dead_code)]
-
+use crate::checksum::{Checksum, VerifiedReader};
+use crate::diagnostic::Diagnostic;
+use crate::encoding::Encoding;
+use crate::entry::{Entry, EntryPath};
+use crate::game::Game;
+use crate::kind::FileKind;
+use crate::layout::{Region, RegionKind};
+use crate::pool::ReaderPool;
+use crate::profile::{self, event, span};
+use crate::reader::{self, FileReader, Limited, SubReader};
+use crate::write::normalize_path;
 use crate::{cp1252, hash};
-use log::{error, info, trace, warn};
-use std::{error, fmt, fs, io, path};
+use log::{error, info, warn};
+use std::{collections::BTreeSet, convert, error, fmt, fs, io, mem, path, str, sync::Arc};
 
-trait ReadSeek: io::Read + io::Seek {}
+pub(crate) trait ReadSeek: io::Read + io::Seek {}
 impl<T: io::Read + io::Seek> ReadSeek for T {}
 
 /// Represents an error when reading a BSA file
@@ -21,6 +30,16 @@ pub enum ReadError {
     ReaderError(io::Error),
     FailedToEncodeCharacter(cp1252::EncodingError),
     IncorrectHash(IncorrectHashError),
+    FileTooLarge(u64),
+    InvalidUtf8(std::string::FromUtf8Error),
+    CompressionFeatureDisabled(&'static str),
+    CorruptRecord(String),
+    EmbeddedNameTooLong(u64),
+    UnexpectedFileOffset(u64, u64),
+    GameVersionMismatch(Game, Version),
+    NotOpenedFromPath,
+    TooManyEntries(u64),
+    ChecksumMismatch(Checksum, Checksum),
 }
 
 #[derive(Debug, Clone)]
@@ -47,6 +66,40 @@ impl fmt::Display for ReadError {
                 "Incorrect hash for '{}' (expected {}, found {})",
                 &err.name, err.expected_hash, err.actual_hash
             ),
+            Self::FileTooLarge(limit) => write!(f, "File is larger than {} bytes", limit),
+            Self::InvalidUtf8(_) => write!(f, "File contents are not valid UTF-8"),
+            Self::CompressionFeatureDisabled(feature) => write!(
+                f,
+                "Reading compressed files requires the `{}` feature",
+                feature
+            ),
+            Self::CorruptRecord(context) => write!(f, "Corrupt record: {}", context),
+            Self::UnexpectedFileOffset(expected, actual) => write!(
+                f,
+                "Expected file at offset {}, but the previous file ends at {}",
+                expected, actual
+            ),
+            Self::EmbeddedNameTooLong(offset) => write!(
+                f,
+                "Embedded file name at offset {} is longer than the file",
+                offset
+            ),
+            Self::GameVersionMismatch(game, version) => write!(
+                f,
+                "{} uses BSA version {}, but the file has version {}",
+                game,
+                game.version().serialize(),
+                version.serialize()
+            ),
+            Self::NotOpenedFromPath => write!(f, "BSA file was not opened from a path"),
+            Self::TooManyEntries(max) => {
+                write!(f, "BSA file has more than {} folders and files", max)
+            }
+            Self::ChecksumMismatch(expected, actual) => write!(
+                f,
+                "Incorrect checksum (expected {}, found {})",
+                expected, actual
+            ),
         }
     }
 }
@@ -55,6 +108,7 @@ impl error::Error for ReadError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Self::ReaderError(e) => Some(e),
+            Self::InvalidUtf8(e) => Some(e),
             _ => None,
         }
     }
@@ -70,18 +124,79 @@ impl From<io::Error> for ReadError {
     }
 }
 
+impl ReadError {
+    /// Returns whether the error was caused by a corrupt or malformed BSA
+    /// file. Retrying won't help, but other files may still be readable.
+    pub fn is_corruption(&self) -> bool {
+        matches!(
+            self,
+            Self::MissingHeader
+                | Self::UnexpectedFolderRecordOffset
+                | Self::ExpectedNullByte
+                | Self::UnexpectedEndOfFile
+                | Self::FailedToReadFileOffset
+                | Self::IncorrectHash(_)
+                | Self::CorruptRecord(_)
+                | Self::EmbeddedNameTooLong(_)
+                | Self::UnexpectedFileOffset(_, _)
+                | Self::ChecksumMismatch(_, _)
+        )
+    }
+
+    /// Returns whether the error was reported by the underlying reader, in
+    /// which case retrying may succeed
+    pub fn is_io(&self) -> bool {
+        matches!(self, Self::ReaderError(_))
+    }
+
+    /// Returns whether the BSA uses a version or feature that this crate
+    /// (as configured) doesn't support
+    pub fn is_unsupported(&self) -> bool {
+        matches!(
+            self,
+            Self::UnknownVersion(_)
+                | Self::UnknownCompressionAlgorithm
+                | Self::CompressionFeatureDisabled(_)
+                | Self::GameVersionMismatch(_, _)
+        )
+    }
+}
+
+impl From<ReadError> for io::Error {
+    fn from(e: ReadError) -> Self {
+        let kind = match e {
+            ReadError::ReaderError(e) => return e,
+            ReadError::UnexpectedEndOfFile => io::ErrorKind::UnexpectedEof,
+            ReadError::FailedToEncodeCharacter(_) => io::ErrorKind::InvalidInput,
+            ReadError::InvalidUtf8(_) => io::ErrorKind::InvalidData,
+            _ if e.is_corruption() => io::ErrorKind::InvalidData,
+            _ if e.is_unsupported() => io::ErrorKind::Unsupported,
+            _ => io::ErrorKind::Other,
+        };
+        io::Error::new(kind, e)
+    }
+}
+
 impl From<cp1252::EncodingError> for ReadError {
     fn from(e: cp1252::EncodingError) -> Self {
         Self::FailedToEncodeCharacter(e)
     }
 }
 
-#[derive(Clone, Debug)]
+/// Represents an error when writing a BSA file
+#[non_exhaustive]
+#[derive(Debug)]
 pub enum WriteError {
     UnencodableCharacters(cp1252::EncodingError),
     FileNameMoreThan255Characters,
     CompressionUnsupported,
+    CompressionFeatureDisabled(&'static str),
     MissingFileName,
+    MissingFolderName,
+    ArchiveTooLarge,
+    WriterError(io::Error),
+    GameLimitExceeded(Game, String),
+    CodecMismatch(Codec, Version),
 }
 
 impl fmt::Display for WriteError {
@@ -89,10 +204,27 @@ impl fmt::Display for WriteError {
         match self {
             Self::UnencodableCharacters(_) => write!(f, "Unencodable characters found"),
             Self::CompressionUnsupported => write!(f, "Compression is not currently supported"),
+            Self::CompressionFeatureDisabled(feature) => write!(
+                f,
+                "Writing compressed files requires the `{}` feature",
+                feature
+            ),
             Self::FileNameMoreThan255Characters => {
                 write!(f, "File name is longer than 255 characters")
             }
             Self::MissingFileName => write!(f, "Missing file name"),
+            Self::MissingFolderName => write!(f, "Missing folder name"),
+            Self::ArchiveTooLarge => write!(f, "Archive is too large for the BSA format"),
+            Self::WriterError(_) => write!(f, "Error writing file"),
+            Self::GameLimitExceeded(game, problem) => {
+                write!(f, "Archive would not work in {}: {}", game, problem)
+            }
+            Self::CodecMismatch(codec, version) => write!(
+                f,
+                "Files compressed with {} can't be stored in BSA version {}",
+                codec,
+                version.serialize()
+            ),
         }
     }
 }
@@ -101,24 +233,43 @@ impl error::Error for WriteError {
     fn source(&self) -> Option<&(dyn error::Error + 'static)> {
         match self {
             Self::UnencodableCharacters(e) => Some(e),
+            Self::WriterError(e) => Some(e),
             _ => None,
         }
     }
 }
 
+impl From<io::Error> for WriteError {
+    fn from(e: io::Error) -> Self {
+        Self::WriterError(e)
+    }
+}
+
+impl From<cp1252::EncodingError> for WriteError {
+    fn from(e: cp1252::EncodingError) -> Self {
+        Self::UnencodableCharacters(e)
+    }
+}
+
+/// Represents a BSA format version
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
-struct Version(u32);
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(try_from = "u32", into = "u32")
+)]
+pub struct Version(u32);
 
 impl Version {
-    const OBLIVION: Version = Version(103);
-    const SKYRIM: Version = Version(104);
-    const SKYRIM_SPECIAL_EDITION: Version = Version(105);
+    pub const OBLIVION: Version = Version(103);
+    pub const SKYRIM: Version = Version(104);
+    pub const SKYRIM_SPECIAL_EDITION: Version = Version(105);
 
-    fn serialize(self) -> u32 {
+    pub(crate) fn serialize(self) -> u32 {
         self.0
     }
 
-    fn deserialize(value: u32) -> Result<Self, ReadError> {
+    pub(crate) fn deserialize(value: u32) -> Result<Self, ReadError> {
         Ok(match value {
             103 => Self::OBLIVION,
             104 => Self::SKYRIM,
@@ -128,22 +279,128 @@ impl Version {
     }
 }
 
+impl str::FromStr for Version {
+    type Err = ParseVersionError;
+
+    /// Parses a version number, e.g. `"105"`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.trim()
+            .parse()
+            .ok()
+            .and_then(|value| Self::deserialize(value).ok())
+            .ok_or_else(|| ParseVersionError(s.to_string()))
+    }
+}
+
+/// Represents an error when parsing a [`Version`]
+#[derive(Debug, Clone)]
+pub struct ParseVersionError(String);
+
+impl fmt::Display for ParseVersionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Unknown BSA version {:?} (expected 103, 104 or 105)",
+            self.0
+        )
+    }
+}
+
+impl error::Error for ParseVersionError {}
+
+impl convert::TryFrom<u32> for Version {
+    type Error = ReadError;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        Self::deserialize(value)
+    }
+}
+
+impl From<Version> for u32 {
+    fn from(version: Version) -> Self {
+        version.serialize()
+    }
+}
+
+/// The archive flag used by this library to mark archives compressed with zstd
+const ZSTD_CODEC_FLAG: u32 = 0x8000_0000;
+
+/// Represents the archive flags stored in the BSA header
 #[derive(Clone, Copy, Debug)]
-struct ArchiveFlags {
-    include_directory_names: bool,
-    include_file_names: bool,
-    compressed_archive: bool,
-    retain_directory_names: bool,
-    retain_file_names: bool,
-    retain_file_name_offsets: bool,
-    xbox360_archive: bool,
-    retain_strings: bool,
-    embed_file_names: bool,
-    xmem_codec: bool,
+pub struct ArchiveFlags {
+    pub(crate) include_directory_names: bool,
+    pub(crate) include_file_names: bool,
+    pub(crate) compressed_archive: bool,
+    pub(crate) retain_directory_names: bool,
+    pub(crate) retain_file_names: bool,
+    pub(crate) retain_file_name_offsets: bool,
+    pub(crate) xbox360_archive: bool,
+    pub(crate) retain_strings: bool,
+    pub(crate) embed_file_names: bool,
+    pub(crate) xmem_codec: bool,
+    pub(crate) zstd_codec: bool,
+    pub(crate) unknown_bits: u32,
 }
 
 impl ArchiveFlags {
-    fn serialize(self) -> u32 {
+    /// Returns whether folder names are stored in the BSA
+    pub fn include_directory_names(self) -> bool {
+        self.include_directory_names
+    }
+
+    /// Returns whether file names are stored in the BSA
+    pub fn include_file_names(self) -> bool {
+        self.include_file_names
+    }
+
+    /// Returns whether files are compressed by default
+    pub fn compressed_archive(self) -> bool {
+        self.compressed_archive
+    }
+
+    /// Returns whether the retain directory names flag is set
+    pub fn retain_directory_names(self) -> bool {
+        self.retain_directory_names
+    }
+
+    /// Returns whether the retain file names flag is set
+    pub fn retain_file_names(self) -> bool {
+        self.retain_file_names
+    }
+
+    /// Returns whether the retain file name offsets flag is set
+    pub fn retain_file_name_offsets(self) -> bool {
+        self.retain_file_name_offsets
+    }
+
+    /// Returns whether the BSA uses the big-endian Xbox 360 format
+    pub fn xbox360_archive(self) -> bool {
+        self.xbox360_archive
+    }
+
+    /// Returns whether the retain strings flag is set
+    pub fn retain_strings(self) -> bool {
+        self.retain_strings
+    }
+
+    /// Returns whether full file paths are stored before the contents of each file
+    pub fn embed_file_names(self) -> bool {
+        self.embed_file_names
+    }
+
+    /// Returns whether files are compressed using the Xbox XMem codec
+    pub fn xmem_codec(self) -> bool {
+        self.xmem_codec
+    }
+
+    /// Returns whether files are compressed using zstd. This is an extension
+    /// of the format by this library (see [`BsaBuilder::zstd`](crate::BsaBuilder::zstd)),
+    /// which the games can't read.
+    pub fn zstd_codec(self) -> bool {
+        self.zstd_codec
+    }
+
+    pub(crate) fn serialize(self) -> u32 {
         let mut res = 0;
         if self.include_directory_names {
             res |= 0x01;
@@ -175,10 +432,23 @@ impl ArchiveFlags {
         if self.xmem_codec {
             res |= 0x200;
         }
-        res
+        if self.zstd_codec {
+            res |= ZSTD_CODEC_FLAG;
+        }
+        res | self.unknown_bits
+    }
+
+    /// Returns the raw flag bits, including any unrecognized bits
+    pub fn raw(self) -> u32 {
+        self.serialize()
     }
 
-    fn deserialize(value: u32) -> Self {
+    /// Returns the flag bits that are not recognized by this library
+    pub fn unknown_bits(self) -> u32 {
+        self.unknown_bits
+    }
+
+    pub(crate) fn deserialize(value: u32) -> Self {
         let mut res = Self {
             include_directory_names: false,
             include_file_names: false,
@@ -190,6 +460,8 @@ impl ArchiveFlags {
             retain_strings: false,
             embed_file_names: false,
             xmem_codec: false,
+            zstd_codec: false,
+            unknown_bits: value & !0x3ff & !ZSTD_CODEC_FLAG,
         };
         if (value & 0x01) != 0 {
             res.include_directory_names = true;
@@ -221,25 +493,31 @@ impl ArchiveFlags {
         if (value & 0x200) != 0 {
             res.xmem_codec = true;
         }
+        if (value & ZSTD_CODEC_FLAG) != 0 {
+            res.zstd_codec = true;
+        }
         res
     }
 }
 
+/// Represents the file flags stored in the BSA header, which describe the
+/// types of files in the archive
 #[derive(Clone, Copy, Debug)]
-struct FileFlags {
-    meshes: bool,
-    textures: bool,
-    menus: bool,
-    sounds: bool,
-    voices: bool,
-    shaders: bool,
-    trees: bool,
-    fonts: bool,
-    miscellaneous: bool,
+pub struct FileFlags {
+    pub(crate) meshes: bool,
+    pub(crate) textures: bool,
+    pub(crate) menus: bool,
+    pub(crate) sounds: bool,
+    pub(crate) voices: bool,
+    pub(crate) shaders: bool,
+    pub(crate) trees: bool,
+    pub(crate) fonts: bool,
+    pub(crate) miscellaneous: bool,
+    pub(crate) unknown_bits: u32,
 }
 
 impl FileFlags {
-    fn serialize(self) -> u32 {
+    pub(crate) fn serialize(self) -> u32 {
         let mut res = 0;
         if self.meshes {
             res |= 0x01;
@@ -268,10 +546,20 @@ impl FileFlags {
         if self.miscellaneous {
             res |= 0x100;
         }
-        res
+        res | self.unknown_bits
+    }
+
+    /// Returns the raw flag bits, including any unrecognized bits
+    pub fn raw(self) -> u32 {
+        self.serialize()
+    }
+
+    /// Returns the flag bits that are not recognized by this library
+    pub fn unknown_bits(self) -> u32 {
+        self.unknown_bits
     }
 
-    fn deserialize(value: u32) -> Self {
+    pub(crate) fn deserialize(value: u32) -> Self {
         let mut res = Self {
             meshes: false,
             textures: false,
@@ -282,6 +570,7 @@ impl FileFlags {
             trees: false,
             fonts: false,
             miscellaneous: false,
+            unknown_bits: value & !0x1ff,
         };
         if (value & 0x01) != 0 {
             res.meshes = true;
@@ -314,18 +603,173 @@ impl FileFlags {
     }
 }
 
-/// Represents a file inside a BSA
+impl FileFlags {
+    /// Returns whether the archive is marked as containing files of the given type
+    pub fn contains(self, file_type: FileType) -> bool {
+        match file_type {
+            FileType::Meshes => self.meshes,
+            FileType::Textures => self.textures,
+            FileType::Menus => self.menus,
+            FileType::Sounds => self.sounds,
+            FileType::Voices => self.voices,
+            FileType::Shaders => self.shaders,
+            FileType::Trees => self.trees,
+            FileType::Fonts => self.fonts,
+            FileType::Miscellaneous => self.miscellaneous,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, file_type: FileType) {
+        match file_type {
+            FileType::Meshes => self.meshes = true,
+            FileType::Textures => self.textures = true,
+            FileType::Menus => self.menus = true,
+            FileType::Sounds => self.sounds = true,
+            FileType::Voices => self.voices = true,
+            FileType::Shaders => self.shaders = true,
+            FileType::Trees => self.trees = true,
+            FileType::Fonts => self.fonts = true,
+            FileType::Miscellaneous => self.miscellaneous = true,
+        }
+    }
+}
+
+/// The type of a file, as recorded in the [`FileFlags`] of the BSA header
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum FileType {
+    Meshes,
+    Textures,
+    Menus,
+    Sounds,
+    Voices,
+    Shaders,
+    Trees,
+    Fonts,
+    Miscellaneous,
+}
+
+impl FileType {
+    /// All file types, in the order of their header flag bits
+    pub const ALL: [Self; 9] = [
+        Self::Meshes,
+        Self::Textures,
+        Self::Menus,
+        Self::Sounds,
+        Self::Voices,
+        Self::Shaders,
+        Self::Trees,
+        Self::Fonts,
+        Self::Miscellaneous,
+    ];
+
+    /// Determines the type of a file from its path inside the BSA, based on
+    /// the top-level folder, or on the extension if the folder is not
+    /// recognized
+    pub fn from_path(path: &str) -> Self {
+        let path = path.replace('/', "\\").to_ascii_lowercase();
+        match path.split('\\').next().unwrap_or("") {
+            "meshes" => return Self::Meshes,
+            "textures" => return Self::Textures,
+            "menus" | "interface" => return Self::Menus,
+            "sound" if path.starts_with(r"sound\voice\") => return Self::Voices,
+            "sound" | "music" => return Self::Sounds,
+            "shaders" => return Self::Shaders,
+            "trees" => return Self::Trees,
+            "fonts" => return Self::Fonts,
+            _ => (),
+        }
+        match path.rsplit('.').next().unwrap_or("") {
+            "nif" | "kf" | "egm" | "tri" => Self::Meshes,
+            "dds" | "tga" => Self::Textures,
+            "swf" => Self::Menus,
+            "wav" | "xwm" | "mp3" => Self::Sounds,
+            "fuz" | "lip" => Self::Voices,
+            "fxp" => Self::Shaders,
+            "spt" => Self::Trees,
+            "fnt" => Self::Fonts,
+            _ => Self::Miscellaneous,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Meshes => "meshes",
+            Self::Textures => "textures",
+            Self::Menus => "menus",
+            Self::Sounds => "sounds",
+            Self::Voices => "voices",
+            Self::Shaders => "shaders",
+            Self::Trees => "trees",
+            Self::Fonts => "fonts",
+            Self::Miscellaneous => "misc",
+        }
+    }
+}
+
+impl fmt::Display for FileType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl str::FromStr for FileType {
+    type Err = ParseFileTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "miscellaneous" => Ok(Self::Miscellaneous),
+            s => Self::ALL
+                .iter()
+                .copied()
+                .find(|file_type| file_type.name() == s)
+                .ok_or_else(|| ParseFileTypeError(s.to_string())),
+        }
+    }
+}
+
+/// Represents an error when parsing a [`FileType`]
+#[derive(Debug, Clone)]
+pub struct ParseFileTypeError(String);
+
+impl fmt::Display for ParseFileTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown file type {:?} (expected one of ", self.0)?;
+        for (idx, file_type) in FileType::ALL.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", file_type)?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl error::Error for ParseFileTypeError {}
+
+/// Represents a file inside a BSA.
+///
+/// Files are compared, hashed and ordered by their folder and file name
+/// hashes, so two entries for the same path are equal even if they are in
+/// different archives.
 #[derive(Clone)]
 pub struct File {
+    folder_hash: u64,
+    name_hash: u64,
     name: Option<String>,
+    raw_name: Option<Vec<u8>>,
+    record_offset: u64,
+    // where the stored contents actually start, which differs from the
+    // recorded offset if `OffsetMismatch::TrustStream` applied
+    start: u64,
     offset: u64,
     size: u64,
     compressed: bool,
     uncompressed_size: u64,
     version: Version,
+    zstd: bool,
 }
 
-fn serialize_bstring(s: &str, zero: bool, vec: &mut Vec<u8>) -> Result<(), WriteError> {
+pub(crate) fn serialize_bstring(s: &str, zero: bool, vec: &mut Vec<u8>) -> Result<(), WriteError> {
     let mut encoded_str = vec![];
     for ch in s.chars() {
         match cp1252::encode_char(ch) {
@@ -383,90 +827,142 @@ fn read_u64(
     }
 }
 
-fn deserialize_bstring(bytes: &mut impl io::Read, zero: bool) -> Result<String, ReadError> {
-    let length_byte = read_u8(bytes)?;
-    let name_length = usize::from(length_byte) - if zero { 1 } else { 0 };
-    let mut encoded_filename = vec![0; name_length];
-    bytes.read_exact(&mut encoded_filename)?;
-    let mut decoded_name = String::new();
-    for byte in encoded_filename {
-        decoded_name.push(cp1252::decode_byte(byte));
+/// The number of records that are read at once by [`read_record_chunks`]
+const RECORD_CHUNK: usize = 1024;
+
+/// Reads `count` records of `record_len` bytes each, in chunks of at most
+/// [`RECORD_CHUNK`] records. The counts come from the archive and can't be
+/// trusted, so nothing is allocated for records that haven't been read yet.
+fn read_record_chunks(
+    reader: &mut impl io::Read,
+    count: u32,
+    record_len: usize,
+    mut parse: impl FnMut(&mut &[u8]) -> Result<(), ReadError>,
+) -> Result<(), ReadError> {
+    let mut buf = vec![];
+    let mut remaining = count as usize;
+    while remaining > 0 {
+        let chunk = remaining.min(RECORD_CHUNK);
+        buf.resize(chunk * record_len, 0);
+        reader.read_exact(&mut buf)?;
+        for record in buf.chunks_exact(record_len) {
+            parse(&mut &record[..])?;
+        }
+        remaining -= chunk;
     }
+    Ok(())
+}
+
+fn deserialize_bstring(bytes: &mut impl io::Read, zero: bool) -> Result<Vec<u8>, ReadError> {
+    let length_byte = read_u8(bytes)?;
+    let name_length = usize::from(length_byte)
+        .checked_sub(if zero { 1 } else { 0 })
+        .ok_or_else(|| ReadError::CorruptRecord("zero-length name".to_string()))?;
+    let mut encoded_name = vec![0; name_length];
+    bytes.read_exact(&mut encoded_name)?;
     if zero {
         let null_byte = read_u8(bytes)?;
         if null_byte != 0 {
             return Err(ReadError::ExpectedNullByte);
         }
     }
-    Ok(decoded_name)
+    Ok(encoded_name)
 }
 
-fn deserialize_null_terminated_string(bytes: &mut impl io::Read) -> Result<String, ReadError> {
-    let mut encoded_filename = vec![];
+fn deserialize_null_terminated_string(bytes: &mut impl io::Read) -> Result<Vec<u8>, ReadError> {
+    let mut encoded_name = vec![];
     loop {
         let byte = read_u8(bytes)?;
         if byte == 0 {
             break;
         }
-        encoded_filename.push(byte);
+        encoded_name.push(byte);
     }
-    let mut decoded_name = String::new();
-    for byte in encoded_filename {
-        decoded_name.push(cp1252::decode_byte(byte));
-    }
-    Ok(decoded_name)
+    Ok(encoded_name)
 }
 
 impl File {
-    // fn serialize(&self, archive_flags: ArchiveFlags, compress: bool) -> Result<io::Chain<&[u8], &mut R>, WriteError> {
-    //     if compress {
-    //         return Err(WriteError::CompressionUnsupported)
-    //     }
-    //     let mut res = vec![];
-    //     if archive_flags.embed_file_names {
-    //         if let Some(name) = &self.name {
-    //             serialize_bstring(&name, false, &mut res)?;
-    //         } else {
-    //             return Err(WriteError::MissingFileName);
-    //         }
-    //     }
-    //     Ok(res.chain(&mut self.data))
-    // }
-
     fn deserialize(
         archive_flags: ArchiveFlags,
-        compressed: bool,
-        offset: u64,
-        size: u64,
+        file_record: &FileRecord,
         data: &mut (impl io::Read + io::Seek),
-        version: Version,
+        game: Game,
+        offset_mismatch: OffsetMismatch,
+        alignment: u64,
     ) -> Result<File, ReadError> {
-        trace!(
+        let compressed = archive_flags.compressed_archive != file_record.override_compressed;
+        let offset = u64::from(file_record.offset);
+        let size = u64::from(file_record.size);
+        event!(
+            trace,
+            { offset = offset, size = size, compressed = compressed },
             "Deserialising file at offset {}, size {}, compressed {}",
             offset,
             size,
             compressed
         );
         let actual_pos = data.stream_position()?;
-        if actual_pos != offset {
-            warn!(
-                "expected file to be at offset {}, actually at {}",
-                actual_pos, offset
+        // in aligned archives, files are padded to the next aligned offset
+        let is_padding = actual_pos < offset
+            && alignment > 1
+            && offset.is_multiple_of(alignment)
+            && offset - actual_pos < alignment;
+        let mut start = offset;
+        if is_padding {
+            event!(
+                trace,
+                { offset = actual_pos, padding = offset - actual_pos },
+                "skipping {} bytes of padding",
+                offset - actual_pos
             );
             data.seek(io::SeekFrom::Start(offset))?;
+        } else if actual_pos != offset {
+            event!(
+                warn,
+                { offset = offset, actual_offset = actual_pos },
+                "expected file to be at offset {}, actually at {}",
+                offset, actual_pos
+            );
+            match offset_mismatch {
+                OffsetMismatch::TrustRecords => {
+                    data.seek(io::SeekFrom::Start(offset))?;
+                }
+                OffsetMismatch::TrustStream => start = actual_pos,
+                OffsetMismatch::Error => {
+                    return Err(ReadError::UnexpectedFileOffset(offset, actual_pos));
+                }
+            }
         }
         let name = None;
-        let name_offset = if archive_flags.embed_file_names && version != Version::OBLIVION {
+        let name_offset = if archive_flags.embed_file_names && game.embeds_file_names() {
             let length_byte = read_u8(data)?;
-            data.seek(io::SeekFrom::Current(i64::from(length_byte)))?;
-            u64::from(length_byte + 1)
+            let name_offset = u64::from(length_byte) + 1;
+            if name_offset > size {
+                return Err(ReadError::EmbeddedNameTooLong(offset));
+            }
+            // read the name instead of seeking past it, so that a truncated
+            // file is detected here
+            let mut embedded_name = vec![0; usize::from(length_byte)];
+            data.read_exact(&mut embedded_name)?;
+            name_offset
         } else {
             0
         };
-        let data_size = (if compressed { size - 4 } else { size }) - name_offset;
+        // sizes come straight from the file, so they can't be trusted
+        let data_size = size
+            .checked_sub(if compressed { 4 } else { 0 })
+            .and_then(|size| size.checked_sub(name_offset))
+            .ok_or_else(|| {
+                ReadError::CorruptRecord(format!(
+                    "file at offset {} is too small ({} bytes) for its headers",
+                    offset, size
+                ))
+            })?;
         let uncompressed_size = if compressed {
             let original_size = read_u32(data, Some(archive_flags))?;
-            info!(
+            event!(
+                info,
+                { size = data_size, uncompressed_size = original_size },
                 "compressed size {}, uncompressed size {}",
                 data_size, original_size
             );
@@ -475,15 +971,27 @@ impl File {
             data_size
         };
         let data_offset = data.stream_position()?;
-        info!("data_offset {}, original offset {}", data_offset, offset);
-        data.seek(io::SeekFrom::Current(data_size as i64))?;
+        event!(
+            info,
+            { data_offset = data_offset, offset = offset },
+            "data_offset {}, original offset {}",
+            data_offset,
+            offset
+        );
+        data.seek(io::SeekFrom::Start(data_offset + data_size))?;
         Ok(File {
+            folder_hash: 0,
+            name_hash: 0,
             name,
+            raw_name: None,
+            record_offset: offset,
+            start,
             offset: data_offset,
             size: data_size,
             compressed,
             uncompressed_size,
-            version,
+            version: game.version(),
+            zstd: archive_flags.zstd_codec,
         })
     }
 
@@ -496,8 +1004,205 @@ impl File {
         }
     }
 
+    /// Returns the file name exactly as it is stored in the BSA, before being
+    /// decoded
+    pub fn name_raw(&self) -> Option<&[u8]> {
+        self.raw_name.as_deref()
+    }
+
+    /// Returns the hash of the file name, as stored in the BSA
+    pub fn name_hash(&self) -> u64 {
+        self.name_hash
+    }
+
+    /// Returns the hash of the name of the folder containing this file
+    pub fn folder_hash(&self) -> u64 {
+        self.folder_hash
+    }
+
+    /// Returns the full path of this file (e.g. `textures\armor\iron.dds`),
+    /// or `None` if the file or its folder has no name
+    pub fn full_path(&self, folder: &Folder) -> Option<EntryPath> {
+        Some(EntryPath::new(
+            folder.name_hash,
+            folder.name()?,
+            self.name_hash,
+            self.name()?,
+        ))
+    }
+
+    /// Guesses the type of this file from its extension and the first few
+    /// bytes of its contents
+    pub fn kind(&self, bsa: &mut Bsa) -> Result<FileKind, ReadError> {
+        let mut buf = vec![];
+        io::Read::read_to_end(&mut io::Read::take(self.read_contents(bsa)?, 64), &mut buf)?;
+        Ok(FileKind::detect(self.name().unwrap_or(""), &buf))
+    }
+
+    /// Returns whether the contents of this file are compressed
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    /// Reads the contents of this file as they are stored in the BSA, without
+    /// decompressing them. For compressed files, the stored uncompressed size
+    /// is not included.
+    pub fn read_stored(&self, bsa: &mut Bsa) -> Result<Vec<u8>, ReadError> {
+        let mut res = vec![];
+        self.read_stored_into(bsa, &mut res)?;
+        Ok(res)
+    }
+
+    /// Like [`File::read_stored`], but reads into `buf`, reusing its
+    /// allocation
+    pub(crate) fn read_stored_into(
+        &self,
+        bsa: &mut Bsa,
+        buf: &mut Vec<u8>,
+    ) -> Result<(), ReadError> {
+        buf.clear();
+        let reader = &mut bsa.reader;
+        reader.seek(io::SeekFrom::Start(self.offset))?;
+        io::Read::read_to_end(&mut io::Read::take(reader, self.size), buf)?;
+        Ok(())
+    }
+
+    /// Reads the contents of this file as they are stored in the BSA, together
+    /// with the codec they are compressed with. This allows copying files
+    /// between archives without decompressing and recompressing them.
+    pub fn read_raw(&self, bsa: &mut Bsa) -> Result<RawFile, ReadError> {
+        let data = self.read_stored(bsa)?;
+        Ok(RawFile {
+            codec: self.stored_codec(&data)?,
+            data,
+            uncompressed_size: self.uncompressed_size,
+        })
+    }
+
+    /// Returns the codec of the given stored contents of this file, according
+    /// to the compression bits
+    pub(crate) fn stored_codec(&self, data: &[u8]) -> Result<Codec, ReadError> {
+        if !self.compressed {
+            Ok(Codec::None)
+        } else if self.zstd {
+            Ok(Codec::Zstd)
+        } else if self.version == Version::SKYRIM_SPECIAL_EDITION {
+            Ok(Codec::detect_lz4(data))
+        } else {
+            Codec::for_version(self.version).ok_or(ReadError::UnknownCompressionAlgorithm)
+        }
+    }
+
+    /// Returns the codec of the contents of this file, according to the
+    /// compression bits. Only the first bytes of LZ4 compressed contents are
+    /// read, to tell frames and blocks apart.
+    fn codec(&self, bsa: &mut Bsa) -> Result<Codec, ReadError> {
+        if !self.compressed || self.zstd || self.version != Version::SKYRIM_SPECIAL_EDITION {
+            return self.stored_codec(&[]);
+        }
+        let reader = &mut bsa.reader;
+        reader.seek(io::SeekFrom::Start(self.offset))?;
+        let mut magic = vec![];
+        io::Read::read_to_end(&mut io::Read::take(&mut *reader, 4), &mut magic)?;
+        reader.seek(io::SeekFrom::Start(self.offset))?;
+        Ok(Codec::detect_lz4(&magic))
+    }
+
+    /// Reads the contents of this file like [`File::read_to_vec`], but if a
+    /// compressed file fails to decompress, also tries the other codecs and
+    /// finally reading it as uncompressed, since mislabeled compression bits
+    /// are a known kind of corruption. Contents that decompress to a different
+    /// size than expected count as failing too. Other codecs are only accepted
+    /// if they decompress to the expected size, and uncompressed contents only
+    /// if their magic bytes match the file extension (see [`FileKind`]).
+    /// Returns the original error if the file can't be recovered.
+    pub fn recover(&self, bsa: &mut Bsa) -> Result<Recovered, ReadError> {
+        let res = self.read_to_vec(bsa).and_then(|data| {
+            if data.len() as u64 == self.uncompressed_size {
+                Ok(data)
+            } else {
+                Err(ReadError::ReaderError(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "read {} bytes instead of {}",
+                        data.len(),
+                        self.uncompressed_size
+                    ),
+                )))
+            }
+        });
+        let err = match res {
+            Ok(data) => {
+                return Ok(Recovered {
+                    codec: self.codec(bsa)?,
+                    data,
+                    mislabeled: false,
+                })
+            }
+            Err(err) if self.compressed => err,
+            Err(err) => return Err(err),
+        };
+        event!(
+            warn,
+            { name = self.name(), offset = self.offset, error = %err },
+            "failed to decompress {:?}: {}",
+            self.name(),
+            err
+        );
+        let stored = self.read_stored(bsa)?;
+        let labeled = self.stored_codec(&stored)?;
+        for &codec in &[Codec::Zlib, Codec::Lz4, Codec::Lz4Block, Codec::Zstd] {
+            if codec == labeled {
+                continue;
+            }
+            if let Some(data) = reader::try_decode(codec, &stored, self.uncompressed_size) {
+                return Ok(Recovered {
+                    data,
+                    codec,
+                    mislabeled: true,
+                });
+            }
+        }
+        // the uncompressed size is really the start of the contents
+        let size = match <u32 as convert::TryFrom<u64>>::try_from(self.uncompressed_size) {
+            Ok(size) => size,
+            Err(_) => return Err(err),
+        };
+        let mut data = if bsa.archive_flags().xbox360_archive {
+            size.to_be_bytes().to_vec()
+        } else {
+            size.to_le_bytes().to_vec()
+        };
+        data.extend(stored);
+        // any bytes can be read as uncompressed, so only accept them if they
+        // look like the kind of file their name says they are
+        let expected = FileKind::from_name(self.name().unwrap_or_default());
+        if expected == FileKind::Unknown || FileKind::from_magic(&data) != expected {
+            return Err(err);
+        }
+        Ok(Recovered {
+            data,
+            codec: Codec::None,
+            mislabeled: true,
+        })
+    }
+
+    /// Returns the size of the file contents after decompression
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+
+    /// Returns the size of the file contents as they are stored in the BSA,
+    /// excluding any embedded name or stored uncompressed size
+    pub fn stored_size(&self) -> u64 {
+        self.size
+    }
+
     /// Returns a reader for the contents of this BSA file.
-    pub fn read_contents<'a>(&self, bsa: &'a mut Bsa) -> Result<Box<dyn io::Read + 'a>, ReadError> {
+    pub fn read_contents<'a>(&self, bsa: &'a mut Bsa) -> Result<FileReader<'a>, ReadError> {
+        let decompression_buffer_size = bsa.header.decompression_buffer_size;
+        #[cfg(feature = "lz4")]
+        let lz4_block = self.compressed && self.codec(bsa)? == Codec::Lz4Block;
         let reader = &mut bsa.reader;
         reader.seek(io::SeekFrom::Start(self.offset))?;
         info!(
@@ -505,50 +1210,409 @@ impl File {
             reader.stream_position()?,
             self.size
         );
-        let file_reader = io::Read::take(reader, self.size);
-        Ok(if self.compressed {
-            if self.version == Version::SKYRIM_SPECIAL_EDITION {
-                Box::new(lz4::Decoder::new(file_reader)?)
+        let file_reader = Limited::new(reader, self.size);
+        let inner: Box<dyn io::Read + 'a> = if self.compressed {
+            if self.zstd {
+                #[cfg(feature = "zstd")]
+                {
+                    Box::new(zstd::stream::read::Decoder::new(file_reader)?)
+                }
+                #[cfg(not(feature = "zstd"))]
+                {
+                    return Err(ReadError::CompressionFeatureDisabled("zstd"));
+                }
+            } else if self.version == Version::SKYRIM_SPECIAL_EDITION {
+                #[cfg(feature = "lz4")]
+                {
+                    if lz4_block {
+                        Box::new(crate::reader::Lz4BlockDecoder::new(
+                            file_reader,
+                            self.uncompressed_size,
+                        ))
+                    } else {
+                        Box::new(lz4::Decoder::new(file_reader)?)
+                    }
+                }
+                #[cfg(not(feature = "lz4"))]
+                {
+                    return Err(ReadError::CompressionFeatureDisabled("lz4"));
+                }
             } else if self.version == Version::SKYRIM || self.version == Version::OBLIVION {
-                Box::new(flate2::read::ZlibDecoder::new(file_reader))
+                #[cfg(feature = "zlib")]
+                {
+                    Box::new(flate2::read::ZlibDecoder::new(file_reader))
+                }
+                #[cfg(not(feature = "zlib"))]
+                {
+                    return Err(ReadError::CompressionFeatureDisabled("zlib"));
+                }
             } else {
                 return Err(ReadError::UnknownCompressionAlgorithm);
             }
         } else {
             Box::new(file_reader)
-        })
+        };
+        let span = span!(
+            "read_file",
+            name = self.name().unwrap_or_default(),
+            offset = self.offset,
+            size = self.size,
+            uncompressed_size = self.uncompressed_size,
+            compressed = self.compressed
+        );
+        Ok(FileReader::new(
+            profile::instrument(inner, span),
+            self.uncompressed_size,
+            decompression_buffer_size,
+            self.compressed,
+        ))
+    }
+
+    /// Writes the decompressed contents of this file to `writer`, returning the
+    /// number of bytes written.
+    ///
+    /// For uncompressed files in a BSA opened from disk, this copies straight
+    /// from the archive file, so that `io::copy` can use `copy_file_range` or
+    /// `sendfile` when writing to a file, pipe or socket on Linux.
+    pub fn send_to<W: io::Write + ?Sized>(
+        &self,
+        bsa: &mut Bsa,
+        writer: &mut W,
+    ) -> Result<u64, ReadError> {
+        match &bsa.file {
+            Some(file) if !self.compressed => {
+                let mut file: &fs::File = file;
+                io::Seek::seek(&mut file, io::SeekFrom::Start(self.offset))?;
+                Ok(io::copy(&mut io::Read::take(file, self.size), writer)?)
+            }
+            _ => Ok(io::copy(&mut self.read_contents(bsa)?, writer)?),
+        }
+    }
+
+    /// Decompresses this file and checks that its contents are intact and
+    /// match the stored uncompressed size. Errors from the underlying reader
+    /// are returned as `Err`, while problems with the file itself are
+    /// reported in the returned `FileVerification`.
+    pub fn verify(&self, bsa: &mut Bsa) -> Result<FileVerification, ReadError> {
+        let mut reader = self.read_contents(bsa)?;
+        let actual = match io::copy(&mut reader, &mut io::sink()) {
+            Ok(actual) => actual,
+            Err(e) if self.compressed => return Ok(FileVerification::CorruptData(e.to_string())),
+            Err(e) => return Err(e.into()),
+        };
+        if actual != self.uncompressed_size {
+            return Ok(FileVerification::SizeMismatch {
+                expected: self.uncompressed_size,
+                actual,
+            });
+        }
+        Ok(FileVerification::Ok)
+    }
+
+    /// Returns a reader for the decompressed contents of this file that
+    /// verifies their checksum while streaming, without a separate pass. The
+    /// read that reaches the end of the file fails if the checksum doesn't
+    /// match, see [`VerifiedReader`].
+    pub fn read_verified<'a>(
+        &self,
+        bsa: &'a mut Bsa,
+        expected: Checksum,
+    ) -> Result<VerifiedReader<'a>, ReadError> {
+        Ok(VerifiedReader::new(self.read_contents(bsa)?, expected))
+    }
+
+    /// Computes the SHA-256 checksum of the decompressed contents of this file.
+    pub fn checksum(&self, bsa: &mut Bsa) -> Result<Checksum, ReadError> {
+        Ok(Checksum::from_reader(&mut self.read_contents(bsa)?)?)
     }
 
     /// Reads the contents of this BSA file, and returns the result as a `Vec<u8>`.
     pub fn read_to_vec(&self, bsa: &mut Bsa) -> Result<Vec<u8>, ReadError> {
-        let mut reader = self.read_contents(bsa)?;
         let mut res = vec![];
-        reader.read_to_end(&mut res)?;
+        bsa.read_into(self, &mut res)?;
         Ok(res)
     }
-}
 
-/// Represents a folder inside a BSA file
-#[derive(Debug, Clone)]
-pub struct Folder {
-    name: Option<String>,
-    files: Vec<File>,
-}
+    /// Reads the contents of this BSA file like `read_to_vec`, but returns
+    /// `ReadError::FileTooLarge` instead if the file is larger than `max` bytes.
+    pub fn read_to_vec_limited(&self, bsa: &mut Bsa, max: u64) -> Result<Vec<u8>, ReadError> {
+        if self.uncompressed_size > max {
+            return Err(ReadError::FileTooLarge(max));
+        }
+        // don't trust the stored size, in case the compressed data is larger
+        let mut reader = io::Read::take(self.read_contents(bsa)?, max.saturating_add(1));
+        let mut res = vec![];
+        io::Read::read_to_end(&mut reader, &mut res)?;
+        if res.len() as u64 > max {
+            return Err(ReadError::FileTooLarge(max));
+        }
+        Ok(res)
+    }
 
-impl Folder {
-    /// Returns a list of files in this BSA folder
-    pub fn files(&self) -> impl Iterator<Item = &File> {
-        self.files.iter()
+    /// Reads the contents of this BSA file, decoding it as Windows-1252 text
+    /// (the encoding used by most text files in Bethesda games).
+    pub fn read_to_string_cp1252(&self, bsa: &mut Bsa) -> Result<String, ReadError> {
+        Ok(self
+            .read_to_vec(bsa)?
+            .into_iter()
+            .map(cp1252::decode_byte)
+            .collect())
     }
 
-    /// Returns the file name
-    pub fn name(&self) -> Option<&str> {
-        if let Some(name) = &self.name {
-            Some(name.as_str())
+    /// Reads the contents of this BSA file, decoding it as UTF-8 text. Returns
+    /// `ReadError::InvalidUtf8` if the file is not valid UTF-8.
+    pub fn read_to_string_utf8(&self, bsa: &mut Bsa) -> Result<String, ReadError> {
+        String::from_utf8(self.read_to_vec(bsa)?).map_err(ReadError::InvalidUtf8)
+    }
+}
+
+/// How the contents of a file are compressed
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Codec {
+    /// The contents are stored uncompressed
+    None,
+    /// A zlib stream, used by Oblivion, Fallout 3, Fallout: New Vegas and Skyrim
+    Zlib,
+    /// An LZ4 frame, used by Skyrim Special Edition
+    Lz4,
+    /// An LZ4 block without frame headers, written by some third-party tools
+    /// for Skyrim Special Edition
+    Lz4Block,
+    /// A zstd frame, only used by archives written with
+    /// [`BsaBuilder::zstd`](crate::BsaBuilder::zstd)
+    Zstd,
+}
+
+/// The magic number at the start of every LZ4 frame
+pub(crate) const LZ4_FRAME_MAGIC: [u8; 4] = [0x04, 0x22, 0x4d, 0x18];
+
+impl Codec {
+    /// Returns the codec compressed files use in BSAs with the given version
+    pub(crate) fn for_version(version: Version) -> Option<Self> {
+        match version {
+            Version::SKYRIM_SPECIAL_EDITION => Some(Self::Lz4),
+            Version::SKYRIM | Version::OBLIVION => Some(Self::Zlib),
+            _ => None,
+        }
+    }
+
+    /// Returns whether compressed files in BSAs with the given version can
+    /// use this codec
+    pub(crate) fn is_supported_by(self, version: Version) -> bool {
+        match self {
+            Self::None => true,
+            Self::Lz4 | Self::Lz4Block => version == Version::SKYRIM_SPECIAL_EDITION,
+            Self::Zlib => version == Version::SKYRIM || version == Version::OBLIVION,
+            Self::Zstd => false,
+        }
+    }
+
+    /// Tells LZ4 frames and blocks apart, given at least the first 4 bytes of
+    /// the compressed data
+    pub(crate) fn detect_lz4(data: &[u8]) -> Self {
+        if data.starts_with(&LZ4_FRAME_MAGIC) {
+            Self::Lz4
+        } else {
+            Self::Lz4Block
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::None => write!(f, "no compression"),
+            Self::Zlib => write!(f, "zlib"),
+            Self::Lz4 => write!(f, "LZ4"),
+            Self::Lz4Block => write!(f, "LZ4 block"),
+            Self::Zstd => write!(f, "zstd"),
+        }
+    }
+}
+
+/// The stored contents of a file, returned by [`File::read_raw`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawFile {
+    data: Vec<u8>,
+    codec: Codec,
+    uncompressed_size: u64,
+}
+
+impl RawFile {
+    /// Returns the stored bytes, excluding any embedded name or stored
+    /// uncompressed size
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the stored bytes, consuming `self`
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Returns the codec the data is compressed with
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Returns the size of the contents after decompression
+    pub fn uncompressed_size(&self) -> u64 {
+        self.uncompressed_size
+    }
+}
+
+/// The contents of a file read with [`File::recover`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recovered {
+    data: Vec<u8>,
+    codec: Codec,
+    mislabeled: bool,
+}
+
+impl Recovered {
+    /// Returns the decompressed contents
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Returns the decompressed contents, consuming `self`
+    pub fn into_data(self) -> Vec<u8> {
+        self.data
+    }
+
+    /// Returns the codec the contents were decoded with
+    pub fn codec(&self) -> Codec {
+        self.codec
+    }
+
+    /// Returns whether the contents could only be read by ignoring the
+    /// compression bits of the file
+    pub fn is_mislabeled(&self) -> bool {
+        self.mislabeled
+    }
+}
+
+/// The result of [`File::verify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileVerification {
+    /// The file contents are intact
+    Ok,
+    /// The decompressed size of the file differs from the size stored in the BSA
+    SizeMismatch { expected: u64, actual: u64 },
+    /// The compressed data could not be decompressed
+    CorruptData(String),
+}
+
+impl fmt::Display for FileVerification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ok => write!(f, "OK"),
+            Self::SizeMismatch { expected, actual } => {
+                write!(f, "expected {} bytes, found {}", expected, actual)
+            }
+            Self::CorruptData(e) => write!(f, "corrupt data: {}", e),
+        }
+    }
+}
+
+impl PartialEq for File {
+    fn eq(&self, other: &Self) -> bool {
+        (self.folder_hash, self.name_hash) == (other.folder_hash, other.name_hash)
+    }
+}
+
+impl Eq for File {}
+
+impl std::hash::Hash for File {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        (self.folder_hash, self.name_hash).hash(state);
+    }
+}
+
+impl PartialOrd for File {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for File {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        (self.folder_hash, self.name_hash).cmp(&(other.folder_hash, other.name_hash))
+    }
+}
+
+/// Represents a folder inside a BSA file. Folders are compared, hashed and
+/// ordered by their name hashes.
+#[derive(Debug, Clone)]
+pub struct Folder {
+    name_hash: u64,
+    name: Option<String>,
+    raw_name: Option<Vec<u8>>,
+    files: Vec<File>,
+}
+
+impl Folder {
+    /// Returns a list of files in this BSA folder
+    pub fn files(&self) -> impl Iterator<Item = &File> {
+        self.files.iter()
+    }
+
+    /// Returns whether this folder contains no files
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty()
+    }
+
+    /// Returns the files in this BSA folder as a slice, sorted by name hash
+    pub fn files_slice(&self) -> &[File] {
+        &self.files
+    }
+
+    /// Returns the file name
+    pub fn name(&self) -> Option<&str> {
+        if let Some(name) = &self.name {
+            Some(name.as_str())
         } else {
             None
         }
     }
+
+    /// Returns the folder name exactly as it is stored in the BSA, before
+    /// being decoded
+    pub fn name_raw(&self) -> Option<&[u8]> {
+        self.raw_name.as_deref()
+    }
+
+    /// Returns the hash of the folder name, as stored in the BSA
+    pub fn name_hash(&self) -> u64 {
+        self.name_hash
+    }
+}
+
+impl PartialEq for Folder {
+    fn eq(&self, other: &Self) -> bool {
+        self.name_hash == other.name_hash
+    }
+}
+
+impl Eq for Folder {}
+
+impl std::hash::Hash for Folder {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.name_hash.hash(state);
+    }
+}
+
+impl PartialOrd for Folder {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Folder {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.name_hash.cmp(&other.name_hash)
+    }
 }
 
 impl fmt::Debug for File {
@@ -567,16 +1631,36 @@ struct BsaHeader {
     archive_flags: ArchiveFlags,
     folder_count: u32,
     file_count: u32,
-    total_folder_name_length: u32,
-    total_file_name_length: u32,
     file_flags: FileFlags,
+    game: Game,
     folders: Vec<Folder>,
+    diagnostics: Vec<Diagnostic>,
+    // the regions before the file data
+    record_regions: Vec<Region>,
+    decompression_buffer_size: usize,
 }
 
-/// Represents a BSA file
+/// Represents a BSA file.
+///
+/// A `Bsa` is both `Send` and `Sync`, so it can be moved between threads or
+/// shared behind an `Arc<Mutex<_>>`.
 pub struct Bsa {
-    header: BsaHeader,
-    reader: Box<dyn ReadSeek>,
+    header: Arc<BsaHeader>,
+    reader: Box<dyn ReadSeek + Send + Sync>,
+    // a handle to the same file as `reader`, if opened from disk
+    file: Option<fs::File>,
+    source: Option<Arc<Source>>,
+}
+
+/// Where a BSA file was opened from, so that it can be reopened later
+#[derive(Debug)]
+struct Source {
+    path: path::PathBuf,
+    options: OpenOptions,
+    len: u64,
+    modified: Option<std::time::SystemTime>,
+    #[cfg(feature = "meta")]
+    meta: Option<crate::meta::ArchiveMeta>,
 }
 
 impl fmt::Debug for Bsa {
@@ -585,13 +1669,11 @@ impl fmt::Debug for Bsa {
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-struct Hash(u64);
-
 #[derive(Debug, Clone)]
 struct FolderRecord {
     name_hash: u64,
     name: Option<String>,
+    raw_name: Option<Vec<u8>>,
     file_count: u32,
     offset: u64,
     file_records: Vec<FileRecord>,
@@ -604,49 +1686,383 @@ struct FileRecord {
     override_compressed: bool,
     offset: u32,
     name: Option<String>,
+    raw_name: Option<Vec<u8>>,
+}
+
+/// Returns the largest power of two (up to 1 MiB) that all of the given file
+/// offsets are a multiple of, or 1 if there are none
+fn offsets_alignment(offsets: impl Iterator<Item = u64>) -> u64 {
+    let mut alignment = 1 << 20;
+    let mut empty = true;
+    for offset in offsets {
+        empty = false;
+        while alignment > 1 && offset % alignment != 0 {
+            alignment /= 2;
+        }
+    }
+    if empty {
+        1
+    } else {
+        alignment
+    }
 }
 
-/// Opens the specified BSA file from a reader
-pub fn read<R: io::Read + io::Seek + 'static>(mut data: R) -> Result<Bsa, ReadError> {
-    let header = Bsa::read_header(&mut data)?;
-    Ok(Bsa {
-        header,
-        reader: Box::new(data),
-    })
+/// Returns whether the path of a folder, followed by a backslash, starts with
+/// the given lowercase prefix. Case and the kind of slashes are ignored.
+fn folder_starts_with(name: &str, prefix: &str) -> bool {
+    prefix.len() <= name.len() + 1
+        && name
+            .as_bytes()
+            .iter()
+            .chain(b"\\")
+            .zip(prefix.as_bytes())
+            .all(|(&a, &b)| if a == b'/' { b'\\' } else { a.to_ascii_lowercase() } == b)
 }
 
-/// Opens the specified BSA file.
+fn verify_file_hash(raw_name: &[u8], name_hash: u64, name: &str) -> Result<(), ReadError> {
+    let computed_hash = hash::compute_hash_bytes(raw_name, hash::Type::File);
+    if computed_hash != name_hash {
+        event!(
+            error,
+            { hash = name_hash, computed_hash = computed_hash, name = name },
+            "Incorrect hash: calculated {:016x} instead of {:016x} for '{}'",
+            computed_hash,
+            name_hash,
+            name
+        );
+        return Err(ReadError::IncorrectHash(IncorrectHashError {
+            actual_hash: name_hash,
+            expected_hash: computed_hash,
+            name: name.to_string(),
+        }));
+    }
+    event!(
+        trace,
+        { hash = computed_hash, name = name },
+        "Matching hash: {:016x} for '{}'",
+        computed_hash,
+        name
+    );
+    Ok(())
+}
+
+/// Specifies how control characters (including null bytes) in file and folder
+/// names are handled. This only affects the decoded names: the raw name bytes
+/// are always preserved.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ControlCharacters {
+    /// Keep control characters as they are
+    Keep,
+    /// Replace each control character with an underscore
+    Replace,
+    /// Remove control characters
+    Remove,
+}
+
+impl ControlCharacters {
+    fn sanitize(self, name: &str) -> String {
+        match self {
+            Self::Keep => name.to_string(),
+            Self::Replace => name
+                .chars()
+                .map(|ch| if ch.is_control() { '_' } else { ch })
+                .collect(),
+            Self::Remove => name.chars().filter(|ch| !ch.is_control()).collect(),
+        }
+    }
+}
+
+/// Specifies what happens when the offset in a file record doesn't match the
+/// end of the previous file. In archives whose files are all aligned (see
+/// [`Bsa::data_alignment`]), gaps shorter than the alignment are treated as
+/// padding instead.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OffsetMismatch {
+    /// Log a warning and read the file from the offset in its record
+    TrustRecords,
+    /// Log a warning and read the file from the end of the previous file
+    TrustStream,
+    /// Fail with `ReadError::UnexpectedFileOffset`
+    Error,
+}
+
+/// Specifies which name hashes are checked when opening a BSA file
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HashVerification {
+    /// Don't verify any hashes
+    None,
+    /// Only verify folder name hashes
+    FoldersOnly,
+    /// Verify both folder and file name hashes
+    All,
+}
+
+/// The default size of the buffer of a [`FileReader`], same as `io::BufReader`
+const DEFAULT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Options for opening BSA files, similar to `std::fs::OpenOptions`.
 ///
 /// ```no_run
 /// use std::error::Error;
 ///
 /// fn main() -> Result<(), Box<dyn Error>> {
-///     let mut bsa = bsa::open("file.bsa")?;
-///     for folder in bsa.folders() {
-///         for file in folder.files() {
-///             println!("File {:?} in folder {:?}", file.name(), folder.name());
-///             let contents = file.read_to_vec(&mut bsa)?;
-///             println!("{:?}", &contents);
-///         }
-///     }
+///     let bsa = bsa::OpenOptions::new()
+///         .verify_hashes(bsa::HashVerification::FoldersOnly)
+///         .open("Skyrim - Voices_en0.bsa")?;
+///     println!("{} folders", bsa.folders().count());
 ///     Ok(())
 /// }
 /// ```
-pub fn open<P: AsRef<path::Path>>(path: P) -> Result<Bsa, ReadError> {
-    let file = fs::File::open(path)?;
-    let bsa = read(file)?;
-    Ok(bsa)
+#[derive(Debug, Clone)]
+pub struct OpenOptions {
+    verify_hashes: HashVerification,
+    name_encoding: Encoding,
+    control_characters: ControlCharacters,
+    offset_mismatch: OffsetMismatch,
+    game: Option<Game>,
+    max_entries: Option<u64>,
+    io_buffer_size: Option<usize>,
+    decompression_buffer_size: usize,
+    #[cfg(feature = "rayon")]
+    parallel_hashes: bool,
 }
 
-impl Bsa {
-    /// Returns a list of folders in this BSA
-    pub fn folders(&self) -> impl Iterator<Item = Folder> {
-        self.header.folders.clone().into_iter()
+impl Default for OpenOptions {
+    fn default() -> Self {
+        Self {
+            verify_hashes: HashVerification::All,
+            name_encoding: Encoding::Windows1252,
+            control_characters: ControlCharacters::Keep,
+            offset_mismatch: OffsetMismatch::TrustRecords,
+            game: None,
+            max_entries: None,
+            io_buffer_size: None,
+            decompression_buffer_size: DEFAULT_BUFFER_SIZE,
+            #[cfg(feature = "rayon")]
+            parallel_hashes: false,
+        }
     }
+}
 
-    fn read_header(
-        data: &mut (impl io::Read + io::Seek + 'static),
-    ) -> Result<BsaHeader, ReadError> {
+impl OpenOptions {
+    /// Creates a new set of options with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets which name hashes should be verified (defaults to `HashVerification::All`)
+    pub fn verify_hashes(&mut self, verify_hashes: HashVerification) -> &mut Self {
+        self.verify_hashes = verify_hashes;
+        self
+    }
+
+    /// Sets the encoding used to decode file and folder names (defaults to
+    /// `Encoding::Windows1252`)
+    pub fn name_encoding(&mut self, name_encoding: Encoding) -> &mut Self {
+        self.name_encoding = name_encoding;
+        self
+    }
+
+    /// Sets how control characters in names are handled (defaults to
+    /// `ControlCharacters::Keep`). Names containing control characters are
+    /// reported in [`Bsa::diagnostics`].
+    pub fn control_characters(&mut self, control_characters: ControlCharacters) -> &mut Self {
+        self.control_characters = control_characters;
+        self
+    }
+
+    /// Sets what happens when a file record's offset overlaps with the previous
+    /// file (defaults to `OffsetMismatch::TrustRecords`)
+    pub fn offset_mismatch(&mut self, offset_mismatch: OffsetMismatch) -> &mut Self {
+        self.offset_mismatch = offset_mismatch;
+        self
+    }
+
+    /// Sets the game the BSA belongs to, which must match its version. By
+    /// default, the game is guessed from the version (see [`Game`]).
+    pub fn game(&mut self, game: Game) -> &mut Self {
+        self.game = Some(game);
+        self
+    }
+
+    /// Sets the maximum number of folders and files a BSA may contain, to
+    /// limit the memory used by archives from untrusted sources. Opening a BSA
+    /// with more entries fails with `ReadError::TooManyEntries`. By default,
+    /// there is no limit.
+    pub fn max_entries(&mut self, max_entries: u64) -> &mut Self {
+        self.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Sets the size of the buffer used when reading from BSA files opened
+    /// from a path. Large buffers help on network file systems, where every
+    /// read is slow. The buffer is refilled after every seek, so buffers much
+    /// larger than the files being read waste time. By default, file reads
+    /// are unbuffered. See `benches/buffer_sizes.rs` for measuring the effect.
+    pub fn io_buffer_size(&mut self, io_buffer_size: usize) -> &mut Self {
+        self.io_buffer_size = Some(io_buffer_size);
+        self
+    }
+
+    /// Sets the size of the buffer of each [`FileReader`] (defaults to 8 KiB).
+    /// Decompressed data is only buffered when it is read through `BufRead`.
+    pub fn decompression_buffer_size(&mut self, decompression_buffer_size: usize) -> &mut Self {
+        self.decompression_buffer_size = decompression_buffer_size;
+        self
+    }
+
+    /// Wraps a file that was opened from a path in a buffer of
+    /// `io_buffer_size` bytes, if set
+    fn buffered(&self, file: fs::File) -> Box<dyn ReadSeek + Send + Sync> {
+        match self.io_buffer_size {
+            Some(size) => Box::new(io::BufReader::with_capacity(size, file)),
+            None => Box::new(file),
+        }
+    }
+
+    fn check_entries(&self, entries: u64) -> Result<(), ReadError> {
+        match self.max_entries {
+            Some(max) if entries > max => Err(ReadError::TooManyEntries(max)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Sets whether file name hashes are verified in parallel using rayon,
+    /// after the whole file name block has been read (defaults to `false`).
+    /// This speeds up opening archives with many files.
+    #[cfg(feature = "rayon")]
+    pub fn parallel_hashes(&mut self, parallel_hashes: bool) -> &mut Self {
+        self.parallel_hashes = parallel_hashes;
+        self
+    }
+
+    fn verify_file_hashes_in_parallel(&self) -> bool {
+        #[cfg(feature = "rayon")]
+        return self.parallel_hashes;
+        #[cfg(not(feature = "rayon"))]
+        false
+    }
+
+    fn decode_name(&self, raw_name: &[u8], diagnostics: &mut Vec<Diagnostic>) -> String {
+        let name = self.name_encoding.decode(raw_name);
+        if name.contains(char::is_control) {
+            event!(
+                warn,
+                { name = name.as_str() },
+                "control characters in name {:?}",
+                name
+            );
+            diagnostics.push(Diagnostic::ControlCharactersInName(name.clone()));
+            self.control_characters.sanitize(&name)
+        } else {
+            name
+        }
+    }
+
+    /// Opens the specified BSA file from a reader
+    pub fn read<R: io::Read + io::Seek + Send + Sync + 'static>(
+        &self,
+        mut data: R,
+    ) -> Result<Bsa, ReadError> {
+        let header = Bsa::read_header(&mut data, self)?;
+        Ok(Bsa {
+            header: Arc::new(header),
+            reader: Box::new(data),
+            file: None,
+            source: None,
+        })
+    }
+
+    /// Opens the BSA file stored in the `len` bytes at `offset` in a reader,
+    /// see [`read_at`]
+    pub fn read_at<R: io::Read + io::Seek + Send + Sync + 'static>(
+        &self,
+        data: R,
+        offset: u64,
+        len: u64,
+    ) -> Result<Bsa, ReadError> {
+        self.read(SubReader::new(data, offset, len)?)
+    }
+
+    /// Opens the specified BSA file. The path is retained, see
+    /// [`Bsa::path`] and [`Bsa::reopen`].
+    pub fn open<P: AsRef<path::Path>>(&self, path: P) -> Result<Bsa, ReadError> {
+        let path = path.as_ref();
+        let _span = span!("open", path = %path.display()).entered();
+        let file = fs::File::open(path)?;
+        let metadata = file.metadata()?;
+        let handle = file.try_clone().ok();
+        let mut bsa = self.read(self.buffered(file))?;
+        bsa.file = handle;
+        bsa.source = Some(Arc::new(Source {
+            path: path.to_path_buf(),
+            options: self.clone(),
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            #[cfg(feature = "meta")]
+            meta: crate::meta::ArchiveMeta::read_sidecar(path).unwrap_or_else(|e| {
+                // the sidecar is optional, so an invalid one shouldn't prevent
+                // reading the archive
+                warn!("ignoring metadata sidecar: {}", e);
+                None
+            }),
+        }));
+        Ok(bsa)
+    }
+
+    /// Opens each of the specified BSA files, returning the results in the same
+    /// order. With the `rayon` feature, the files are opened in parallel.
+    pub fn open_many<P, I>(&self, paths: I) -> Vec<Result<Bsa, ReadError>>
+    where
+        P: AsRef<path::Path> + Send,
+        I: IntoIterator<Item = P>,
+    {
+        let paths: Vec<P> = paths.into_iter().collect();
+        #[cfg(feature = "rayon")]
+        {
+            use rayon::iter::{IntoParallelIterator, ParallelIterator};
+            paths.into_par_iter().map(|path| self.open(path)).collect()
+        }
+        #[cfg(not(feature = "rayon"))]
+        {
+            paths.into_iter().map(|path| self.open(path)).collect()
+        }
+    }
+
+    /// Opens the specified BSA file `n` times (at least once), returning a pool of handles
+    /// that can be used by concurrent readers. The archive is only parsed
+    /// once.
+    pub fn open_multi<P: AsRef<path::Path>>(
+        &self,
+        path: P,
+        n: usize,
+    ) -> Result<ReaderPool, ReadError> {
+        let first = self.open(&path)?;
+        let mut handles = Vec::with_capacity(n);
+        for _ in 1..n {
+            let file = fs::File::open(&path)?;
+            let handle = file.try_clone().ok();
+            let mut bsa = first.with_reader(self.buffered(file));
+            bsa.file = handle;
+            handles.push(bsa);
+        }
+        handles.push(first);
+        Ok(ReaderPool::new(handles))
+    }
+}
+
+/// Basic information about a BSA file, as stored in its header
+#[derive(Debug, Clone, Copy)]
+pub struct ArchiveInfo {
+    version: Version,
+    archive_flags: ArchiveFlags,
+    file_flags: FileFlags,
+    folder_count: u32,
+    file_count: u32,
+}
+
+impl ArchiveInfo {
+    fn deserialize(data: &mut impl io::Read) -> Result<Self, ReadError> {
         let mut magic = [0; 4];
         data.read_exact(&mut magic)?;
         if &magic != b"BSA\0" {
@@ -654,136 +2070,903 @@ impl Bsa {
             return Err(ReadError::MissingHeader);
         }
         let version_num = read_u32(data, None)?;
-        trace!("BSA v{}", version_num);
+        event!(trace, { version = version_num }, "BSA v{}", version_num);
         let version = Version::deserialize(version_num)?;
         let offset = read_u32(data, None)?;
         if offset != 36 {
             return Err(ReadError::UnexpectedFolderRecordOffset);
         }
-        let archive_flags_u32 = read_u32(data, None)?;
-        let archive_flags = ArchiveFlags::deserialize(archive_flags_u32);
+        let archive_flags = ArchiveFlags::deserialize(read_u32(data, None)?);
         let folder_count = read_u32(data, Some(archive_flags))?;
         let file_count = read_u32(data, Some(archive_flags))?;
-        let total_folder_name_length = read_u32(data, Some(archive_flags))?;
-        let total_file_name_length = read_u32(data, Some(archive_flags))?;
-        let file_flags_u32 = read_u32(data, None)?;
-        let file_flags = FileFlags::deserialize(file_flags_u32);
-
-        let mut res = BsaHeader {
+        // total folder name length and total file name length
+        read_u32(data, Some(archive_flags))?;
+        read_u32(data, Some(archive_flags))?;
+        let file_flags = FileFlags::deserialize(read_u32(data, None)?);
+        Ok(Self {
             version,
             archive_flags,
+            file_flags,
             folder_count,
             file_count,
-            total_folder_name_length,
-            total_file_name_length,
-            file_flags,
-            folders: vec![],
-        };
+        })
+    }
 
-        // read folder records
-        let mut folder_records = vec![];
-        for _ in 0..res.folder_count {
-            let name_hash = read_u64(data, Some(res.archive_flags))?;
-            let file_count = read_u32(data, Some(res.archive_flags))?;
-            let old_file_offset = read_u32(data, Some(res.archive_flags))?;
-            let offset = match res.version {
-                Version::OBLIVION | Version::SKYRIM => u64::from(old_file_offset),
-                Version::SKYRIM_SPECIAL_EDITION => read_u64(data, Some(res.archive_flags))?,
-                _ => return Err(ReadError::FailedToReadFileOffset),
-            };
-            folder_records.push(FolderRecord {
-                name_hash,
-                file_count,
-                offset,
-                file_records: vec![],
-                name: None,
-            });
-        }
+    /// Returns the version of the BSA
+    pub fn version(&self) -> Version {
+        self.version
+    }
 
-        // read file record blocks
-        for folder_record in &mut folder_records {
-            if res.archive_flags.include_directory_names {
-                let name = deserialize_bstring(data, true)?;
-                let computed_hash = hash::compute_hash(&name, hash::Type::Directory)?;
-                if computed_hash != folder_record.name_hash {
-                    error!(
-                        "Incorrect hash: calculated {:016x} instead of {:016x} for '{}'",
-                        computed_hash, folder_record.name_hash, &name
-                    );
-                    return Err(ReadError::IncorrectHash(IncorrectHashError {
-                        actual_hash: folder_record.name_hash,
-                        expected_hash: computed_hash,
-                        name,
-                    }));
-                } else {
-                    trace!(
-                        "Matching hash: {:016x} for '{}'",
-                        folder_record.name_hash,
-                        &name
-                    );
-                }
-                folder_record.name = Some(name);
-            }
-            for _ in 0..folder_record.file_count {
-                let name_hash = read_u64(data, Some(res.archive_flags))?;
-                let size = read_u32(data, Some(res.archive_flags))?;
-                let offset = read_u32(data, Some(res.archive_flags))?;
-                folder_record.file_records.push(FileRecord {
-                    name_hash,
-                    size: size & 0x3fff_ffff,
-                    override_compressed: size & 0x4000_0000 != 0,
-                    offset,
-                    name: None,
-                });
-            }
-        }
+    /// Returns the archive flags of the BSA
+    pub fn archive_flags(&self) -> ArchiveFlags {
+        self.archive_flags
+    }
 
-        if res.archive_flags.include_file_names {
-            // read file name block
-            for folder_record in &mut folder_records {
-                for file_record in &mut folder_record.file_records {
-                    let file_name = deserialize_null_terminated_string(data)?;
-                    let computed_hash = hash::compute_hash(&file_name, hash::Type::File)?;
-                    if computed_hash != file_record.name_hash {
-                        error!(
+    /// Returns the file flags of the BSA
+    pub fn file_flags(&self) -> FileFlags {
+        self.file_flags
+    }
+
+    /// Returns the number of folders in the BSA
+    pub fn folder_count(&self) -> u32 {
+        self.folder_count
+    }
+
+    /// Returns the number of files in the BSA
+    pub fn file_count(&self) -> u32 {
+        self.file_count
+    }
+}
+
+/// Reads only the header of the specified BSA file, without parsing any of
+/// its folder or file records. This is much faster than opening the file when
+/// scanning large numbers of archives.
+///
+/// ```no_run
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let info = bsa::probe("file.bsa")?;
+///     println!("{} files", info.file_count());
+///     Ok(())
+/// }
+/// ```
+pub fn probe<P: AsRef<path::Path>>(path: P) -> Result<ArchiveInfo, ReadError> {
+    let mut header = [0; 36];
+    io::Read::read_exact(&mut fs::File::open(path)?, &mut header)?;
+    ArchiveInfo::deserialize(&mut &header[..])
+}
+
+/// Returns whether the specified BSA file contains a file with the given path
+/// (case-insensitively, using either forward slashes or backslashes). Like
+/// the game, this only compares name hashes. Only the header, the folder
+/// records and the file records up to the matching folder are read, so this is
+/// much faster than opening the file when searching many archives.
+///
+/// ```no_run
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     if bsa::probe_path("file.bsa", "textures/sky/sun.dds")? {
+///         println!("found");
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn probe_path<P: AsRef<path::Path>>(archive: P, path: &str) -> Result<bool, ReadError> {
+    let path = normalize_path(path).to_ascii_lowercase();
+    let (folder_hash, file_hash) = match path.rsplit_once('\\') {
+        Some((folder_name, file_name)) => match (
+            hash::compute_hash(folder_name, hash::Type::Directory),
+            hash::compute_hash(file_name, hash::Type::File),
+        ) {
+            (Ok(folder_hash), Ok(file_hash)) => (folder_hash, file_hash),
+            _ => return Ok(false),
+        },
+        None => return Ok(false),
+    };
+    let mut data = io::BufReader::new(fs::File::open(archive)?);
+    let info = ArchiveInfo::deserialize(&mut data)?;
+    let flags = Some(info.archive_flags);
+    let folder_record_len = match info.version {
+        Version::SKYRIM_SPECIAL_EDITION => 24,
+        _ => 16,
+    };
+    // the file record blocks are read sequentially, see `Bsa::read_header`
+    let mut skipped_counts = vec![];
+    let mut file_count = None;
+    for _ in 0..info.folder_count {
+        let name_hash = read_u64(&mut data, flags)?;
+        let count = read_u32(&mut data, flags)?;
+        data.seek_relative(folder_record_len - 12)?;
+        if name_hash == folder_hash {
+            file_count = Some(count);
+            break;
+        }
+        skipped_counts.push(count);
+    }
+    let file_count = match file_count {
+        Some(file_count) => file_count,
+        None => return Ok(false),
+    };
+    let records_end = 36 + u64::from(info.folder_count) * folder_record_len as u64;
+    io::Seek::seek(&mut data, io::SeekFrom::Start(records_end))?;
+    let skip_name = |data: &mut io::BufReader<fs::File>| -> Result<(), ReadError> {
+        if info.archive_flags.include_directory_names {
+            let len = read_u8(data)?;
+            data.seek_relative(i64::from(len))?;
+        }
+        Ok(())
+    };
+    for count in skipped_counts {
+        skip_name(&mut data)?;
+        data.seek_relative(i64::from(count) * 16)?;
+    }
+    skip_name(&mut data)?;
+    for _ in 0..file_count {
+        if read_u64(&mut data, flags)? == file_hash {
+            return Ok(true);
+        }
+        data.seek_relative(8)?;
+    }
+    Ok(false)
+}
+
+/// Opens the specified BSA file from a reader.
+///
+/// Any reader that implements `Read + Seek + Send + Sync` can be used, for
+/// example a `Cursor<Vec<u8>>`, or a `Cursor<Arc<[u8]>>` to share an
+/// in-memory archive without copying it.
+///
+/// ```no_run
+/// use std::{error::Error, io::Cursor, sync::Arc};
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let data: Arc<[u8]> = std::fs::read("file.bsa")?.into();
+///     let bsa = bsa::read(Cursor::new(Arc::clone(&data)))?;
+///     println!("{} folders", bsa.folders().count());
+///     Ok(())
+/// }
+/// ```
+pub fn read<R: io::Read + io::Seek + Send + Sync + 'static>(data: R) -> Result<Bsa, ReadError> {
+    OpenOptions::new().read(data)
+}
+
+/// Opens the BSA file stored in the `len` bytes at `offset` in a reader, e.g.
+/// an archive embedded in an installer or another container, without
+/// extracting it first. File offsets inside the archive are relative to
+/// `offset`.
+///
+/// ```no_run
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let installer = std::fs::File::open("installer.bin")?;
+///     let bsa = bsa::read_at(installer, 4096, 1 << 20)?;
+///     println!("{} folders", bsa.folders().count());
+///     Ok(())
+/// }
+/// ```
+pub fn read_at<R: io::Read + io::Seek + Send + Sync + 'static>(
+    data: R,
+    offset: u64,
+    len: u64,
+) -> Result<Bsa, ReadError> {
+    OpenOptions::new().read_at(data, offset, len)
+}
+
+/// Opens the specified BSA file.
+///
+/// ```no_run
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let mut bsa = bsa::open("file.bsa")?;
+///     for folder in bsa.folders() {
+///         for file in folder.files() {
+///             println!("File {:?} in folder {:?}", file.name(), folder.name());
+///             let contents = file.read_to_vec(&mut bsa)?;
+///             println!("{:?}", &contents);
+///         }
+///     }
+///     Ok(())
+/// }
+/// ```
+pub fn open<P: AsRef<path::Path>>(path: P) -> Result<Bsa, ReadError> {
+    OpenOptions::new().open(path)
+}
+
+/// Opens the specified BSA file with `n` separate file handles, so that
+/// several threads can read from it at the same time.
+///
+/// ```no_run
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let pool = bsa::open_multi("file.bsa", 4)?;
+///     std::thread::scope(|s| {
+///         for _ in 0..4 {
+///             s.spawn(|| {
+///                 let mut bsa = pool.get();
+///                 let file = bsa.iter_prefix("meshes").next().map(|(_, f)| f.clone());
+///                 file.map(|file| file.read_to_vec(&mut bsa))
+///             });
+///         }
+///     });
+///     Ok(())
+/// }
+/// ```
+pub fn open_multi<P: AsRef<path::Path>>(path: P, n: usize) -> Result<ReaderPool, ReadError> {
+    OpenOptions::new().open_multi(path, n)
+}
+
+impl convert::TryFrom<&path::Path> for Bsa {
+    type Error = ReadError;
+
+    fn try_from(path: &path::Path) -> Result<Self, Self::Error> {
+        open(path)
+    }
+}
+
+impl Bsa {
+    /// Opens each of the specified BSA files with the default options, e.g.
+    /// all archives in a Data folder at startup. See
+    /// [`OpenOptions::open_many`].
+    ///
+    /// ```no_run
+    /// use std::error::Error;
+    ///
+    /// fn main() -> Result<(), Box<dyn Error>> {
+    ///     let mut paths = vec![];
+    ///     for entry in std::fs::read_dir("Data")? {
+    ///         paths.push(entry?.path());
+    ///     }
+    ///     for (path, bsa) in paths.iter().zip(bsa::Bsa::open_many(&paths)) {
+    ///         println!("{}: {} folders", path.display(), bsa?.folders().count());
+    ///     }
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn open_many<P, I>(paths: I) -> Vec<Result<Bsa, ReadError>>
+    where
+        P: AsRef<path::Path> + Send,
+        I: IntoIterator<Item = P>,
+    {
+        OpenOptions::new().open_many(paths)
+    }
+
+    /// Returns a list of folders in this BSA
+    pub fn folders(&self) -> impl Iterator<Item = Folder> {
+        self.header.folders.clone().into_iter()
+    }
+
+    /// Creates another `Bsa` with the same folders and files as this one, that
+    /// reads file contents from the given reader
+    pub(crate) fn with_reader<R: io::Read + io::Seek + Send + Sync + 'static>(
+        &self,
+        reader: R,
+    ) -> Bsa {
+        Bsa {
+            header: Arc::clone(&self.header),
+            reader: Box::new(reader),
+            file: None,
+            source: self.source.clone(),
+        }
+    }
+
+    /// Returns the path this BSA was opened from, if it was opened with
+    /// [`open`] or [`OpenOptions::open`]
+    pub fn path(&self) -> Option<&path::Path> {
+        Some(&self.source.as_ref()?.path)
+    }
+
+    /// Returns the metadata from the sidecar of this BSA, if it was opened from
+    /// a path and the sidecar exists, see [`ArchiveMeta`](crate::ArchiveMeta)
+    #[cfg(feature = "meta")]
+    pub fn meta(&self) -> Option<&crate::meta::ArchiveMeta> {
+        self.source.as_ref()?.meta.as_ref()
+    }
+
+    /// Returns whether the file at [`Bsa::path`] has a different size or
+    /// modification time than when it was opened, e.g. because a mod manager
+    /// replaced it. Always returns `false` if the BSA wasn't opened from a
+    /// path.
+    pub fn has_changed(&self) -> Result<bool, ReadError> {
+        let source = match &self.source {
+            Some(source) => source,
+            None => return Ok(false),
+        };
+        let metadata = fs::metadata(&source.path)?;
+        Ok(metadata.len() != source.len || metadata.modified().ok() != source.modified)
+    }
+
+    /// Opens the file at [`Bsa::path`] again with the same options, replacing
+    /// the folders, files and reader of this BSA. This recovers from stale
+    /// file handles after the archive has been replaced on disk.
+    pub fn reopen(&mut self) -> Result<(), ReadError> {
+        let source = self.source.as_ref().ok_or(ReadError::NotOpenedFromPath)?;
+        *self = source.options.open(&source.path)?;
+        Ok(())
+    }
+
+    /// Returns every file in this BSA together with its folder and indices,
+    /// without cloning anything
+    pub fn entries(&self) -> impl Iterator<Item = Entry<'_>> {
+        self.header
+            .folders
+            .iter()
+            .enumerate()
+            .flat_map(|(folder_index, folder)| {
+                folder
+                    .files
+                    .iter()
+                    .enumerate()
+                    .map(move |(file_index, file)| Entry {
+                        folder_index,
+                        file_index,
+                        folder,
+                        file,
+                    })
+            })
+    }
+
+    /// Returns the entry at the given folder and file indices, as returned by
+    /// [`Entry::folder_index`] and [`Entry::file_index`]
+    pub fn entry(&self, folder_index: usize, file_index: usize) -> Option<Entry<'_>> {
+        let folder = self.header.folders.get(folder_index)?;
+        Some(Entry {
+            folder_index,
+            file_index,
+            folder,
+            file: folder.files.get(file_index)?,
+        })
+    }
+
+    /// Returns the file at the given folder and file indices. Together with
+    /// [`Bsa::entries`] and [`Bsa::find_entry`], this lets bindings and GUIs
+    /// refer to files by integers instead of paths.
+    pub fn file_at(&self, folder_index: usize, file_index: usize) -> Option<&File> {
+        self.header.folders.get(folder_index)?.files.get(file_index)
+    }
+
+    /// Looks up a file by its full path (case-insensitively, using either
+    /// forward slashes or backslashes), returning its entry with its indices
+    pub fn find_entry(&self, path: &str) -> Option<Entry<'_>> {
+        let path = normalize_path(path);
+        let (folder_name, file_name) = path.rsplit_once('\\').unwrap_or(("", &path));
+        let folder = self.folder(folder_name)?;
+        let folder_index = self
+            .header
+            .folders
+            .iter()
+            .position(|other| std::ptr::eq(other, folder))?;
+        let name_hash =
+            hash::compute_hash(&file_name.to_ascii_lowercase(), hash::Type::File).ok()?;
+        let file_index = folder.files.iter().position(|file| {
+            file.name_hash == name_hash
+                && file
+                    .name()
+                    .is_none_or(|name| name.eq_ignore_ascii_case(file_name))
+        })?;
+        self.entry(folder_index, file_index)
+    }
+
+    /// Returns the set of distinct paths of the named files in this BSA.
+    /// Entries that appear more than once (which the game ignores) are only
+    /// included once.
+    pub fn unique_paths(&self) -> BTreeSet<EntryPath> {
+        self.iter_prefix("")
+            .filter_map(|(folder, file)| file.full_path(folder))
+            .collect()
+    }
+
+    /// Returns a canonical listing of the files in this BSA, for committing to
+    /// version control and diffing between versions: one lowercase path per
+    /// line, in alphabetical order, without duplicates, and with every line
+    /// terminated by a newline. Files without names are left out.
+    pub fn canonical_listing(&self) -> String {
+        let mut paths: Vec<String> = self
+            .iter_prefix("")
+            .filter_map(|(folder, file)| Some(file.full_path(folder)?.as_str().to_lowercase()))
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths.iter().map(|path| format!("{}\n", path)).collect()
+    }
+
+    /// Returns the modification time of the BSA file, if it was opened from
+    /// disk
+    pub(crate) fn modified(&self) -> Option<std::time::SystemTime> {
+        self.file.as_ref()?.metadata().ok()?.modified().ok()
+    }
+
+    /// Returns an estimate of the memory (in bytes) used by the parsed folder
+    /// and file records
+    pub fn header_memory(&self) -> usize {
+        fn name_len(name: &Option<String>, raw_name: &Option<Vec<u8>>) -> usize {
+            name.as_ref().map_or(0, String::capacity) + raw_name.as_ref().map_or(0, Vec::capacity)
+        }
+        let mut res = mem::size_of::<BsaHeader>();
+        for folder in &self.header.folders {
+            res += mem::size_of::<Folder>() + name_len(&folder.name, &folder.raw_name);
+            res += folder.files.capacity() * mem::size_of::<File>();
+            for file in &folder.files {
+                res += name_len(&file.name, &file.raw_name);
+            }
+        }
+        res
+    }
+
+    /// Reads the contents of the given file into `buf`, replacing what it
+    /// contained before. The allocation of the buffer is reused, and only
+    /// grown to the uncompressed size of the file if it is too small, so
+    /// reading many files into the same buffer avoids allocating for each one.
+    /// If reading fails, the buffer may contain part of the file.
+    pub fn read_into(&mut self, file: &File, buf: &mut Vec<u8>) -> Result<(), ReadError> {
+        buf.clear();
+        let mut reader = file.read_contents(self)?;
+        // don't trust the stored size too much when preallocating
+        buf.reserve_exact(reader.expected_len().min(1 << 26) as usize);
+        io::Read::read_to_end(&mut reader, buf)?;
+        Ok(())
+    }
+
+    /// Returns the folders in this BSA as a slice, sorted by name hash. Unlike
+    /// `folders`, this doesn't clone anything.
+    pub fn folders_slice(&self) -> &[Folder] {
+        &self.header.folders
+    }
+
+    /// Looks up a folder by name (case-insensitively, using either forward
+    /// slashes or backslashes)
+    pub fn folder(&self, name: &str) -> Option<&Folder> {
+        let name = normalize_path(name).to_ascii_lowercase();
+        let name_hash = hash::compute_hash(&name, hash::Type::Directory).ok()?;
+        // folder records are sorted by hash
+        let folders = &self.header.folders;
+        let start = folders.partition_point(|folder| folder.name_hash < name_hash);
+        folders[start..]
+            .iter()
+            .take_while(|folder| folder.name_hash == name_hash)
+            .find(|folder| {
+                folder.name().is_none_or(|folder_name| {
+                    normalize_path(folder_name).eq_ignore_ascii_case(&name)
+                })
+            })
+    }
+
+    /// Returns every file whose path starts with the given prefix, together
+    /// with its folder. For example, `iter_prefix("textures\\armor\\")`
+    /// returns all files in the `textures\armor` folder and its subfolders.
+    /// Matching is case-insensitive, and forward slashes can be used instead
+    /// of backslashes. Only the files of matching folders are visited, and
+    /// nothing is allocated per folder or file.
+    pub fn iter_prefix<'a>(&'a self, prefix: &str) -> impl Iterator<Item = (&'a Folder, &'a File)> {
+        let mut prefix = prefix.replace('/', "\\").to_ascii_lowercase();
+        if prefix.starts_with('\\') {
+            prefix.remove(0);
+        }
+        // the files in the folder that the prefix ends in, if the prefix ends
+        // in part of a file name (e.g. `iron` in `textures\armor\iron`)
+        let partial = match prefix.rsplit_once('\\') {
+            Some((folder, name)) if !name.is_empty() => {
+                self.folder(folder).map(|folder| (folder, name.to_string()))
+            }
+            _ => None,
+        };
+        let partial = partial.into_iter().flat_map(|(folder, name)| {
+            folder
+                .files
+                .iter()
+                .filter(move |file| {
+                    file.name().is_some_and(|file_name| {
+                        file_name
+                            .as_bytes()
+                            .get(..name.len())
+                            .is_some_and(|start| start.eq_ignore_ascii_case(name.as_bytes()))
+                    })
+                })
+                .map(move |file| (folder, file))
+        });
+        // the folders that are entirely inside the prefix
+        self.header
+            .folders
+            .iter()
+            .filter(move |folder| folder_starts_with(folder.name().unwrap_or(""), &prefix))
+            .flat_map(|folder| {
+                folder
+                    .files
+                    .iter()
+                    .filter(|file| file.name().is_some())
+                    .map(move |file| (folder, file))
+            })
+            .chain(partial)
+    }
+
+    /// Returns every file in this BSA together with its folder, ordered by the
+    /// position of the file contents in the archive. Reading files in this
+    /// order results in sequential rather than random I/O.
+    pub fn files_by_offset(&self) -> Vec<(&Folder, &File)> {
+        let mut res: Vec<(&Folder, &File)> = self
+            .header
+            .folders
+            .iter()
+            .flat_map(|folder| folder.files.iter().map(move |file| (folder, file)))
+            .collect();
+        res.sort_by_key(|(_, file)| file.offset);
+        res
+    }
+
+    /// Passes every named file in this BSA to `f` together with a reader for
+    /// its contents, in the order they are stored in the archive. This allows
+    /// extracting files to somewhere other than the local file system.
+    ///
+    /// ```no_run
+    /// use std::error::Error;
+    ///
+    /// fn main() -> Result<(), Box<dyn Error>> {
+    ///     let mut bsa = bsa::open("file.bsa")?;
+    ///     bsa.extract_with(|path, reader| {
+    ///         println!("{} ({} bytes)", path, reader.expected_len());
+    ///         std::io::copy(reader, &mut std::io::sink())?;
+    ///         Ok(())
+    ///     })?;
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn extract_with(
+        &mut self,
+        mut f: impl FnMut(&EntryPath, &mut FileReader) -> io::Result<()>,
+    ) -> Result<(), ReadError> {
+        let header = Arc::clone(&self.header);
+        let mut files: Vec<(&Folder, &File)> = header
+            .folders
+            .iter()
+            .flat_map(|folder| folder.files.iter().map(move |file| (folder, file)))
+            .collect();
+        files.sort_by_key(|(_, file)| file.offset);
+        for (folder, file) in files {
+            if let Some(path) = file.full_path(folder) {
+                f(&path, &mut file.read_contents(self)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the byte layout of the BSA file: the header, folder records, file
+    /// record blocks, file names and the data of every file, ordered by
+    /// offset. Gaps between regions are padding or unused bytes.
+    pub fn layout(&self) -> Vec<Region> {
+        let mut res = self.header.record_regions.clone();
+        for (folder_index, folder) in self.header.folders.iter().enumerate() {
+            for (file_index, file) in folder.files.iter().enumerate() {
+                res.push(Region {
+                    start: file.start,
+                    end: file.offset + file.size,
+                    kind: RegionKind::FileData(folder_index, file_index),
+                });
+            }
+        }
+        res.sort_by_key(|region| (region.start, region.end));
+        res
+    }
+
+    /// Returns the largest power of two (up to 1 MiB) that the start of every
+    /// file in this BSA is aligned to, or 1 if the files are not aligned
+    pub fn data_alignment(&self) -> u64 {
+        offsets_alignment(
+            self.header
+                .folders
+                .iter()
+                .flat_map(|folder| folder.files.iter().map(|file| file.record_offset)),
+        )
+    }
+
+    /// Returns the version of this BSA
+    pub fn version(&self) -> Version {
+        self.header.version
+    }
+
+    /// Returns the game this BSA belongs to, as selected with
+    /// [`OpenOptions::game`] or guessed from the version
+    pub fn game(&self) -> Game {
+        self.header.game
+    }
+
+    /// Returns the archive flags of this BSA
+    pub fn archive_flags(&self) -> ArchiveFlags {
+        self.header.archive_flags
+    }
+
+    /// Returns the file flags of this BSA
+    pub fn file_flags(&self) -> FileFlags {
+        self.header.file_flags
+    }
+
+    /// Returns any non-fatal problems that were found while reading this BSA
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.header.diagnostics
+    }
+
+    fn read_header(
+        data: &mut (impl io::Read + io::Seek + 'static),
+        options: &OpenOptions,
+    ) -> Result<BsaHeader, ReadError> {
+        let ArchiveInfo {
+            version,
+            archive_flags,
+            file_flags,
+            folder_count,
+            file_count,
+        } = ArchiveInfo::deserialize(data)?;
+        let _span = span!(
+            "read_records",
+            version = version.serialize(),
+            folder_count,
+            file_count
+        )
+        .entered();
+        let game = match options.game {
+            Some(game) if game.version() != version => {
+                return Err(ReadError::GameVersionMismatch(game, version));
+            }
+            Some(game) => game,
+            None => Game::from_version(version),
+        };
+
+        let mut res = BsaHeader {
+            version,
+            archive_flags,
+            folder_count,
+            file_count,
+            file_flags,
+            game,
+            folders: vec![],
+            diagnostics: vec![],
+            record_regions: vec![],
+            decompression_buffer_size: options.decompression_buffer_size,
+        };
+        let region = |start, end, kind| Region { start, end, kind };
+        res.record_regions
+            .push(region(0, data.stream_position()?, RegionKind::Header));
+        if archive_flags.unknown_bits != 0 {
+            event!(
+                warn,
+                { flags = archive_flags.unknown_bits },
+                "unknown archive flag bits: {:#x}",
+                archive_flags.unknown_bits
+            );
+            res.diagnostics
+                .push(Diagnostic::UnknownArchiveFlags(archive_flags.unknown_bits));
+        }
+        if file_flags.unknown_bits != 0 {
+            event!(
+                warn,
+                { flags = file_flags.unknown_bits },
+                "unknown file flag bits: {:#x}",
+                file_flags.unknown_bits
+            );
+            res.diagnostics
+                .push(Diagnostic::UnknownFileFlags(file_flags.unknown_bits));
+        }
+
+        // read folder records
+        let folder_records_start = data.stream_position()?;
+        let folder_record_len = match res.version {
+            Version::OBLIVION | Version::SKYRIM => 16,
+            Version::SKYRIM_SPECIAL_EDITION => 24,
+            _ => return Err(ReadError::FailedToReadFileOffset),
+        };
+        options.check_entries(u64::from(res.folder_count) + u64::from(res.file_count))?;
+        // the header counts might not match the records, so check those too
+        let mut entries = u64::from(res.folder_count);
+        let mut folder_records = vec![];
+        read_record_chunks(data, res.folder_count, folder_record_len, |record| {
+            let name_hash = read_u64(record, Some(res.archive_flags))?;
+            let file_count = read_u32(record, Some(res.archive_flags))?;
+            // the file record blocks are read sequentially, so their offsets
+            // are only used to sanity check the folder records
+            let mut offset = read_u32(record, Some(res.archive_flags))?.into();
+            if res.version == Version::SKYRIM_SPECIAL_EDITION {
+                offset = read_u64(record, Some(res.archive_flags))?;
+            }
+            entries += u64::from(file_count);
+            options.check_entries(entries)?;
+            folder_records.push(FolderRecord {
+                name_hash,
+                file_count,
+                offset,
+                file_records: vec![],
+                name: None,
+                raw_name: None,
+            });
+            Ok(())
+        })?;
+
+        res.record_regions.push(region(
+            folder_records_start,
+            data.stream_position()?,
+            RegionKind::FolderRecords,
+        ));
+
+        // read file record blocks
+        for (folder_index, folder_record) in folder_records.iter_mut().enumerate() {
+            let block_start = data.stream_position()?;
+            if res.archive_flags.include_directory_names {
+                let raw_name = deserialize_bstring(data, true)?;
+                let name = options.decode_name(&raw_name, &mut res.diagnostics);
+                if options.verify_hashes != HashVerification::None {
+                    let computed_hash = hash::compute_hash_bytes(&raw_name, hash::Type::Directory);
+                    if computed_hash != folder_record.name_hash {
+                        event!(
+                            error,
+                            {
+                                hash = folder_record.name_hash,
+                                computed_hash = computed_hash,
+                                name = name.as_str()
+                            },
                             "Incorrect hash: calculated {:016x} instead of {:016x} for '{}'",
-                            computed_hash, file_record.name_hash, &file_name
+                            computed_hash,
+                            folder_record.name_hash,
+                            &name
                         );
                         return Err(ReadError::IncorrectHash(IncorrectHashError {
-                            actual_hash: file_record.name_hash,
+                            actual_hash: folder_record.name_hash,
                             expected_hash: computed_hash,
-                            name: file_name,
+                            name,
                         }));
                     } else {
-                        trace!("Matching hash: {:016x} for '{}'", computed_hash, &file_name);
+                        event!(
+                            trace,
+                            { hash = folder_record.name_hash, name = name.as_str() },
+                            "Matching hash: {:016x} for '{}'",
+                            folder_record.name_hash,
+                            &name
+                        );
+                    }
+                }
+                folder_record.name = Some(name);
+                folder_record.raw_name = Some(raw_name);
+            }
+            let file_records = &mut folder_record.file_records;
+            read_record_chunks(data, folder_record.file_count, 16, |record| {
+                let name_hash = read_u64(record, Some(res.archive_flags))?;
+                let size = read_u32(record, Some(res.archive_flags))?;
+                let offset = read_u32(record, Some(res.archive_flags))?;
+                file_records.push(FileRecord {
+                    name_hash,
+                    size: size & 0x3fff_ffff,
+                    override_compressed: size & 0x4000_0000 != 0,
+                    offset,
+                    name: None,
+                    raw_name: None,
+                });
+                Ok(())
+            })?;
+            res.record_regions.push(region(
+                block_start,
+                data.stream_position()?,
+                RegionKind::FileRecordBlock(folder_index),
+            ));
+        }
+
+        let file_names_start = data.stream_position()?;
+        if res.archive_flags.include_file_names {
+            // read file name block
+            for folder_record in &mut folder_records {
+                for file_record in &mut folder_record.file_records {
+                    let raw_name = deserialize_null_terminated_string(data)?;
+                    let file_name = options.decode_name(&raw_name, &mut res.diagnostics);
+                    if options.verify_hashes == HashVerification::All
+                        && !options.verify_file_hashes_in_parallel()
+                    {
+                        verify_file_hash(&raw_name, file_record.name_hash, &file_name)?;
                     }
                     file_record.name = Some(file_name);
+                    file_record.raw_name = Some(raw_name);
+                }
+            }
+            #[cfg(feature = "rayon")]
+            if options.verify_hashes == HashVerification::All
+                && options.verify_file_hashes_in_parallel()
+            {
+                use rayon::iter::{IntoParallelIterator, ParallelIterator};
+
+                let file_records: Vec<&FileRecord> = folder_records
+                    .iter()
+                    .flat_map(|folder_record| &folder_record.file_records)
+                    .collect();
+                // report the first incorrect hash, like serial verification
+                if let Some(err) = file_records.into_par_iter().find_map_first(|file_record| {
+                    let raw_name = file_record.raw_name.as_deref()?;
+                    let name = file_record.name.as_deref()?;
+                    verify_file_hash(raw_name, file_record.name_hash, name).err()
+                }) {
+                    return Err(err);
                 }
             }
+            res.record_regions.push(region(
+                file_names_start,
+                data.stream_position()?,
+                RegionKind::FileNames,
+            ));
         }
 
+        // the folder records point to the file record blocks, plus the total
+        // length of the file names for some reason
+        let file_names_len = data.stream_position()? - file_names_start;
+        for region in &res.record_regions {
+            if let RegionKind::FileRecordBlock(folder_index) = region.kind {
+                let folder_record = &folder_records[folder_index];
+                let expected = region.start + file_names_len;
+                if folder_record.offset != expected {
+                    let folder = folder_record
+                        .name
+                        .clone()
+                        .unwrap_or_else(|| format!("{:016x}", folder_record.name_hash));
+                    event!(
+                        warn,
+                        {
+                            folder = %folder,
+                            offset = folder_record.offset,
+                            expected_offset = expected
+                        },
+                        "folder record for '{}' points to offset {} instead of {}",
+                        folder, folder_record.offset, expected
+                    );
+                    res.diagnostics
+                        .push(Diagnostic::FolderRecordOffsetMismatch {
+                            folder,
+                            stored: folder_record.offset,
+                            expected,
+                        });
+                }
+            }
+        }
+
+        // the sizes have to be fixed before they are checked against the
+        // embedded names, or files shorter than their names would be rejected
+        if res.archive_flags.embed_file_names
+            && game.embeds_file_names()
+            && Self::fix_sizes_excluding_embedded_names(&mut folder_records, data)?
+        {
+            warn!("file sizes don't include the embedded file names");
+            res.diagnostics.push(Diagnostic::SizeExcludesEmbeddedName);
+        }
+        let alignment = offsets_alignment(folder_records.iter().flat_map(|folder_record| {
+            folder_record
+                .file_records
+                .iter()
+                .map(|file_record| u64::from(file_record.offset))
+        }));
         for folder_record in folder_records {
             let mut folder = Folder {
+                name_hash: folder_record.name_hash,
                 name: folder_record.name,
+                raw_name: folder_record.raw_name,
                 files: vec![],
             };
             for file_record in folder_record.file_records {
                 if file_record.override_compressed {
-                    warn!("override_compressed is set");
+                    event!(
+                        warn,
+                        { hash = file_record.name_hash, offset = file_record.offset },
+                        "override_compressed is set"
+                    );
                 }
-                let compressed =
-                    archive_flags.compressed_archive != file_record.override_compressed;
-
                 let mut file = File::deserialize(
                     res.archive_flags,
-                    compressed,
-                    file_record.offset.into(),
-                    file_record.size.into(),
+                    &file_record,
                     data,
-                    version,
+                    game,
+                    options.offset_mismatch,
+                    alignment,
                 )?;
+                file.folder_hash = folder_record.name_hash;
+                file.name_hash = file_record.name_hash;
                 if file.name.is_none() && file_record.name.is_some() {
                     file.name = file_record.name;
+                    file.raw_name = file_record.raw_name;
                 }
                 folder.files.push(file);
             }
@@ -793,26 +2976,1023 @@ impl Bsa {
         Ok(res)
     }
 
-    fn write_u32(v: &mut Vec<u8>, value: u32, archive_flags: Option<ArchiveFlags>) {
-        let bytes = if archive_flags.is_some() && archive_flags.unwrap().xbox360_archive {
-            value.to_be_bytes()
-        } else {
-            value.to_le_bytes()
+    /// Some tools write file sizes that don't include the embedded file name,
+    /// so the data of each file ends exactly one name length before the next
+    /// file starts. If that is the case for every file, the sizes in the file
+    /// records are fixed and `true` is returned. The position of the reader is
+    /// left unchanged.
+    fn fix_sizes_excluding_embedded_names(
+        folder_records: &mut [FolderRecord],
+        data: &mut (impl io::Read + io::Seek),
+    ) -> Result<bool, ReadError> {
+        let mut files: Vec<&mut FileRecord> = folder_records
+            .iter_mut()
+            .flat_map(|folder_record| folder_record.file_records.iter_mut())
+            .collect();
+        if files.is_empty() {
+            return Ok(false);
+        }
+        files.sort_by_key(|file| file.offset);
+        let position = data.stream_position()?;
+        let end = data.seek(io::SeekFrom::End(0))?;
+        let mut name_lens = Vec::with_capacity(files.len());
+        for (i, file) in files.iter().enumerate() {
+            let next = files.get(i + 1).map_or(end, |next| u64::from(next.offset));
+            data.seek(io::SeekFrom::Start(file.offset.into()))?;
+            let name_len = match read_u8(data) {
+                Ok(length_byte) => u32::from(length_byte) + 1,
+                Err(_) => break,
+            };
+            let fixed_size = match file.size.checked_add(name_len) {
+                Some(size) => size,
+                None => break,
+            };
+            if u64::from(file.offset) + u64::from(fixed_size) != next {
+                break;
+            }
+            name_lens.push(name_len);
+        }
+        data.seek(io::SeekFrom::Start(position))?;
+        if name_lens.len() != files.len() {
+            return Ok(false);
+        }
+        for (file, name_len) in files.into_iter().zip(name_lens) {
+            file.size += name_len;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "lz4")]
+    use super::FileVerification;
+    use super::{
+        Bsa, ControlCharacters, Diagnostic, FileType, HashVerification, IncorrectHashError,
+        OffsetMismatch, OpenOptions, ReadError,
+    };
+    use crate::bsa::Version;
+    use crate::checksum::Checksum;
+    use crate::game::Game;
+    use crate::layout::RegionKind;
+    use crate::write::BsaBuilder;
+    use std::convert::TryFrom;
+    use std::io;
+
+    /// Returns the contents of the archive described by a builder
+    fn write(builder: &BsaBuilder) -> Vec<u8> {
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        data
+    }
+
+    /// Writes the archive described by a builder and reads it back
+    fn round_trip(builder: &BsaBuilder) -> Bsa {
+        super::read(io::Cursor::new(write(builder))).unwrap()
+    }
+
+    #[test]
+    fn test_hash_verification() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/b.txt", vec![1]).unwrap();
+        let mut data = write(&builder);
+        // corrupt the file name hash
+        let hash_offset = 36 + 16 + 3;
+        data[hash_offset] ^= 0xff;
+        let read = |verify_hashes| {
+            super::OpenOptions::new()
+                .verify_hashes(verify_hashes)
+                .read(std::io::Cursor::new(data.clone()))
+        };
+        assert!(read(HashVerification::All).is_err());
+        assert!(read(HashVerification::FoldersOnly).is_ok());
+        assert!(read(HashVerification::None).is_ok());
+    }
+
+    #[test]
+    fn test_error_categories() {
+        let err = ReadError::IncorrectHash(IncorrectHashError {
+            actual_hash: 1,
+            expected_hash: 2,
+            name: "a".to_string(),
+        });
+        assert!(err.is_corruption() && !err.is_io() && !err.is_unsupported());
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::InvalidData);
+
+        let err = ReadError::UnknownVersion(1);
+        assert!(err.is_unsupported() && !err.is_corruption());
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::Unsupported);
+
+        let err = ReadError::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(err.is_io() && !err.is_corruption());
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::PermissionDenied);
+
+        let err = ReadError::UnexpectedEndOfFile;
+        assert!(err.is_corruption());
+        assert_eq!(io::Error::from(err).kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    #[cfg(feature = "zlib")]
+    fn test_read_helpers() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.compress(true);
+        builder.add_file("a/b.txt", vec![b'a', 0x80]).unwrap();
+        let mut bsa = round_trip(&builder);
+        let file = bsa
+            .folders()
+            .next()
+            .unwrap()
+            .files()
+            .next()
+            .unwrap()
+            .clone();
+        assert_eq!(file.read_contents(&mut bsa).unwrap().expected_len(), 2);
+        assert_eq!(file.read_to_vec_limited(&mut bsa, 2).unwrap().len(), 2);
+        assert!(file.read_to_vec_limited(&mut bsa, 1).is_err());
+        assert_eq!(file.read_to_string_cp1252(&mut bsa).unwrap(), "a\u{20ac}");
+        assert!(file.read_to_string_utf8(&mut bsa).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "zlib")]
+    fn test_read_raw() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.compress(true);
+        builder.add_file("a/b.txt", vec![b'a'; 100]).unwrap();
+        builder
+            .add_file_with_compression("a/c.txt", vec![b'c'; 10], false)
+            .unwrap();
+        let mut bsa = round_trip(&builder);
+        let folder = bsa.folders_slice()[0].clone();
+
+        let raw = folder.files_slice()[0].read_raw(&mut bsa).unwrap();
+        assert_eq!(raw.codec(), super::Codec::Zlib);
+        assert_eq!(raw.uncompressed_size(), 100);
+        let mut contents = vec![];
+        io::Read::read_to_end(
+            &mut flate2::read::ZlibDecoder::new(raw.data()),
+            &mut contents,
+        )
+        .unwrap();
+        assert_eq!(contents, vec![b'a'; 100]);
+
+        let raw = folder.files_slice()[1].read_raw(&mut bsa).unwrap();
+        assert_eq!(raw.codec(), super::Codec::None);
+        assert_eq!(raw.into_data(), vec![b'c'; 10]);
+    }
+
+    #[test]
+    #[cfg(all(feature = "zlib", feature = "lz4"))]
+    fn test_recover() {
+        let contents = b"hello world".to_vec();
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        io::Write::write_all(&mut zlib, &contents).unwrap();
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.ignore_game_limits(true);
+        builder.add_file("a/b.txt", contents.clone()).unwrap();
+        builder
+            .add_precompressed_file("a/c.txt", zlib.finish().unwrap(), 11)
+            .unwrap();
+        let mut data = write(&builder);
+        // set the compression bit of the first (uncompressed) file
+        data[36 + 24 + 3 + 8 + 3] |= 0x40;
+        let mut bsa = super::read(io::Cursor::new(data)).unwrap();
+        let folder = bsa.folders_slice()[0].clone();
+        for file in folder.files() {
+            assert!(file.read_to_vec(&mut bsa).is_err());
+            let recovered = file.recover(&mut bsa).unwrap();
+            assert!(recovered.is_mislabeled());
+            assert_eq!(recovered.data(), &contents[..]);
+            let codec = match file.name() {
+                Some("b.txt") => super::Codec::None,
+                _ => super::Codec::Zlib,
+            };
+            assert_eq!(recovered.codec(), codec);
+        }
+
+        // binary data in a text file can't have been stored uncompressed
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file("a/b.txt", vec![5, 0, 0, 0, 1, 2]).unwrap();
+        let mut data = write(&builder);
+        data[36 + 24 + 3 + 8 + 3] |= 0x40;
+        let mut bsa = super::read(io::Cursor::new(data)).unwrap();
+        let file = bsa.iter_prefix("").next().unwrap().1.clone();
+        assert!(file.recover(&mut bsa).is_err());
+
+        // contents that decompress to the wrong size aren't intact either
+        let mut zlib = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        io::Write::write_all(&mut zlib, &contents).unwrap();
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder
+            .add_precompressed_file("a/b.txt", zlib.finish().unwrap(), 12)
+            .unwrap();
+        let mut bsa = round_trip(&builder);
+        let file = bsa.iter_prefix("").next().unwrap().1.clone();
+        assert_eq!(file.read_to_vec(&mut bsa).unwrap(), contents);
+        assert!(file.recover(&mut bsa).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_recover_intact() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder
+            .ignore_game_limits(true)
+            .compress(true)
+            .lz4_block(true);
+        let frame = super::Codec::Lz4.compress(&[1; 100], None).unwrap();
+        builder
+            .add_precompressed_file("a/b.txt", frame, 100)
+            .unwrap();
+        builder.add_file("a/c.txt", vec![2; 100]).unwrap();
+        builder
+            .add_file_with_compression("a/d.txt", vec![3; 100], false)
+            .unwrap();
+        let mut bsa = round_trip(&builder);
+        let folder = bsa.folders_slice()[0].clone();
+        for file in folder.files() {
+            let recovered = file.recover(&mut bsa).unwrap();
+            assert!(!recovered.is_mislabeled());
+            assert_eq!(recovered.data().len(), 100);
+            let codec = match file.name() {
+                Some("b.txt") => super::Codec::Lz4,
+                Some("c.txt") => super::Codec::Lz4Block,
+                _ => super::Codec::None,
+            };
+            assert_eq!(recovered.codec(), codec);
+        }
+    }
+
+    #[test]
+    fn test_unknown_flags() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file("a/b.txt", vec![1]).unwrap();
+        let mut data = write(&builder);
+        data[13] |= 0x08; // archive flag 0x800
+        let bsa = super::read(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(bsa.archive_flags().unknown_bits(), 0x800);
+        assert_eq!(bsa.archive_flags().raw(), 0x803);
+        assert_eq!(bsa.diagnostics(), &[Diagnostic::UnknownArchiveFlags(0x800)]);
+    }
+
+    #[test]
+    fn test_file_types() {
+        assert_eq!(FileType::from_path(r"Sound\Voice\a.fuz"), FileType::Voices);
+        assert_eq!(FileType::from_path("sound/fx/a.wav"), FileType::Sounds);
+        assert_eq!(FileType::from_path(r"data\a.dds"), FileType::Textures);
+        assert_eq!(
+            FileType::from_path(r"scripts\a.pex"),
+            FileType::Miscellaneous
+        );
+
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("meshes/a.nif", vec![1]).unwrap();
+        builder.add_file("sound/voice/a.fuz", vec![2]).unwrap();
+        let bsa = round_trip(&builder);
+        let flags = bsa.file_flags();
+        assert!(flags.contains(FileType::Meshes) && flags.contains(FileType::Voices));
+        assert!(!flags.contains(FileType::Sounds));
+        assert_eq!("misc".parse::<FileType>().unwrap(), FileType::Miscellaneous);
+    }
+
+    #[test]
+    fn test_control_characters() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/b\u{1}\u{a0}.txt", vec![1]).unwrap();
+        let data = write(&builder);
+        let bsa = OpenOptions::new()
+            .control_characters(ControlCharacters::Replace)
+            .read(std::io::Cursor::new(data))
+            .unwrap();
+        let folder = bsa.folders().next().unwrap();
+        let file = folder.files().next().unwrap();
+        assert_eq!(file.name(), Some("b_\u{a0}.txt"));
+        assert_eq!(file.name_raw(), Some(&b"b\x01\xa0.txt"[..]));
+        assert_eq!(
+            bsa.diagnostics(),
+            &[Diagnostic::ControlCharactersInName(
+                "b\u{1}\u{a0}.txt".into()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_folder_lookup() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        for path in &[
+            r"textures\armor\a.dds",
+            r"textures\armor\iron\b.dds",
+            r"textures\armory\c.dds",
+            r"meshes\d.nif",
+        ] {
+            builder.add_file(path, vec![1]).unwrap();
+        }
+        let bsa = round_trip(&builder);
+
+        let folder = bsa.folder("Textures/Armor").unwrap();
+        assert_eq!(folder.name(), Some(r"textures\armor"));
+        assert!(bsa.folder("textures").is_none());
+
+        let names = |prefix| {
+            bsa.iter_prefix(prefix)
+                .map(|(_, file)| file.name().unwrap().to_string())
+                .collect::<Vec<_>>()
+        };
+        let mut armor = names(r"textures\armor\");
+        armor.sort();
+        assert_eq!(armor, vec!["a.dds", "b.dds"]);
+        assert_eq!(names("textures/armor/iron/").len(), 1);
+        assert_eq!(names("textures/arm").len(), 3);
+        assert_eq!(names(r"meshes\d").len(), 1);
+        assert_eq!(names(r"textures\armor\i").len(), 1);
+        assert_eq!(names(r"textures\armor\A").len(), 1);
+        assert!(names("textures\\\u{e4}").is_empty());
+        assert_eq!(names("").len(), 4);
+    }
+
+    #[test]
+    fn test_shared_reader() {
+        fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/b.txt", vec![1, 2, 3]).unwrap();
+        let data = write(&builder);
+        let data: std::sync::Arc<[u8]> = data.into();
+        let mut bsa = crate::bsa::read(std::io::Cursor::new(data)).unwrap();
+        assert_send_sync(&bsa);
+
+        let file = bsa.iter_prefix("").next().unwrap().1.clone();
+        let contents = std::thread::spawn(move || file.read_to_vec(&mut bsa).unwrap())
+            .join()
+            .unwrap();
+        assert_eq!(contents, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_send_to() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file("a/b.txt", b"hello".to_vec()).unwrap();
+        let path = std::env::temp_dir().join(format!("bsa-send-to-{}.bsa", std::process::id()));
+        builder
+            .write(&mut std::fs::File::create(&path).unwrap())
+            .unwrap();
+        let mut bsa = crate::bsa::open(&path).unwrap();
+
+        let file = bsa.iter_prefix("").next().unwrap().1.clone();
+        let mut out = vec![];
+        assert_eq!(file.send_to(&mut bsa, &mut out).unwrap(), 5);
+        assert_eq!(out, b"hello");
+        drop(bsa);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reopen() {
+        let write = |path: &std::path::Path, contents: &[u8]| {
+            let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+            builder.add_file("a/b.txt", contents.to_vec()).unwrap();
+            builder
+                .write(&mut std::fs::File::create(path).unwrap())
+                .unwrap();
+        };
+        let path = std::env::temp_dir().join(format!("bsa-reopen-{}.bsa", std::process::id()));
+        let replacement = path.with_extension("new");
+        write(&path, b"hello");
+        let mut bsa = crate::bsa::open(&path).unwrap();
+        assert_eq!(bsa.path(), Some(path.as_path()));
+        assert!(!bsa.has_changed().unwrap());
+
+        // replace the archive like a mod manager would
+        write(&replacement, b"hello world");
+        std::fs::rename(&replacement, &path).unwrap();
+        assert!(bsa.has_changed().unwrap());
+        bsa.reopen().unwrap();
+        assert!(!bsa.has_changed().unwrap());
+        let file = bsa.iter_prefix("").next().unwrap().1.clone();
+        assert_eq!(file.read_to_vec(&mut bsa).unwrap(), b"hello world");
+        let data = std::fs::read(&path).unwrap();
+        drop(bsa);
+        std::fs::remove_file(&path).unwrap();
+
+        let mut bsa = crate::bsa::read(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(bsa.path(), None);
+        assert!(!bsa.has_changed().unwrap());
+        assert!(matches!(bsa.reopen(), Err(ReadError::NotOpenedFromPath)));
+    }
+
+    #[test]
+    fn test_open_many() {
+        let dir = std::env::temp_dir().join(format!("bsa-open-many-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let paths: Vec<_> = ["a.bsa", "missing.bsa", "b.bsa"]
+            .iter()
+            .map(|name| dir.join(name))
+            .collect();
+        for path in &[&paths[0], &paths[2]] {
+            let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+            builder.add_file("a/b.txt", b"hello".to_vec()).unwrap();
+            builder
+                .write(&mut std::fs::File::create(path).unwrap())
+                .unwrap();
+        }
+        let results = Bsa::open_many(&paths);
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap().path(),
+            Some(paths[0].as_path())
+        );
+        assert!(results[1].as_ref().unwrap_err().is_io());
+        assert_eq!(
+            results[2].as_ref().unwrap().path(),
+            Some(paths[2].as_path())
+        );
+        drop(results);
+
+        let bsa = Bsa::try_from(paths[0].as_path()).unwrap();
+        assert_eq!(bsa.folders().count(), 1);
+        drop(bsa);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            "105".parse::<Version>().unwrap(),
+            Version::SKYRIM_SPECIAL_EDITION
+        );
+        assert!("106".parse::<Version>().is_err());
+        assert!("sse".parse::<Version>().is_err());
+    }
+
+    #[test]
+    fn test_read_into() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file("a/b.txt", vec![1; 1000]).unwrap();
+        builder.add_file("a/c.txt", vec![2; 10]).unwrap();
+        let mut bsa = round_trip(&builder);
+        let files: Vec<_> = bsa.iter_prefix("").map(|(_, file)| file.clone()).collect();
+
+        let mut buf = vec![];
+        bsa.read_into(&files[0], &mut buf).unwrap();
+        assert_eq!(buf, vec![1; 1000]);
+        assert_eq!(buf.capacity(), 1000);
+        let ptr = buf.as_ptr();
+        bsa.read_into(&files[1], &mut buf).unwrap();
+        assert_eq!(buf, vec![2; 10]);
+        assert_eq!(buf.as_ptr(), ptr);
+    }
+
+    #[test]
+    fn test_probe() {
+        let path = std::env::temp_dir().join(format!("bsa-probe-{}.bsa", std::process::id()));
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.compress(cfg!(feature = "zlib"));
+        builder.add_file(r"a\x.txt", vec![1]).unwrap();
+        builder.add_file(r"b\y.txt", vec![2]).unwrap();
+        builder.add_file(r"b\z.txt", vec![3]).unwrap();
+        builder
+            .write(&mut std::fs::File::create(&path).unwrap())
+            .unwrap();
+        let info = super::probe(&path).unwrap();
+        assert_eq!(info.version(), Version::SKYRIM);
+        assert_eq!(info.folder_count(), 2);
+        assert_eq!(info.file_count(), 3);
+        assert_eq!(
+            info.archive_flags().compressed_archive(),
+            cfg!(feature = "zlib")
+        );
+        assert!(info.file_flags().contains(FileType::Miscellaneous));
+
+        // only the header is read, so the rest of the archive can be missing
+        let data = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &data[..36]).unwrap();
+        assert_eq!(super::probe(&path).unwrap().file_count(), 3);
+        std::fs::write(&path, &data[..35]).unwrap();
+        assert!(super::probe(&path).is_err());
+        let mut ba2 = data[..36].to_vec();
+        ba2[..4].copy_from_slice(b"BTDX");
+        std::fs::write(&path, ba2).unwrap();
+        assert!(matches!(super::probe(&path), Err(ReadError::MissingHeader)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_probe_path() {
+        let path = std::env::temp_dir().join(format!("bsa-probe-path-{}.bsa", std::process::id()));
+        for &version in &[
+            Version::OBLIVION,
+            Version::SKYRIM,
+            Version::SKYRIM_SPECIAL_EDITION,
+        ] {
+            let mut builder = BsaBuilder::new(version);
+            for name in &[r"a\x.txt", r"a\y.txt", r"b\c\x.txt", r"d\z.txt"] {
+                builder.add_file(name, vec![1]).unwrap();
+            }
+            builder
+                .write(&mut std::fs::File::create(&path).unwrap())
+                .unwrap();
+            for name in &["a/x.txt", r"A\Y.TXT", "b/c/x.txt", "d/z.txt"] {
+                assert!(crate::bsa::probe_path(&path, name).unwrap(), "{}", name);
+            }
+            for name in &["a/z.txt", "b/x.txt", "e/x.txt", "x.txt"] {
+                assert!(!crate::bsa::probe_path(&path, name).unwrap(), "{}", name);
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_extract_with() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file(r"b\y.txt", b"world".to_vec()).unwrap();
+        builder.add_file(r"a\x.txt", b"hello".to_vec()).unwrap();
+        let mut bsa = round_trip(&builder);
+
+        let mut extracted = vec![];
+        bsa.extract_with(|path, reader| {
+            let mut contents = String::new();
+            std::io::Read::read_to_string(reader, &mut contents)?;
+            extracted.push((path.to_string(), contents));
+            Ok(())
+        })
+        .unwrap();
+        extracted.sort();
+        assert_eq!(
+            extracted,
+            vec![
+                (r"a\x.txt".to_string(), "hello".to_string()),
+                (r"b\y.txt".to_string(), "world".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_layout() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/x.txt", b"hello".to_vec()).unwrap();
+        builder.add_file("b/y.txt", b"world".to_vec()).unwrap();
+        let mut data = write(&builder);
+        let len = data.len() as u64;
+
+        let bsa = crate::bsa::read(std::io::Cursor::new(data.clone())).unwrap();
+        assert!(bsa.diagnostics().is_empty());
+        let layout = bsa.layout();
+        let kinds: Vec<_> = layout.iter().map(|region| region.kind()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                RegionKind::Header,
+                RegionKind::FolderRecords,
+                RegionKind::FileRecordBlock(0),
+                RegionKind::FileRecordBlock(1),
+                RegionKind::FileNames,
+                RegionKind::FileData(0, 0),
+                RegionKind::FileData(1, 0),
+            ]
+        );
+        assert!(layout.windows(2).all(|w| w[0].end() == w[1].start()));
+        assert_eq!(layout.last().unwrap().end(), len);
+
+        // data read from the stream despite the offset in the second file's
+        // record is shown where it really is (moving it forward by 3 bytes, as
+        // 1 byte would be taken for padding in an archive aligned to 2 bytes)
+        let offset_offset = 36 + 2 * 16 + 3 + 16 + 3 + 12;
+        for delta in [-1i8, 3] {
+            let mut data = data.clone();
+            data[offset_offset] = data[offset_offset].wrapping_add(delta as u8);
+            let bsa = OpenOptions::new()
+                .offset_mismatch(OffsetMismatch::TrustStream)
+                .read(std::io::Cursor::new(data))
+                .unwrap();
+            assert_eq!(bsa.layout(), layout);
+        }
+
+        // the offset of the first folder's file record block
+        data[36 + 12] += 1;
+        let bsa = crate::bsa::read(std::io::Cursor::new(data)).unwrap();
+        assert!(matches!(
+            bsa.diagnostics(),
+            [Diagnostic::FolderRecordOffsetMismatch { folder, stored, expected }]
+                if folder == "a" && stored == &(expected + 1)
+        ));
+    }
+
+    #[test]
+    fn test_game() {
+        let mut builder = BsaBuilder::new(Game::FalloutNV.version());
+        builder.embed_file_names(true);
+        builder.add_file("a/b.txt", b"hello".to_vec()).unwrap();
+        let data = write(&builder);
+
+        let read = |game: Option<Game>| {
+            let mut options = OpenOptions::new();
+            if let Some(game) = game {
+                options.game(game);
+            }
+            options.read(std::io::Cursor::new(data.clone()))
+        };
+        assert_eq!(read(None).unwrap().game(), Game::Skyrim);
+        let mut bsa = read(Some(Game::FalloutNV)).unwrap();
+        assert_eq!(bsa.game(), Game::FalloutNV);
+        let file = bsa.iter_prefix("").next().unwrap().1.clone();
+        assert_eq!(file.read_to_vec(&mut bsa).unwrap(), b"hello");
+        assert!(matches!(
+            read(Some(Game::SkyrimSE)),
+            Err(super::ReadError::GameVersionMismatch(
+                Game::SkyrimSE,
+                Version::SKYRIM
+            ))
+        ));
+    }
+
+    #[test]
+    fn test_files_by_offset() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file("a/x.txt", vec![1]).unwrap();
+        builder.add_file("a/y.txt", vec![2]).unwrap();
+        builder.add_file("b/z.txt", vec![3]).unwrap();
+        let mut data = write(&builder);
+        // swap the offsets of the two files in the first folder, so that the
+        // records are no longer in the order of the contents
+        let first = 36 + 2 * 24 + 3 + 12;
+        let second = first + 16;
+        for i in 0..4 {
+            data.swap(first + i, second + i);
+        }
+        let bsa = super::read(io::Cursor::new(data)).unwrap();
+        let names = |files: Vec<(&super::Folder, &super::File)>| -> Vec<String> {
+            files
+                .into_iter()
+                .map(|(folder, file)| file.full_path(folder).unwrap().as_str().to_string())
+                .collect()
         };
-        for b in std::array::IntoIter::new(bytes) {
-            v.push(b);
-        }
-    }
-
-    // pub fn write(&self) -> Vec<u8> {
-    //     let mut res = vec![b'B', b'S', b'A', 0x00];
-    //     Self::write_u32(&mut res, self.version.serialize(), None);
-    //     Self::write_u32(&mut res, self.archive_flags.serialize(), None);
-    //     Self::write_u32(&mut res, self.folder_count, Some(self.archive_flags));
-    //     Self::write_u32(&mut res, self.file_count, Some(self.archive_flags));
-    //     Self::write_u32(&mut res, self.total_folder_name_length, Some(self.archive_flags));
-    //     Self::write_u32(&mut res, self.total_file_name_length, Some(self.archive_flags));
-    //     Self::write_u32(&mut res, self.file_flags.serialize(), Some(self.archive_flags));
-    //     res
-    // }
+        let by_record = names(
+            bsa.folders_slice()
+                .iter()
+                .flat_map(|folder| folder.files_slice().iter().map(move |file| (folder, file)))
+                .collect(),
+        );
+        let by_offset = names(bsa.files_by_offset());
+        assert_ne!(by_offset, by_record);
+        let offsets: Vec<_> = bsa
+            .files_by_offset()
+            .into_iter()
+            .map(|(_, file)| file.offset)
+            .collect();
+        assert_eq!(offsets.len(), 3);
+        assert!(offsets.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+
+    #[test]
+    fn test_canonical_listing() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.keep_original_case(true);
+        for path in &[r"b\Z.txt", r"a\y.txt", r"A\X.txt"] {
+            builder.add_file(path, vec![1]).unwrap();
+        }
+        let bsa = round_trip(&builder);
+        assert_eq!(bsa.canonical_listing(), "a\\x.txt\na\\y.txt\nb\\z.txt\n");
+    }
+
+    #[test]
+    fn test_slices() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        for path in &[r"a\x.txt", r"a\y.txt", r"b\z.txt"] {
+            builder.add_file(path, vec![1]).unwrap();
+        }
+        let bsa = round_trip(&builder);
+
+        let folders = bsa.folders_slice();
+        assert_eq!(folders.len(), 2);
+        assert!(folders
+            .windows(2)
+            .all(|w| w[0].name_hash() < w[1].name_hash()));
+        let folder = bsa.folder("a").unwrap();
+        let files = folder.files_slice();
+        assert_eq!(files.len(), 2);
+        let hash = files[1].name_hash();
+        assert_eq!(
+            files.binary_search_by_key(&hash, |file| file.name_hash()),
+            Ok(1)
+        );
+    }
+
+    #[test]
+    fn test_corrupt_record() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/b.txt", vec![1]).unwrap();
+        let mut data = write(&builder);
+        // set the compression bit on a one-byte file, which is too small to
+        // contain the uncompressed size
+        let size_offset = 36 + 16 + 3 + 8;
+        data[size_offset + 3] |= 0x40;
+        match super::read(std::io::Cursor::new(data)) {
+            Err(super::ReadError::CorruptRecord(context)) => assert!(context.contains("offset")),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_embedded_name_length() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.embed_file_names(true);
+        builder.add_file("a/b.txt", vec![1]).unwrap();
+        let data = write(&builder);
+        let offset_offset = 36 + 16 + 3 + 12;
+        let mut offset = [0; 4];
+        offset.copy_from_slice(&data[offset_offset..offset_offset + 4]);
+        let offset = u32::from_le_bytes(offset) as usize;
+        // embedded name "a\b.txt", followed by the file contents
+        assert_eq!(data[offset], 7);
+        let read = |length_byte| {
+            let mut data = data.clone();
+            data[offset] = length_byte;
+            super::read(std::io::Cursor::new(data))
+        };
+
+        let mut bsa = read(8).unwrap();
+        let file = bsa
+            .folders()
+            .next()
+            .unwrap()
+            .files()
+            .next()
+            .unwrap()
+            .clone();
+        assert_eq!(file.read_to_vec(&mut bsa).unwrap(), Vec::<u8>::new());
+        for length_byte in &[9, 255] {
+            match read(*length_byte) {
+                Err(super::ReadError::EmbeddedNameTooLong(o)) => assert_eq!(o, offset as u64),
+                res => panic!("unexpected result: {:?}", res),
+            }
+        }
+
+        // a name that runs past the end of the archive
+        let mut truncated = data.clone();
+        truncated.truncate(offset + 4);
+        match super::read(std::io::Cursor::new(truncated)) {
+            Err(super::ReadError::UnexpectedEndOfFile) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_offset_mismatch() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/b.txt", vec![1, 2]).unwrap();
+        builder.add_file("a/c.txt", vec![3, 4]).unwrap();
+        let data = write(&builder);
+        let offset_offset = 36 + 16 + 3 + 16 + 12;
+        let read = |data: &[u8], offset_mismatch| {
+            let mut bsa = OpenOptions::new()
+                .offset_mismatch(offset_mismatch)
+                .read(std::io::Cursor::new(data.to_vec()))?;
+            let files = bsa.folders().next().unwrap().files_slice().to_vec();
+            files[1].read_to_vec(&mut bsa)
+        };
+        // move the second file back by one byte, so it overlaps the first, or
+        // forward by one byte, leaving a gap that isn't alignment padding
+        for delta in [-1i8, 1] {
+            let mut data = data.clone();
+            data[offset_offset] = data[offset_offset].wrapping_add(delta as u8);
+            let stream = read(&data, OffsetMismatch::TrustStream).unwrap();
+            assert_eq!(stream, [3, 4]);
+            assert_ne!(read(&data, OffsetMismatch::TrustRecords).unwrap(), stream);
+            assert!(matches!(
+                read(&data, OffsetMismatch::Error),
+                Err(super::ReadError::UnexpectedFileOffset(..))
+            ));
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_verify() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.compress(true);
+        builder.add_file("a/b.txt", vec![7; 1000]).unwrap();
+        let data = write(&builder);
+        let offset_offset = 36 + 24 + 3 + 12;
+        let mut offset = [0; 4];
+        offset.copy_from_slice(&data[offset_offset..offset_offset + 4]);
+        let offset = u32::from_le_bytes(offset) as usize;
+        let verify = |data: Vec<u8>| {
+            let mut bsa = super::read(std::io::Cursor::new(data)).unwrap();
+            let file = bsa.iter_prefix("").next().unwrap().1.clone();
+            file.verify(&mut bsa).unwrap()
+        };
+        assert_eq!(verify(data.clone()), FileVerification::Ok);
+
+        // the uncompressed size is stored before the compressed data
+        let mut wrong_size = data.clone();
+        wrong_size[offset] = 0xff;
+        assert_eq!(
+            verify(wrong_size),
+            FileVerification::SizeMismatch {
+                expected: 1023,
+                actual: 1000
+            }
+        );
+
+        let mut corrupt = data;
+        corrupt[offset + 4] ^= 0xff;
+        assert!(matches!(verify(corrupt), FileVerification::CorruptData(_)));
+    }
+
+    #[test]
+    fn test_size_excludes_embedded_name() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.embed_file_names(true);
+        builder.add_file("a/b.txt", vec![1; 20]).unwrap();
+        builder.add_file("a/c.txt", vec![2; 20]).unwrap();
+        // shorter than its embedded name
+        builder.add_file("a/d.txt", vec![3; 3]).unwrap();
+        let mut data = write(&builder);
+        let bsa = super::read(std::io::Cursor::new(data.clone())).unwrap();
+        assert!(bsa.diagnostics().is_empty());
+
+        // subtract the length of the embedded name "a\b.txt" (plus its length byte)
+        for i in 0..3 {
+            data[36 + 16 + 3 + 16 * i + 8] -= 8;
+        }
+        let mut bsa = super::read(std::io::Cursor::new(data)).unwrap();
+        assert_eq!(bsa.diagnostics(), &[Diagnostic::SizeExcludesEmbeddedName]);
+        let files = bsa.folders().next().unwrap().files_slice().to_vec();
+        let mut contents = files
+            .iter()
+            .map(|file| file.read_to_vec(&mut bsa).unwrap())
+            .collect::<Vec<_>>();
+        contents.sort();
+        assert_eq!(contents, vec![vec![1; 20], vec![2; 20], vec![3; 3]]);
+    }
+
+    #[test]
+    fn test_entry_identity() {
+        let read = |paths: &[&str]| {
+            let mut builder = BsaBuilder::new(Version::SKYRIM);
+            for path in paths {
+                builder.add_file(path, vec![1]).unwrap();
+            }
+            round_trip(&builder)
+        };
+        let a = read(&["a/x.txt", "a/y.txt", "b/x.txt"]);
+        let b = read(&["A/X.TXT", "c/z.txt"]);
+
+        let files = |bsa: &super::Bsa| {
+            bsa.iter_prefix("")
+                .map(|(_, file)| file.clone())
+                .collect::<std::collections::HashSet<_>>()
+        };
+        let conflicts: Vec<_> = files(&a).intersection(&files(&b)).cloned().collect();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].name(), Some("x.txt"));
+
+        let paths = a.unique_paths();
+        assert_eq!(paths.len(), 3);
+        assert!(b.unique_paths().iter().any(|path| paths.contains(path)));
+    }
+
+    #[test]
+    fn test_max_entries() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file(r"a\b.txt", vec![1]).unwrap();
+        builder.add_file(r"a\c.txt", vec![2]).unwrap();
+        builder.add_file(r"d\e.txt", vec![3]).unwrap();
+        let mut data = write(&builder);
+        let read = |data: &[u8], max_entries| {
+            OpenOptions::new()
+                .max_entries(max_entries)
+                .read(io::Cursor::new(data.to_vec()))
+        };
+        assert!(read(&data, 5).is_ok());
+        assert!(matches!(read(&data, 4), Err(ReadError::TooManyEntries(4))));
+
+        // a huge file count in a folder record is only noticed when the
+        // records run out, without allocating space for them up front
+        data[36 + 8..36 + 12].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(
+            read(&data, u64::MAX),
+            Err(ReadError::UnexpectedEndOfFile)
+        ));
+        assert!(matches!(
+            read(&data, 1000),
+            Err(ReadError::TooManyEntries(1000))
+        ));
+    }
+
+    #[test]
+    fn test_read_at() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file(r"a\b.txt", b"hello".to_vec()).unwrap();
+        let mut archive = vec![];
+        builder.write(&mut archive).unwrap();
+        let mut data = vec![0xff; 100];
+        data.extend_from_slice(&archive);
+        data.extend_from_slice(&[0xff; 100]);
+
+        let mut bsa = super::read_at(io::Cursor::new(data), 100, archive.len() as u64).unwrap();
+        let folder = bsa.folders().next().unwrap();
+        let file = folder.files().next().unwrap();
+        assert_eq!(file.read_to_vec(&mut bsa).unwrap(), b"hello");
+    }
+
+    #[test]
+    fn test_read_verified() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file(r"a\b.txt", b"hello".to_vec()).unwrap();
+        let mut bsa = round_trip(&builder);
+        let folder = bsa.folders().next().unwrap();
+        let file = folder.files().next().unwrap();
+
+        let mut contents = vec![];
+        io::Read::read_to_end(
+            &mut file
+                .read_verified(&mut bsa, Checksum::from_bytes(b"hello"))
+                .unwrap(),
+            &mut contents,
+        )
+        .unwrap();
+        assert_eq!(contents, b"hello");
+
+        let mut reader = file
+            .read_verified(&mut bsa, Checksum::from_bytes(b"other"))
+            .unwrap();
+        let err = io::Read::read_to_end(&mut reader, &mut contents).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(io::Read::read(&mut reader, &mut [0; 4]).is_err());
+    }
+
+    #[test]
+    fn test_expected_len() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file("a/b.txt", vec![1; 10]).unwrap();
+        #[cfg(feature = "lz4")]
+        builder
+            .add_file_with_compression("a/c.txt", vec![2; 1000], true)
+            .unwrap();
+        let mut bsa = round_trip(&builder);
+        let files = bsa.folders_slice()[0].files_slice().to_vec();
+        for file in files {
+            let mut reader = file.read_contents(&mut bsa).unwrap();
+            let expected_len = reader.expected_len();
+            assert_eq!(expected_len, file.uncompressed_size());
+            assert_eq!(
+                format!("{:?}", reader),
+                format!("FileReader ({} bytes)", expected_len)
+            );
+            let mut data = vec![];
+            io::Read::read_to_end(&mut reader, &mut data).unwrap();
+            assert_eq!(data.len() as u64, expected_len);
+        }
+    }
+
+    #[test]
+    fn test_buf_read() {
+        let contents = "first line\nsecond line\n".repeat(100);
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder
+            .add_file("a/b.txt", contents.clone().into_bytes())
+            .unwrap();
+        #[cfg(feature = "lz4")]
+        builder
+            .add_file_with_compression("a/c.txt", contents.clone().into_bytes(), true)
+            .unwrap();
+        let mut bsa = OpenOptions::new()
+            .decompression_buffer_size(7)
+            .read(io::Cursor::new(write(&builder)))
+            .unwrap();
+        let files = bsa.folders_slice()[0].files_slice().to_vec();
+        for file in files {
+            let mut reader = file.read_contents(&mut bsa).unwrap();
+            let mut line = String::new();
+            io::BufRead::read_line(&mut reader, &mut line).unwrap();
+            assert_eq!(line, "first line\n");
+            let mut rest = String::new();
+            io::Read::read_to_string(&mut reader, &mut rest).unwrap();
+            assert_eq!(line + &rest, contents);
+        }
+    }
+
+    #[test]
+    fn test_buffer_sizes() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        let contents: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        builder.add_file(r"a\b.bin", contents.clone()).unwrap();
+        let path = std::env::temp_dir().join(format!("bsa-buffers-{}.bsa", std::process::id()));
+        builder
+            .write(&mut std::fs::File::create(&path).unwrap())
+            .unwrap();
+
+        for &(io_buffer_size, decompression_buffer_size) in
+            &[(1, 1), (64 * 1024, 3), (512, 1 << 20)]
+        {
+            let mut bsa = OpenOptions::new()
+                .io_buffer_size(io_buffer_size)
+                .decompression_buffer_size(decompression_buffer_size)
+                .open(&path)
+                .unwrap();
+            let folder = bsa.folders().next().unwrap();
+            let file = folder.files().next().unwrap();
+            assert_eq!(file.read_to_vec(&mut bsa).unwrap(), contents);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
 }
@@ -0,0 +1,199 @@
+use crate::bsa::{Bsa, OpenOptions, ReadError};
+use std::{fmt, fs, io, path};
+
+/// Opens archives on demand and keeps the most recently used ones open, as
+/// long as their parsed records fit in a memory budget. This lets tools such
+/// as mod managers look through hundreds of archives without holding every
+/// header in memory at once.
+///
+/// ```no_run
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let mut cache = bsa::BsaCache::open_dir("Data")?;
+///     cache.budget(16 << 20);
+///     for path in cache.archives().to_vec() {
+///         let bsa = cache.get(&path)?;
+///         if bsa.find_entry("textures/sky/sun.dds").is_some() {
+///             println!("{}", path.display());
+///         }
+///     }
+///     Ok(())
+/// }
+/// ```
+pub struct BsaCache {
+    archives: Vec<path::PathBuf>,
+    options: OpenOptions,
+    budget: usize,
+    // least recently used first
+    entries: Vec<CacheEntry>,
+    memory: usize,
+}
+
+struct CacheEntry {
+    path: path::PathBuf,
+    bsa: Bsa,
+    memory: usize,
+}
+
+impl BsaCache {
+    /// Creates an empty cache with a budget of 64 MiB
+    pub fn new() -> Self {
+        Self {
+            archives: vec![],
+            options: OpenOptions::new(),
+            budget: 64 << 20,
+            entries: vec![],
+            memory: 0,
+        }
+    }
+
+    /// Creates a cache for the `.bsa` files in the given directory (but not in
+    /// its subdirectories). No archives are opened until they are needed.
+    pub fn open_dir<P: AsRef<path::Path>>(dir: P) -> io::Result<Self> {
+        let mut res = Self::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_bsa = path
+                .extension()
+                .is_some_and(|extension| extension.eq_ignore_ascii_case("bsa"));
+            if is_bsa && path.is_file() {
+                res.archives.push(path);
+            }
+        }
+        res.archives.sort();
+        Ok(res)
+    }
+
+    /// Sets the amount of memory (in bytes) that the parsed records of the
+    /// open archives may use, as estimated by [`Bsa::header_memory`]. The most
+    /// recently used archive is always kept open, even if it exceeds the
+    /// budget on its own.
+    pub fn budget(&mut self, budget: usize) -> &mut Self {
+        self.budget = budget;
+        self.evict();
+        self
+    }
+
+    /// Sets the options used to open archives
+    pub fn options(&mut self, options: OpenOptions) -> &mut Self {
+        self.options = options;
+        self
+    }
+
+    /// Returns the archives found by [`BsaCache::open_dir`], sorted by path
+    pub fn archives(&self) -> &[path::PathBuf] {
+        &self.archives
+    }
+
+    /// Returns the archive at the given path, opening it if it isn't cached.
+    /// Opening an archive may close the least recently used ones to stay
+    /// within the budget.
+    pub fn get<P: AsRef<path::Path>>(&mut self, path: P) -> Result<&mut Bsa, ReadError> {
+        let path = path.as_ref();
+        let entry = match self.entries.iter().position(|entry| entry.path == path) {
+            Some(index) => self.entries.remove(index),
+            None => {
+                let bsa = self.options.open(path)?;
+                let memory = bsa.header_memory();
+                self.memory += memory;
+                CacheEntry {
+                    path: path.to_path_buf(),
+                    bsa,
+                    memory,
+                }
+            }
+        };
+        self.entries.push(entry);
+        self.evict();
+        Ok(&mut self.entries.last_mut().unwrap().bsa)
+    }
+
+    /// Returns whether the archive at the given path is currently open
+    pub fn is_cached<P: AsRef<path::Path>>(&self, path: P) -> bool {
+        self.entries.iter().any(|entry| entry.path == path.as_ref())
+    }
+
+    /// Returns the estimated memory (in bytes) used by the open archives
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Closes all open archives
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.memory = 0;
+    }
+
+    fn evict(&mut self) {
+        while self.memory > self.budget && self.entries.len() > 1 {
+            let entry = self.entries.remove(0);
+            self.memory -= entry.memory;
+        }
+    }
+}
+
+impl Default for BsaCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for BsaCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "BsaCache ({} of {} archives open, {} bytes)",
+            self.entries.len(),
+            self.archives.len(),
+            self.memory
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BsaCache;
+    use crate::bsa::Version;
+    use crate::write::BsaBuilder;
+
+    #[test]
+    fn test_cache_eviction() {
+        let dir = std::env::temp_dir().join(format!("bsa-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in &["a.bsa", "b.BSA", "c.bsa"] {
+            let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+            builder
+                .add_file(&format!("meshes/{}.nif", name), vec![1])
+                .unwrap();
+            builder
+                .write(&mut std::fs::File::create(dir.join(name)).unwrap())
+                .unwrap();
+        }
+        std::fs::write(dir.join("readme.txt"), "").unwrap();
+
+        let mut cache = BsaCache::open_dir(&dir).unwrap();
+        let archives = cache.archives().to_vec();
+        assert_eq!(archives.len(), 3);
+        let memory = cache.get(&archives[0]).unwrap().header_memory();
+        assert_eq!(cache.memory(), memory);
+
+        // room for two archives
+        cache.budget(memory * 5 / 2);
+        assert!(cache
+            .get(&archives[1])
+            .unwrap()
+            .find_entry("meshes/b.BSA.nif")
+            .is_some());
+        cache.get(&archives[0]).unwrap();
+        cache.get(&archives[2]).unwrap();
+        assert!(cache.is_cached(&archives[0]));
+        assert!(!cache.is_cached(&archives[1]));
+        assert!(cache.is_cached(&archives[2]));
+
+        cache.budget(0);
+        assert!(!cache.is_cached(&archives[0]));
+        assert!(cache.is_cached(&archives[2]));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,141 @@
+use crate::bsa::ReadError;
+use crate::reader::FileReader;
+use sha2::Digest;
+use std::{fmt, io, str};
+
+/// A SHA-256 checksum of the decompressed contents of a file
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Checksum([u8; 32]);
+
+impl Checksum {
+    /// Computes the checksum of all data read from the given reader
+    pub fn from_reader(reader: &mut impl io::Read) -> io::Result<Self> {
+        let mut hasher = sha2::Sha256::new();
+        io::copy(reader, &mut hasher)?;
+        Ok(Self(hasher.finalize().into()))
+    }
+
+    /// Computes the checksum of the given bytes
+    pub fn from_bytes(bytes: &[u8]) -> Self {
+        Self(sha2::Sha256::digest(bytes).into())
+    }
+
+    /// Returns the raw checksum bytes
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Checksum {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for b in &self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+/// Represents an error when parsing a checksum from a hexadecimal string
+#[derive(Debug, Clone, Copy)]
+pub struct ParseChecksumError;
+
+impl fmt::Display for ParseChecksumError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Expected 64 hexadecimal digits")
+    }
+}
+
+impl std::error::Error for ParseChecksumError {}
+
+/// Decodes a string of hex digits, such as a checksum or a signing key, into
+/// bytes. Returns `None` if the string isn't valid hex.
+///
+/// ```
+/// assert_eq!(bsa::decode_hex("00ff"), Some(vec![0, 255]));
+/// assert_eq!(bsa::decode_hex("0g"), None);
+/// ```
+pub fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+impl str::FromStr for Checksum {
+    type Err = ParseChecksumError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = decode_hex(s).ok_or(ParseChecksumError)?;
+        let mut res = [0; 32];
+        if bytes.len() != res.len() {
+            return Err(ParseChecksumError);
+        }
+        res.copy_from_slice(&bytes);
+        Ok(Self(res))
+    }
+}
+
+/// A reader for the decompressed contents of a file that computes their
+/// checksum while they are read, and fails with
+/// [`ReadError::ChecksumMismatch`] (as an `io::Error` of kind `InvalidData`)
+/// at the end of the file if it doesn't match the expected checksum. Created
+/// by [`File::read_verified`](crate::File::read_verified).
+pub struct VerifiedReader<'a> {
+    inner: FileReader<'a>,
+    hasher: Option<sha2::Sha256>,
+    expected: Checksum,
+    mismatch: Option<Checksum>,
+}
+
+impl<'a> VerifiedReader<'a> {
+    pub(crate) fn new(inner: FileReader<'a>, expected: Checksum) -> Self {
+        Self {
+            inner,
+            hasher: Some(sha2::Sha256::new()),
+            expected,
+            mismatch: None,
+        }
+    }
+
+    /// Returns the total number of bytes this reader is expected to produce,
+    /// see [`FileReader::expected_len`]
+    pub fn expected_len(&self) -> u64 {
+        self.inner.expected_len()
+    }
+
+    /// Returns the checksum the contents are expected to have
+    pub fn expected(&self) -> Checksum {
+        self.expected
+    }
+}
+
+impl io::Read for VerifiedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        if n > 0 {
+            if let Some(hasher) = &mut self.hasher {
+                hasher.update(&buf[..n]);
+            }
+        } else if !buf.is_empty() {
+            if let Some(hasher) = self.hasher.take() {
+                let actual = Checksum(hasher.finalize().into());
+                if actual != self.expected {
+                    self.mismatch = Some(actual);
+                }
+            }
+            if let Some(actual) = self.mismatch {
+                return Err(ReadError::ChecksumMismatch(self.expected, actual).into());
+            }
+        }
+        Ok(n)
+    }
+}
+
+impl fmt::Debug for VerifiedReader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "VerifiedReader (expected {})", self.expected)
+    }
+}
@@ -0,0 +1,295 @@
+use crate::bsa::{FileType, WriteError};
+use crate::game::Game;
+use crate::write::BsaBuilder;
+use std::collections::BTreeMap;
+use std::{fs, io, path};
+
+/// Returns the name of a plugin without its extension, e.g. `MyMod` for
+/// `MyMod.esp`
+fn plugin_stem(plugin: &str) -> &str {
+    match plugin.rsplit_once('.') {
+        Some((stem, extension))
+            if ["esp", "esm", "esl"]
+                .iter()
+                .any(|ext| extension.eq_ignore_ascii_case(ext)) =>
+        {
+            stem
+        }
+        _ => plugin,
+    }
+}
+
+/// Returns whether the game loads the archive with the given file name
+/// automatically when the given plugin is active.
+///
+/// Every game loads `<plugin>.bsa`. Oblivion also loads every archive whose
+/// name starts with the plugin name, Fallout 3 and Fallout: New Vegas load
+/// `<plugin> - <anything>.bsa`, and Skyrim Special Edition loads
+/// `<plugin> - Textures.bsa`.
+///
+/// ```
+/// use bsa::Game;
+///
+/// assert!(bsa::loads_with_plugin(Game::SkyrimSE, "MyMod.esp", "MyMod - Textures.bsa"));
+/// assert!(!bsa::loads_with_plugin(Game::SkyrimSE, "MyMod.esp", "MyMod - Voices.bsa"));
+/// ```
+pub fn loads_with_plugin(game: Game, plugin: &str, archive: &str) -> bool {
+    let plugin = plugin_stem(plugin).to_lowercase();
+    let archive = archive.to_lowercase();
+    let stem = match archive.strip_suffix(".bsa") {
+        Some(stem) => stem,
+        None => return false,
+    };
+    if stem == plugin {
+        return true;
+    }
+    let suffix = stem.strip_prefix(&plugin);
+    match game {
+        Game::Oblivion => suffix.is_some(),
+        Game::Fallout3 | Game::FalloutNV => {
+            suffix.is_some_and(|suffix| suffix.starts_with(" - ") && suffix.len() > 3)
+        }
+        Game::SkyrimSE => suffix == Some(" - textures"),
+        Game::Skyrim => false,
+    }
+}
+
+/// Builds the archives that belong to a plugin, named so that the game loads
+/// them automatically (see [`loads_with_plugin`]). Textures go into
+/// `<plugin> - Textures.bsa` and voices into `<plugin> - Voices.bsa` if the
+/// game loads them, everything else into `<plugin>.bsa`. Sounds and voices are
+/// never compressed, since the games can't always play compressed sounds.
+///
+/// Archives that would grow larger than the game can load are split into
+/// numbered parts, e.g. `<plugin> - Voices 2.bsa`, in the games that load
+/// them (Oblivion, Fallout 3 and Fallout: New Vegas).
+///
+/// ```no_run
+/// use bsa::{CompanionArchives, Game};
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let mut archives = CompanionArchives::new(Game::SkyrimSE, "MyMod.esp");
+///     archives.compress(true);
+///     archives.add_file(r"meshes\foo.nif", std::fs::read("foo.nif")?)?;
+///     archives.add_file(r"textures\foo.dds", std::fs::read("foo.dds")?)?;
+///     // writes MyMod.bsa and MyMod - Textures.bsa
+///     archives.write_to_dir("Data")?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct CompanionArchives {
+    game: Game,
+    plugin: String,
+    compress: bool,
+    max_archive_size: u64,
+    archives: BTreeMap<String, BsaBuilder>,
+    /// The total size of the file contents in each archive
+    sizes: BTreeMap<String, u64>,
+}
+
+impl CompanionArchives {
+    /// Creates the archives for the given plugin (with or without its
+    /// extension)
+    pub fn new(game: Game, plugin: &str) -> Self {
+        Self {
+            game,
+            plugin: plugin_stem(plugin).to_string(),
+            compress: false,
+            max_archive_size: game.max_archive_size(),
+            archives: BTreeMap::new(),
+            sizes: BTreeMap::new(),
+        }
+    }
+
+    /// Sets whether file contents should be compressed, except for sounds and
+    /// voices
+    pub fn compress(&mut self, compress: bool) -> &mut Self {
+        self.compress = compress;
+        for builder in self.archives.values_mut() {
+            builder.compress(compress);
+        }
+        self
+    }
+
+    /// Sets the size (in bytes) of the file contents, before compression, at
+    /// which archives are split into parts (defaults to
+    /// [`Game::max_archive_size`]). Archives are never split in games that
+    /// wouldn't load the parts.
+    pub fn max_archive_size(&mut self, max_archive_size: u64) -> &mut Self {
+        self.max_archive_size = max_archive_size;
+        self
+    }
+
+    /// Returns the name of the archive for the given type of files without its
+    /// `.bsa` extension, e.g. `Textures`, or `None` for `<plugin>.bsa`
+    fn archive_suffix(&self, file_type: FileType) -> Option<&'static str> {
+        let suffix = match file_type {
+            FileType::Textures => "Textures",
+            FileType::Voices => "Voices",
+            _ => return None,
+        };
+        let name = format!("{} - {}.bsa", self.plugin, suffix);
+        Some(suffix).filter(|_| loads_with_plugin(self.game, &self.plugin, &name))
+    }
+
+    /// Returns the file name of the archive that files of the given type go
+    /// into
+    pub fn archive_name(&self, file_type: FileType) -> String {
+        match self.archive_suffix(file_type) {
+            Some(suffix) => format!("{} - {}.bsa", self.plugin, suffix),
+            None => format!("{}.bsa", self.plugin),
+        }
+    }
+
+    /// Returns the file name of the given part (starting at 1) of the archive
+    /// for the given type of files, or `None` if the game wouldn't load it
+    fn part_name(&self, file_type: FileType, part: usize) -> Option<String> {
+        if part == 1 {
+            return Some(self.archive_name(file_type));
+        }
+        let suffix = self.archive_suffix(file_type).unwrap_or("Main");
+        let name = format!("{} - {} {}.bsa", self.plugin, suffix, part);
+        Some(name).filter(|name| loads_with_plugin(self.game, &self.plugin, name))
+    }
+
+    /// Adds a file to the archive for its type, starting a new part if the
+    /// archive would grow too large
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) -> Result<&mut Self, WriteError> {
+        let file_type = FileType::from_path(path);
+        let compress = self.compress && !matches!(file_type, FileType::Sounds | FileType::Voices);
+        let mut part = 1;
+        let name = loop {
+            let name = self
+                .part_name(file_type, part)
+                .expect("the first part is always loaded");
+            let size = match self.sizes.get(&name) {
+                Some(&size) => size,
+                None => break name,
+            };
+            let fits = size.saturating_add(data.len() as u64) <= self.max_archive_size;
+            match self.part_name(file_type, part + 1) {
+                Some(_) if !fits => part += 1,
+                _ => break name,
+            }
+        };
+        *self.sizes.entry(name.clone()).or_default() += data.len() as u64;
+        let (game, default_compress) = (self.game, self.compress);
+        self.archives
+            .entry(name)
+            .or_insert_with(|| {
+                let mut builder = BsaBuilder::for_game(game);
+                builder.compress(default_compress);
+                builder
+            })
+            .add_file_with_compression(path, data, compress)?;
+        Ok(self)
+    }
+
+    /// Returns the archives that have files, by file name
+    pub fn archives(&self) -> impl Iterator<Item = (&str, &BsaBuilder)> {
+        self.archives
+            .iter()
+            .map(|(name, builder)| (name.as_str(), builder))
+    }
+
+    /// Writes every archive into the given folder, returning their paths
+    pub fn write_to_dir<P: AsRef<path::Path>>(
+        &self,
+        dir: P,
+    ) -> Result<Vec<path::PathBuf>, WriteError> {
+        let mut res = vec![];
+        for (name, builder) in &self.archives {
+            let path = dir.as_ref().join(name);
+            builder.write(&mut io::BufWriter::new(fs::File::create(&path)?))?;
+            res.push(path);
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{loads_with_plugin, CompanionArchives};
+    use crate::bsa::FileType;
+    use crate::game::Game;
+
+    #[test]
+    fn test_loads_with_plugin() {
+        let loads = |game, archive| loads_with_plugin(game, "MyMod.esp", archive);
+        for &game in &[
+            Game::Oblivion,
+            Game::Fallout3,
+            Game::FalloutNV,
+            Game::Skyrim,
+            Game::SkyrimSE,
+        ] {
+            assert!(loads(game, "mymod.BSA"));
+            assert!(!loads(game, "Other.bsa"));
+            assert!(!loads(game, "MyMod.ba2"));
+        }
+        assert!(loads(Game::Oblivion, "MyModVoices.bsa"));
+        assert!(loads(Game::FalloutNV, "MyMod - Voices.bsa"));
+        assert!(!loads(Game::FalloutNV, "MyModVoices.bsa"));
+        assert!(loads(Game::SkyrimSE, "MyMod - Textures.bsa"));
+        assert!(!loads(Game::SkyrimSE, "MyMod - Voices.bsa"));
+        assert!(!loads(Game::Skyrim, "MyMod - Textures.bsa"));
+    }
+
+    #[test]
+    fn test_voices() {
+        let mut archives = CompanionArchives::new(Game::FalloutNV, "MyMod.esp");
+        archives.max_archive_size(10);
+        for name in &["a", "b", "c"] {
+            let path = format!(r"sound\voice\mymod.esp\{}.ogg", name);
+            archives.add_file(&path, vec![0; 6]).unwrap();
+        }
+        archives.add_file(r"meshes\a.nif", vec![0; 20]).unwrap();
+        let names: Vec<_> = archives.archives().map(|(name, _)| name).collect();
+        assert_eq!(
+            names,
+            vec![
+                "MyMod - Voices 2.bsa",
+                "MyMod - Voices 3.bsa",
+                "MyMod - Voices.bsa",
+                "MyMod.bsa"
+            ]
+        );
+        for name in names {
+            assert!(loads_with_plugin(Game::FalloutNV, "MyMod.esp", name));
+        }
+
+        // Skyrim only loads one archive per plugin
+        let mut archives = CompanionArchives::new(Game::SkyrimSE, "MyMod.esp");
+        archives.max_archive_size(10);
+        for name in &["a", "b", "c"] {
+            let path = format!(r"sound\voice\mymod.esp\{}.fuz", name);
+            archives.add_file(&path, vec![0; 6]).unwrap();
+        }
+        let names: Vec<_> = archives.archives().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["MyMod.bsa"]);
+    }
+
+    #[test]
+    fn test_companion_archives() {
+        let mut archives = CompanionArchives::new(Game::SkyrimSE, "MyMod.esp");
+        archives
+            .add_file(r"meshes\a.nif", vec![1])
+            .unwrap()
+            .add_file(r"textures\b.dds", vec![2])
+            .unwrap();
+        let names: Vec<_> = archives.archives().map(|(name, _)| name).collect();
+        assert_eq!(names, vec!["MyMod - Textures.bsa", "MyMod.bsa"]);
+        for (name, builder) in archives.archives() {
+            assert!(loads_with_plugin(Game::SkyrimSE, "MyMod.esp", name));
+            assert_eq!(
+                builder.contains(r"textures\b.dds"),
+                name == "MyMod - Textures.bsa"
+            );
+        }
+
+        let archives = CompanionArchives::new(Game::Skyrim, "MyMod");
+        assert_eq!(archives.archive_name(FileType::Textures), "MyMod.bsa");
+    }
+}
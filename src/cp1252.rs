@@ -1,4 +1,4 @@
-use std::{array, convert, error, fmt};
+use std::{convert, error, fmt};
 
 #[derive(Clone, Copy, Debug)]
 pub struct EncodingError(char);
@@ -20,7 +20,7 @@ pub fn encode_char(ch: char) -> Result<u8, EncodingError> {
     if value <= 0x7f {
         Ok(value as u8)
     } else {
-        for (byte, encoded_char) in array::IntoIter::new(CP1252_VALUES) {
+        for (byte, encoded_char) in CP1252_VALUES {
             if encoded_char == u32::from(ch) {
                 return Ok(byte);
             }
@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// A non-fatal problem found while reading a BSA file
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+    /// The archive flags contain bits that are not recognized
+    UnknownArchiveFlags(u32),
+    /// The file flags contain bits that are not recognized
+    UnknownFileFlags(u32),
+    /// A file or folder name contains control characters
+    ControlCharactersInName(String),
+    /// The file sizes don't include the embedded file names, as written by
+    /// some buggy tools. The sizes are corrected when reading the files.
+    SizeExcludesEmbeddedName,
+    /// The offset of a folder's file record block, as stored in its folder
+    /// record, doesn't match where the block actually is. The game might not
+    /// be able to find the files in that folder.
+    FolderRecordOffsetMismatch {
+        /// The name of the folder, or its hash if it has no name
+        folder: String,
+        /// The offset stored in the folder record
+        stored: u64,
+        /// The offset the folder record should contain
+        expected: u64,
+    },
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownArchiveFlags(bits) => write!(f, "Unknown archive flags: {:#x}", bits),
+            Self::UnknownFileFlags(bits) => write!(f, "Unknown file flags: {:#x}", bits),
+            Self::ControlCharactersInName(name) => {
+                write!(f, "Name contains control characters: {:?}", name)
+            }
+            Self::SizeExcludesEmbeddedName => {
+                write!(f, "File sizes don't include the embedded file names")
+            }
+            Self::FolderRecordOffsetMismatch {
+                folder,
+                stored,
+                expected,
+            } => write!(
+                f,
+                "Folder record for {:?} points to offset {} instead of {}",
+                folder, stored, expected
+            ),
+        }
+    }
+}
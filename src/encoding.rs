@@ -0,0 +1,87 @@
+use crate::cp1252;
+
+/// The character encoding used to decode file and folder names.
+///
+/// Bethesda's tools always use Windows-1252, but some localized mod archives
+/// store names in the encoding of the author's system (e.g. Windows-1251 or
+/// Shift-JIS). The encoding only affects how names are displayed: name hashes
+/// are always computed from the raw bytes stored in the archive.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum Encoding {
+    /// Windows-1252 (the default)
+    #[default]
+    Windows1252,
+    /// Any encoding supported by the `encoding_rs` crate
+    #[cfg(feature = "encoding_rs")]
+    Other(&'static encoding_rs::Encoding),
+    /// A custom decoding function
+    Custom(fn(&[u8]) -> String),
+}
+
+impl Encoding {
+    /// Looks up an encoding by its WHATWG label, e.g. `windows-1251` or `shift_jis`
+    #[cfg(feature = "encoding_rs")]
+    pub fn for_label(label: &str) -> Option<Self> {
+        encoding_rs::Encoding::for_label(label.as_bytes()).map(Self::Other)
+    }
+
+    /// Decodes the given name
+    pub fn decode(self, bytes: &[u8]) -> String {
+        match self {
+            Self::Windows1252 => bytes.iter().copied().map(cp1252::decode_byte).collect(),
+            #[cfg(feature = "encoding_rs")]
+            Self::Other(encoding) => encoding.decode_without_bom_handling(bytes).0.into_owned(),
+            Self::Custom(decode) => decode(bytes),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Encoding;
+    use crate::bsa::{OpenOptions, Version};
+    use crate::write::BsaBuilder;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(Encoding::Windows1252.decode(b"a\x80"), "a\u{20ac}");
+        let custom = Encoding::Custom(|bytes| bytes.iter().map(|&b| char::from(b)).collect());
+        assert_eq!(custom.decode(b"a\x80"), "a\u{80}");
+    }
+
+    #[test]
+    fn test_hashes_use_raw_bytes() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/\u{e9}.txt", vec![1]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let bsa = OpenOptions::new()
+            .name_encoding(Encoding::Custom(|bytes| format!("{:?}", bytes)))
+            .read(std::io::Cursor::new(data))
+            .unwrap();
+        let folder = bsa.folders().next().unwrap();
+        assert_eq!(folder.name(), Some("[97]"));
+        assert_eq!(folder.name_raw(), Some(&b"a"[..]));
+        let file = folder.files().next().unwrap();
+        assert_eq!(file.name(), Some("[233, 46, 116, 120, 116]"));
+        assert_eq!(file.name_raw(), Some(&b"\xe9.txt"[..]));
+    }
+
+    #[test]
+    fn test_raw_names() {
+        for &embed_file_names in &[false, true] {
+            let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+            builder.embed_file_names(embed_file_names);
+            builder.add_file("\u{e9}/\u{20ac}.txt", vec![1]).unwrap();
+            let mut data = vec![];
+            builder.write(&mut data).unwrap();
+            let bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+            let folder = bsa.folders().next().unwrap();
+            assert_eq!(folder.name(), Some("\u{e9}"));
+            assert_eq!(folder.name_raw(), Some(&b"\xe9"[..]));
+            let file = folder.files().next().unwrap();
+            assert_eq!(file.name(), Some("\u{20ac}.txt"));
+            assert_eq!(file.name_raw(), Some(&b"\x80.txt"[..]));
+        }
+    }
+}
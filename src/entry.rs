@@ -0,0 +1,227 @@
+use crate::bsa::{File, Folder};
+use std::{cmp, fmt, hash};
+
+/// A file in a BSA together with its folder and its position, returned by
+/// [`Bsa::entries`](crate::Bsa::entries). The indices can be used to look the
+/// entry up again with [`Bsa::entry`](crate::Bsa::entry), e.g. from a
+/// virtualized list view.
+#[derive(Debug, Clone, Copy)]
+pub struct Entry<'a> {
+    pub(crate) folder_index: usize,
+    pub(crate) file_index: usize,
+    pub(crate) folder: &'a Folder,
+    pub(crate) file: &'a File,
+}
+
+impl<'a> Entry<'a> {
+    /// Returns the index of the folder in [`Bsa::folders_slice`](crate::Bsa::folders_slice)
+    pub fn folder_index(&self) -> usize {
+        self.folder_index
+    }
+
+    /// Returns the index of the file in [`Folder::files_slice`]
+    pub fn file_index(&self) -> usize {
+        self.file_index
+    }
+
+    /// Returns the folder containing the file
+    pub fn folder(&self) -> &'a Folder {
+        self.folder
+    }
+
+    /// Returns the file
+    pub fn file(&self) -> &'a File {
+        self.file
+    }
+
+    /// Returns the full path of the file, or `None` if the file or its folder
+    /// has no name
+    pub fn path(&self) -> Option<EntryPath> {
+        self.file.full_path(self.folder)
+    }
+
+    /// Returns the size of the file contents after decompression
+    pub fn size(&self) -> u64 {
+        self.file.uncompressed_size()
+    }
+
+    /// Returns whether the contents of the file are compressed
+    pub fn is_compressed(&self) -> bool {
+        self.file.is_compressed()
+    }
+}
+
+/// The full path of a file inside a BSA, such as `textures\armor\iron.dds`,
+/// returned by [`File::full_path`](crate::File::full_path).
+///
+/// Paths always use backslashes as separators. They are compared, hashed and
+/// ordered by their folder and file name hashes, which matches the
+/// case-insensitive way the game looks up files, and the order in which the
+/// records are stored in the BSA.
+#[derive(Debug, Clone)]
+pub struct EntryPath {
+    folder_hash: u64,
+    file_hash: u64,
+    path: String,
+    folder_len: usize,
+}
+
+impl EntryPath {
+    pub(crate) fn new(folder_hash: u64, folder: &str, file_hash: u64, file: &str) -> Self {
+        let folder = folder.replace('/', "\\");
+        let folder = folder.trim_end_matches('\\');
+        Self {
+            folder_hash,
+            file_hash,
+            path: format!("{}\\{}", folder, file),
+            folder_len: folder.len(),
+        }
+    }
+
+    /// Returns the full path as a string
+    pub fn as_str(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the folder part of the path
+    pub fn folder(&self) -> &str {
+        &self.path[..self.folder_len]
+    }
+
+    /// Returns the file name part of the path
+    pub fn file_name(&self) -> &str {
+        &self.path[self.folder_len + 1..]
+    }
+
+    /// Returns the hash of the folder name, as stored in the BSA
+    pub fn folder_hash(&self) -> u64 {
+        self.folder_hash
+    }
+
+    /// Returns the hash of the file name, as stored in the BSA
+    pub fn file_hash(&self) -> u64 {
+        self.file_hash
+    }
+}
+
+impl fmt::Display for EntryPath {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.path)
+    }
+}
+
+impl AsRef<str> for EntryPath {
+    fn as_ref(&self) -> &str {
+        &self.path
+    }
+}
+
+impl PartialEq for EntryPath {
+    fn eq(&self, other: &Self) -> bool {
+        (self.folder_hash, self.file_hash) == (other.folder_hash, other.file_hash)
+    }
+}
+
+impl Eq for EntryPath {}
+
+impl hash::Hash for EntryPath {
+    fn hash<H: hash::Hasher>(&self, state: &mut H) {
+        (self.folder_hash, self.file_hash).hash(state);
+    }
+}
+
+impl PartialOrd for EntryPath {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for EntryPath {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        (self.folder_hash, self.file_hash).cmp(&(other.folder_hash, other.file_hash))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BsaBuilder, Version};
+
+    #[test]
+    fn test_full_path() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.keep_original_case(true);
+        builder
+            .add_file("Textures/Armor/Iron.dds", vec![1])
+            .unwrap();
+        builder.add_file("textures/armor/b.dds", vec![2]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+
+        let mut paths: Vec<_> = bsa
+            .iter_prefix("")
+            .filter_map(|(folder, file)| file.full_path(folder))
+            .collect();
+        assert_eq!(paths.len(), 2);
+        let iron = paths
+            .iter()
+            .find(|path| path.file_name() == "Iron.dds")
+            .unwrap();
+        assert_eq!(iron.to_string(), r"Textures\Armor\Iron.dds");
+        assert_eq!(iron.folder(), r"Textures\Armor");
+        paths.sort();
+        assert!(paths[0].file_hash() < paths[1].file_hash());
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        for path in &["a/x.txt", "a/y.txt", "b/z.txt"] {
+            builder.add_file(path, vec![1, 2]).unwrap();
+        }
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+
+        let entries: Vec<_> = bsa.entries().collect();
+        assert_eq!(entries.len(), 3);
+        for entry in &entries {
+            let again = bsa.entry(entry.folder_index(), entry.file_index()).unwrap();
+            assert_eq!(again.path(), entry.path());
+            assert_eq!(entry.size(), 2);
+        }
+        assert!(bsa.entry(2, 0).is_none());
+
+        let entry = bsa.find_entry("B/Z.txt").unwrap();
+        assert_eq!(entry.path().unwrap().as_str(), r"b\z.txt");
+        let file = bsa.file_at(entry.folder_index(), entry.file_index());
+        assert_eq!(file, Some(entry.file()));
+        assert!(bsa.find_entry("b/x.txt").is_none());
+        assert!(bsa.find_entry("c/z.txt").is_none());
+        assert_eq!(
+            bsa.find_entry(r"A\Y.TXT").map(|entry| entry.path()),
+            Some(bsa.entry(0, 1).unwrap().path())
+        );
+        assert!(bsa.file_at(0, 2).is_none());
+        assert!(bsa.file_at(2, 0).is_none());
+    }
+
+    #[test]
+    fn test_find_nameless() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        for path in &["a/x.txt", "a/y.txt"] {
+            builder.add_file(path, vec![1, 2]).unwrap();
+        }
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        // clear the flag for file names, so that files are only matched by hash
+        data[12] &= !0x2;
+        let bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+
+        let entry = bsa.find_entry("a/y.txt").unwrap();
+        assert!(entry.file().name().is_none());
+        let hash = crate::hash::compute_hash("y.txt", crate::hash::Type::File).unwrap();
+        assert_eq!(entry.file().name_hash(), hash);
+        assert!(bsa.find_entry("a/z.txt").is_none());
+    }
+}
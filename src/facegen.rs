@@ -0,0 +1,388 @@
+//! Checking that the facegen files of the NPCs in a Skyrim plugin are
+//! packaged, since NPCs without them have dark or grey faces in game.
+
+use crate::vfs::FileSystem;
+use std::convert::TryFrom;
+use std::{error, fmt, io};
+
+/// Represents an error when reading a plugin
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum PluginError {
+    /// The plugin is malformed, contains the reason
+    InvalidPlugin(&'static str),
+    /// A compressed record couldn't be decompressed
+    Io(io::Error),
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidPlugin(reason) => write!(f, "Invalid plugin: {}", reason),
+            Self::Io(_) => write!(f, "Failed to decompress plugin record"),
+        }
+    }
+}
+
+impl error::Error for PluginError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PluginError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+const RECORD_HEADER_SIZE: usize = 24;
+const FLAG_DELETED: u32 = 0x20;
+const FLAG_COMPRESSED: u32 = 0x0004_0000;
+const TEMPLATE_USE_TRAITS: u16 = 0x1;
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    let bytes = data.get(offset..offset.checked_add(2)?)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Calls `f` with the type and data of each subrecord in a record
+fn for_each_subrecord(data: &[u8], mut f: impl FnMut(&[u8], &[u8])) -> Result<(), PluginError> {
+    let truncated = || PluginError::InvalidPlugin("subrecord is truncated");
+    let mut pos = 0;
+    // the size of the next subrecord, if it doesn't fit into 16 bits
+    let mut large_size = None;
+    while pos < data.len() {
+        let kind = data.get(pos..pos + 4).ok_or_else(truncated)?;
+        let size = read_u16(data, pos + 4).ok_or_else(truncated)?;
+        let size = large_size.take().unwrap_or(usize::from(size));
+        let contents = data
+            .get(pos + 6..)
+            .and_then(|rest| rest.get(..size))
+            .ok_or_else(truncated)?;
+        if kind == b"XXXX" {
+            large_size = Some(read_u32(contents, 0).ok_or_else(truncated)? as usize);
+        } else {
+            f(kind, contents);
+        }
+        pos += 6 + size;
+    }
+    Ok(())
+}
+
+/// Returns the data of a record, decompressing it if needed. Returns `None`
+/// if the record is compressed and the `zlib` feature is disabled.
+fn record_data(flags: u32, data: &[u8]) -> Result<Option<Vec<u8>>, PluginError> {
+    if flags & FLAG_COMPRESSED == 0 {
+        return Ok(Some(data.to_vec()));
+    }
+    #[cfg(feature = "zlib")]
+    {
+        let size = read_u32(data, 0)
+            .ok_or(PluginError::InvalidPlugin("compressed record is truncated"))?;
+        let mut res = Vec::with_capacity((size as usize).min(1 << 20));
+        io::Read::read_to_end(&mut flate2::read::ZlibDecoder::new(&data[4..]), &mut res)?;
+        Ok(Some(res))
+    }
+    #[cfg(not(feature = "zlib"))]
+    {
+        Ok(None)
+    }
+}
+
+/// The masters and NPCs of a Skyrim plugin (`.esm`, `.esp` or `.esl`), read
+/// by a minimal parser that skips every other record
+#[derive(Debug, Clone, Default)]
+pub struct PluginNpcs {
+    masters: Vec<String>,
+    /// The form ID of each NPC, and whether it needs facegen files
+    npcs: Vec<(u32, bool)>,
+}
+
+impl PluginNpcs {
+    /// Parses the contents of a plugin
+    pub fn parse(data: &[u8]) -> Result<Self, PluginError> {
+        if !data.starts_with(b"TES4") {
+            return Err(PluginError::InvalidPlugin("missing TES4 header"));
+        }
+        let truncated = || PluginError::InvalidPlugin("record is truncated");
+        let mut res = Self::default();
+        let mut pos = 0;
+        while pos < data.len() {
+            let kind = data.get(pos..pos + 4).ok_or_else(truncated)?;
+            let size = read_u32(data, pos + 4).ok_or_else(truncated)? as usize;
+            if kind == b"GRUP" {
+                let label = data.get(pos + 8..pos + 12).ok_or_else(truncated)?;
+                let group_type = read_u32(data, pos + 12).ok_or_else(truncated)?;
+                if size < RECORD_HEADER_SIZE {
+                    return Err(PluginError::InvalidPlugin("group is too small"));
+                }
+                if group_type == 0 && label != b"NPC_" {
+                    // skip top-level groups of other record types entirely
+                    pos = pos.checked_add(size).ok_or_else(truncated)?;
+                } else {
+                    pos += RECORD_HEADER_SIZE;
+                }
+                continue;
+            }
+            let flags = read_u32(data, pos + 8).ok_or_else(truncated)?;
+            let form_id = read_u32(data, pos + 12).ok_or_else(truncated)?;
+            let contents = (pos + RECORD_HEADER_SIZE)
+                .checked_add(size)
+                .and_then(|end| data.get(pos + RECORD_HEADER_SIZE..end))
+                .ok_or_else(truncated)?;
+            if kind == b"TES4" {
+                for_each_subrecord(contents, |kind, data| {
+                    if kind == b"MAST" {
+                        let name = data.strip_suffix(&[0]).unwrap_or(data);
+                        res.masters.push(String::from_utf8_lossy(name).into_owned());
+                    }
+                })?;
+            } else if kind == b"NPC_" && flags & FLAG_DELETED == 0 {
+                // NPCs that use the traits of a template get their face from it
+                let mut uses_traits = false;
+                if let Some(contents) = record_data(flags, contents)? {
+                    for_each_subrecord(&contents, |kind, data| {
+                        if kind == b"ACBS" {
+                            uses_traits = read_u16(data, 18)
+                                .is_some_and(|template| template & TEMPLATE_USE_TRAITS != 0);
+                        }
+                    })?;
+                }
+                res.npcs.push((form_id, !uses_traits));
+            }
+            pos += RECORD_HEADER_SIZE + size;
+        }
+        Ok(res)
+    }
+
+    /// Returns the masters of the plugin, in order
+    pub fn masters(&self) -> &[String] {
+        &self.masters
+    }
+
+    /// Returns the form IDs of the NPCs that the plugin defines (rather than
+    /// overrides) and that need facegen files, without the load order index.
+    /// NPCs that use the traits of a template are skipped.
+    pub fn defined_npcs(&self) -> Vec<u32> {
+        let own_index = u32::try_from(self.masters.len()).unwrap_or(u32::MAX);
+        self.npcs
+            .iter()
+            .filter(|&&(form_id, needs_facegen)| needs_facegen && form_id >> 24 == own_index)
+            .map(|&(form_id, _)| form_id & 0x00ff_ffff)
+            .collect()
+    }
+}
+
+/// Returns the path of the face geometry of an NPC, e.g.
+/// `meshes\actors\character\facegendata\facegeom\MyMod.esp\00000D62.nif`
+pub fn facegeom_path(plugin: &str, form_id: u32) -> String {
+    format!(
+        "meshes\\actors\\character\\facegendata\\facegeom\\{}\\{:08X}.nif",
+        plugin,
+        form_id & 0x00ff_ffff
+    )
+}
+
+/// Returns the path of the face tint texture of an NPC, e.g.
+/// `textures\actors\character\facegendata\facetint\MyMod.esp\00000D62.dds`
+pub fn facetint_path(plugin: &str, form_id: u32) -> String {
+    format!(
+        "textures\\actors\\character\\facegendata\\facetint\\{}\\{:08X}.dds",
+        plugin,
+        form_id & 0x00ff_ffff
+    )
+}
+
+/// An NPC whose facegen files are missing, returned by [`missing_facegen`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingFacegen {
+    form_id: u32,
+    missing: Vec<String>,
+}
+
+impl MissingFacegen {
+    /// Returns the form ID of the NPC, without the load order index
+    pub fn form_id(&self) -> u32 {
+        self.form_id
+    }
+
+    /// Returns the paths of the missing files
+    pub fn missing(&self) -> &[String] {
+        &self.missing
+    }
+}
+
+/// Checks that the face geometry and tint of each NPC defined by a plugin
+/// exist in a file system (usually a [`Vfs`](crate::Vfs) of the plugin's
+/// archives), returning the NPCs with missing files
+pub fn missing_facegen<F: FileSystem + ?Sized>(
+    fs: &F,
+    plugin: &str,
+    form_ids: &[u32],
+) -> Vec<MissingFacegen> {
+    form_ids
+        .iter()
+        .filter_map(|&form_id| {
+            let missing: Vec<_> = [
+                facegeom_path(plugin, form_id),
+                facetint_path(plugin, form_id),
+            ]
+            .iter()
+            .filter(|path| !fs.exists(path))
+            .cloned()
+            .collect();
+            if missing.is_empty() {
+                None
+            } else {
+                Some(MissingFacegen {
+                    form_id: form_id & 0x00ff_ffff,
+                    missing,
+                })
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{facegeom_path, facetint_path, missing_facegen, PluginError, PluginNpcs};
+    use crate::bsa::{self, Version};
+    use crate::vfs::BsaFs;
+    use crate::write::BsaBuilder;
+
+    fn record(kind: &[u8; 4], flags: u32, form_id: u32, data: &[u8]) -> Vec<u8> {
+        let mut res = kind.to_vec();
+        res.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        res.extend_from_slice(&flags.to_le_bytes());
+        res.extend_from_slice(&form_id.to_le_bytes());
+        res.extend_from_slice(&[0; 8]);
+        res.extend_from_slice(data);
+        res
+    }
+
+    fn group(label: &[u8; 4], records: &[Vec<u8>]) -> Vec<u8> {
+        let contents = records.concat();
+        let mut res = b"GRUP".to_vec();
+        res.extend_from_slice(&(contents.len() as u32 + 24).to_le_bytes());
+        res.extend_from_slice(label);
+        res.extend_from_slice(&[0; 12]);
+        res.extend_from_slice(&contents);
+        res
+    }
+
+    fn subrecord(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut res = kind.to_vec();
+        res.extend_from_slice(&(data.len() as u16).to_le_bytes());
+        res.extend_from_slice(data);
+        res
+    }
+
+    fn acbs(template_flags: u16) -> Vec<u8> {
+        let mut data = [0; 24];
+        data[18..20].copy_from_slice(&template_flags.to_le_bytes());
+        subrecord(b"ACBS", &data)
+    }
+
+    fn plugin() -> Vec<u8> {
+        let header = [
+            subrecord(b"HEDR", &[0; 12]),
+            subrecord(b"MAST", b"Skyrim.esm\0"),
+            subrecord(b"DATA", &[0; 8]),
+        ]
+        .concat();
+        [
+            record(b"TES4", 0, 0, &header),
+            group(b"WEAP", &[record(b"WEAP", 0, 0x0100_0800, &[])]),
+            group(
+                b"NPC_",
+                &[
+                    // an override of a Skyrim.esm NPC
+                    record(b"NPC_", 0, 0x0001_3bb9, &acbs(0)),
+                    record(b"NPC_", 0, 0x0100_0d62, &acbs(0)),
+                    record(b"NPC_", 0, 0x0100_0d63, &acbs(0)),
+                    // uses the traits of a template
+                    record(b"NPC_", 0, 0x0100_0d64, &acbs(0x1)),
+                    record(b"NPC_", 0x20, 0x0100_0d65, &[]),
+                ],
+            ),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_plugin_npcs() {
+        let npcs = PluginNpcs::parse(&plugin()).unwrap();
+        assert_eq!(npcs.masters(), ["Skyrim.esm"]);
+        assert_eq!(npcs.defined_npcs(), [0xd62, 0xd63]);
+
+        let plugin = plugin();
+        assert!(matches!(
+            PluginNpcs::parse(&plugin[..plugin.len() - 1]),
+            Err(PluginError::InvalidPlugin(_))
+        ));
+        assert!(matches!(
+            PluginNpcs::parse(b"GRUP"),
+            Err(PluginError::InvalidPlugin(_))
+        ));
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn test_compressed_npc() {
+        use std::io::Write;
+
+        let data = acbs(0x1);
+        let mut compressed = (data.len() as u32).to_le_bytes().to_vec();
+        let mut encoder = flate2::write::ZlibEncoder::new(vec![], flate2::Compression::default());
+        encoder.write_all(&data).unwrap();
+        compressed.extend(encoder.finish().unwrap());
+        let plugin = [
+            record(b"TES4", 0, 0, &[]),
+            group(
+                b"NPC_",
+                &[
+                    record(b"NPC_", 0x0004_0000, 0x0000_0801, &compressed),
+                    record(b"NPC_", 0, 0x0000_0802, &[]),
+                ],
+            ),
+        ]
+        .concat();
+        assert_eq!(PluginNpcs::parse(&plugin).unwrap().defined_npcs(), [0x802]);
+    }
+
+    #[test]
+    fn test_missing_facegen() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder
+            .add_file(&facegeom_path("MyMod.esp", 0x0100_0d62), vec![1])
+            .unwrap();
+        builder
+            .add_file(&facetint_path("MyMod.esp", 0xd62), vec![2])
+            .unwrap();
+        builder
+            .add_file(&facegeom_path("MyMod.esp", 0xd63), vec![3])
+            .unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let fs = BsaFs::new(bsa::read(std::io::Cursor::new(data)).unwrap());
+
+        let missing = missing_facegen(&fs, "MyMod.esp", &[0xd62, 0x0100_0d63, 0xd64]);
+        assert_eq!(missing.len(), 2);
+        assert_eq!(missing[0].form_id(), 0xd63);
+        assert_eq!(
+            missing[0].missing(),
+            [r"textures\actors\character\facegendata\facetint\MyMod.esp\00000D63.dds"]
+        );
+        assert_eq!(missing[1].form_id(), 0xd64);
+        assert_eq!(missing[1].missing().len(), 2);
+    }
+}
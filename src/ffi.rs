@@ -0,0 +1,493 @@
+//! A small C ABI for listing and extracting files, intended for game editor
+//! plugins. Entries are referred to by index, in the same order as
+//! [`Bsa::entries`], and all strings and file contents are written into
+//! caller-provided buffers.
+//!
+//! Build a shared library with `cargo rustc --release --features ffi --lib
+//! --crate-type cdylib`, and see `include/bsa.h` for the matching declarations.
+
+use crate::bsa::{self, Bsa, Codec, File};
+use std::convert::TryFrom;
+use std::{ffi, os::raw::c_char, ptr};
+
+/// An open BSA file, together with a flat list of its entries
+pub struct BsaHandle {
+    bsa: Bsa,
+    entries: Vec<(ffi::CString, File)>,
+    // buffers and decompressors reused by `bsa_entry_read`, so that reading
+    // files doesn't allocate once the buffers are large enough
+    stored: Vec<u8>,
+    contents: Vec<u8>,
+    decoders: Decoders,
+}
+
+/// An LZ4 frame decompression context, which unlike `lz4::Decoder` can be
+/// reused for many frames
+#[cfg(feature = "lz4")]
+struct Lz4Context(lz4::liblz4::LZ4FDecompressionContext);
+
+#[cfg(feature = "lz4")]
+impl Lz4Context {
+    fn new() -> Option<Self> {
+        use lz4::liblz4::*;
+        let mut ctx = LZ4FDecompressionContext(ptr::null_mut());
+        let code = unsafe { LZ4F_createDecompressionContext(&mut ctx, LZ4F_VERSION) };
+        if unsafe { LZ4F_isError(code) } != 0 {
+            return None;
+        }
+        Some(Self(ctx))
+    }
+
+    /// Decompresses a frame into `dst`, returning the number of bytes written
+    /// and whether the end of the frame was reached
+    fn decompress(&mut self, src: &[u8], dst: &mut [u8]) -> Option<(usize, bool)> {
+        use lz4::liblz4::*;
+        let (mut read, mut written) = (0, 0);
+        loop {
+            let mut src_size = src.len() - read;
+            let mut dst_size = dst.len() - written;
+            let hint = unsafe {
+                LZ4F_decompress(
+                    self.0,
+                    dst[written..].as_mut_ptr(),
+                    &mut dst_size,
+                    src[read..].as_ptr(),
+                    &mut src_size,
+                    ptr::null(),
+                )
+            };
+            if unsafe { LZ4F_isError(hint) } != 0 {
+                return None;
+            }
+            read += src_size;
+            written += dst_size;
+            if hint == 0 || written == dst.len() || (src_size == 0 && dst_size == 0) {
+                return Some((written, hint == 0));
+            }
+        }
+    }
+}
+
+#[cfg(feature = "lz4")]
+impl Drop for Lz4Context {
+    fn drop(&mut self) {
+        unsafe { lz4::liblz4::LZ4F_freeDecompressionContext(self.0) };
+    }
+}
+
+/// The decompressors of each codec, created when they're first needed
+#[derive(Default)]
+struct Decoders {
+    #[cfg(feature = "zlib")]
+    zlib: Option<flate2::Decompress>,
+    #[cfg(feature = "lz4")]
+    lz4: Option<Lz4Context>,
+    #[cfg(feature = "zstd")]
+    zstd: Option<zstd::stream::raw::Decoder<'static>>,
+}
+
+impl Decoders {
+    /// Decompresses at most `max` bytes of `src` into `dst`, replacing its
+    /// contents. Returns `None` if the data is invalid or the codec is
+    /// disabled.
+    #[allow(unused_variables, unreachable_code)]
+    fn decode(
+        &mut self,
+        codec: Codec,
+        src: &[u8],
+        uncompressed_size: u64,
+        max: usize,
+        dst: &mut Vec<u8>,
+    ) -> Option<()> {
+        let len = usize::try_from(uncompressed_size).map_or(max, |size| size.min(max));
+        dst.clear();
+        match codec {
+            #[cfg(feature = "zlib")]
+            Codec::Zlib => {
+                dst.resize(len, 0);
+                let zlib = self
+                    .zlib
+                    .get_or_insert_with(|| flate2::Decompress::new(true));
+                zlib.reset(true);
+                zlib.decompress(src, dst, flate2::FlushDecompress::Finish)
+                    .ok()?;
+                dst.truncate(zlib.total_out() as usize);
+            }
+            #[cfg(feature = "lz4")]
+            Codec::Lz4 => {
+                dst.resize(len, 0);
+                let lz4 = match &mut self.lz4 {
+                    Some(lz4) => lz4,
+                    None => self.lz4.insert(Lz4Context::new()?),
+                };
+                let (written, finished) = match lz4.decompress(src, dst) {
+                    Some(res) => res,
+                    None => {
+                        self.lz4 = None;
+                        return None;
+                    }
+                };
+                if !finished {
+                    // the context is in the middle of a frame
+                    self.lz4 = None;
+                }
+                dst.truncate(written);
+            }
+            #[cfg(feature = "lz4")]
+            Codec::Lz4Block => {
+                // blocks can't be decompressed partially, and LZ4 can't
+                // compress by more than a factor of 255
+                if uncompressed_size > src.len() as u64 * 255 + 16 {
+                    return None;
+                }
+                let size = i32::try_from(uncompressed_size).ok()?;
+                dst.resize(size as usize, 0);
+                let written = lz4::block::decompress_to_buffer(src, Some(size), dst).ok()?;
+                dst.truncate(written.min(max));
+            }
+            #[cfg(feature = "zstd")]
+            Codec::Zstd => {
+                use zstd::stream::raw::Operation;
+                dst.resize(len, 0);
+                let zstd = match &mut self.zstd {
+                    Some(zstd) => zstd,
+                    None => self.zstd.insert(zstd::stream::raw::Decoder::new().ok()?),
+                };
+                zstd.reinit().ok()?;
+                let (mut read, mut written) = (0, 0);
+                loop {
+                    let status = zstd
+                        .run_on_buffers(&src[read..], &mut dst[written..])
+                        .ok()?;
+                    read += status.bytes_read;
+                    written += status.bytes_written;
+                    if status.remaining == 0
+                        || written == dst.len()
+                        || (status.bytes_read == 0 && status.bytes_written == 0)
+                    {
+                        break;
+                    }
+                }
+                dst.truncate(written);
+            }
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        }
+        Some(())
+    }
+}
+
+unsafe fn entry<'a>(handle: *const BsaHandle, index: usize) -> Option<&'a (ffi::CString, File)> {
+    handle.as_ref().and_then(|handle| handle.entries.get(index))
+}
+
+/// Opens the BSA file at the given (UTF-8, NUL-terminated) path. Returns a null
+/// pointer if the file could not be opened.
+///
+/// # Safety
+///
+/// `path` must be a valid pointer to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bsa_open(path: *const c_char) -> *mut BsaHandle {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let path = match ffi::CStr::from_ptr(path).to_str() {
+        Ok(path) => path,
+        Err(_) => return ptr::null_mut(),
+    };
+    let bsa = match bsa::open(path) {
+        Ok(bsa) => bsa,
+        Err(_) => return ptr::null_mut(),
+    };
+    let mut entries = vec![];
+    for folder in bsa.folders() {
+        for file in folder.files() {
+            let path = file
+                .full_path(&folder)
+                .map(|path| path.to_string())
+                .unwrap_or_default();
+            // names never contain NUL bytes, as they are NUL-terminated on disk
+            let path = ffi::CString::new(path).unwrap_or_default();
+            entries.push((path, file.clone()));
+        }
+    }
+    Box::into_raw(Box::new(BsaHandle {
+        bsa,
+        entries,
+        stored: vec![],
+        contents: vec![],
+        decoders: Decoders::default(),
+    }))
+}
+
+/// Closes a BSA file previously opened with `bsa_open`.
+///
+/// # Safety
+///
+/// `handle` must be null or a pointer returned by `bsa_open` that has not been
+/// closed yet.
+#[no_mangle]
+pub unsafe extern "C" fn bsa_close(handle: *mut BsaHandle) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Returns the number of files in the BSA.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid pointer returned by `bsa_open`.
+#[no_mangle]
+pub unsafe extern "C" fn bsa_entry_count(handle: *const BsaHandle) -> usize {
+    handle.as_ref().map_or(0, |handle| handle.entries.len())
+}
+
+/// Returns the uncompressed size of the file at the given index, or 0 if the
+/// index is out of range.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid pointer returned by `bsa_open`.
+#[no_mangle]
+pub unsafe extern "C" fn bsa_entry_size(handle: *const BsaHandle, index: usize) -> u64 {
+    entry(handle, index).map_or(0, |(_, file)| file.uncompressed_size())
+}
+
+/// Returns the index of the file with the given (UTF-8, NUL-terminated) path,
+/// or -1 if there is no such file. Paths are matched case-insensitively and
+/// can use either forward slashes or backslashes.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid pointer returned by `bsa_open`, and `path`
+/// must be null or a valid pointer to a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn bsa_entry_find(handle: *const BsaHandle, path: *const c_char) -> isize {
+    let handle = match handle.as_ref() {
+        Some(handle) if !path.is_null() => handle,
+        _ => return -1,
+    };
+    let entry = match ffi::CStr::from_ptr(path).to_str() {
+        Ok(path) => handle.bsa.find_entry(path),
+        Err(_) => None,
+    };
+    let index = entry.and_then(|entry| {
+        handle.bsa.entries().position(|other| {
+            (other.folder_index(), other.file_index()) == (entry.folder_index(), entry.file_index())
+        })
+    });
+    index.map_or(-1, |index| index as isize)
+}
+
+/// Copies the NUL-terminated path of the file at the given index into `buf`.
+/// Returns the length of the path (excluding the NUL terminator), which may be
+/// larger than `buf_len` if the buffer was too small, or -1 if the index is out
+/// of range. Entries without a name have an empty path.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid pointer returned by `bsa_open`, and `buf`
+/// must be null or point to at least `buf_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bsa_entry_path(
+    handle: *const BsaHandle,
+    index: usize,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> isize {
+    let (path, _) = match entry(handle, index) {
+        Some(entry) => entry,
+        None => return -1,
+    };
+    let bytes = path.as_bytes_with_nul();
+    if !buf.is_null() && buf_len >= bytes.len() {
+        ptr::copy_nonoverlapping(bytes.as_ptr() as *const c_char, buf, bytes.len());
+    }
+    (bytes.len() - 1) as isize
+}
+
+/// Reads the decompressed contents of the file at the given index into `buf`.
+/// Returns the number of bytes written, or -1 on error. At most `buf_len`
+/// bytes are written; use `bsa_entry_size` to size the buffer. The buffer
+/// doesn't need to be initialized.
+///
+/// # Safety
+///
+/// `handle` must be null or a valid pointer returned by `bsa_open`, and `buf`
+/// must point to at least `buf_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn bsa_entry_read(
+    handle: *mut BsaHandle,
+    index: usize,
+    buf: *mut u8,
+    buf_len: usize,
+) -> i64 {
+    let BsaHandle {
+        bsa,
+        entries,
+        stored,
+        contents,
+        decoders,
+    } = match handle.as_mut() {
+        Some(handle) => handle,
+        None => return -1,
+    };
+    if buf.is_null() {
+        return -1;
+    }
+    let file = match entries.get(index) {
+        Some((_, file)) => file,
+        None => return -1,
+    };
+    if file.read_stored_into(bsa, stored).is_err() {
+        return -1;
+    }
+    let data = if file.is_compressed() {
+        let codec = match file.stored_codec(stored) {
+            Ok(codec) => codec,
+            Err(_) => return -1,
+        };
+        let decoded = decoders.decode(codec, stored, file.uncompressed_size(), buf_len, contents);
+        if decoded.is_none() {
+            return -1;
+        }
+        &contents[..]
+    } else {
+        &stored[..]
+    };
+    // `buf` may be uninitialized, so only write to it through a raw pointer
+    let len = data.len().min(buf_len);
+    ptr::copy_nonoverlapping(data.as_ptr(), buf, len);
+    len as i64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bsa::Version;
+    use crate::write::BsaBuilder;
+    use std::mem::MaybeUninit;
+
+    fn open(builder: &BsaBuilder, name: &str) -> *mut BsaHandle {
+        let path =
+            std::env::temp_dir().join(format!("bsa-ffi-{}-{}.bsa", name, std::process::id()));
+        builder
+            .write(&mut std::fs::File::create(&path).unwrap())
+            .unwrap();
+        let c_path = ffi::CString::new(path.to_str().unwrap()).unwrap();
+        let handle = unsafe { bsa_open(c_path.as_ptr()) };
+        std::fs::remove_file(&path).unwrap();
+        assert!(!handle.is_null());
+        handle
+    }
+
+    fn read(handle: *mut BsaHandle, path: &str, buf_len: usize) -> Option<Vec<u8>> {
+        let path = ffi::CString::new(path).unwrap();
+        let index = unsafe { bsa_entry_find(handle, path.as_ptr()) };
+        assert!(index >= 0);
+        let mut buf = vec![MaybeUninit::<u8>::uninit(); buf_len];
+        let n = unsafe { bsa_entry_read(handle, index as usize, buf.as_mut_ptr().cast(), buf_len) };
+        let n = usize::try_from(n).ok()?;
+        Some(
+            buf[..n]
+                .iter()
+                .map(|b| unsafe { b.assume_init() })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file(r"meshes\a.nif", vec![1, 2, 3]).unwrap();
+        builder.add_file(r"textures\b.dds", vec![4; 10]).unwrap();
+        let handle = open(&builder, "entries");
+        unsafe {
+            assert_eq!(bsa_entry_count(handle), 2);
+            let path = ffi::CString::new("Textures/B.dds").unwrap();
+            let index = bsa_entry_find(handle, path.as_ptr());
+            assert!(index >= 0);
+            assert_eq!(bsa_entry_size(handle, index as usize), 10);
+            let missing = ffi::CString::new("textures/c.dds").unwrap();
+            assert_eq!(bsa_entry_find(handle, missing.as_ptr()), -1);
+            assert_eq!(bsa_entry_find(handle, ptr::null()), -1);
+            assert_eq!(bsa_entry_find(ptr::null(), path.as_ptr()), -1);
+            // indices follow the order of `Bsa::entries`
+            for (i, entry) in (*handle).bsa.entries().enumerate() {
+                let path = entry.path().unwrap().as_str().to_string();
+                let path = ffi::CString::new(path).unwrap();
+                assert_eq!(bsa_entry_find(handle, path.as_ptr()), i as isize);
+            }
+
+            let mut buf = [0 as c_char; 32];
+            let len = bsa_entry_path(handle, index as usize, buf.as_mut_ptr(), buf.len());
+            assert_eq!(len, 14);
+            let name = ffi::CStr::from_ptr(buf.as_ptr());
+            assert_eq!(name.to_str().unwrap(), r"textures\b.dds");
+            // the buffer is too small, so only the length is returned
+            let mut small = [0 as c_char; 4];
+            let len = bsa_entry_path(handle, index as usize, small.as_mut_ptr(), small.len());
+            assert_eq!((len, small), (14, [0; 4]));
+
+            assert_eq!(bsa_entry_size(handle, 2), 0);
+            assert_eq!(bsa_entry_read(handle, 2, buf.as_mut_ptr().cast(), 1), -1);
+            bsa_close(handle);
+            assert_eq!(bsa_entry_count(ptr::null()), 0);
+        }
+    }
+
+    #[test]
+    fn test_read() {
+        let data: Vec<u8> = (0..5000u32).map(|i| (i % 7) as u8).collect();
+        let mut builders = vec![("uncompressed", BsaBuilder::new(Version::SKYRIM))];
+        #[cfg(feature = "zlib")]
+        {
+            let mut builder = BsaBuilder::new(Version::SKYRIM);
+            builder.compress(true);
+            builders.push(("zlib", builder));
+        }
+        #[cfg(feature = "lz4")]
+        {
+            let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+            builder.compress(true);
+            builders.push(("lz4", builder.clone()));
+            builder.lz4_block(true).ignore_game_limits(true);
+            builders.push(("lz4-block", builder));
+        }
+        #[cfg(feature = "zstd")]
+        {
+            let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+            builder.compress(true).zstd(true).ignore_game_limits(true);
+            builders.push(("zstd", builder));
+        }
+        for (name, mut builder) in builders {
+            builder.add_file(r"meshes\a.nif", data.clone()).unwrap();
+            builder.add_file(r"meshes\b.nif", vec![9; 3]).unwrap();
+            let handle = open(&builder, name);
+            // reading the files in turn reuses the buffers and decompressors
+            for _ in 0..2 {
+                assert_eq!(
+                    read(handle, r"meshes\a.nif", 8000).unwrap(),
+                    data,
+                    "{}",
+                    name
+                );
+                assert_eq!(
+                    read(handle, r"meshes\b.nif", 8000).unwrap(),
+                    [9; 3],
+                    "{}",
+                    name
+                );
+                assert_eq!(
+                    read(handle, r"meshes\a.nif", 100).unwrap(),
+                    data[..100],
+                    "{}",
+                    name
+                );
+            }
+            unsafe { bsa_close(handle) };
+        }
+    }
+}
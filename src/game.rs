@@ -0,0 +1,78 @@
+use crate::bsa::{Version, LZ4_FRAME_MAGIC};
+use std::fmt;
+
+/// A game that uses BSA files. The game determines how format quirks that
+/// aren't fully described by the version number are handled.
+///
+/// Fallout 3, Fallout: New Vegas and Skyrim all use version 104 and can't be
+/// told apart from the archive alone, so version 104 archives are treated as
+/// Skyrim archives unless a game is selected with
+/// [`OpenOptions::game`](crate::OpenOptions::game).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Game {
+    Oblivion,
+    Fallout3,
+    FalloutNV,
+    Skyrim,
+    SkyrimSE,
+}
+
+impl Game {
+    /// Returns the BSA version used by this game
+    pub fn version(self) -> Version {
+        match self {
+            Self::Oblivion => Version::OBLIVION,
+            Self::Fallout3 | Self::FalloutNV | Self::Skyrim => Version::SKYRIM,
+            Self::SkyrimSE => Version::SKYRIM_SPECIAL_EDITION,
+        }
+    }
+
+    /// Returns the game assumed for archives with the given version
+    pub fn from_version(version: Version) -> Self {
+        match version {
+            Version::OBLIVION => Self::Oblivion,
+            Version::SKYRIM_SPECIAL_EDITION => Self::SkyrimSE,
+            _ => Self::Skyrim,
+        }
+    }
+
+    /// Returns the largest archive (in bytes) that this game can load
+    /// reliably. Oblivion, Fallout 3, Fallout: New Vegas and Skyrim use signed
+    /// offsets in places and break with archives larger than 2 GiB.
+    pub fn max_archive_size(self) -> u64 {
+        match self {
+            Self::SkyrimSE => u64::from(u32::MAX),
+            _ => 1 << 31,
+        }
+    }
+
+    /// Returns whether the given compressed file data (without the
+    /// uncompressed size) uses the codec of this game: LZ4 frames for Skyrim
+    /// Special Edition and zlib streams for all other games
+    pub(crate) fn uses_codec(self, data: &[u8]) -> bool {
+        match self {
+            Self::SkyrimSE => data.starts_with(&LZ4_FRAME_MAGIC),
+            _ => data.first().is_some_and(|&b| b & 0x0f == 8),
+        }
+    }
+
+    /// Returns whether archive flag `0x100` means that file names are embedded
+    /// in the file data. Oblivion uses that bit for something else.
+    pub fn embeds_file_names(self) -> bool {
+        self != Self::Oblivion
+    }
+}
+
+impl fmt::Display for Game {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Oblivion => "Oblivion",
+            Self::Fallout3 => "Fallout 3",
+            Self::FalloutNV => "Fallout: New Vegas",
+            Self::Skyrim => "Skyrim",
+            Self::SkyrimSE => "Skyrim Special Edition",
+        };
+        write!(f, "{}", name)
+    }
+}
@@ -7,18 +7,26 @@ pub(crate) enum Type {
 }
 
 pub(crate) fn compute_hash(name: &str, t: Type) -> Result<u64, cp1252::EncodingError> {
-    let name = name.replace('/', r"\");
-    Ok(match t {
-        Type::Directory => compute_hash_with_ext(&cp1252::encode_str(&name)?, &[]),
+    Ok(compute_hash_bytes(&cp1252::encode_str(name)?, t))
+}
+
+/// Computes the hash of a name that has already been encoded
+pub(crate) fn compute_hash_bytes(name: &[u8], t: Type) -> u64 {
+    let name: Vec<u8> = name
+        .iter()
+        .map(|&b| if b == b'/' { b'\\' } else { b })
+        .collect();
+    match t {
+        Type::Directory => compute_hash_with_ext(&name, &[]),
         Type::File => {
-            if let Some(ext_idx) = name.rfind('.') {
+            if let Some(ext_idx) = name.iter().rposition(|&b| b == b'.') {
                 let (name, ext) = name.split_at(ext_idx);
-                compute_hash_with_ext(&cp1252::encode_str(&name)?, &cp1252::encode_str(&ext)?)
+                compute_hash_with_ext(name, ext)
             } else {
-                compute_hash_with_ext(&cp1252::encode_str(&name)?, &[])
+                compute_hash_with_ext(&name, &[])
             }
         }
-    })
+    }
 }
 
 fn compute_hash_with_ext(name: &[u8], ext: &[u8]) -> u64 {
@@ -0,0 +1,127 @@
+use std::{fmt, path};
+
+/// The type of a file inside a BSA, guessed from its extension and contents
+#[non_exhaustive]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum FileKind {
+    /// NetImmerse/Gamebryo model (`.nif`, `.kf`)
+    Nif,
+    /// DirectDraw Surface texture (`.dds`)
+    Dds,
+    /// WAV audio (`.wav`)
+    Wav,
+    /// xWMA audio (`.xwm`)
+    Xwm,
+    /// Compiled or source Papyrus script (`.pex`, `.psc`)
+    Script,
+    /// Plain text (`.txt`, `.ini`, `.xml`, ...)
+    Text,
+    /// Anything else
+    Unknown,
+}
+
+impl FileKind {
+    /// Guesses the file type from a file name
+    pub fn from_name(name: &str) -> Self {
+        let extension = path::Path::new(name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("nif") | Some("kf") => Self::Nif,
+            Some("dds") => Self::Dds,
+            Some("wav") => Self::Wav,
+            Some("xwm") => Self::Xwm,
+            Some("pex") | Some("psc") => Self::Script,
+            Some("txt") | Some("ini") | Some("xml") | Some("json") | Some("csv") | Some("lst")
+            | Some("cfg") | Some("html") | Some("css") => Self::Text,
+            _ => Self::Unknown,
+        }
+    }
+
+    /// Guesses the file type from the first few bytes of the file contents
+    pub fn from_magic(bytes: &[u8]) -> Self {
+        if bytes.starts_with(b"Gamebryo File Format")
+            || bytes.starts_with(b"NetImmerse File Format")
+        {
+            Self::Nif
+        } else if bytes.starts_with(b"DDS ") {
+            Self::Dds
+        } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"WAVE") {
+            Self::Wav
+        } else if bytes.starts_with(b"RIFF") && bytes.get(8..12) == Some(b"XWMA") {
+            Self::Xwm
+        } else if bytes.starts_with(&[0xfa, 0x57, 0xc0, 0xde]) {
+            Self::Script
+        } else if !bytes.is_empty()
+            && bytes
+                .iter()
+                .all(|&b| b >= 0x20 || b == b'\t' || b == b'\n' || b == b'\r')
+        {
+            Self::Text
+        } else {
+            Self::Unknown
+        }
+    }
+
+    /// Guesses the file type from both the file name and its first few bytes.
+    /// Magic bytes take priority over the extension, except for text files
+    /// whose extension indicates a more specific type (e.g. `.psc` scripts).
+    pub fn detect(name: &str, bytes: &[u8]) -> Self {
+        match (Self::from_magic(bytes), Self::from_name(name)) {
+            (Self::Text, Self::Unknown) | (Self::Unknown, Self::Text) => Self::Text,
+            (Self::Text, kind) | (Self::Unknown, kind) => kind,
+            (kind, _) => kind,
+        }
+    }
+
+    /// Returns a MIME type for this kind of file
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Nif => "application/octet-stream",
+            Self::Dds => "image/vnd-ms.dds",
+            Self::Wav => "audio/wav",
+            Self::Xwm => "audio/x-xwma",
+            Self::Text => "text/plain",
+            Self::Script | Self::Unknown => "application/octet-stream",
+        }
+    }
+}
+
+impl fmt::Display for FileKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Self::Nif => "nif",
+            Self::Dds => "dds",
+            Self::Wav => "wav",
+            Self::Xwm => "xwm",
+            Self::Script => "script",
+            Self::Text => "text",
+            Self::Unknown => "unknown",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FileKind;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            FileKind::detect("a.nif", b"Gamebryo File Format, Version 20"),
+            FileKind::Nif
+        );
+        assert_eq!(FileKind::detect("a.bin", b"DDS |\0\0\0"), FileKind::Dds);
+        assert_eq!(
+            FileKind::detect("a.psc", b"Scriptname Foo"),
+            FileKind::Script
+        );
+        assert_eq!(FileKind::detect("a.dat", b"hello world\n"), FileKind::Text);
+        assert_eq!(
+            FileKind::detect("a.xwm", b"RIFF\0\0\0\0XWMA"),
+            FileKind::Xwm
+        );
+        assert_eq!(FileKind::detect("a.dat", &[0, 1, 2]), FileKind::Unknown);
+    }
+}
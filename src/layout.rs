@@ -0,0 +1,65 @@
+use std::fmt;
+
+/// What a [`Region`] of a BSA file contains
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// The 36 byte archive header
+    Header,
+    /// The folder records
+    FolderRecords,
+    /// The name and file records of the folder at the given index in
+    /// [`Bsa::folders_slice`](crate::Bsa::folders_slice)
+    FileRecordBlock(usize),
+    /// The names of all files
+    FileNames,
+    /// The contents of a file, including its embedded name and uncompressed
+    /// size, given as folder and file indices (see [`Bsa::entry`](crate::Bsa::entry))
+    FileData(usize, usize),
+}
+
+impl fmt::Display for RegionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Header => write!(f, "header"),
+            Self::FolderRecords => write!(f, "folder records"),
+            Self::FileRecordBlock(_) => write!(f, "file record block"),
+            Self::FileNames => write!(f, "file names"),
+            Self::FileData(_, _) => write!(f, "file data"),
+        }
+    }
+}
+
+/// A range of bytes in a BSA file, returned by [`Bsa::layout`](crate::Bsa::layout)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub(crate) start: u64,
+    pub(crate) end: u64,
+    pub(crate) kind: RegionKind,
+}
+
+impl Region {
+    /// Returns the offset of the first byte of the region
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    /// Returns the offset just past the last byte of the region
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Returns the size of the region in bytes
+    pub fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Returns whether the region is empty
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns what the region contains
+    pub fn kind(&self) -> RegionKind {
+        self.kind
+    }
+}
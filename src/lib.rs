@@ -17,9 +17,117 @@
 //!     Ok(())
 //! }
 //! ```
+//!
+//! This crate contains no unsafe code unless the `ffi` feature (which exports
+//! a C API) is enabled.
+//!
+//! With the `tracing` feature, opening archives, parsing their records and
+//! reading files emit [`tracing`](https://docs.rs/tracing) spans, which can be
+//! used to profile asset loading. Warnings and other log messages are then also
+//! emitted as `tracing` events with structured fields such as offsets, hashes
+//! and names, and are still forwarded to [`log`](https://docs.rs/log) when no
+//! `tracing` subscriber is installed.
+//!
+//! With the `notify` feature, [`Vfs::watch`] watches the archives of a virtual
+//! file system for changes, so that [`Vfs::refresh`] only reloads them after
+//! they have been replaced.
+//!
+//! With the `serde` feature, [`ArchiveSpec`] and the types it contains can be
+//! serialized and deserialized.
+//!
+//! With the `audio` feature, [`Fuz`] splits voice files into lip sync data and
+//! audio, and [`AudioConverter`] converts XWM audio into WAV or Ogg files by
+//! running `ffmpeg`.
+//!
+//! With the `meta` feature, [`ArchiveMeta`] reads and writes a JSON sidecar
+//! with metadata that archives can't store, such as the original modification
+//! times and licenses of files, and [`File::meta`] returns the metadata of a
+//! file.
+//!
+//! With the `thumbnail` feature, [`File::thumbnail`] decodes DDS textures into
+//! small PNG images, e.g. for asset browsers.
 
+#![cfg_attr(not(feature = "ffi"), forbid(unsafe_code))]
+
+#[cfg(feature = "audio")]
+mod audio;
+mod ba2;
+#[cfg(feature = "bevy")]
+mod bevy;
 mod bsa;
+mod cache;
+mod checksum;
+mod companion;
 mod cp1252;
+mod diagnostic;
+mod encoding;
+mod entry;
+mod facegen;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+mod game;
 mod hash;
+mod kind;
+mod layout;
+mod lint;
+mod manifest;
+#[cfg(feature = "meta")]
+mod meta;
+mod packer;
+#[cfg(feature = "rayon")]
+mod par;
+pub mod path;
+mod plan;
+mod pool;
+mod profile;
+mod reader;
+mod spec;
+mod strings;
+#[cfg(feature = "thumbnail")]
+mod thumbnail;
+mod validator;
+mod vfs;
+mod write;
+
+#[cfg(feature = "audio")]
+pub use crate::audio::{AudioConverter, AudioError, AudioFormat, Fuz};
+pub use crate::ba2::Ba2Fs;
+pub use crate::bsa::{
+    open, open_multi, probe, probe_path, read, read_at, ArchiveFlags, ArchiveInfo, Bsa, Codec,
+    ControlCharacters, File, FileFlags, FileType, FileVerification, Folder, HashVerification,
+    OffsetMismatch, OpenOptions, ParseFileTypeError, ParseVersionError, RawFile, ReadError,
+    Recovered, Version, WriteError,
+};
+pub use crate::cache::BsaCache;
+pub use crate::checksum::{decode_hex, Checksum, ParseChecksumError, VerifiedReader};
+pub use crate::companion::{loads_with_plugin, CompanionArchives};
+pub use crate::diagnostic::Diagnostic;
+pub use crate::encoding::Encoding;
+pub use crate::entry::{Entry, EntryPath};
+pub use crate::facegen::{
+    facegeom_path, facetint_path, missing_facegen, MissingFacegen, PluginError, PluginNpcs,
+};
+pub use crate::game::Game;
+pub use crate::kind::FileKind;
+pub use crate::layout::{Region, RegionKind};
+pub use crate::lint::{Finding, FlagLint, LintRule, Linter, ParseLintRuleError};
+pub use crate::manifest::{Manifest, ManifestMismatch, ParseManifestError};
+#[cfg(feature = "meta")]
+pub use crate::meta::{ArchiveMeta, EntryMeta, MetaError};
+pub use crate::packer::{Packer, PackerGuess};
+pub use crate::plan::{
+    BuildEntry, BuildPlan, ExtractAction, ExtractCount, ExtractEntry, ExtractPlan, ExtractStats,
+    LinkMode, Overwrite,
+};
+pub use crate::pool::{PooledBsa, ReaderPool};
+pub use crate::reader::{FileReader, SubReader};
+pub use crate::spec::{ArchiveSpec, FileSpec, FolderSpec};
+pub use crate::strings::{parse_strings, StringsEncoding, StringsError, StringsKind};
+#[cfg(feature = "thumbnail")]
+pub use crate::thumbnail::{dds_thumbnail, ThumbnailError};
+pub use crate::validator::{builtin_validators, DdsValidator, NifValidator, Validator};
+pub use crate::vfs::{BsaFs, Conflict, FileSystem, LayerReloaded, LooseFs, UnpairedScript, Vfs};
+pub use crate::write::BsaBuilder;
 
-pub use crate::bsa::{open, read, Bsa, File, Folder, ReadError};
+#[cfg(feature = "bevy")]
+pub use crate::bevy::BsaAssetReader;
@@ -0,0 +1,639 @@
+use crate::bsa::{ArchiveFlags, Bsa, File, Folder};
+use crate::game::Game;
+use crate::hash::{compute_hash_bytes, Type};
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    error, fmt, str,
+};
+
+/// A risky combination of archive flags, returned by [`ArchiveFlags::lint`]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagLint {
+    /// Folder names are not stored
+    MissingDirectoryNames,
+    /// File names are not stored
+    MissingFileNames,
+    /// The retain file name offsets flag is set
+    RetainFileNameOffsets,
+    /// The Xbox 360 flag is set in an archive for a PC game
+    Xbox360OnPc,
+    /// The XMem codec flag is set in an archive for a PC game
+    XmemOnPc,
+    /// Files are compressed with zstd
+    ZstdCodec,
+    /// Flag bits that no game or tool is known to use are set
+    UnknownBits(u32),
+}
+
+impl fmt::Display for FlagLint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::MissingDirectoryNames => write!(
+                f,
+                "Folder names are missing: tools can't list or extract the files by path, and \
+                 the game can't match them against loose files"
+            ),
+            Self::MissingFileNames => write!(
+                f,
+                "File names are missing: tools can't list or extract the files by path, and \
+                 the game can't load sounds and voice files from the archive"
+            ),
+            Self::RetainFileNameOffsets => write!(
+                f,
+                "Retain file name offsets is set: this was only used for console archives, and \
+                 some tools misread archives with it"
+            ),
+            Self::Xbox360OnPc => write!(
+                f,
+                "Xbox 360 flag is set: the PC version of the game reads the archive as \
+                 little-endian and fails to decompress its files"
+            ),
+            Self::XmemOnPc => write!(
+                f,
+                "XMem codec flag is set: the PC version of the game can't decompress XMem data"
+            ),
+            Self::ZstdCodec => write!(
+                f,
+                "Files are compressed with zstd: only this library can read them, not the game"
+            ),
+            Self::UnknownBits(bits) => write!(
+                f,
+                "Unknown flags {:#x} are set: the game ignores them, but other tools might not",
+                bits
+            ),
+        }
+    }
+}
+
+impl ArchiveFlags {
+    /// Returns the flags that are known to work with every supported game,
+    /// which are also the flags [`BsaBuilder`](crate::BsaBuilder) writes by
+    /// default. Compression is left unset, since both compressed and
+    /// uncompressed archives work.
+    pub fn recommended() -> Self {
+        ArchiveFlags {
+            include_directory_names: true,
+            include_file_names: true,
+            compressed_archive: false,
+            retain_directory_names: false,
+            retain_file_names: false,
+            retain_file_name_offsets: false,
+            xbox360_archive: false,
+            retain_strings: false,
+            embed_file_names: false,
+            xmem_codec: false,
+            zstd_codec: false,
+            unknown_bits: 0,
+        }
+    }
+
+    /// Checks these flags for combinations that are known to cause problems
+    /// with the given game or common tools
+    pub fn lint(self, game: Game) -> Vec<FlagLint> {
+        let mut res = vec![];
+        if !self.include_directory_names {
+            res.push(FlagLint::MissingDirectoryNames);
+        }
+        if !self.include_file_names {
+            res.push(FlagLint::MissingFileNames);
+        }
+        if self.retain_file_name_offsets {
+            res.push(FlagLint::RetainFileNameOffsets);
+        }
+        if self.xbox360_archive {
+            res.push(FlagLint::Xbox360OnPc);
+        }
+        // Oblivion doesn't use bit 0x200 for the XMem codec
+        if self.xmem_codec && game != Game::Oblivion {
+            res.push(FlagLint::XmemOnPc);
+        }
+        if self.zstd_codec {
+            res.push(FlagLint::ZstdCodec);
+        }
+        if self.unknown_bits != 0 {
+            res.push(FlagLint::UnknownBits(self.unknown_bits));
+        }
+        res
+    }
+}
+
+impl Bsa {
+    /// Checks the archive flags of this BSA against the game it is for, see
+    /// [`ArchiveFlags::lint`]
+    pub fn lint_flags(&self) -> Vec<FlagLint> {
+        self.archive_flags().lint(self.game())
+    }
+}
+
+/// A check run by a [`Linter`]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintRule {
+    /// Archive flags that cause problems, see [`ArchiveFlags::lint`]
+    Flags,
+    /// Folder and file records that are not sorted by hash. The game looks
+    /// records up with a binary search and can miss files in unsorted
+    /// archives.
+    SortedRecords,
+    /// Folders, or files in the same folder, with the same hash. The game
+    /// can only find one of them.
+    DuplicateHashes,
+    /// Stored hashes that don't match the names
+    Hashes,
+    /// Paths longer than the games' 259 character limit
+    NameLength,
+    /// Names with uppercase letters, which are lowercased by Archive.exe
+    NameCase,
+    /// Compressed files whose contents are already compressed, such as
+    /// sounds and videos
+    CompressedMedia,
+}
+
+impl LintRule {
+    /// All lint rules
+    pub const ALL: [Self; 7] = [
+        Self::Flags,
+        Self::SortedRecords,
+        Self::DuplicateHashes,
+        Self::Hashes,
+        Self::NameLength,
+        Self::NameCase,
+        Self::CompressedMedia,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Flags => "flags",
+            Self::SortedRecords => "sorted-records",
+            Self::DuplicateHashes => "duplicate-hashes",
+            Self::Hashes => "hashes",
+            Self::NameLength => "name-length",
+            Self::NameCase => "name-case",
+            Self::CompressedMedia => "compressed-media",
+        }
+    }
+}
+
+impl fmt::Display for LintRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl str::FromStr for LintRule {
+    type Err = ParseLintRuleError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.to_ascii_lowercase().replace('_', "-");
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|rule| rule.name() == name)
+            .ok_or_else(|| ParseLintRuleError(s.to_string()))
+    }
+}
+
+/// Represents an error when parsing a [`LintRule`]
+#[derive(Debug, Clone)]
+pub struct ParseLintRuleError(String);
+
+impl fmt::Display for ParseLintRuleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Unknown lint rule {:?} (expected one of ", self.0)?;
+        for (idx, rule) in LintRule::ALL.iter().enumerate() {
+            if idx > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}", rule)?;
+        }
+        f.write_str(")")
+    }
+}
+
+impl error::Error for ParseLintRuleError {}
+
+/// A problem found by a [`Linter`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    rule: LintRule,
+    path: Option<String>,
+    message: String,
+    fixable: bool,
+}
+
+impl Finding {
+    /// Returns the rule that found the problem
+    pub fn rule(&self) -> LintRule {
+        self.rule
+    }
+
+    /// Returns the path of the folder or file with the problem, or `None` if
+    /// the problem is with the whole archive
+    pub fn path(&self) -> Option<&str> {
+        self.path.as_deref()
+    }
+
+    /// Returns a description of the problem
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// Returns whether the problem is fixed by rewriting the archive with
+    /// [`BsaBuilder`](crate::BsaBuilder): records are sorted, hashes are
+    /// recomputed, names are lowercased, flags are reset to the
+    /// [recommended](ArchiveFlags::recommended) flags, and media can be stored
+    /// uncompressed
+    pub fn is_fixable(&self) -> bool {
+        self.fixable
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.path {
+            Some(path) => write!(f, "[{}] {}: {}", self.rule, path, self.message),
+            None => write!(f, "[{}] {}", self.rule, self.message),
+        }
+    }
+}
+
+/// Extensions of files that are already compressed
+const COMPRESSED_MEDIA: [&str; 7] = ["bik", "fuz", "jpg", "mp3", "ogg", "png", "xwm"];
+
+/// Checks BSA files for problems that don't stop them from being read, but
+/// can cause problems with the game or other tools.
+///
+/// ```no_run
+/// use bsa::{LintRule, Linter};
+///
+/// let bsa = bsa::open("Mod.bsa").unwrap();
+/// for finding in Linter::new().allow(LintRule::NameCase).check(&bsa) {
+///     println!("{}", finding);
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Linter {
+    rules: Vec<LintRule>,
+}
+
+impl Linter {
+    /// Creates a linter with all rules enabled
+    pub fn new() -> Self {
+        Self {
+            rules: LintRule::ALL.to_vec(),
+        }
+    }
+
+    /// Disables a rule
+    pub fn allow(&mut self, rule: LintRule) -> &mut Self {
+        self.rules.retain(|&enabled| enabled != rule);
+        self
+    }
+
+    /// Enables a rule
+    pub fn deny(&mut self, rule: LintRule) -> &mut Self {
+        if !self.rules.contains(&rule) {
+            self.rules.push(rule);
+        }
+        self
+    }
+
+    /// Enables only the given rules
+    pub fn only(&mut self, rules: &[LintRule]) -> &mut Self {
+        self.rules = rules.to_vec();
+        self
+    }
+
+    /// Returns the enabled rules
+    pub fn rules(&self) -> &[LintRule] {
+        &self.rules
+    }
+
+    /// Runs the enabled rules on a BSA. To find hash mismatches, the BSA must
+    /// be opened with [`HashVerification::None`](crate::HashVerification::None).
+    pub fn check(&self, bsa: &Bsa) -> Vec<Finding> {
+        let mut res = vec![];
+        let enabled = |rule| self.rules.contains(&rule);
+
+        if enabled(LintRule::Flags) {
+            for lint in bsa.lint_flags() {
+                res.push(Finding {
+                    rule: LintRule::Flags,
+                    path: None,
+                    message: lint.to_string(),
+                    // only flags that don't describe the contents can be reset
+                    fixable: matches!(
+                        lint,
+                        FlagLint::RetainFileNameOffsets | FlagLint::UnknownBits(_)
+                    ),
+                });
+            }
+        }
+
+        let mut push = |rule, path: Option<String>, message: String| {
+            let fixable = match rule {
+                LintRule::SortedRecords
+                | LintRule::Hashes
+                | LintRule::NameCase
+                | LintRule::CompressedMedia => true,
+                LintRule::Flags | LintRule::DuplicateHashes | LintRule::NameLength => false,
+            };
+            res.push(Finding {
+                rule,
+                path,
+                message,
+                fixable,
+            })
+        };
+
+        let folders = bsa.folders_slice();
+        let folder_name = |folder: &Folder| match folder.name() {
+            Some(name) => name.to_string(),
+            None => format!("{:#018x}", folder.name_hash()),
+        };
+        if enabled(LintRule::SortedRecords)
+            && folders
+                .windows(2)
+                .any(|pair| pair[0].name_hash() > pair[1].name_hash())
+        {
+            push(
+                LintRule::SortedRecords,
+                None,
+                "Folder records are not sorted by hash".to_string(),
+            );
+        }
+        if enabled(LintRule::DuplicateHashes) {
+            for (first, second) in duplicate_hashes(folders, Folder::name_hash) {
+                push(
+                    LintRule::DuplicateHashes,
+                    Some(folder_name(second)),
+                    format!("Folder has the same hash as {}", folder_name(first)),
+                );
+            }
+        }
+        for folder in folders {
+            let folder_name = folder_name(folder);
+            if enabled(LintRule::SortedRecords)
+                && folder
+                    .files_slice()
+                    .windows(2)
+                    .any(|pair| pair[0].name_hash() > pair[1].name_hash())
+            {
+                push(
+                    LintRule::SortedRecords,
+                    Some(folder_name.clone()),
+                    "File records are not sorted by hash".to_string(),
+                );
+            }
+            if enabled(LintRule::DuplicateHashes) {
+                let file_name = |file: &File| match file.name() {
+                    Some(name) => name.to_string(),
+                    None => format!("{:#018x}", file.name_hash()),
+                };
+                for (first, second) in duplicate_hashes(folder.files_slice(), File::name_hash) {
+                    push(
+                        LintRule::DuplicateHashes,
+                        Some(format!("{}\\{}", folder_name, file_name(second))),
+                        format!("File has the same hash as {}", file_name(first)),
+                    );
+                }
+            }
+            if enabled(LintRule::Hashes) {
+                if let Some(name) = folder.name_raw() {
+                    if compute_hash_bytes(name, Type::Directory) != folder.name_hash() {
+                        push(
+                            LintRule::Hashes,
+                            Some(folder_name.clone()),
+                            "Folder name doesn't match its hash".to_string(),
+                        );
+                    }
+                }
+            }
+            if enabled(LintRule::NameCase)
+                && folder
+                    .name()
+                    .is_some_and(|name| name.contains(char::is_uppercase))
+            {
+                push(
+                    LintRule::NameCase,
+                    Some(folder_name.clone()),
+                    "Folder name contains uppercase letters".to_string(),
+                );
+            }
+
+            for file in folder.files() {
+                let path = match (folder.name(), file.name()) {
+                    (Some(folder), Some(file)) => format!("{}\\{}", folder, file),
+                    (_, Some(file)) => format!("{}\\{}", folder_name, file),
+                    (_, None) => format!("{}\\{:#018x}", folder_name, file.name_hash()),
+                };
+                if enabled(LintRule::Hashes) {
+                    if let Some(name) = file.name_raw() {
+                        if compute_hash_bytes(name, Type::File) != file.name_hash() {
+                            push(
+                                LintRule::Hashes,
+                                Some(path.clone()),
+                                "File name doesn't match its hash".to_string(),
+                            );
+                        }
+                    }
+                }
+                if enabled(LintRule::NameLength) && path.len() > 259 {
+                    push(
+                        LintRule::NameLength,
+                        Some(path.clone()),
+                        format!(
+                            "Path is {} characters long, but the games only support 259",
+                            path.len()
+                        ),
+                    );
+                }
+                if enabled(LintRule::NameCase)
+                    && file
+                        .name()
+                        .is_some_and(|name| name.contains(char::is_uppercase))
+                {
+                    push(
+                        LintRule::NameCase,
+                        Some(path.clone()),
+                        "File name contains uppercase letters".to_string(),
+                    );
+                }
+                let extension = file
+                    .name()
+                    .and_then(|name| name.rsplit_once('.'))
+                    .map(|(_, extension)| extension.to_ascii_lowercase());
+                if enabled(LintRule::CompressedMedia)
+                    && file.is_compressed()
+                    && extension.is_some_and(|extension| COMPRESSED_MEDIA.contains(&&*extension))
+                {
+                    push(
+                        LintRule::CompressedMedia,
+                        Some(path),
+                        "File is already compressed: compressing it again only slows down \
+                         loading, and the games can fail to play compressed sounds"
+                            .to_string(),
+                    );
+                }
+            }
+        }
+        res
+    }
+}
+
+/// Returns the pairs of items with the same hash, pairing each item with the
+/// first one that has its hash
+fn duplicate_hashes<T>(items: &[T], hash: impl Fn(&T) -> u64) -> Vec<(&T, &T)> {
+    let mut first = HashMap::new();
+    let mut res = vec![];
+    for item in items {
+        match first.entry(hash(item)) {
+            Entry::Occupied(entry) => res.push((*entry.get(), item)),
+            Entry::Vacant(entry) => {
+                entry.insert(item);
+            }
+        }
+    }
+    res
+}
+
+impl Default for Linter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlagLint, LintRule, Linter};
+    use crate::bsa::{ArchiveFlags, Version};
+    use crate::game::Game;
+    use crate::write::BsaBuilder;
+
+    fn findings(builder: &BsaBuilder, linter: &Linter) -> Vec<(LintRule, Option<String>, bool)> {
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+        linter
+            .check(&bsa)
+            .into_iter()
+            .map(|finding| {
+                let path = finding.path().map(str::to_string);
+                (finding.rule(), path, finding.is_fixable())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_lint() {
+        for &game in &[Game::Oblivion, Game::Skyrim, Game::SkyrimSE] {
+            assert_eq!(ArchiveFlags::recommended().lint(game), vec![]);
+        }
+        let flags = ArchiveFlags::deserialize(0x1 | 0x20 | 0x200 | 0x1000);
+        assert_eq!(
+            flags.lint(Game::SkyrimSE),
+            vec![
+                FlagLint::MissingFileNames,
+                FlagLint::RetainFileNameOffsets,
+                FlagLint::XmemOnPc,
+                FlagLint::UnknownBits(0x1000),
+            ]
+        );
+        assert_eq!(
+            flags.lint(Game::Oblivion),
+            vec![
+                FlagLint::MissingFileNames,
+                FlagLint::RetainFileNameOffsets,
+                FlagLint::UnknownBits(0x1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_linter() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.keep_original_case(true);
+        let path = format!(r"meshes\{}\{}.nif", "b".repeat(200), "c".repeat(100));
+        builder.add_file(r"meshes\A.nif", vec![1]).unwrap();
+        builder.add_file(&path, vec![2]).unwrap();
+        assert_eq!(
+            findings(&builder, &Linter::new()),
+            vec![
+                (LintRule::NameLength, Some(path), false),
+                (LintRule::NameCase, Some(r"meshes\A.nif".to_string()), true),
+            ]
+        );
+        assert_eq!(
+            findings(
+                &builder,
+                Linter::new()
+                    .allow(LintRule::NameCase)
+                    .allow(LintRule::NameLength)
+            ),
+            vec![]
+        );
+        assert_eq!("name_case".parse::<LintRule>().unwrap(), LintRule::NameCase);
+        assert!("foo".parse::<LintRule>().is_err());
+    }
+
+    #[test]
+    fn test_duplicate_hashes() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file(r"a\x.txt", vec![1]).unwrap();
+        builder.add_file(r"a\y.txt", vec![2]).unwrap();
+        builder.add_file(r"b\z.txt", vec![3]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let bsa = crate::read(std::io::Cursor::new(data.clone())).unwrap();
+        let folders = bsa.folders_slice();
+        let files = folders
+            .iter()
+            .find(|folder| folder.name() == Some("a"))
+            .unwrap()
+            .files_slice();
+        // give the second folder and the second file the hash of the first
+        let replace = |data: &mut Vec<u8>, from: u64, to: u64| {
+            let from = from.to_le_bytes();
+            let pos = data.windows(8).position(|bytes| bytes == from).unwrap();
+            data[pos..pos + 8].copy_from_slice(&to.to_le_bytes());
+        };
+        replace(&mut data, folders[1].name_hash(), folders[0].name_hash());
+        replace(&mut data, files[1].name_hash(), files[0].name_hash());
+        let bsa = crate::OpenOptions::new()
+            .verify_hashes(crate::HashVerification::None)
+            .read(std::io::Cursor::new(data))
+            .unwrap();
+        let findings: Vec<_> = Linter::new()
+            .only(&[LintRule::SortedRecords, LintRule::DuplicateHashes])
+            .check(&bsa)
+            .into_iter()
+            .map(|finding| (finding.rule(), finding.is_fixable()))
+            .collect();
+        // duplicates are still sorted
+        assert_eq!(
+            findings,
+            vec![
+                (LintRule::DuplicateHashes, false),
+                (LintRule::DuplicateHashes, false)
+            ]
+        );
+    }
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn test_compressed_media() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.compress(true);
+        builder.add_file(r"sound\a.ogg", vec![0; 100]).unwrap();
+        builder.add_file(r"sound\b.wav", vec![0; 100]).unwrap();
+        assert_eq!(
+            findings(&builder, Linter::new().only(&[LintRule::CompressedMedia])),
+            vec![(
+                LintRule::CompressedMedia,
+                Some(r"sound\a.ogg".to_string()),
+                true
+            )]
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::bsa::{Bsa, ReadError};
+use crate::checksum::Checksum;
+use std::{collections::BTreeMap, error, fmt, str};
+
+/// A list of the SHA-256 checksums of every file in a BSA.
+///
+/// Manifests are stored in a sidecar file next to the archive (so the game
+/// ignores them), using the same format as `sha256sum`: one line per file,
+/// containing the checksum, two spaces, and the path inside the archive.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Manifest {
+    entries: BTreeMap<String, Checksum>,
+}
+
+/// A difference between a manifest and the contents of a BSA
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ManifestMismatch {
+    /// The file is listed in the manifest, but is missing from the BSA
+    Missing(String),
+    /// The file exists in the BSA, but is not listed in the manifest
+    Unexpected(String),
+    /// The contents of the file differ from the manifest
+    Modified(String),
+}
+
+impl fmt::Display for ManifestMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Missing(path) => write!(f, "{}: missing", path),
+            Self::Unexpected(path) => write!(f, "{}: not in manifest", path),
+            Self::Modified(path) => write!(f, "{}: checksum mismatch", path),
+        }
+    }
+}
+
+/// Represents an error when parsing a manifest
+#[derive(Debug, Clone, Copy)]
+pub struct ParseManifestError {
+    line: usize,
+}
+
+impl fmt::Display for ParseManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Invalid manifest entry on line {}", self.line)
+    }
+}
+
+impl error::Error for ParseManifestError {}
+
+impl Manifest {
+    /// Computes a manifest for the given BSA file. Files without names are skipped.
+    pub fn from_bsa(bsa: &mut Bsa) -> Result<Self, ReadError> {
+        let mut entries = BTreeMap::new();
+        for folder in bsa.folders() {
+            for file in folder.files() {
+                if let Some(path) = file.full_path(&folder) {
+                    entries.insert(path.as_str().to_ascii_lowercase(), file.checksum(bsa)?);
+                }
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    /// Returns the checksum of every file, ordered by path
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &Checksum)> {
+        self.entries
+            .iter()
+            .map(|(path, checksum)| (path.as_str(), checksum))
+    }
+
+    /// Compares this manifest with the contents of the given BSA file,
+    /// returning all differences
+    pub fn verify(&self, bsa: &mut Bsa) -> Result<Vec<ManifestMismatch>, ReadError> {
+        let actual = Self::from_bsa(bsa)?;
+        let mut res = vec![];
+        for (path, checksum) in &self.entries {
+            match actual.entries.get(path) {
+                None => res.push(ManifestMismatch::Missing(path.clone())),
+                Some(actual) if actual != checksum => {
+                    res.push(ManifestMismatch::Modified(path.clone()))
+                }
+                Some(_) => (),
+            }
+        }
+        for path in actual.entries.keys() {
+            if !self.entries.contains_key(path) {
+                res.push(ManifestMismatch::Unexpected(path.clone()));
+            }
+        }
+        Ok(res)
+    }
+
+    /// Signs the manifest with the given ed25519 key
+    #[cfg(feature = "signature")]
+    pub fn sign(&self, key: &ed25519_dalek::SigningKey) -> ed25519_dalek::Signature {
+        ed25519_dalek::Signer::sign(key, self.to_string().as_bytes())
+    }
+
+    /// Returns whether the given ed25519 signature of this manifest is valid
+    #[cfg(feature = "signature")]
+    pub fn verify_signature(
+        &self,
+        key: &ed25519_dalek::VerifyingKey,
+        signature: &ed25519_dalek::Signature,
+    ) -> bool {
+        key.verify_strict(self.to_string().as_bytes(), signature)
+            .is_ok()
+    }
+}
+
+impl fmt::Display for Manifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (path, checksum) in &self.entries {
+            writeln!(f, "{}  {}", checksum, path)?;
+        }
+        Ok(())
+    }
+}
+
+impl str::FromStr for Manifest {
+    type Err = ParseManifestError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut entries = BTreeMap::new();
+        for (idx, line) in s.lines().enumerate() {
+            if line.is_empty() {
+                continue;
+            }
+            let err = ParseManifestError { line: idx + 1 };
+            let (checksum, path) = line.split_at(line.find("  ").ok_or(err)?);
+            let checksum = checksum.parse().map_err(|_| err)?;
+            entries.insert(path[2..].to_ascii_lowercase(), checksum);
+        }
+        Ok(Self { entries })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Manifest, ManifestMismatch};
+    use crate::bsa::Version;
+    use crate::write::BsaBuilder;
+
+    fn bsa(files: &[(&str, &[u8])]) -> crate::bsa::Bsa {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        for (path, data) in files {
+            builder.add_file(path, data.to_vec()).unwrap();
+        }
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        crate::bsa::read(std::io::Cursor::new(data)).unwrap()
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let manifest =
+            Manifest::from_bsa(&mut bsa(&[("a/b.txt", b"b"), ("a/c.txt", b"c")])).unwrap();
+        let parsed: Manifest = manifest.to_string().parse().unwrap();
+        assert_eq!(manifest, parsed);
+
+        let mut modified = bsa(&[("a/b.txt", b"b"), ("a/c.txt", b"x"), ("a/d.txt", b"d")]);
+        assert_eq!(
+            parsed.verify(&mut modified).unwrap(),
+            vec![
+                ManifestMismatch::Modified(r"a\c.txt".into()),
+                ManifestMismatch::Unexpected(r"a\d.txt".into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,267 @@
+//! Metadata about the files in an archive that the BSA format can't store,
+//! kept in a JSON sidecar file next to the archive.
+
+use crate::bsa::{Bsa, File};
+use crate::hash;
+use crate::vfs::index_key;
+use std::collections::{BTreeMap, HashMap};
+use std::{error, fmt, fs, io, path};
+
+/// Represents an error when reading or writing a metadata sidecar
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum MetaError {
+    /// The sidecar file couldn't be read
+    Io(io::Error),
+    /// The sidecar file is not valid JSON or doesn't contain metadata
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for MetaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(_) => write!(f, "Failed to access metadata sidecar"),
+            Self::Json(_) => write!(f, "Invalid metadata sidecar"),
+        }
+    }
+}
+
+impl error::Error for MetaError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Io(e) => Some(e),
+            Self::Json(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for MetaError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for MetaError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Json(e)
+    }
+}
+
+/// Metadata about a single file in an archive
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct EntryMeta {
+    /// The modification time of the original loose file, in seconds since the
+    /// Unix epoch
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<u64>,
+    /// The authors of the file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub authors: Vec<String>,
+    /// The license of the file, e.g. an SPDX expression like `CC-BY-4.0`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub license: Option<String>,
+    /// Any other fields, which are kept when the sidecar is written again
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+/// The contents of a sidecar file
+#[derive(serde::Serialize, serde::Deserialize)]
+struct Sidecar {
+    files: BTreeMap<String, EntryMeta>,
+}
+
+/// Returns the folder and file name hashes of a path, as stored in a BSA
+fn path_hashes(path: &str) -> Option<(u64, u64)> {
+    let (folder, file) = path.rsplit_once('\\')?;
+    Some((
+        hash::compute_hash(folder, hash::Type::Directory).ok()?,
+        hash::compute_hash(file, hash::Type::File).ok()?,
+    ))
+}
+
+/// The metadata of the files in an archive, stored in a sidecar file named
+/// after the archive (e.g. `archive.bsa.meta.json`) that the games ignore.
+/// This keeps provenance information such as the original modification times,
+/// authors and licenses of packaged files.
+///
+/// Archives opened from a path load their sidecar if it exists, see
+/// [`File::meta`].
+///
+/// ```no_run
+/// use bsa::{ArchiveMeta, EntryMeta};
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let mut meta = ArchiveMeta::new();
+///     meta.insert(r"textures\foo.dds", EntryMeta {
+///         authors: vec!["Jane".to_string()],
+///         license: Some("CC-BY-4.0".to_string()),
+///         ..EntryMeta::default()
+///     });
+///     meta.write_sidecar("archive.bsa")?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ArchiveMeta {
+    files: BTreeMap<String, EntryMeta>,
+    // the paths of the files, indexed by their folder and file name hashes
+    hashes: HashMap<(u64, u64), String>,
+}
+
+impl ArchiveMeta {
+    /// Creates an empty set of metadata
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the path of the sidecar file of an archive
+    pub fn sidecar_path<P: AsRef<path::Path>>(archive: P) -> path::PathBuf {
+        let mut path = archive.as_ref().as_os_str().to_owned();
+        path.push(".meta.json");
+        path.into()
+    }
+
+    /// Parses the contents of a sidecar file
+    pub fn from_json(json: &str) -> Result<Self, MetaError> {
+        let sidecar: Sidecar = serde_json::from_str(json)?;
+        let mut res = Self::new();
+        for (path, meta) in sidecar.files {
+            res.insert(&path, meta);
+        }
+        Ok(res)
+    }
+
+    /// Returns the contents of a sidecar file with this metadata
+    pub fn to_json(&self) -> String {
+        let sidecar = Sidecar {
+            files: self.files.clone(),
+        };
+        serde_json::to_string_pretty(&sidecar)
+            .expect("metadata with string keys can always be serialized")
+    }
+
+    /// Reads the sidecar file of an archive, returning `None` if it doesn't
+    /// exist
+    pub fn read_sidecar<P: AsRef<path::Path>>(archive: P) -> Result<Option<Self>, MetaError> {
+        match fs::read_to_string(Self::sidecar_path(archive)) {
+            Ok(json) => Ok(Some(Self::from_json(&json)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes this metadata to the sidecar file of an archive. The sidecar is
+    /// written to a temporary `.tmp` file first, which is renamed once it is
+    /// complete, so an existing sidecar is never left half-written.
+    pub fn write_sidecar<P: AsRef<path::Path>>(&self, archive: P) -> io::Result<()> {
+        let path = Self::sidecar_path(archive);
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        fs::write(&tmp_path, self.to_json())?;
+        fs::rename(&tmp_path, &path)
+    }
+
+    /// Sets the metadata of the file at the given path, replacing any previous
+    /// metadata of it. Paths are case-insensitive and can use either kind of
+    /// slash.
+    pub fn insert(&mut self, path: &str, meta: EntryMeta) -> &mut Self {
+        let path = index_key(path);
+        if let Some(hashes) = path_hashes(&path) {
+            self.hashes.insert(hashes, path.clone());
+        }
+        self.files.insert(path, meta);
+        self
+    }
+
+    /// Returns the metadata of the file at the given path
+    pub fn get(&self, path: &str) -> Option<&EntryMeta> {
+        self.files.get(&index_key(path))
+    }
+
+    /// Returns the metadata of every file, ordered by path
+    pub fn entries(&self) -> impl Iterator<Item = (&str, &EntryMeta)> {
+        self.files.iter().map(|(path, meta)| (path.as_str(), meta))
+    }
+
+    /// Returns the metadata of a file by its folder and file name hashes
+    fn get_hashed(&self, folder_hash: u64, name_hash: u64) -> Option<&EntryMeta> {
+        self.files.get(self.hashes.get(&(folder_hash, name_hash))?)
+    }
+}
+
+impl File {
+    /// Returns the metadata of this file from the sidecar of the archive (see
+    /// [`ArchiveMeta`]), if the archive has one and it lists this file. Files
+    /// are looked up by their hashes, so this also works for files without
+    /// names.
+    pub fn meta<'a>(&self, bsa: &'a Bsa) -> Option<&'a EntryMeta> {
+        bsa.meta()?.get_hashed(self.folder_hash(), self.name_hash())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArchiveMeta, EntryMeta};
+    use crate::bsa::{self, Version};
+    use crate::write::BsaBuilder;
+
+    #[test]
+    fn test_sidecar_round_trip() {
+        let json = r#"{
+            "files": {
+                "Textures/Foo.dds": {
+                    "modified": 1700000000,
+                    "authors": ["Jane"],
+                    "license": "CC-BY-4.0",
+                    "source": {"url": "https://example.com"}
+                }
+            }
+        }"#;
+        let meta = ArchiveMeta::from_json(json).unwrap();
+        let entry = meta.get(r"textures\foo.dds").unwrap();
+        assert_eq!(entry.modified, Some(1_700_000_000));
+        assert_eq!(entry.authors, ["Jane"]);
+        assert_eq!(entry.license.as_deref(), Some("CC-BY-4.0"));
+        assert!(entry.extra.contains_key("source"));
+        assert_eq!(ArchiveMeta::from_json(&meta.to_json()).unwrap(), meta);
+        assert!(ArchiveMeta::from_json("{").is_err());
+    }
+
+    #[test]
+    fn test_file_meta() {
+        let dir = std::env::temp_dir().join(format!("bsa-meta-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("test.bsa");
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file(r"meshes\a.nif", vec![1]).unwrap();
+        builder.add_file(r"meshes\b.nif", vec![2]).unwrap();
+        builder
+            .write(&mut std::fs::File::create(&archive).unwrap())
+            .unwrap();
+
+        assert!(bsa::open(&archive).unwrap().meta().is_none());
+        let mut meta = ArchiveMeta::new();
+        meta.insert(
+            "Meshes/A.nif",
+            EntryMeta {
+                modified: Some(42),
+                ..EntryMeta::default()
+            },
+        );
+        meta.write_sidecar(&archive).unwrap();
+        assert!(!dir.join("test.bsa.meta.json.tmp").exists());
+        let bsa = bsa::open(&archive).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let folder = bsa.folder("meshes").unwrap();
+        for file in folder.files() {
+            let modified = file.meta(&bsa).map(|meta| meta.modified);
+            match file.name() {
+                Some("a.nif") => assert_eq!(modified, Some(Some(42))),
+                _ => assert_eq!(modified, None),
+            }
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use crate::bsa::Bsa;
+use crate::diagnostic::Diagnostic;
+use crate::layout::RegionKind;
+use std::fmt;
+
+/// A tool that BSA files are created with, as guessed by
+/// [`Bsa::packer_guess`]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packer {
+    /// Bethesda's Archive.exe, which is also used by the Creation Kit
+    ArchiveExe,
+    /// This library
+    BsaCrate,
+    /// A third-party tool such as BSArch or BSAopt
+    ThirdParty,
+}
+
+impl fmt::Display for Packer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::ArchiveExe => write!(f, "Archive.exe (Creation Kit)"),
+            Self::BsaCrate => write!(f, "the bsa crate"),
+            Self::ThirdParty => write!(f, "a third-party tool (e.g. BSArch or BSAopt)"),
+        }
+    }
+}
+
+/// A guess of which tool created a BSA file, with the quirks it is based on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackerGuess {
+    packer: Option<Packer>,
+    evidence: Vec<String>,
+}
+
+impl PackerGuess {
+    /// Returns the most likely tool, or `None` if the archive doesn't have
+    /// any quirks that point to one
+    pub fn packer(&self) -> Option<Packer> {
+        self.packer
+    }
+
+    /// Returns a description of each quirk that was found
+    pub fn evidence(&self) -> &[String] {
+        &self.evidence
+    }
+}
+
+impl Bsa {
+    /// Guesses which tool created this BSA from quirks in its flags, records
+    /// and file order, which helps when triaging reports about archives that
+    /// don't work in the game. This is only a heuristic: archives without any
+    /// unusual properties can't be told apart.
+    pub fn packer_guess(&self) -> PackerGuess {
+        let mut evidence = vec![];
+        let mut votes = [0; 3];
+        let mut vote = |packer: Packer, weight: u32, reason: String| {
+            votes[packer as usize] += weight;
+            evidence.push(reason);
+        };
+
+        let flags = self.archive_flags();
+        if flags.zstd_codec() {
+            vote(
+                Packer::BsaCrate,
+                10,
+                "files are compressed with zstd, which only this library writes".to_string(),
+            );
+        }
+        if flags.retain_directory_names()
+            || flags.retain_file_names()
+            || flags.retain_file_name_offsets()
+        {
+            vote(
+                Packer::ArchiveExe,
+                2,
+                "the 'retain names' archive flags are set, which are options of Archive.exe"
+                    .to_string(),
+            );
+        }
+        for diagnostic in self.diagnostics() {
+            match diagnostic {
+                Diagnostic::SizeExcludesEmbeddedName => vote(
+                    Packer::ThirdParty,
+                    3,
+                    "file sizes don't include the embedded file names".to_string(),
+                ),
+                Diagnostic::FolderRecordOffsetMismatch { .. } => vote(
+                    Packer::ThirdParty,
+                    3,
+                    "folder records point to the wrong offsets".to_string(),
+                ),
+                _ => (),
+            }
+        }
+
+        let mut uppercase = false;
+        for folder in self.folders_slice() {
+            uppercase |= folder
+                .name()
+                .is_some_and(|name| name.contains(char::is_uppercase));
+            for file in folder.files() {
+                uppercase |= file
+                    .name()
+                    .is_some_and(|name| name.contains(char::is_uppercase));
+            }
+        }
+        if uppercase {
+            vote(
+                Packer::ThirdParty,
+                1,
+                "names contain uppercase letters, which Archive.exe converts to lowercase"
+                    .to_string(),
+            );
+        }
+
+        let data: Vec<_> = self
+            .layout()
+            .into_iter()
+            .filter_map(|region| match region.kind() {
+                RegionKind::FileData(folder, file) => Some((region, (folder, file))),
+                _ => None,
+            })
+            .collect();
+        if data.windows(2).any(|pair| pair[0].1 > pair[1].1) {
+            vote(
+                Packer::ThirdParty,
+                2,
+                "file data is not stored in the order of the file records".to_string(),
+            );
+        }
+        if data
+            .windows(2)
+            .any(|pair| pair[0].0.end() < pair[1].0.start())
+        {
+            vote(
+                Packer::ThirdParty,
+                1,
+                format!(
+                    "file data is padded (aligned to {} bytes), which Archive.exe never does",
+                    self.data_alignment()
+                ),
+            );
+        }
+
+        let packers = [Packer::ArchiveExe, Packer::BsaCrate, Packer::ThirdParty];
+        let best = votes.iter().copied().max().unwrap_or(0);
+        let mut winners = packers
+            .iter()
+            .filter(|&&packer| votes[packer as usize] == best);
+        let packer = match (winners.next(), winners.next()) {
+            (Some(&packer), None) if best > 0 => Some(packer),
+            _ => None,
+        };
+        PackerGuess { packer, evidence }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Packer;
+    use crate::bsa::Version;
+    use crate::write::BsaBuilder;
+
+    fn guess(alignment: u32) -> super::PackerGuess {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.alignment(alignment);
+        builder.add_file(r"meshes\a.nif", vec![1; 10]).unwrap();
+        builder.add_file(r"meshes\b.nif", vec![2; 10]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        crate::read(std::io::Cursor::new(data))
+            .unwrap()
+            .packer_guess()
+    }
+
+    #[test]
+    fn test_packer_guess() {
+        let plain = guess(1);
+        assert_eq!(plain.packer(), None);
+        assert!(plain.evidence().is_empty());
+
+        let padded = guess(4096);
+        assert_eq!(padded.packer(), Some(Packer::ThirdParty));
+        assert_eq!(padded.evidence().len(), 1);
+    }
+}
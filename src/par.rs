@@ -0,0 +1,97 @@
+use crate::bsa::{Bsa, File, Folder, ReadError};
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::io;
+
+impl Bsa {
+    /// Calls `f` on every file in this BSA in parallel, using rayon.
+    ///
+    /// Since reading file contents requires a reader, `open` is used to
+    /// create a new reader (usually by opening the BSA file again) for each
+    /// rayon job that needs one. The `Bsa` passed to `f` reads from that
+    /// reader.
+    ///
+    /// ```no_run
+    /// use rayon::iter::ParallelIterator;
+    ///
+    /// let bsa = bsa::open("file.bsa")?;
+    /// let checksums = bsa
+    ///     .par_files(|| std::fs::File::open("file.bsa"), |bsa, _, file| file.checksum(bsa))
+    ///     .collect::<Result<Vec<_>, _>>()?;
+    /// # Ok::<(), bsa::ReadError>(())
+    /// ```
+    pub fn par_files<'a, R, O, F, T>(
+        &'a self,
+        open: O,
+        f: F,
+    ) -> impl ParallelIterator<Item = Result<T, ReadError>> + 'a
+    where
+        R: io::Read + io::Seek + Send + Sync + 'static,
+        O: Fn() -> io::Result<R> + Send + Sync + 'a,
+        F: Fn(&mut Bsa, &Folder, &File) -> Result<T, ReadError> + Send + Sync + 'a,
+        T: Send + 'a,
+    {
+        let files: Vec<(&Folder, &File)> = self
+            .folders_slice()
+            .iter()
+            .flat_map(|folder| folder.files().map(move |file| (folder, file)))
+            .collect();
+        files.into_par_iter().map_init(
+            || None,
+            move |reader: &mut Option<Bsa>, (folder, file)| {
+                if reader.is_none() {
+                    *reader = Some(self.with_reader(open()?));
+                }
+                f(reader.as_mut().unwrap(), folder, file)
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BsaBuilder, Version};
+    use rayon::iter::ParallelIterator;
+    use std::io;
+
+    #[test]
+    fn test_par_files() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        for i in 0..100u8 {
+            builder.add_file(&format!("a/{}.txt", i), vec![i]).unwrap();
+        }
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let bsa = crate::read(io::Cursor::new(data.clone())).unwrap();
+        let mut contents = bsa
+            .par_files(
+                || Ok(io::Cursor::new(data.clone())),
+                |bsa, _, file| file.read_to_vec(bsa),
+            )
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        contents.sort();
+        assert_eq!(contents, (0..100).map(|i| vec![i]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_parallel_hashes() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        for i in 0..100u8 {
+            builder.add_file(&format!("a/{}.txt", i), vec![i]).unwrap();
+        }
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let read = |data: &[u8]| {
+            crate::OpenOptions::new()
+                .parallel_hashes(true)
+                .read(io::Cursor::new(data.to_vec()))
+        };
+        assert_eq!(read(&data).unwrap().folders_slice()[0].files().count(), 100);
+        // corrupt the hash of the first file name
+        data[36 + 16 + 3] ^= 0xff;
+        assert!(matches!(
+            read(&data),
+            Err(crate::ReadError::IncorrectHash(_))
+        ));
+    }
+}
@@ -0,0 +1,239 @@
+//! Helpers for converting loose file paths into paths inside a BSA, for
+//! rewriting paths inside a BSA, and for turning them back into loose paths.
+
+use crate::cp1252;
+use crate::write::normalize_path;
+use std::{error, fmt, path};
+
+/// Represents an error when converting a loose file path into a path inside
+/// a BSA
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathError {
+    OutsideDataRoot,
+    NotUnicode,
+    UnencodableCharacters,
+    MissingFolder,
+    OutsideOutputFolder,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::OutsideDataRoot => write!(f, "Path is not inside the data folder"),
+            Self::NotUnicode => write!(f, "Path is not valid Unicode"),
+            Self::UnencodableCharacters => {
+                write!(f, "Path contains characters that can't be stored in a BSA")
+            }
+            Self::MissingFolder => write!(f, "Files must be inside a folder"),
+            Self::OutsideOutputFolder => {
+                write!(f, "Path could be written outside the output folder")
+            }
+        }
+    }
+}
+
+impl error::Error for PathError {}
+
+/// Splits a path into its normal components, also splitting at backslashes
+/// so that Windows paths work on every platform
+fn components(path: &path::Path) -> Result<Vec<String>, PathError> {
+    let mut res = vec![];
+    for component in path.components() {
+        match component {
+            path::Component::Normal(part) => {
+                let part = part.to_str().ok_or(PathError::NotUnicode)?;
+                for part in part.split('\\') {
+                    match part {
+                        "" | "." => (),
+                        ".." => return Err(PathError::OutsideDataRoot),
+                        _ => res.push(part.to_string()),
+                    }
+                }
+            }
+            path::Component::CurDir => (),
+            path::Component::ParentDir => return Err(PathError::OutsideDataRoot),
+            path::Component::RootDir | path::Component::Prefix(_) => {
+                res.push(component.as_os_str().to_string_lossy().into_owned())
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Converts the path of a loose file inside the given data folder into the
+/// canonical path of the file inside a BSA: relative to the data folder,
+/// lowercase, and separated by backslashes. This is the form that is hashed,
+/// so files added with it can be looked up by the game.
+///
+/// The data folder is matched case-insensitively, like on Windows.
+///
+/// ```
+/// use std::path::Path;
+///
+/// let path = bsa::path::normalize(Path::new("Data/Textures/Foo/bar.DDS"), Path::new("Data"));
+/// assert_eq!(path.unwrap(), r"textures\foo\bar.dds");
+/// ```
+pub fn normalize(os_path: &path::Path, data_root: &path::Path) -> Result<String, PathError> {
+    let parts = components(os_path)?;
+    let root = components(data_root)?;
+    if parts.len() < root.len()
+        || !parts
+            .iter()
+            .zip(&root)
+            .all(|(part, root)| part.eq_ignore_ascii_case(root))
+    {
+        return Err(PathError::OutsideDataRoot);
+    }
+    let parts = &parts[root.len()..];
+    if parts.len() < 2 {
+        return Err(PathError::MissingFolder);
+    }
+    let res = parts.join("\\").to_ascii_lowercase();
+    if cp1252::encode_str(&res).is_err() {
+        return Err(PathError::UnencodableCharacters);
+    }
+    Ok(res)
+}
+
+/// Joins a path inside a BSA onto an output folder, e.g. the one files are
+/// extracted to. Since the names in an archive can't be trusted, paths with a
+/// part that is empty, `.`, `..` or a root or drive prefix are rejected, so
+/// the result is always inside the output folder. Either kind of slash can be
+/// used.
+///
+/// ```
+/// use std::path::Path;
+///
+/// let path = bsa::path::output_path(Path::new("out"), r"textures\sky\sun.dds");
+/// assert_eq!(path.unwrap(), Path::new("out/textures/sky/sun.dds"));
+/// assert!(bsa::path::output_path(Path::new("out"), r"..\..\sun.dds").is_err());
+/// ```
+pub fn output_path(dir: &path::Path, bsa_path: &str) -> Result<path::PathBuf, PathError> {
+    let mut res = dir.to_path_buf();
+    for part in bsa_path.split(['\\', '/']) {
+        let mut components = path::Path::new(part).components();
+        match (components.next(), components.next()) {
+            (Some(path::Component::Normal(name)), None) if name == part => res.push(part),
+            _ => return Err(PathError::OutsideOutputFolder),
+        }
+    }
+    Ok(res)
+}
+
+/// Replaces the folder `from` at the start of a path inside a BSA with `to`,
+/// returning `None` if the path is not inside `from`. Folders are matched
+/// case-insensitively and by whole names, so `textures\oldmod` matches
+/// `textures\oldmod\a.dds` but not `textures\oldmodx\a.dds`. Either kind of
+/// slash can be used.
+///
+/// ```
+/// let path = bsa::path::replace_prefix(r"Textures\OldMod\a.dds", "textures/oldmod", r"textures\newmod");
+/// assert_eq!(path.unwrap(), r"textures\newmod\a.dds");
+/// ```
+pub fn replace_prefix(path: &str, from: &str, to: &str) -> Option<String> {
+    let path = normalize_path(path);
+    let from = normalize_path(from);
+    let mut parts = path.split('\\');
+    for folder in from.split('\\').filter(|folder| !folder.is_empty()) {
+        if !parts.next()?.eq_ignore_ascii_case(folder) {
+            return None;
+        }
+    }
+    let rest: Vec<_> = parts.collect();
+    if rest.is_empty() {
+        // the path is the folder itself
+        return None;
+    }
+    let to = normalize_path(to);
+    Some(if to.is_empty() {
+        rest.join("\\")
+    } else {
+        format!("{}\\{}", to, rest.join("\\"))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize, output_path, replace_prefix, PathError};
+    use std::path::Path;
+
+    #[test]
+    fn test_normalize() {
+        let normalize = |path: &str, root: &str| normalize(Path::new(path), Path::new(root));
+        assert_eq!(
+            normalize("Data/Textures/Foo/bar.DDS", "Data").unwrap(),
+            r"textures\foo\bar.dds"
+        );
+        assert_eq!(
+            normalize(r"data\Meshes\.\a.nif", "DATA").unwrap(),
+            r"meshes\a.nif"
+        );
+        assert_eq!(normalize("Meshes/a.nif", "").unwrap(), r"meshes\a.nif");
+        assert_eq!(
+            normalize("Other/Meshes/a.nif", "Data"),
+            Err(PathError::OutsideDataRoot)
+        );
+        assert_eq!(
+            normalize("Data/../Meshes/a.nif", "Data"),
+            Err(PathError::OutsideDataRoot)
+        );
+        assert_eq!(
+            normalize(r"Data\..\Meshes\a.nif", "Data"),
+            Err(PathError::OutsideDataRoot)
+        );
+        assert_eq!(
+            normalize("Data/a.nif", "Data"),
+            Err(PathError::MissingFolder)
+        );
+        assert_eq!(
+            normalize("Data/Meshes/\u{4e00}.nif", "Data"),
+            Err(PathError::UnencodableCharacters)
+        );
+    }
+
+    #[test]
+    fn test_replace_prefix() {
+        let replace = |path: &str| replace_prefix(path, r"textures\oldmod", "Textures/NewMod");
+        assert_eq!(
+            replace(r"textures\OldMod\armor\a.dds").unwrap(),
+            r"Textures\NewMod\armor\a.dds"
+        );
+        assert_eq!(replace(r"textures\oldmodx\a.dds"), None);
+        assert_eq!(replace(r"meshes\oldmod\a.nif"), None);
+        assert_eq!(replace(r"textures\oldmod"), None);
+        assert_eq!(
+            replace_prefix(r"textures\oldmod\a.dds", r"textures\oldmod", "textures").unwrap(),
+            r"textures\a.dds"
+        );
+    }
+
+    #[test]
+    fn test_output_path() {
+        let out = Path::new("out");
+        assert_eq!(
+            output_path(out, r"textures\sky/sun.dds").unwrap(),
+            Path::new("out/textures/sky/sun.dds")
+        );
+        for path in &[
+            r"..\..\x.dds",
+            r"textures\..\..\x.dds",
+            r"\textures\x.dds",
+            "/textures/x.dds",
+            r"textures\\x.dds",
+            r"textures\.\x.dds",
+            "",
+        ] {
+            assert_eq!(
+                output_path(out, path),
+                Err(PathError::OutsideOutputFolder),
+                "{}",
+                path
+            );
+        }
+        if cfg!(windows) {
+            assert!(output_path(out, r"C:\x.dds").is_err());
+            assert!(output_path(out, r"textures\C:x.dds").is_err());
+        }
+    }
+}
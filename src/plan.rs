@@ -0,0 +1,963 @@
+use crate::bsa::{Bsa, Codec, File, ReadError};
+use crate::checksum::Checksum;
+use log::warn;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs, io, path, time,
+};
+
+/// A file that will be written when executing an [`ExtractPlan`]
+#[derive(Debug, Clone)]
+pub struct ExtractEntry {
+    path: path::PathBuf,
+    file: File,
+    options: WriteOptions,
+}
+
+/// Options from the [`ExtractPlan`] that affect how each file is written
+#[derive(Debug, Clone, Copy)]
+struct WriteOptions {
+    long_paths: bool,
+    recover: bool,
+    #[cfg(unix)]
+    file_mode: Option<u32>,
+    #[cfg(unix)]
+    dir_mode: Option<u32>,
+}
+
+impl ExtractEntry {
+    /// Returns the path the file will be extracted to
+    pub fn path(&self) -> &path::Path {
+        &self.path
+    }
+
+    /// Returns the file inside the BSA
+    pub fn file(&self) -> &File {
+        &self.file
+    }
+
+    /// Extracts this file, creating its parent directories if necessary.
+    ///
+    /// The contents are written to a temporary `.tmp` file first, which is
+    /// renamed once it is complete, so the output file is never left
+    /// half-written.
+    pub fn extract(&self, bsa: &mut Bsa) -> Result<(), ReadError> {
+        self.extract_recovering(bsa).map(|_| ())
+    }
+
+    /// Extracts this file, returning the codec it was recovered with if it was
+    /// mislabeled (see [`ExtractPlan::recover`])
+    fn extract_recovering(&self, bsa: &mut Bsa) -> Result<Option<Codec>, ReadError> {
+        let path = self.prepare_path()?;
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = path.with_file_name(tmp_name);
+        let mut output = fs::File::create(&tmp_path).map_err(|e| self.with_context(e))?;
+        #[cfg(unix)]
+        if let Some(mode) = self.options.file_mode {
+            use std::os::unix::fs::PermissionsExt;
+            // set explicitly, so that the umask doesn't apply
+            output
+                .set_permissions(fs::Permissions::from_mode(mode))
+                .map_err(|e| self.with_context(e))?;
+        }
+        let res = match self
+            .file
+            .read_contents(bsa)
+            .and_then(|mut reader| Ok(io::copy(&mut reader, &mut output)?))
+        {
+            Err(_) if self.options.recover && self.file.is_compressed() => {
+                self.file.recover(bsa).and_then(|recovered| {
+                    output.set_len(0)?;
+                    io::Seek::rewind(&mut output)?;
+                    io::Write::write_all(&mut output, recovered.data())?;
+                    Ok(Some(recovered.codec()).filter(|_| recovered.is_mislabeled()))
+                })
+            }
+            res => res.map(|_| None),
+        };
+        drop(output);
+        if res.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        let recovered = res?;
+        fs::rename(&tmp_path, &path).map_err(|e| self.with_context(e))?;
+        Ok(recovered)
+    }
+
+    /// Creates a link to an identical file that has already been extracted,
+    /// instead of extracting this file
+    fn link(&self, original: &path::Path, mode: LinkMode) -> Result<(), ReadError> {
+        let path = self.prepare_path()?;
+        if fs::symlink_metadata(&path).is_ok() {
+            fs::remove_file(&path).map_err(|e| self.with_context(e))?;
+        }
+        let res = match mode {
+            LinkMode::HardLink => {
+                let original = if self.options.long_paths {
+                    extended_length_path(original)?
+                } else {
+                    original.to_path_buf()
+                };
+                fs::hard_link(original, &path)
+            }
+            LinkMode::SymLink => symlink(&relative_path(&self.path, original)?, &path),
+        };
+        res.map_err(|e| self.with_context(e))?;
+        Ok(())
+    }
+
+    /// Moves an existing file at the output path aside, so that it can be
+    /// restored if the extraction is rolled back. Returns the path it was
+    /// moved to, if the file existed.
+    fn move_aside(&self) -> io::Result<Option<path::PathBuf>> {
+        let path = self.target_path()?;
+        if fs::symlink_metadata(&path).is_err() {
+            return Ok(None);
+        }
+        let mut backup_name = path.file_name().unwrap_or_default().to_os_string();
+        backup_name.push(".rollback");
+        let backup = path.with_file_name(backup_name);
+        fs::rename(&path, &backup).map_err(|e| self.with_context(e))?;
+        Ok(Some(backup))
+    }
+
+    /// Returns whether this file should be written, according to the given
+    /// overwrite policy. `archive_modified` is the modification time of the
+    /// BSA file, if known.
+    fn should_write(
+        &self,
+        bsa: &mut Bsa,
+        overwrite: Overwrite,
+        archive_modified: Option<time::SystemTime>,
+    ) -> Result<bool, ReadError> {
+        let path = self.target_path()?;
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => return Ok(true),
+        };
+        Ok(match overwrite {
+            Overwrite::Always => true,
+            Overwrite::Never => false,
+            Overwrite::IfNewer => match (archive_modified, metadata.modified()) {
+                (Some(archive_modified), Ok(modified)) => archive_modified > modified,
+                _ => true,
+            },
+            Overwrite::IfDifferent => {
+                metadata.len() != self.file.uncompressed_size()
+                    || Checksum::from_reader(&mut fs::File::open(&path)?)?
+                        != self.file.checksum(bsa)?
+            }
+        })
+    }
+
+    /// Returns the path to write to
+    fn target_path(&self) -> io::Result<path::PathBuf> {
+        if self.options.long_paths {
+            extended_length_path(&self.path).map_err(|e| self.with_context(e))
+        } else {
+            Ok(self.path.clone())
+        }
+    }
+
+    /// Returns the path to write to, after creating its parent directories
+    fn prepare_path(&self) -> io::Result<path::PathBuf> {
+        let path = self.target_path()?;
+        if let Some(parent) = path.parent() {
+            self.create_dirs(parent).map_err(|e| self.with_context(e))?;
+        }
+        Ok(path)
+    }
+
+    #[cfg(unix)]
+    fn create_dirs(&self, dir: &path::Path) -> io::Result<()> {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = match self.options.dir_mode {
+            Some(mode) => mode,
+            None => return fs::create_dir_all(dir),
+        };
+        let missing: Vec<_> = dir
+            .ancestors()
+            .take_while(|ancestor| !ancestor.exists())
+            .collect();
+        fs::create_dir_all(dir)?;
+        for dir in missing {
+            fs::set_permissions(dir, fs::Permissions::from_mode(mode))?;
+        }
+        Ok(())
+    }
+
+    #[cfg(not(unix))]
+    fn create_dirs(&self, dir: &path::Path) -> io::Result<()> {
+        fs::create_dir_all(dir)
+    }
+
+    fn with_context(&self, e: io::Error) -> io::Error {
+        io::Error::new(e.kind(), format!("{}: {}", self.path.display(), e))
+    }
+}
+
+/// Specifies what happens when an [`ExtractPlan`] would write to a file that
+/// already exists
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Overwrite {
+    /// Always replace existing files
+    Always,
+    /// Never replace existing files
+    Never,
+    /// Replace existing files that are older than the BSA file. Files are
+    /// always replaced if the BSA wasn't opened from disk.
+    IfNewer,
+    /// Replace existing files if their size or checksum differs from the file
+    /// in the BSA
+    IfDifferent,
+}
+
+/// What an [`ExtractPlan`] is about to do with a file, passed to the callback
+/// of [`ExtractPlan::execute_with`]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ExtractAction<'a> {
+    /// The file will be written
+    Create,
+    /// The file will be linked to the given identical file
+    Link(&'a path::Path),
+    /// The file already exists and will be kept, according to the
+    /// [`Overwrite`] policy
+    Skip,
+}
+
+/// The number of files and bytes in one group of an [`ExtractStats`]
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct ExtractCount {
+    files: u64,
+    bytes: u64,
+}
+
+impl ExtractCount {
+    /// Returns the number of files
+    pub fn files(&self) -> u64 {
+        self.files
+    }
+
+    /// Returns the total size of the files in bytes
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    fn add(&mut self, bytes: u64) {
+        self.files += 1;
+        self.bytes += bytes;
+    }
+}
+
+/// Statistics about the files written by [`ExtractPlan::execute`]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtractStats {
+    written: ExtractCount,
+    linked: u64,
+    skipped: u64,
+    elapsed: time::Duration,
+    by_extension: BTreeMap<String, ExtractCount>,
+    by_folder: BTreeMap<String, ExtractCount>,
+    recovered: Vec<(path::PathBuf, Codec)>,
+}
+
+impl ExtractStats {
+    /// Returns the number of files written
+    pub fn files_written(&self) -> u64 {
+        self.written.files
+    }
+
+    /// Returns the total size of the files written, in bytes
+    pub fn bytes_written(&self) -> u64 {
+        self.written.bytes
+    }
+
+    /// Returns the number of files that were linked to an identical file
+    /// instead of being written
+    pub fn files_linked(&self) -> u64 {
+        self.linked
+    }
+
+    /// Returns the number of existing files that were kept
+    pub fn files_skipped(&self) -> u64 {
+        self.skipped
+    }
+
+    /// Returns how long the extraction took
+    pub fn elapsed(&self) -> time::Duration {
+        self.elapsed
+    }
+
+    /// Returns the number of bytes written per second
+    pub fn throughput(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.written.bytes as f64 / secs
+        } else {
+            0.0
+        }
+    }
+
+    /// Returns the files written for each (lowercase) file extension. Files
+    /// without an extension are counted under an empty string.
+    pub fn by_extension(&self) -> &BTreeMap<String, ExtractCount> {
+        &self.by_extension
+    }
+
+    /// Returns the files written for each folder in the BSA. Folders without
+    /// a name are counted under their hash.
+    pub fn by_folder(&self) -> &BTreeMap<String, ExtractCount> {
+        &self.by_folder
+    }
+
+    /// Returns the files with mislabeled compression that were recovered
+    /// (see [`ExtractPlan::recover`]), with the codec that worked
+    pub fn recovered(&self) -> &[(path::PathBuf, Codec)] {
+        &self.recovered
+    }
+
+    fn add_written(&mut self, entry: &ExtractEntry, folder: &str) {
+        let bytes = entry.file.uncompressed_size();
+        let extension = entry
+            .path
+            .extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        self.written.add(bytes);
+        self.by_extension.entry(extension).or_default().add(bytes);
+        self.by_folder
+            .entry(folder.to_string())
+            .or_default()
+            .add(bytes);
+    }
+}
+
+/// Specifies how files with identical contents are linked together by an
+/// [`ExtractPlan`], instead of being written multiple times
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LinkMode {
+    /// Create hard links to the first copy of the file
+    HardLink,
+    /// Create (relative) symbolic links to the first copy of the file
+    SymLink,
+}
+
+#[cfg(unix)]
+fn symlink(original: &path::Path, link: &path::Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(original, link)
+}
+
+#[cfg(windows)]
+fn symlink(original: &path::Path, link: &path::Path) -> io::Result<()> {
+    std::os::windows::fs::symlink_file(original, link)
+}
+
+#[cfg(not(any(unix, windows)))]
+fn symlink(_original: &path::Path, _link: &path::Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "symbolic links are not supported on this platform",
+    ))
+}
+
+/// Returns the path of `original` relative to the directory containing `link`
+fn relative_path(link: &path::Path, original: &path::Path) -> io::Result<path::PathBuf> {
+    let link = path::absolute(link)?;
+    let original = path::absolute(original)?;
+    let link_dir: Vec<_> = link
+        .parent()
+        .into_iter()
+        .flat_map(|dir| dir.components())
+        .collect();
+    let original: Vec<_> = original.components().collect();
+    let common = link_dir
+        .iter()
+        .zip(&original)
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut relative = path::PathBuf::new();
+    for _ in common..link_dir.len() {
+        relative.push("..");
+    }
+    relative.extend(&original[common..]);
+    Ok(relative)
+}
+
+/// Converts a path to an extended-length (`\\?\`) path, which isn't limited to
+/// `MAX_PATH` characters
+#[cfg(windows)]
+fn extended_length_path(path: &path::Path) -> io::Result<path::PathBuf> {
+    use path::{Component, Prefix};
+    use std::ffi::OsString;
+
+    let path = path::absolute(path)?;
+    let mut components = path.components();
+    let extended = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::Disk(_) => {
+                let mut extended = OsString::from(r"\\?\");
+                extended.push(path.as_os_str());
+                extended
+            }
+            Prefix::UNC(server, share) => {
+                let mut extended = OsString::from(r"\\?\UNC\");
+                extended.push(server);
+                extended.push("\\");
+                extended.push(share);
+                extended.push(components.as_path());
+                extended
+            }
+            // already a verbatim or device path
+            _ => return Ok(path),
+        },
+        _ => return Ok(path),
+    };
+    Ok(extended.into())
+}
+
+#[cfg(not(windows))]
+fn extended_length_path(path: &path::Path) -> io::Result<path::PathBuf> {
+    Ok(path.to_path_buf())
+}
+
+/// A list of files to extract from a BSA, which can be inspected (e.g. for a
+/// dry run) before anything is written to disk.
+///
+/// ```no_run
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let mut bsa = bsa::open("file.bsa")?;
+///     let mut plan = bsa::ExtractPlan::new(&bsa, "out");
+///     plan.retain(|entry| entry.path().extension().is_some_and(|ext| ext == "dds"));
+///     println!("Extracting {} bytes", plan.total_size());
+///     plan.execute(&mut bsa)?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct ExtractPlan {
+    entries: Vec<ExtractEntry>,
+    options: WriteOptions,
+    link_duplicates: Option<LinkMode>,
+    rollback_on_error: bool,
+    overwrite: Overwrite,
+}
+
+impl Default for ExtractPlan {
+    fn default() -> Self {
+        Self {
+            entries: vec![],
+            options: WriteOptions {
+                long_paths: true,
+                recover: false,
+                #[cfg(unix)]
+                file_mode: None,
+                #[cfg(unix)]
+                dir_mode: None,
+            },
+            link_duplicates: None,
+            rollback_on_error: false,
+            overwrite: Overwrite::Always,
+        }
+    }
+}
+
+impl ExtractPlan {
+    /// Plans the extraction of every named file in the BSA into the given
+    /// directory, in the order the file contents are stored in the archive.
+    /// Files whose names would place them outside the directory (see
+    /// [`crate::path::output_path`]) are skipped with a warning.
+    pub fn new<P: AsRef<path::Path>>(bsa: &Bsa, into: P) -> Self {
+        let mut plan = Self::default();
+        for (folder, file) in bsa.files_by_offset() {
+            if let (Some(folder_name), Some(file_name)) = (folder.name(), file.name()) {
+                let bsa_path = format!("{}\\{}", folder_name, file_name);
+                match crate::path::output_path(into.as_ref(), &bsa_path) {
+                    Ok(path) => {
+                        plan.push(path, file.clone());
+                    }
+                    Err(e) => warn!("skipping {}: {}", bsa_path, e),
+                }
+            }
+        }
+        plan
+    }
+
+    /// Adds a file to be extracted to the given path. The path is used as is,
+    /// so paths built from names inside an archive should be checked with
+    /// [`crate::path::output_path`] first.
+    pub fn push(&mut self, path: path::PathBuf, file: File) -> &mut Self {
+        self.entries.push(ExtractEntry {
+            path,
+            file,
+            options: self.options,
+        });
+        self
+    }
+
+    fn update_options(&mut self, f: impl Fn(&mut WriteOptions)) -> &mut Self {
+        f(&mut self.options);
+        for entry in &mut self.entries {
+            f(&mut entry.options);
+        }
+        self
+    }
+
+    /// Sets whether extended-length paths are used on Windows, so that files
+    /// can be extracted to paths longer than `MAX_PATH` (defaults to `true`).
+    /// This has no effect on other platforms.
+    pub fn long_paths(&mut self, long_paths: bool) -> &mut Self {
+        self.update_options(|options| options.long_paths = long_paths)
+    }
+
+    /// Sets whether compressed files that fail to decompress are read with
+    /// [`File::recover`] instead of failing (defaults to `false`). Recovered
+    /// files are listed in [`ExtractStats::recovered`].
+    pub fn recover(&mut self, recover: bool) -> &mut Self {
+        self.update_options(|options| options.recover = recover)
+    }
+
+    /// Sets the permissions of extracted files (e.g. `0o644`). By default,
+    /// files are created with the permissions given by the process umask.
+    #[cfg(unix)]
+    pub fn file_mode(&mut self, file_mode: Option<u32>) -> &mut Self {
+        self.update_options(|options| options.file_mode = file_mode)
+    }
+
+    /// Sets the permissions of directories created during extraction (e.g.
+    /// `0o755`). Existing directories are not changed.
+    #[cfg(unix)]
+    pub fn dir_mode(&mut self, dir_mode: Option<u32>) -> &mut Self {
+        self.update_options(|options| options.dir_mode = dir_mode)
+    }
+
+    /// Sets whether files with identical contents are linked to the first
+    /// extracted copy instead of being written again (defaults to `None`,
+    /// which writes every file)
+    pub fn link_duplicates(&mut self, link_duplicates: Option<LinkMode>) -> &mut Self {
+        self.link_duplicates = link_duplicates;
+        self
+    }
+
+    /// Sets whether the files that were already extracted are deleted again if
+    /// an error occurs, so that a failed extraction leaves no partial output
+    /// behind (defaults to `false`). Existing files that would be replaced are
+    /// moved aside to a `.rollback` file while extracting and restored.
+    pub fn rollback_on_error(&mut self, rollback_on_error: bool) -> &mut Self {
+        self.rollback_on_error = rollback_on_error;
+        self
+    }
+
+    /// Sets what happens to files that already exist (defaults to
+    /// `Overwrite::Always`)
+    pub fn overwrite(&mut self, overwrite: Overwrite) -> &mut Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    /// Only keeps the entries for which the given predicate returns true
+    pub fn retain(&mut self, f: impl FnMut(&ExtractEntry) -> bool) -> &mut Self {
+        self.entries.retain(f);
+        self
+    }
+
+    /// Returns the files that will be extracted
+    pub fn entries(&self) -> &[ExtractEntry] {
+        &self.entries
+    }
+
+    /// Returns the total size of all files after decompression
+    pub fn total_size(&self) -> u64 {
+        self.entries
+            .iter()
+            .map(|entry| entry.file.uncompressed_size())
+            .sum()
+    }
+
+    /// Extracts all files in the plan, returning statistics about the files
+    /// that were written
+    pub fn execute(&self, bsa: &mut Bsa) -> Result<ExtractStats, ReadError> {
+        self.execute_with(bsa, |_, _| ())
+    }
+
+    /// Extracts all files in the plan, calling `f` with what is about to
+    /// happen before each file is written
+    pub fn execute_with(
+        &self,
+        bsa: &mut Bsa,
+        mut f: impl FnMut(&ExtractEntry, ExtractAction),
+    ) -> Result<ExtractStats, ReadError> {
+        let start = time::Instant::now();
+        let mut written = vec![];
+        let mut stats = ExtractStats::default();
+        let res = self.execute_entries(bsa, &mut f, &mut written, &mut stats);
+        for (entry, backup) in written {
+            let path = match entry.target_path() {
+                Ok(path) => path,
+                Err(_) => continue,
+            };
+            if res.is_err() && self.rollback_on_error {
+                // the new file might not exist if writing it failed
+                let _ = fs::remove_file(&path);
+                if let Some(backup) = backup {
+                    let _ = fs::rename(backup, path);
+                }
+            } else if let Some(backup) = backup {
+                let _ = fs::remove_file(backup);
+            }
+        }
+        res?;
+        stats.elapsed = start.elapsed();
+        Ok(stats)
+    }
+
+    fn execute_entries<'a>(
+        &'a self,
+        bsa: &mut Bsa,
+        f: &mut impl FnMut(&ExtractEntry, ExtractAction),
+        written: &mut Vec<(&'a ExtractEntry, Option<path::PathBuf>)>,
+        stats: &mut ExtractStats,
+    ) -> Result<(), ReadError> {
+        let archive_modified = bsa.modified();
+        let folder_names: HashMap<u64, String> = bsa
+            .folders_slice()
+            .iter()
+            .map(|folder| {
+                let name = match folder.name() {
+                    Some(name) => name.to_string(),
+                    None => format!("{:016x}", folder.name_hash()),
+                };
+                (folder.name_hash(), name)
+            })
+            .collect();
+        let folder_name = |entry: &ExtractEntry| {
+            folder_names
+                .get(&entry.file.folder_hash())
+                .map_or("", String::as_str)
+        };
+        // only files whose size occurs more than once can be duplicates
+        let mut sizes = HashMap::<u64, usize>::new();
+        if self.link_duplicates.is_some() {
+            for entry in &self.entries {
+                *sizes.entry(entry.file.uncompressed_size()).or_default() += 1;
+            }
+        }
+        let mut extracted = HashMap::<Checksum, &path::Path>::new();
+        // paths that were already written by this run
+        let mut targets = HashSet::new();
+        for entry in &self.entries {
+            if self.overwrite != Overwrite::Always
+                && !entry.should_write(bsa, self.overwrite, archive_modified)?
+            {
+                f(entry, ExtractAction::Skip);
+                stats.skipped += 1;
+                continue;
+            }
+            // files that are replaced are kept until the extraction succeeds,
+            // so that rolling back restores them
+            let backup = if self.rollback_on_error && targets.insert(&entry.path) {
+                entry.move_aside()?
+            } else {
+                None
+            };
+            written.push((entry, backup));
+            let mode = match self.link_duplicates {
+                Some(mode) if sizes[&entry.file.uncompressed_size()] > 1 => mode,
+                _ => {
+                    f(entry, ExtractAction::Create);
+                    if let Some(codec) = entry.extract_recovering(bsa)? {
+                        stats.recovered.push((entry.path.clone(), codec));
+                    }
+                    stats.add_written(entry, folder_name(entry));
+                    continue;
+                }
+            };
+            let checksum = entry.file.checksum(bsa)?;
+            if let Some(original) = extracted.get(&checksum) {
+                f(entry, ExtractAction::Link(original));
+                entry.link(original, mode)?;
+                stats.linked += 1;
+            } else {
+                f(entry, ExtractAction::Create);
+                if let Some(codec) = entry.extract_recovering(bsa)? {
+                    stats.recovered.push((entry.path.clone(), codec));
+                }
+                extracted.insert(checksum, &entry.path);
+                stats.add_written(entry, folder_name(entry));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A file that will be written by a [`BsaBuilder`](crate::BsaBuilder)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildEntry {
+    pub(crate) path: String,
+    pub(crate) size: u64,
+    pub(crate) compressed: bool,
+}
+
+impl BuildEntry {
+    /// Returns the path of the file inside the BSA
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the size of the file contents before compression
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns whether the file will be stored compressed
+    pub fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+}
+
+/// A description of the BSA file that a [`BsaBuilder`](crate::BsaBuilder)
+/// will write, returned by [`BsaBuilder::plan`](crate::BsaBuilder::plan)
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildPlan {
+    pub(crate) entries: Vec<BuildEntry>,
+}
+
+impl BuildPlan {
+    /// Returns the files in the order they will be written
+    pub fn entries(&self) -> &[BuildEntry] {
+        &self.entries
+    }
+
+    /// Returns the total size of all files before compression
+    pub fn total_size(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ExtractAction, ExtractCount, ExtractPlan, LinkMode, Overwrite};
+    use crate::{BsaBuilder, Version};
+    use std::fs;
+
+    #[test]
+    fn test_link_duplicates() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/x.fuz", vec![0; 10]).unwrap();
+        builder.add_file("a/y.fuz", vec![0; 10]).unwrap();
+        builder.add_file("b/z.fuz", vec![1; 10]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("bsa-link-{}", std::process::id()));
+        // creating symbolic links requires extra privileges on Windows
+        let modes: &[LinkMode] = if cfg!(unix) {
+            &[LinkMode::HardLink, LinkMode::SymLink]
+        } else {
+            &[LinkMode::HardLink]
+        };
+        for mode in modes {
+            let mut plan = ExtractPlan::new(&bsa, &dir);
+            plan.link_duplicates(Some(*mode));
+            let mut linked = 0;
+            plan.execute_with(&mut bsa, |_, action| {
+                linked += matches!(action, ExtractAction::Link(_)) as usize
+            })
+            .unwrap();
+            assert_eq!(linked, 1);
+            for (name, byte) in &[("a/x.fuz", 0), ("a/y.fuz", 0), ("b/z.fuz", 1)] {
+                assert_eq!(fs::read(dir.join(name)).unwrap(), vec![*byte; 10]);
+            }
+            let link_count = ["a/x.fuz", "a/y.fuz"]
+                .iter()
+                .filter(|name| {
+                    fs::symlink_metadata(dir.join(name))
+                        .unwrap()
+                        .file_type()
+                        .is_symlink()
+                })
+                .count();
+            assert_eq!(link_count, if *mode == LinkMode::SymLink { 1 } else { 0 });
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_unsafe_names() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file(r"aa\bb\x\y.txt", vec![1]).unwrap();
+        builder.add_file(r"a\z.txt", vec![2]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        // rename the first folder so that it points outside the output folder
+        let pos = data
+            .windows(7)
+            .position(|name| name == br"aa\bb\x")
+            .unwrap();
+        data[pos..pos + 7].copy_from_slice(br"..\..\x");
+        let bsa = crate::OpenOptions::new()
+            .verify_hashes(crate::HashVerification::None)
+            .read(std::io::Cursor::new(data))
+            .unwrap();
+        assert!(bsa
+            .folders_slice()
+            .iter()
+            .any(|folder| folder.name() == Some(r"..\..\x")));
+
+        let dir = std::path::Path::new("out");
+        let plan = ExtractPlan::new(&bsa, dir);
+        let paths: Vec<_> = plan.entries().iter().map(|entry| entry.path()).collect();
+        assert_eq!(paths, [dir.join("a").join("z.txt")]);
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_rollback_on_error() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.compress(true);
+        builder.add_file("a/x.txt", vec![1; 100]).unwrap();
+        builder.add_file("a/y.txt", vec![2; 100]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let valid = data.clone();
+        // corrupt the end of the file stored last
+        let len = data.len();
+        data[len - 2] ^= 0xff;
+        let mut bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("bsa-rollback-{}", std::process::id()));
+        for &rollback in &[false, true] {
+            let mut plan = ExtractPlan::new(&bsa, &dir);
+            plan.rollback_on_error(rollback);
+            assert!(plan.execute(&mut bsa).is_err());
+            let mut files: Vec<_> = fs::read_dir(dir.join("a"))
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            files.sort();
+            let expected: &[&str] = if rollback { &[] } else { &["x.txt"] };
+            assert_eq!(files, expected);
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        // files that existed before are restored, not deleted
+        let mut plan = ExtractPlan::new(&bsa, &dir);
+        plan.rollback_on_error(true);
+        fs::create_dir_all(dir.join("a")).unwrap();
+        fs::write(dir.join("a").join("x.txt"), b"old").unwrap();
+        assert!(plan.execute(&mut bsa).is_err());
+        let mut files: Vec<_> = fs::read_dir(dir.join("a"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["x.txt"]);
+        assert_eq!(fs::read(dir.join("a").join("x.txt")).unwrap(), b"old");
+
+        // and replaced once the extraction succeeds
+        let mut bsa = crate::read(std::io::Cursor::new(valid)).unwrap();
+        plan.execute(&mut bsa).unwrap();
+        assert_eq!(fs::read(dir.join("a").join("x.txt")).unwrap(), vec![1; 100]);
+        assert!(!dir.join("a").join("x.txt.rollback").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_modes() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/b/x.txt", vec![1]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("bsa-modes-{}", std::process::id()));
+        let mut plan = ExtractPlan::new(&bsa, &dir);
+        plan.file_mode(Some(0o640)).dir_mode(Some(0o750));
+        plan.execute(&mut bsa).unwrap();
+        let mode = |path: &str| fs::metadata(dir.join(path)).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode("a/b/x.txt"), 0o640);
+        assert_eq!(mode("a/b"), 0o750);
+        assert_eq!(mode(""), 0o750);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_overwrite() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/x.txt", vec![1; 4]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("bsa-overwrite-{}", std::process::id()));
+        let path = dir.join("a").join("x.txt");
+        let extract = |bsa: &mut crate::Bsa, overwrite, existing: &[u8]| {
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, existing).unwrap();
+            let mut plan = ExtractPlan::new(bsa, &dir);
+            plan.overwrite(overwrite);
+            let mut actions = vec![];
+            plan.execute_with(bsa, |_, action| actions.push(action == ExtractAction::Skip))
+                .unwrap();
+            (actions, fs::read(&path).unwrap())
+        };
+        assert_eq!(
+            extract(&mut bsa, Overwrite::Never, b"old"),
+            (vec![true], b"old".to_vec())
+        );
+        assert_eq!(
+            extract(&mut bsa, Overwrite::Always, b"old"),
+            (vec![false], vec![1; 4])
+        );
+        assert_eq!(
+            extract(&mut bsa, Overwrite::IfDifferent, &[1; 4]),
+            (vec![true], vec![1; 4])
+        );
+        assert_eq!(
+            extract(&mut bsa, Overwrite::IfDifferent, &[2; 4]),
+            (vec![false], vec![1; 4])
+        );
+        // the modification time of an in-memory BSA is unknown
+        assert_eq!(
+            extract(&mut bsa, Overwrite::IfNewer, b"old"),
+            (vec![false], vec![1; 4])
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_file("a/x.txt", vec![1; 4]).unwrap();
+        builder.add_file("a/y.DDS", vec![2; 8]).unwrap();
+        builder.add_file("b/z.dds", vec![3; 16]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+
+        let dir = std::env::temp_dir().join(format!("bsa-stats-{}", std::process::id()));
+        let stats = ExtractPlan::new(&bsa, &dir).execute(&mut bsa).unwrap();
+        assert_eq!((stats.files_written(), stats.bytes_written()), (3, 28));
+        assert_eq!((stats.files_linked(), stats.files_skipped()), (0, 0));
+        let counts = |map: &std::collections::BTreeMap<String, ExtractCount>| {
+            map.iter()
+                .map(|(key, count)| (key.clone(), count.files(), count.bytes()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            counts(stats.by_extension()),
+            vec![("dds".to_string(), 2, 24), ("txt".to_string(), 1, 4)]
+        );
+        assert_eq!(
+            counts(stats.by_folder()),
+            vec![("a".to_string(), 2, 12), ("b".to_string(), 1, 16)]
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
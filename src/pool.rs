@@ -0,0 +1,118 @@
+use crate::bsa::Bsa;
+use std::{fmt, ops, sync};
+
+/// A fixed number of handles to the same BSA file, which are handed out to
+/// concurrent readers. Created by [`open_multi`](crate::open_multi).
+///
+/// All handles share the parsed folder and file records, so they only cost
+/// one open file each.
+pub struct ReaderPool {
+    handles: sync::Mutex<Vec<Bsa>>,
+    returned: sync::Condvar,
+    size: usize,
+}
+
+impl ReaderPool {
+    pub(crate) fn new(handles: Vec<Bsa>) -> Self {
+        Self {
+            size: handles.len(),
+            handles: sync::Mutex::new(handles),
+            returned: sync::Condvar::new(),
+        }
+    }
+
+    /// Returns the total number of handles in this pool
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Takes a handle from the pool, blocking until one becomes available.
+    /// The handle is returned to the pool when it is dropped.
+    pub fn get(&self) -> PooledBsa<'_> {
+        let mut handles = self.handles.lock().unwrap();
+        loop {
+            if let Some(bsa) = handles.pop() {
+                return PooledBsa {
+                    pool: self,
+                    bsa: Some(bsa),
+                };
+            }
+            handles = self.returned.wait(handles).unwrap();
+        }
+    }
+
+    /// Takes a handle from the pool, or returns `None` if all handles are
+    /// currently in use
+    pub fn try_get(&self) -> Option<PooledBsa<'_>> {
+        let bsa = self.handles.lock().unwrap().pop()?;
+        Some(PooledBsa {
+            pool: self,
+            bsa: Some(bsa),
+        })
+    }
+}
+
+impl fmt::Debug for ReaderPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ReaderPool ({} handles)", self.size)
+    }
+}
+
+/// A handle taken from a [`ReaderPool`], which can be used like a `&mut Bsa`
+#[derive(Debug)]
+pub struct PooledBsa<'a> {
+    pool: &'a ReaderPool,
+    bsa: Option<Bsa>,
+}
+
+impl ops::Deref for PooledBsa<'_> {
+    type Target = Bsa;
+
+    fn deref(&self) -> &Bsa {
+        self.bsa.as_ref().unwrap()
+    }
+}
+
+impl ops::DerefMut for PooledBsa<'_> {
+    fn deref_mut(&mut self) -> &mut Bsa {
+        self.bsa.as_mut().unwrap()
+    }
+}
+
+impl Drop for PooledBsa<'_> {
+    fn drop(&mut self) {
+        if let Some(bsa) = self.bsa.take() {
+            self.pool.handles.lock().unwrap().push(bsa);
+            self.pool.returned.notify_one();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BsaBuilder, Version};
+
+    #[test]
+    fn test_reader_pool() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file("a/b.txt", vec![1, 2, 3]).unwrap();
+        let path = std::env::temp_dir().join(format!("bsa-pool-{}.bsa", std::process::id()));
+        builder
+            .write(&mut std::fs::File::create(&path).unwrap())
+            .unwrap();
+        let pool = crate::open_multi(&path, 2).unwrap();
+        assert_eq!(pool.size(), 2);
+
+        let file = pool.get().iter_prefix("").next().unwrap().1.clone();
+        std::thread::scope(|s| {
+            for _ in 0..4 {
+                s.spawn(|| assert_eq!(file.read_to_vec(&mut pool.get()).unwrap(), [1, 2, 3]));
+            }
+        });
+        let (a, b) = (pool.try_get(), pool.try_get());
+        assert!(a.is_some() && b.is_some() && pool.try_get().is_none());
+        drop((a, b));
+        drop(pool);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+//! Optional `tracing` spans and structured events for applications that use
+//! this crate, enabled with the `tracing` feature
+
+use std::io;
+
+/// Creates an info level span if the `tracing` feature is enabled. Otherwise,
+/// the fields are not evaluated and a placeholder is returned.
+macro_rules! span {
+    ($($args:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        {
+            tracing::info_span!($($args)*)
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            crate::profile::Span
+        }
+    }};
+}
+pub(crate) use span;
+
+/// Logs a message at the given level. With the `tracing` feature, it is emitted
+/// as a `tracing` event carrying the fields in braces, which are otherwise not
+/// evaluated; without it, only the message is passed to `log`.
+macro_rules! event {
+    ($level:ident, { $($fields:tt)+ }, $($args:tt)+) => {{
+        #[cfg(feature = "tracing")]
+        {
+            tracing::$level!($($fields)+, $($args)+)
+        }
+        #[cfg(not(feature = "tracing"))]
+        {
+            log::$level!($($args)+)
+        }
+    }};
+}
+pub(crate) use event;
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Span;
+
+/// Stands in for `tracing::Span` when the `tracing` feature is disabled
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+impl Span {
+    pub(crate) fn entered(self) -> Self {
+        self
+    }
+}
+
+/// Enters `span` while reading from `reader`, so that the time spent reading
+/// and decompressing a file is attributed to the span
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn instrument<'a>(reader: Box<dyn io::Read + 'a>, span: Span) -> Box<dyn io::Read + 'a> {
+    #[cfg(feature = "tracing")]
+    {
+        Box::new(Instrumented { reader, span })
+    }
+    #[cfg(not(feature = "tracing"))]
+    {
+        reader
+    }
+}
+
+#[cfg(feature = "tracing")]
+struct Instrumented<'a> {
+    reader: Box<dyn io::Read + 'a>,
+    span: Span,
+}
+
+#[cfg(feature = "tracing")]
+impl io::Read for Instrumented<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let _enter = self.span.enter();
+        self.reader.read(buf)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        let _enter = self.span.enter();
+        self.reader.read_vectored(bufs)
+    }
+}
@@ -0,0 +1,363 @@
+use crate::bsa::Codec;
+use std::{convert::TryFrom, fmt, io};
+
+/// Like `io::Take`, but forwards vectored reads to the underlying reader
+pub(crate) struct Limited<R> {
+    inner: R,
+    remaining: u64,
+}
+
+impl<R: io::Read> Limited<R> {
+    pub(crate) fn new(inner: R, limit: u64) -> Self {
+        Self {
+            inner,
+            remaining: limit,
+        }
+    }
+}
+
+impl<R: io::Read> io::Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let max = buf
+            .len()
+            .min(usize::try_from(self.remaining).unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..max])?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        let mut remaining = usize::try_from(self.remaining).unwrap_or(usize::MAX);
+        let mut limited = vec![];
+        for buf in bufs.iter_mut() {
+            if remaining == 0 {
+                break;
+            }
+            let len = buf.len().min(remaining);
+            remaining -= len;
+            limited.push(io::IoSliceMut::new(&mut buf[..len]));
+        }
+        let n = self.inner.read_vectored(&mut limited)?;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// A reader over a range of bytes in another reader, e.g. an archive embedded
+/// in an installer or another container. Offsets are relative to the start of
+/// the range, and reads stop at its end.
+///
+/// ```
+/// use std::io::{Cursor, Read};
+///
+/// let mut reader = bsa::SubReader::new(Cursor::new(b"abcdefgh".to_vec()), 2, 3).unwrap();
+/// let mut data = String::new();
+/// reader.read_to_string(&mut data).unwrap();
+/// assert_eq!(data, "cde");
+/// ```
+#[derive(Debug)]
+pub struct SubReader<R> {
+    inner: R,
+    start: u64,
+    len: u64,
+    position: u64,
+}
+
+impl<R: io::Read + io::Seek> SubReader<R> {
+    /// Creates a reader over the `len` bytes at `offset` in the given reader
+    pub fn new(mut inner: R, offset: u64, len: u64) -> io::Result<Self> {
+        offset
+            .checked_add(len)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "range is out of bounds"))?;
+        inner.seek(io::SeekFrom::Start(offset))?;
+        Ok(Self {
+            inner,
+            start: offset,
+            len,
+            position: 0,
+        })
+    }
+
+    /// Returns the underlying reader
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: io::Read + io::Seek> io::Read for SubReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.len.saturating_sub(self.position);
+        let max = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        if max == 0 {
+            return Ok(0);
+        }
+        let n = self.inner.read(&mut buf[..max])?;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: io::Read + io::Seek> io::Seek for SubReader<R> {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            io::SeekFrom::Start(offset) => Some(offset),
+            io::SeekFrom::End(offset) => self.len.checked_add_signed(offset),
+            io::SeekFrom::Current(offset) => self.position.checked_add_signed(offset),
+        }
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )
+        })?;
+        let absolute = self.start.checked_add(position).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek position is out of bounds",
+            )
+        })?;
+        self.inner.seek(io::SeekFrom::Start(absolute))?;
+        self.position = position;
+        Ok(position)
+    }
+}
+
+/// Decompresses LZ4 data in the block format (without frame headers), as
+/// written by some third-party packers. The whole block is decompressed on the
+/// first read, so errors are reported by `read` like with `lz4::Decoder`.
+#[cfg(feature = "lz4")]
+pub(crate) struct Lz4BlockDecoder<R> {
+    inner: Option<R>,
+    uncompressed_size: u64,
+    decoded: io::Cursor<Vec<u8>>,
+}
+
+#[cfg(feature = "lz4")]
+impl<R: io::Read> Lz4BlockDecoder<R> {
+    pub(crate) fn new(inner: R, uncompressed_size: u64) -> Self {
+        Self {
+            inner: Some(inner),
+            uncompressed_size,
+            decoded: io::Cursor::new(vec![]),
+        }
+    }
+}
+
+#[cfg(feature = "lz4")]
+impl<R: io::Read> io::Read for Lz4BlockDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(mut inner) = self.inner.take() {
+            let mut compressed = vec![];
+            inner.read_to_end(&mut compressed)?;
+            // LZ4 can't compress by more than a factor of 255, so don't
+            // allocate huge buffers for corrupt sizes
+            if self.uncompressed_size > compressed.len() as u64 * 255 + 16 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "uncompressed size is too large for LZ4 block",
+                ));
+            }
+            let size = i32::try_from(self.uncompressed_size)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            self.decoded = io::Cursor::new(lz4::block::decompress(&compressed, Some(size))?);
+        }
+        self.decoded.read(buf)
+    }
+}
+
+/// Decompresses `data` with the given codec, returning `None` if it isn't
+/// valid or doesn't decompress to exactly `size` bytes
+pub(crate) fn try_decode(codec: Codec, data: &[u8], size: u64) -> Option<Vec<u8>> {
+    let reader: Box<dyn io::Read + '_> = match codec {
+        Codec::None => Box::new(data),
+        #[cfg(feature = "zlib")]
+        Codec::Zlib => Box::new(flate2::read::ZlibDecoder::new(data)),
+        #[cfg(feature = "lz4")]
+        Codec::Lz4 => Box::new(lz4::Decoder::new(data).ok()?),
+        #[cfg(feature = "lz4")]
+        Codec::Lz4Block => Box::new(Lz4BlockDecoder::new(data, size)),
+        #[cfg(feature = "zstd")]
+        Codec::Zstd => Box::new(zstd::stream::read::Decoder::new(data).ok()?),
+        #[allow(unreachable_patterns)]
+        _ => return None,
+    };
+    let mut res = vec![];
+    io::Read::read_to_end(&mut io::Read::take(reader, size + 1), &mut res).ok()?;
+    if res.len() as u64 == size {
+        Some(res)
+    } else {
+        None
+    }
+}
+
+/// A reader for the decompressed contents of a file inside a BSA, returned by
+/// [`File::read_contents`](crate::File::read_contents).
+///
+/// The reader implements `BufRead`, so it doesn't need to be wrapped in an
+/// `io::BufReader`. Uncompressed files are read through a buffer, while reads
+/// of compressed files go straight to the decoder, which buffers its input
+/// itself; the buffer is then only filled when `BufRead` methods are used.
+/// Large reads bypass the buffer. The buffer size can be set with
+/// [`OpenOptions::decompression_buffer_size`](crate::OpenOptions::decompression_buffer_size).
+pub struct FileReader<'a> {
+    inner: Inner<'a>,
+    expected_len: u64,
+}
+
+enum Inner<'a> {
+    Buffered(io::BufReader<Box<dyn io::Read + 'a>>),
+    Decoder(DecoderReader<'a>),
+}
+
+/// A decoder whose output is only buffered when it is read through `BufRead`
+struct DecoderReader<'a> {
+    decoder: Box<dyn io::Read + 'a>,
+    buffer: Vec<u8>,
+    capacity: usize,
+    position: usize,
+}
+
+impl io::Read for DecoderReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.position < self.buffer.len() {
+            let n = io::Read::read(&mut &self.buffer[self.position..], buf)?;
+            self.position += n;
+            Ok(n)
+        } else {
+            self.decoder.read(buf)
+        }
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        if self.position < self.buffer.len() {
+            let n = io::Read::read_vectored(&mut &self.buffer[self.position..], bufs)?;
+            self.position += n;
+            Ok(n)
+        } else {
+            self.decoder.read_vectored(bufs)
+        }
+    }
+}
+
+impl io::BufRead for DecoderReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        if self.position >= self.buffer.len() {
+            self.buffer.resize(self.capacity.max(1), 0);
+            let n = self.decoder.read(&mut self.buffer)?;
+            self.buffer.truncate(n);
+            self.position = 0;
+        }
+        Ok(&self.buffer[self.position..])
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.position = (self.position + amt).min(self.buffer.len());
+    }
+}
+
+impl<'a> FileReader<'a> {
+    /// Creates a reader for the contents of a file. Decoders of compressed
+    /// files are not wrapped in a buffer.
+    pub(crate) fn new(
+        inner: Box<dyn io::Read + 'a>,
+        expected_len: u64,
+        buffer_size: usize,
+        compressed: bool,
+    ) -> Self {
+        let inner = if compressed {
+            Inner::Decoder(DecoderReader {
+                decoder: inner,
+                buffer: vec![],
+                capacity: buffer_size,
+                position: 0,
+            })
+        } else {
+            Inner::Buffered(io::BufReader::with_capacity(buffer_size, inner))
+        };
+        Self {
+            inner,
+            expected_len,
+        }
+    }
+
+    /// Returns the total number of bytes this reader is expected to produce,
+    /// i.e. the size of the file after decompression. This can be used to set
+    /// a `Content-Length` header or to preallocate buffers.
+    pub fn expected_len(&self) -> u64 {
+        self.expected_len
+    }
+}
+
+impl io::Read for FileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.inner {
+            Inner::Buffered(reader) => reader.read(buf),
+            Inner::Decoder(reader) => reader.read(buf),
+        }
+    }
+
+    fn read_vectored(&mut self, bufs: &mut [io::IoSliceMut<'_>]) -> io::Result<usize> {
+        match &mut self.inner {
+            Inner::Buffered(reader) => reader.read_vectored(bufs),
+            Inner::Decoder(reader) => reader.read_vectored(bufs),
+        }
+    }
+}
+
+impl io::BufRead for FileReader<'_> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        match &mut self.inner {
+            Inner::Buffered(reader) => reader.fill_buf(),
+            Inner::Decoder(reader) => reader.fill_buf(),
+        }
+    }
+
+    fn consume(&mut self, amt: usize) {
+        match &mut self.inner {
+            Inner::Buffered(reader) => reader.consume(amt),
+            Inner::Decoder(reader) => reader.consume(amt),
+        }
+    }
+}
+
+impl fmt::Debug for FileReader<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FileReader ({} bytes)", self.expected_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Limited, SubReader};
+    use std::io::{self, Read, Seek};
+
+    #[test]
+    fn test_limited_vectored_read() {
+        let mut reader = Limited::new(&b"abcdefgh"[..], 5);
+        let (mut a, mut b) = ([0; 3], [0; 3]);
+        let n = reader
+            .read_vectored(&mut [io::IoSliceMut::new(&mut a), io::IoSliceMut::new(&mut b)])
+            .unwrap();
+        assert_eq!(n, 5);
+        assert_eq!((&a, &b[..2]), (b"abc", &b"de"[..]));
+        assert_eq!(reader.read(&mut a).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_sub_reader() {
+        let mut reader = SubReader::new(io::Cursor::new(b"abcdefgh".to_vec()), 2, 4).unwrap();
+        let mut data = vec![];
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"cdef");
+        assert_eq!(reader.seek(io::SeekFrom::End(-1)).unwrap(), 3);
+        let mut byte = [0];
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(&byte, b"f");
+        assert_eq!(reader.seek(io::SeekFrom::Current(-3)).unwrap(), 1);
+        reader.read_exact(&mut byte).unwrap();
+        assert_eq!(&byte, b"d");
+        assert!(reader.seek(io::SeekFrom::Current(-5)).is_err());
+    }
+}
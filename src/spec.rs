@@ -0,0 +1,143 @@
+use crate::bsa::{Version, WriteError};
+use crate::game::Game;
+use crate::write::BsaBuilder;
+use std::io;
+
+/// A complete description of an archive to write, as plain data. Unlike
+/// [`BsaBuilder`], every part of it can be constructed and inspected
+/// directly, so code that decides what goes into an archive can be tested
+/// without writing anything. With the `serde` feature, specs can also be
+/// serialized.
+///
+/// ```
+/// use bsa::{ArchiveSpec, FileSpec, FolderSpec, Version};
+///
+/// let mut spec = ArchiveSpec::new(Version::SKYRIM_SPECIAL_EDITION);
+/// spec.folders.push(FolderSpec {
+///     name: r"meshes\foo".to_string(),
+///     files: vec![FileSpec::new("bar.nif", vec![1, 2, 3])],
+/// });
+/// let data = spec.to_bytes().unwrap();
+/// assert_eq!(&data[..4], b"BSA\0");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchiveSpec {
+    /// The version of the archive format, see [`BsaBuilder::new`]
+    pub version: Version,
+    /// The game whose limits are checked, see [`BsaBuilder::for_game`]
+    pub game: Game,
+    /// See [`BsaBuilder::compress`]
+    pub compress: bool,
+    /// See [`BsaBuilder::lz4_block`]
+    pub lz4_block: bool,
+    /// See [`BsaBuilder::zstd`]
+    pub zstd: bool,
+    /// See [`BsaBuilder::embed_file_names`]
+    pub embed_file_names: bool,
+    /// See [`BsaBuilder::keep_original_case`]
+    pub keep_original_case: bool,
+    /// See [`BsaBuilder::alignment`]
+    pub alignment: u32,
+    /// See [`BsaBuilder::empty_folders`]
+    pub empty_folders: bool,
+    /// See [`BsaBuilder::ignore_game_limits`]
+    pub ignore_game_limits: bool,
+    /// The folders in the archive, with their files
+    pub folders: Vec<FolderSpec>,
+}
+
+/// A folder in an [`ArchiveSpec`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FolderSpec {
+    /// The folder path, using either forward slashes or backslashes
+    pub name: String,
+    /// The files in the folder
+    pub files: Vec<FileSpec>,
+}
+
+/// A file in a [`FolderSpec`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileSpec {
+    /// The file name, without the folder
+    pub name: String,
+    /// The file contents
+    pub data: Vec<u8>,
+    /// Overrides [`ArchiveSpec::compress`] for this file
+    pub compress: Option<bool>,
+    /// If set, `data` is already compressed with the codec of the archive,
+    /// and this is its uncompressed size
+    pub uncompressed_size: Option<u32>,
+}
+
+impl ArchiveSpec {
+    /// Creates an empty spec with the same defaults as [`BsaBuilder::new`]
+    pub fn new(version: Version) -> Self {
+        BsaBuilder::new(version).spec()
+    }
+
+    /// Writes the archive described by this spec to the given writer
+    pub fn write(&self, writer: &mut impl io::Write) -> Result<(), WriteError> {
+        BsaBuilder::from_spec(self)?.write(writer)
+    }
+
+    /// Returns the bytes of the archive described by this spec
+    pub fn to_bytes(&self) -> Result<Vec<u8>, WriteError> {
+        let mut res = vec![];
+        self.write(&mut res)?;
+        Ok(res)
+    }
+}
+
+impl FileSpec {
+    /// Creates a spec for a file with the given uncompressed contents
+    pub fn new(name: &str, data: Vec<u8>) -> Self {
+        Self {
+            name: name.to_string(),
+            data,
+            compress: None,
+            uncompressed_size: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArchiveSpec, FileSpec, FolderSpec};
+    use crate::bsa::Version;
+
+    #[cfg(feature = "zlib")]
+    #[test]
+    fn test_spec_round_trip() {
+        use crate::write::BsaBuilder;
+
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.compress(true).alignment(16);
+        builder.add_file(r"meshes\a.nif", vec![1; 100]).unwrap();
+        builder
+            .add_file_with_compression(r"Textures\B.dds", vec![2; 10], false)
+            .unwrap();
+        builder.add_folder("empty").unwrap();
+
+        let spec = builder.spec();
+        assert_eq!(spec.folders.len(), 3);
+        assert_eq!(spec.folders[2].name, "Textures");
+        assert_eq!(spec.folders[2].files[0].compress, Some(false));
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        assert_eq!(spec.to_bytes().unwrap(), data);
+        assert_eq!(BsaBuilder::from_spec(&spec).unwrap().spec(), spec);
+    }
+
+    #[test]
+    fn test_spec_errors() {
+        let mut spec = ArchiveSpec::new(Version::SKYRIM_SPECIAL_EDITION);
+        spec.folders.push(FolderSpec {
+            name: "".to_string(),
+            files: vec![FileSpec::new("a.nif", vec![])],
+        });
+        assert!(spec.to_bytes().is_err());
+    }
+}
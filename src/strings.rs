@@ -0,0 +1,329 @@
+//! Reading the localized string tables of plugins (`.strings`, `.dlstrings`
+//! and `.ilstrings` files), which are usually packaged in archives.
+
+use crate::bsa::{Bsa, File, ReadError};
+use crate::encoding::Encoding;
+use crate::entry::Entry;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::{error, fmt, str};
+
+/// Represents an error when reading a string table
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum StringsError {
+    /// The string table couldn't be read from the archive
+    Read(ReadError),
+    /// The string table is malformed, contains the reason
+    InvalidTable(&'static str),
+    /// A string is not valid UTF-8, contains the ID of the string
+    InvalidUtf8(u32),
+}
+
+impl fmt::Display for StringsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Read(_) => write!(f, "Failed to read string table"),
+            Self::InvalidTable(reason) => write!(f, "Invalid string table: {}", reason),
+            Self::InvalidUtf8(id) => write!(f, "String {:#x} is not valid UTF-8", id),
+        }
+    }
+}
+
+impl error::Error for StringsError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Read(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ReadError> for StringsError {
+    fn from(e: ReadError) -> Self {
+        Self::Read(e)
+    }
+}
+
+/// The kind of a string table, which determines how its strings are stored
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum StringsKind {
+    /// Names and other short strings (`.strings`), which are null-terminated
+    Strings,
+    /// Descriptions (`.dlstrings`), which are prefixed with their length
+    DlStrings,
+    /// Dialogue (`.ilstrings`), which are prefixed with their length
+    IlStrings,
+}
+
+impl StringsKind {
+    /// Returns the kind of string table with the extension of the given path,
+    /// if any
+    pub fn from_path(path: &str) -> Option<Self> {
+        let (_, extension) = path.rsplit_once('.')?;
+        [Self::Strings, Self::DlStrings, Self::IlStrings]
+            .iter()
+            .copied()
+            .find(|kind| extension.eq_ignore_ascii_case(kind.extension()))
+    }
+
+    /// Returns the file extension of this kind of string table
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Strings => "strings",
+            Self::DlStrings => "dlstrings",
+            Self::IlStrings => "ilstrings",
+        }
+    }
+}
+
+/// The character encoding of the strings in a string table.
+///
+/// Skyrim SE and Fallout 4 store most languages as UTF-8, while Skyrim LE
+/// and English string tables use the Windows code page of the language.
+#[derive(Debug, Clone, Copy)]
+pub enum StringsEncoding {
+    /// UTF-8, where invalid strings are an error
+    Utf8,
+    /// The given single-byte or legacy encoding
+    Legacy(Encoding),
+    /// UTF-8 if every string in the table is valid UTF-8, and the given
+    /// encoding otherwise
+    Detect(Encoding),
+}
+
+impl Default for StringsEncoding {
+    fn default() -> Self {
+        Self::Detect(Encoding::Windows1252)
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    let bytes = data.get(offset..offset.checked_add(4)?)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+/// Returns the raw bytes of a string, without its terminator
+fn raw_string(data: &[u8], offset: usize, kind: StringsKind) -> Result<&[u8], StringsError> {
+    let string = match kind {
+        StringsKind::Strings => {
+            let rest = data
+                .get(offset..)
+                .ok_or(StringsError::InvalidTable("string offset is out of bounds"))?;
+            let len = rest
+                .iter()
+                .position(|&b| b == 0)
+                .ok_or(StringsError::InvalidTable("string is not terminated"))?;
+            &rest[..len]
+        }
+        StringsKind::DlStrings | StringsKind::IlStrings => {
+            let len = read_u32(data, offset)
+                .ok_or(StringsError::InvalidTable("string offset is out of bounds"))?;
+            let start = offset + 4;
+            let string = usize::try_from(len)
+                .ok()
+                .and_then(|len| start.checked_add(len))
+                .and_then(|end| data.get(start..end))
+                .ok_or(StringsError::InvalidTable("string is truncated"))?;
+            // the length includes the terminator
+            string.strip_suffix(&[0]).unwrap_or(string)
+        }
+    };
+    Ok(string)
+}
+
+/// Parses the contents of a string table into a map from string IDs to
+/// strings. The kind of table can be found with [`StringsKind::from_path`].
+pub fn parse_strings(
+    data: &[u8],
+    kind: StringsKind,
+    encoding: StringsEncoding,
+) -> Result<BTreeMap<u32, String>, StringsError> {
+    let (count, data_size) = read_u32(data, 0)
+        .zip(read_u32(data, 4))
+        .ok_or(StringsError::InvalidTable("header is truncated"))?;
+    let data_start = usize::try_from(count)
+        .ok()
+        .and_then(|count| count.checked_mul(8))
+        .and_then(|size| size.checked_add(8))
+        .filter(|&start| start <= data.len())
+        .ok_or(StringsError::InvalidTable("directory is truncated"))?;
+    let string_data = usize::try_from(data_size)
+        .ok()
+        .and_then(|size| data_start.checked_add(size))
+        .and_then(|end| data.get(data_start..end))
+        .ok_or(StringsError::InvalidTable("string data is truncated"))?;
+
+    let mut raw = Vec::with_capacity(count as usize);
+    for entry in data[8..data_start].chunks_exact(8) {
+        let id = read_u32(entry, 0).unwrap();
+        let offset = read_u32(entry, 4).unwrap() as usize;
+        raw.push((id, raw_string(string_data, offset, kind)?));
+    }
+    let decode_legacy = |legacy: Encoding| -> Result<_, StringsError> {
+        Ok(raw
+            .iter()
+            .map(|&(id, string)| (id, legacy.decode(string)))
+            .collect())
+    };
+    match encoding {
+        StringsEncoding::Legacy(legacy) => decode_legacy(legacy),
+        StringsEncoding::Detect(legacy)
+            if raw
+                .iter()
+                .any(|(_, string)| str::from_utf8(string).is_err()) =>
+        {
+            decode_legacy(legacy)
+        }
+        StringsEncoding::Utf8 | StringsEncoding::Detect(_) => raw
+            .iter()
+            .map(|&(id, string)| {
+                str::from_utf8(string)
+                    .map(|string| (id, string.to_string()))
+                    .map_err(|_| StringsError::InvalidUtf8(id))
+            })
+            .collect(),
+    }
+}
+
+impl File {
+    /// Reads this file as a string table, see [`parse_strings`]
+    pub fn read_strings(
+        &self,
+        bsa: &mut Bsa,
+        kind: StringsKind,
+        encoding: StringsEncoding,
+    ) -> Result<BTreeMap<u32, String>, StringsError> {
+        parse_strings(&self.read_to_vec(bsa)?, kind, encoding)
+    }
+}
+
+impl Bsa {
+    /// Returns every string table in the `strings` folder of this BSA, with
+    /// its kind
+    pub fn strings_files(&self) -> Vec<(StringsKind, Entry<'_>)> {
+        self.entries()
+            .filter(|entry| {
+                entry
+                    .folder()
+                    .name()
+                    .is_some_and(|name| name.eq_ignore_ascii_case("strings"))
+            })
+            .filter_map(|entry| Some((StringsKind::from_path(entry.file().name()?)?, entry)))
+            .collect()
+    }
+
+    /// Looks up the string table of the given kind for a plugin and language,
+    /// e.g. `strings\skyrim_english.dlstrings` for `Skyrim.esm` and `english`
+    pub fn find_strings(
+        &self,
+        plugin: &str,
+        language: &str,
+        kind: StringsKind,
+    ) -> Option<Entry<'_>> {
+        let stem = plugin.rsplit_once('.').map_or(plugin, |(stem, _)| stem);
+        self.find_entry(&format!(
+            "strings\\{}_{}.{}",
+            stem,
+            language,
+            kind.extension()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_strings, StringsEncoding, StringsError, StringsKind};
+    use crate::bsa::{self, Version};
+    use crate::encoding::Encoding;
+    use crate::write::BsaBuilder;
+
+    fn table(strings: &[(u32, &[u8])], kind: StringsKind) -> Vec<u8> {
+        let (mut directory, mut data) = (vec![], vec![]);
+        for (id, string) in strings {
+            directory.extend_from_slice(&id.to_le_bytes());
+            directory.extend_from_slice(&(data.len() as u32).to_le_bytes());
+            if kind != StringsKind::Strings {
+                data.extend_from_slice(&(string.len() as u32 + 1).to_le_bytes());
+            }
+            data.extend_from_slice(string);
+            data.push(0);
+        }
+        let mut res = (strings.len() as u32).to_le_bytes().to_vec();
+        res.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        res.extend_from_slice(&directory);
+        res.extend_from_slice(&data);
+        res
+    }
+
+    #[test]
+    fn test_parse_strings() {
+        let strings: &[(u32, &[u8])] = &[(1, b"Iron Sword"), (0x20, "\u{e9}p\u{e9}e".as_bytes())];
+        for &kind in &[StringsKind::Strings, StringsKind::DlStrings] {
+            let map = parse_strings(&table(strings, kind), kind, StringsEncoding::Utf8).unwrap();
+            assert_eq!(map.len(), 2);
+            assert_eq!(map[&1], "Iron Sword");
+            assert_eq!(map[&0x20], "\u{e9}p\u{e9}e");
+        }
+
+        let data = table(&[(1, b"\xe9p\xe9e")], StringsKind::Strings);
+        let map = parse_strings(&data, StringsKind::Strings, StringsEncoding::default()).unwrap();
+        assert_eq!(map[&1], "\u{e9}p\u{e9}e");
+        assert!(matches!(
+            parse_strings(&data, StringsKind::Strings, StringsEncoding::Utf8),
+            Err(StringsError::InvalidUtf8(1))
+        ));
+        let legacy = StringsEncoding::Legacy(Encoding::Windows1252);
+        assert!(parse_strings(&data, StringsKind::Strings, legacy).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_strings() {
+        let data = table(&[(1, b"Iron Sword")], StringsKind::DlStrings);
+        for len in [4, 12, data.len() - 1] {
+            assert!(matches!(
+                parse_strings(&data[..len], StringsKind::DlStrings, StringsEncoding::Utf8),
+                Err(StringsError::InvalidTable(_))
+            ));
+        }
+        let mut data = table(&[(1, b"Iron Sword")], StringsKind::Strings);
+        data.pop();
+        let len = data.len() as u32 - 16;
+        data[4..8].copy_from_slice(&len.to_le_bytes());
+        assert!(matches!(
+            parse_strings(&data, StringsKind::Strings, StringsEncoding::Utf8),
+            Err(StringsError::InvalidTable(_))
+        ));
+    }
+
+    #[test]
+    fn test_find_strings() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        let strings = table(&[(7, b"Whiterun")], StringsKind::Strings);
+        builder
+            .add_file(r"strings\mymod_english.strings", strings)
+            .unwrap();
+        builder
+            .add_file(r"strings\readme.txt", b"hello".to_vec())
+            .unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = bsa::read(std::io::Cursor::new(data)).unwrap();
+
+        let files = bsa.strings_files();
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].0, StringsKind::Strings);
+        assert!(bsa
+            .find_strings("MyMod.esp", "english", StringsKind::DlStrings)
+            .is_none());
+        let file = bsa
+            .find_strings("MyMod.esp", "english", StringsKind::Strings)
+            .unwrap()
+            .file()
+            .clone();
+        let map = file
+            .read_strings(&mut bsa, StringsKind::Strings, StringsEncoding::default())
+            .unwrap();
+        assert_eq!(map[&7], "Whiterun");
+    }
+}
@@ -0,0 +1,319 @@
+//! Decoding DDS textures into downscaled PNG thumbnails.
+
+use crate::bsa::{Bsa, File, ReadError};
+use std::convert::TryFrom;
+use std::{error, fmt, io};
+
+/// Represents an error when creating a thumbnail of a texture
+#[non_exhaustive]
+#[derive(Debug)]
+pub enum ThumbnailError {
+    /// The texture couldn't be read from the archive
+    Read(ReadError),
+    /// The texture is not a valid DDS file, contains the reason
+    InvalidDds(&'static str),
+    /// The pixel format of the texture can't be decoded, contains a
+    /// description of the format
+    UnsupportedFormat(String),
+    /// The thumbnail couldn't be encoded as PNG, contains the reason
+    Encode(String),
+}
+
+impl fmt::Display for ThumbnailError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Read(_) => write!(f, "Failed to read texture"),
+            Self::InvalidDds(reason) => write!(f, "Invalid DDS file: {}", reason),
+            Self::UnsupportedFormat(format) => {
+                write!(f, "Unsupported texture format: {}", format)
+            }
+            Self::Encode(reason) => write!(f, "Failed to encode thumbnail: {}", reason),
+        }
+    }
+}
+
+impl error::Error for ThumbnailError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Read(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ReadError> for ThumbnailError {
+    fn from(e: ReadError) -> Self {
+        Self::Read(e)
+    }
+}
+
+/// The pixel formats that can be decoded
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Format {
+    Bc1,
+    Bc2,
+    Bc3,
+    Bc4,
+    Bc5,
+    Bc7,
+    Rgba,
+    Bgra,
+    Bgrx,
+    Bgr,
+}
+
+impl Format {
+    fn from_fourcc(fourcc: &[u8]) -> Result<Self, ThumbnailError> {
+        Ok(match fourcc {
+            b"DXT1" => Self::Bc1,
+            b"DXT2" | b"DXT3" => Self::Bc2,
+            b"DXT4" | b"DXT5" => Self::Bc3,
+            b"ATI1" | b"BC4U" => Self::Bc4,
+            b"ATI2" | b"BC5U" => Self::Bc5,
+            _ => {
+                return Err(ThumbnailError::UnsupportedFormat(format!(
+                    "FourCC {:?}",
+                    String::from_utf8_lossy(fourcc)
+                )))
+            }
+        })
+    }
+
+    fn from_dxgi(format: u32) -> Result<Self, ThumbnailError> {
+        Ok(match format {
+            28 | 29 => Self::Rgba,
+            71 | 72 => Self::Bc1,
+            74 | 75 => Self::Bc2,
+            77 | 78 => Self::Bc3,
+            80 => Self::Bc4,
+            83 => Self::Bc5,
+            87 | 91 => Self::Bgra,
+            88 | 93 => Self::Bgrx,
+            98 | 99 => Self::Bc7,
+            _ => {
+                return Err(ThumbnailError::UnsupportedFormat(format!(
+                    "DXGI format {}",
+                    format
+                )))
+            }
+        })
+    }
+
+    /// Returns the size of the given mipmap level in bytes, or `None` if it
+    /// overflows
+    fn level_size(self, width: usize, height: usize) -> Option<usize> {
+        let blocks = width.div_ceil(4).checked_mul(height.div_ceil(4))?;
+        let pixels = width.checked_mul(height)?;
+        match self {
+            Self::Bc1 | Self::Bc4 => blocks.checked_mul(8),
+            Self::Bc2 | Self::Bc3 | Self::Bc5 | Self::Bc7 => blocks.checked_mul(16),
+            Self::Rgba | Self::Bgra | Self::Bgrx => pixels.checked_mul(4),
+            Self::Bgr => pixels.checked_mul(3),
+        }
+    }
+
+    /// Decodes a mipmap level into RGBA pixels
+    fn decode(self, data: &[u8], width: usize, height: usize) -> Result<Vec<u8>, ThumbnailError> {
+        type Decoder = fn(&[u8], usize, usize, &mut [u32]) -> Result<(), &'static str>;
+        let decode_blocks = |decode: Decoder| -> Result<Vec<u8>, ThumbnailError> {
+            let mut pixels = vec![0; width * height];
+            decode(data, width, height, &mut pixels).map_err(ThumbnailError::InvalidDds)?;
+            Ok(pixels
+                .into_iter()
+                .flat_map(|pixel| {
+                    let [b, g, r, a] = pixel.to_le_bytes();
+                    [r, g, b, a]
+                })
+                .collect())
+        };
+        let pixels = |size: usize, to_rgba: fn(&[u8]) -> [u8; 4]| {
+            data.chunks_exact(size).flat_map(to_rgba).collect()
+        };
+        match self {
+            Self::Bc1 => decode_blocks(texture2ddecoder::decode_bc1),
+            Self::Bc2 => decode_blocks(texture2ddecoder::decode_bc2),
+            Self::Bc3 => decode_blocks(texture2ddecoder::decode_bc3),
+            Self::Bc4 => decode_blocks(texture2ddecoder::decode_bc4),
+            Self::Bc5 => decode_blocks(texture2ddecoder::decode_bc5),
+            Self::Bc7 => decode_blocks(texture2ddecoder::decode_bc7),
+            Self::Rgba => Ok(data.to_vec()),
+            Self::Bgra => Ok(pixels(4, |p| [p[2], p[1], p[0], p[3]])),
+            Self::Bgrx => Ok(pixels(4, |p| [p[2], p[1], p[0], 255])),
+            Self::Bgr => Ok(pixels(3, |p| [p[2], p[1], p[0], 255])),
+        }
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+/// Returns the format of a DDS file and the offset of its pixel data
+fn parse_header(data: &[u8]) -> Result<(Format, usize), ThumbnailError> {
+    if !data.starts_with(b"DDS ") {
+        return Err(ThumbnailError::InvalidDds("missing DDS magic"));
+    }
+    if data.len() < 128 {
+        return Err(ThumbnailError::InvalidDds("header is truncated"));
+    }
+    let flags = read_u32(data, 80);
+    if flags & 0x4 != 0 {
+        let fourcc = &data[84..88];
+        if fourcc != b"DX10" {
+            return Ok((Format::from_fourcc(fourcc)?, 128));
+        }
+        if data.len() < 148 {
+            return Err(ThumbnailError::InvalidDds("DX10 header is truncated"));
+        }
+        return Ok((Format::from_dxgi(read_u32(data, 128))?, 148));
+    }
+    let (bit_count, red_mask) = (read_u32(data, 88), read_u32(data, 92));
+    let has_alpha = flags & 0x1 != 0;
+    let format = match (bit_count, red_mask) {
+        (32, 0x00ff_0000) if has_alpha => Format::Bgra,
+        (32, 0x00ff_0000) => Format::Bgrx,
+        (32, 0x0000_00ff) if has_alpha => Format::Rgba,
+        (24, 0x00ff_0000) => Format::Bgr,
+        _ => {
+            return Err(ThumbnailError::UnsupportedFormat(format!(
+                "{}-bit uncompressed with red mask {:#x}",
+                bit_count, red_mask
+            )))
+        }
+    };
+    Ok((format, 128))
+}
+
+/// Creates a PNG thumbnail of a DDS texture, downscaled so that neither side
+/// is longer than `max_dim` pixels. Smaller textures keep their size. The
+/// smallest mipmap that is still at least `max_dim` pixels wide or high is
+/// decoded, so large textures with mipmaps are cheap to preview.
+///
+/// BC1 to BC5 and BC7 compressed textures and 24- or 32-bit uncompressed
+/// textures are supported.
+pub fn dds_thumbnail(dds: &[u8], max_dim: u32) -> Result<Vec<u8>, ThumbnailError> {
+    let (format, mut offset) = parse_header(dds)?;
+    let height = read_u32(dds, 12);
+    let width = read_u32(dds, 16);
+    if width == 0 || height == 0 {
+        return Err(ThumbnailError::InvalidDds("texture is empty"));
+    }
+    let max_dim = max_dim.max(1);
+    let levels = read_u32(dds, 28).max(1);
+    let (mut width, mut height) = (width as usize, height as usize);
+    for _ in 1..levels {
+        let (next_width, next_height) = ((width / 2).max(1), (height / 2).max(1));
+        if next_width.max(next_height) < max_dim as usize {
+            break;
+        }
+        offset = format
+            .level_size(width, height)
+            .and_then(|size| offset.checked_add(size))
+            .ok_or(ThumbnailError::InvalidDds("pixel data is truncated"))?;
+        width = next_width;
+        height = next_height;
+    }
+    let data = format
+        .level_size(width, height)
+        .and_then(|size| offset.checked_add(size))
+        .and_then(|end| dds.get(offset..end))
+        .ok_or(ThumbnailError::InvalidDds("pixel data is truncated"))?;
+    let pixels = format.decode(data, width, height)?;
+
+    let (width, height) = (width as u32, height as u32);
+    let mut image = image::RgbaImage::from_raw(width, height, pixels)
+        .ok_or(ThumbnailError::InvalidDds("pixel data is truncated"))?;
+    if width.max(height) > max_dim {
+        let scale = |side: u32| {
+            u32::try_from(u64::from(side) * u64::from(max_dim) / u64::from(width.max(height)))
+                .unwrap_or(max_dim)
+                .max(1)
+        };
+        image = image::imageops::thumbnail(&image, scale(width), scale(height));
+    }
+    let mut png = vec![];
+    image
+        .write_to(&mut io::Cursor::new(&mut png), image::ImageFormat::Png)
+        .map_err(|e| ThumbnailError::Encode(e.to_string()))?;
+    Ok(png)
+}
+
+impl File {
+    /// Reads this file as a DDS texture and creates a PNG thumbnail of it, see
+    /// [`dds_thumbnail`](crate::dds_thumbnail)
+    pub fn thumbnail(&self, bsa: &mut Bsa, max_dim: u32) -> Result<Vec<u8>, ThumbnailError> {
+        dds_thumbnail(&self.read_to_vec(bsa)?, max_dim)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{dds_thumbnail, ThumbnailError};
+
+    fn dds(width: u32, height: u32, levels: u32, fourcc: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut res = b"DDS ".to_vec();
+        let mut header = [0; 31];
+        header[0] = 124;
+        header[2] = height;
+        header[3] = width;
+        header[6] = levels;
+        header[18] = 32;
+        header[19] = 0x4;
+        header[20] = u32::from_le_bytes(*fourcc);
+        for value in &header {
+            res.extend_from_slice(&value.to_le_bytes());
+        }
+        res.extend_from_slice(data);
+        res
+    }
+
+    fn decode(png: &[u8]) -> image::RgbaImage {
+        image::load_from_memory(png).unwrap().to_rgba8()
+    }
+
+    #[test]
+    fn test_bc1_thumbnail() {
+        // one red block (color 0 = 0xf800, all indices 0) for each mipmap
+        let block = [0x00, 0xf8, 0, 0, 0, 0, 0, 0];
+        let data: Vec<u8> = block
+            .iter()
+            .cycle()
+            .take(8 * (8 + 2 + 1))
+            .copied()
+            .collect();
+        let texture = dds(16, 8, 3, b"DXT1", &data);
+
+        let image = decode(&dds_thumbnail(&texture, 64).unwrap());
+        assert_eq!(image.dimensions(), (16, 8));
+        let image = decode(&dds_thumbnail(&texture, 8).unwrap());
+        assert_eq!(image.dimensions(), (8, 4));
+        assert_eq!(image.get_pixel(0, 0).0, [255, 0, 0, 255]);
+        let image = decode(&dds_thumbnail(&texture, 3).unwrap());
+        assert_eq!(image.dimensions(), (3, 1));
+    }
+
+    #[test]
+    fn test_invalid_thumbnail() {
+        assert!(matches!(
+            dds_thumbnail(b"not a texture", 64),
+            Err(ThumbnailError::InvalidDds(_))
+        ));
+        assert!(matches!(
+            dds_thumbnail(&dds(4, 4, 1, b"XYZW", &[0; 8]), 64),
+            Err(ThumbnailError::UnsupportedFormat(_))
+        ));
+        assert!(matches!(
+            dds_thumbnail(&dds(8, 8, 1, b"DXT1", &[0; 8]), 64),
+            Err(ThumbnailError::InvalidDds(_))
+        ));
+        assert!(matches!(
+            dds_thumbnail(&dds(u32::MAX, u32::MAX, 32, b"DXT1", &[0; 8]), 1),
+            Err(ThumbnailError::InvalidDds(_))
+        ));
+    }
+}
@@ -0,0 +1,190 @@
+use crate::bsa::{Bsa, ReadError};
+use crate::entry::EntryPath;
+use std::io::{self, Read};
+
+/// Checks the contents of files inside a BSA, to catch corrupt assets before
+/// they are shipped. Validators are run by [`Bsa::validate_assets`] on every
+/// file with one of their extensions.
+pub trait Validator {
+    /// Returns the lowercase extensions (without the dot) of the files this
+    /// validator checks, e.g. `&["dds"]`
+    fn extensions(&self) -> &[&str];
+
+    /// Checks the decompressed contents of a file, returning a description of
+    /// the problem if it is invalid
+    fn validate(&self, extension: &str, reader: &mut dyn io::Read) -> Result<(), String>;
+}
+
+fn read_up_to(reader: &mut dyn io::Read, len: u64) -> Result<Vec<u8>, String> {
+    let mut data = vec![];
+    reader
+        .take(len)
+        .read_to_end(&mut data)
+        .map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    let mut bytes = [0; 4];
+    bytes.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(bytes)
+}
+
+/// Checks the header of DirectDraw Surface textures
+#[derive(Debug, Default, Clone, Copy)]
+pub struct DdsValidator;
+
+impl Validator for DdsValidator {
+    fn extensions(&self) -> &[&str] {
+        &["dds"]
+    }
+
+    fn validate(&self, _extension: &str, reader: &mut dyn io::Read) -> Result<(), String> {
+        // magic, 124 byte header and the optional 20 byte DX10 header
+        let data = read_up_to(reader, 148)?;
+        if !data.starts_with(b"DDS ") {
+            return Err("missing DDS magic".to_string());
+        }
+        if data.len() < 128 {
+            return Err(format!("header is truncated ({} bytes)", data.len()));
+        }
+        if u32_at(&data, 4) != 124 {
+            return Err(format!("unexpected header size {}", u32_at(&data, 4)));
+        }
+        if u32_at(&data, 76) != 32 {
+            return Err(format!(
+                "unexpected pixel format size {}",
+                u32_at(&data, 76)
+            ));
+        }
+        if u32_at(&data, 12) == 0 || u32_at(&data, 16) == 0 {
+            return Err("width or height is zero".to_string());
+        }
+        let four_cc = u32_at(&data, 80) & 0x4 != 0;
+        if four_cc && &data[84..88] == b"DX10" && data.len() < 148 {
+            return Err(format!("DX10 header is truncated ({} bytes)", data.len()));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the header line of NetImmerse/Gamebryo models
+#[derive(Debug, Default, Clone, Copy)]
+pub struct NifValidator;
+
+impl Validator for NifValidator {
+    fn extensions(&self) -> &[&str] {
+        &["nif", "kf"]
+    }
+
+    fn validate(&self, _extension: &str, reader: &mut dyn io::Read) -> Result<(), String> {
+        let data = read_up_to(reader, 128)?;
+        let line = match data.iter().position(|&b| b == b'\n') {
+            Some(end) => &data[..end],
+            None => return Err("header line is missing or too long".to_string()),
+        };
+        if !line.starts_with(b"Gamebryo File Format, Version ")
+            && !line.starts_with(b"NetImmerse File Format, Version ")
+        {
+            return Err(format!(
+                "unexpected header line {:?}",
+                String::from_utf8_lossy(line)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Returns the validators that are included in this crate
+pub fn builtin_validators() -> Vec<Box<dyn Validator>> {
+    vec![Box::new(DdsValidator), Box::new(NifValidator)]
+}
+
+impl Bsa {
+    /// Runs the given validators on every named file with a matching
+    /// extension, and returns the problems they found
+    pub fn validate_assets(
+        &mut self,
+        validators: &[Box<dyn Validator>],
+    ) -> Result<Vec<(EntryPath, String)>, ReadError> {
+        let files: Vec<_> = self
+            .files_by_offset()
+            .into_iter()
+            .filter_map(|(folder, file)| Some((file.full_path(folder)?, file.clone())))
+            .collect();
+        let mut res = vec![];
+        for (path, file) in files {
+            let extension = match path.file_name().rsplit_once('.') {
+                Some((_, extension)) => extension.to_ascii_lowercase(),
+                None => continue,
+            };
+            for validator in validators {
+                if !validator.extensions().contains(&extension.as_str()) {
+                    continue;
+                }
+                let mut reader = file.read_contents(self)?;
+                if let Err(problem) = validator.validate(&extension, &mut reader) {
+                    res.push((path.clone(), problem));
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{builtin_validators, DdsValidator, Validator};
+    use crate::bsa::Version;
+    use crate::write::BsaBuilder;
+
+    fn dds(width: u32, height: u32) -> Vec<u8> {
+        let mut data = b"DDS ".to_vec();
+        data.resize(128, 0);
+        data[4..8].copy_from_slice(&124u32.to_le_bytes());
+        data[12..16].copy_from_slice(&height.to_le_bytes());
+        data[16..20].copy_from_slice(&width.to_le_bytes());
+        data[76..80].copy_from_slice(&32u32.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn test_dds_validator() {
+        let validate = |data: &[u8]| DdsValidator.validate("dds", &mut &data[..]);
+        assert_eq!(validate(&dds(4, 4)), Ok(()));
+        assert!(validate(&dds(0, 4)).is_err());
+        assert!(validate(&dds(4, 4)[..100]).is_err());
+        assert!(validate(b"PNG").is_err());
+    }
+
+    #[test]
+    fn test_validate_assets() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.add_file(r"textures\a.dds", dds(4, 4)).unwrap();
+        builder.add_file(r"textures\b.DDS", vec![0; 128]).unwrap();
+        builder
+            .add_file(
+                r"meshes\c.nif",
+                b"Gamebryo File Format, Version 20.2.0.7\n".to_vec(),
+            )
+            .unwrap();
+        builder
+            .add_file(r"meshes\d.nif", b"garbage".to_vec())
+            .unwrap();
+        builder
+            .add_file(r"meshes\e.txt", b"garbage".to_vec())
+            .unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+
+        let mut problems: Vec<_> = bsa
+            .validate_assets(&builtin_validators())
+            .unwrap()
+            .into_iter()
+            .map(|(path, _)| path.to_string())
+            .collect();
+        problems.sort();
+        assert_eq!(problems, vec![r"meshes\d.nif", r"textures\b.dds"]);
+    }
+}
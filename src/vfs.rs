@@ -0,0 +1,790 @@
+use crate::ba2::Ba2Fs;
+use crate::bsa::{Bsa, ReadError};
+use crate::write::normalize_path;
+use std::{collections::BTreeMap, fmt, fs, io, path, sync::mpsc};
+
+/// A minimal read-only file system, used to plug BSA files into generic
+/// asset loading code.
+///
+/// Paths can use either forward slashes or backslashes, and are matched
+/// case-insensitively.
+pub trait FileSystem {
+    /// Opens the file at the given path for reading
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read + '_>>;
+
+    /// Returns whether a file exists at the given path
+    fn exists(&self, path: &str) -> bool;
+
+    /// Returns the paths of all files inside the given folder (including
+    /// subfolders). An empty prefix lists every file.
+    fn list(&self, prefix: &str) -> Vec<String>;
+
+    /// Returns the file on disk that this file system reads from, if any, so
+    /// that it can be watched for changes
+    fn source_path(&self) -> Option<&path::Path> {
+        None
+    }
+
+    /// Reloads this file system if the files it reads from have changed on
+    /// disk, returning whether it was reloaded. If reloading fails, the file
+    /// system is left unchanged.
+    fn reload(&mut self) -> io::Result<bool> {
+        Ok(false)
+    }
+}
+
+pub(crate) fn index_key(path: &str) -> String {
+    normalize_path(path).to_ascii_lowercase()
+}
+
+/// A [`FileSystem`] backed by a single BSA file
+///
+/// ```no_run
+/// use bsa::FileSystem;
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let mut fs = bsa::BsaFs::new(bsa::open("file.bsa")?);
+///     for path in fs.list("textures/") {
+///         println!("{}", path);
+///     }
+///     let mut reader = fs.open("textures/foo/bar.dds")?;
+///     std::io::copy(&mut reader, &mut std::io::sink())?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug)]
+pub struct BsaFs {
+    bsa: Bsa,
+    // the folder and file indices of each file, see `Bsa::entry`
+    index: BTreeMap<String, (usize, usize)>,
+}
+
+impl BsaFs {
+    /// Creates a new file system from the given BSA file. If the BSA was
+    /// opened from a path, [`FileSystem::reload`] reopens it when the file on
+    /// disk changes.
+    pub fn new(bsa: Bsa) -> Self {
+        let mut res = Self {
+            bsa,
+            index: BTreeMap::new(),
+        };
+        res.build_index();
+        res
+    }
+
+    fn build_index(&mut self) {
+        self.index.clear();
+        for entry in self.bsa.entries() {
+            if let Some(path) = entry.path() {
+                self.index.insert(
+                    index_key(path.as_str()),
+                    (entry.folder_index(), entry.file_index()),
+                );
+            }
+        }
+    }
+
+    /// Returns the underlying BSA file
+    pub fn into_inner(self) -> Bsa {
+        self.bsa
+    }
+
+    fn path_at(&self, folder_idx: usize, file_idx: usize) -> String {
+        let entry = self.bsa.entry(folder_idx, file_idx).unwrap();
+        entry.path().unwrap().to_string()
+    }
+}
+
+impl FileSystem for BsaFs {
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read + '_>> {
+        let &(folder_idx, file_idx) = self.index.get(&index_key(path)).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path))
+        })?;
+        let file = self.bsa.file_at(folder_idx, file_idx).unwrap().clone();
+        match file.read_contents(&mut self.bsa) {
+            Ok(reader) => Ok(Box::new(reader)),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.index.contains_key(&index_key(path))
+    }
+
+    fn list(&self, prefix: &str) -> Vec<String> {
+        let mut prefix = index_key(prefix);
+        if !prefix.is_empty() {
+            prefix.push('\\');
+        }
+        self.index
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, &(folder_idx, file_idx))| self.path_at(folder_idx, file_idx))
+            .collect()
+    }
+
+    fn source_path(&self) -> Option<&path::Path> {
+        self.bsa.path()
+    }
+
+    fn reload(&mut self) -> io::Result<bool> {
+        if !self.bsa.has_changed()? {
+            return Ok(false);
+        }
+        self.bsa.reopen()?;
+        self.build_index();
+        Ok(true)
+    }
+}
+
+/// A [`FileSystem`] backed by the loose files in a folder, usually the
+/// game's Data folder
+///
+/// The folder is scanned when the file system is created, and again by
+/// [`FileSystem::reload`].
+#[derive(Debug)]
+pub struct LooseFs {
+    root: path::PathBuf,
+    index: BTreeMap<String, String>,
+}
+
+impl LooseFs {
+    /// Creates a new file system from the files inside the given folder
+    pub fn new<P: AsRef<path::Path>>(root: P) -> io::Result<Self> {
+        let root = root.as_ref().to_path_buf();
+        let index = Self::scan(&root)?;
+        Ok(Self { root, index })
+    }
+
+    fn scan(root: &path::Path) -> io::Result<BTreeMap<String, String>> {
+        let mut index = BTreeMap::new();
+        let mut folders = vec![(root.to_path_buf(), String::new())];
+        while let Some((folder, prefix)) = folders.pop() {
+            for entry in fs::read_dir(&folder)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let path = if prefix.is_empty() {
+                    name
+                } else {
+                    format!("{}\\{}", prefix, name)
+                };
+                if entry.file_type()?.is_dir() {
+                    folders.push((entry.path(), path));
+                } else {
+                    index.insert(index_key(&path), path);
+                }
+            }
+        }
+        Ok(index)
+    }
+
+    fn os_path(&self, path: &str) -> path::PathBuf {
+        let mut res = self.root.clone();
+        res.extend(path.split('\\'));
+        res
+    }
+}
+
+impl FileSystem for LooseFs {
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read + '_>> {
+        let path = self.index.get(&index_key(path)).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotFound, format!("{} not found", path))
+        })?;
+        Ok(Box::new(io::BufReader::new(fs::File::open(
+            self.os_path(path),
+        )?)))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.index.contains_key(&index_key(path))
+    }
+
+    fn list(&self, prefix: &str) -> Vec<String> {
+        let mut prefix = index_key(prefix);
+        if !prefix.is_empty() {
+            prefix.push('\\');
+        }
+        self.index
+            .range(prefix.clone()..)
+            .take_while(|(key, _)| key.starts_with(&prefix))
+            .map(|(_, path)| path.clone())
+            .collect()
+    }
+
+    fn source_path(&self) -> Option<&path::Path> {
+        Some(&self.root)
+    }
+
+    fn reload(&mut self) -> io::Result<bool> {
+        let index = Self::scan(&self.root)?;
+        if index == self.index {
+            return Ok(false);
+        }
+        self.index = index;
+        Ok(true)
+    }
+}
+
+/// A layer that only exposes the files allowed by a rule, see
+/// [`Vfs::push_routed`]
+struct Routed {
+    layer: Box<dyn FileSystem + Send>,
+    rule: Box<dyn Fn(&str) -> bool + Send>,
+}
+
+impl FileSystem for Routed {
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read + '_>> {
+        if !(self.rule)(&index_key(path)) {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", path),
+            ));
+        }
+        self.layer.open(path)
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        (self.rule)(&index_key(path)) && self.layer.exists(path)
+    }
+
+    fn list(&self, prefix: &str) -> Vec<String> {
+        let mut res = self.layer.list(prefix);
+        res.retain(|path| (self.rule)(&index_key(path)));
+        res
+    }
+
+    fn source_path(&self) -> Option<&path::Path> {
+        self.layer.source_path()
+    }
+
+    fn reload(&mut self) -> io::Result<bool> {
+        self.layer.reload()
+    }
+}
+
+/// Sent to the subscribers of a [`Vfs`] when one of its layers has been
+/// reloaded, so that cached assets from that layer can be invalidated
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LayerReloaded {
+    layer: usize,
+    path: Option<path::PathBuf>,
+}
+
+impl LayerReloaded {
+    /// Returns the index of the layer, in the order the layers were added
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+
+    /// Returns the file on disk that the layer reads from
+    pub fn path(&self) -> Option<&path::Path> {
+        self.path.as_deref()
+    }
+}
+
+/// A file that is provided by more than one layer of a [`Vfs`], returned by
+/// [`Vfs::conflicts`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    path: String,
+    layers: Vec<usize>,
+}
+
+impl Conflict {
+    /// Returns the path of the file, as listed by the first layer providing it
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the indices of the layers that provide the file, from lowest
+    /// to highest priority
+    pub fn layers(&self) -> &[usize] {
+        &self.layers
+    }
+
+    /// Returns the index of the layer whose copy of the file is used
+    pub fn winner(&self) -> usize {
+        *self.layers.last().unwrap()
+    }
+}
+
+/// A compiled Papyrus script without its source, or a source without its
+/// compiled script, returned by [`Vfs::unpaired_scripts`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnpairedScript {
+    path: String,
+    layer: usize,
+}
+
+impl UnpairedScript {
+    /// Returns the path of the script, as listed by the layer providing it
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the index of the layer whose copy of the script is used
+    pub fn layer(&self) -> usize {
+        self.layer
+    }
+
+    /// Returns whether this is a compiled (`.pex`) script that has no source,
+    /// rather than a source (`.psc`) that was never compiled
+    pub fn is_compiled(&self) -> bool {
+        self.path.to_ascii_lowercase().ends_with(".pex")
+    }
+}
+
+/// Returns the name a script is paired by, e.g. `quests\myquest` for both
+/// `scripts\quests\myquest.pex` and `scripts\source\quests\myquest.psc`, and
+/// whether it is compiled
+fn script_key(key: &str) -> Option<(&str, bool)> {
+    if let Some(name) = key.strip_suffix(".psc") {
+        // Fallout 4 user and base game scripts, Skyrim LE, Skyrim SE
+        [
+            "scripts\\source\\user\\",
+            "scripts\\source\\base\\",
+            "scripts\\source\\",
+            "source\\scripts\\",
+        ]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .map(|name| (name, false))
+    } else {
+        let name = key.strip_suffix(".pex")?.strip_prefix("scripts\\")?;
+        Some((name, true))
+    }
+}
+
+/// Combines multiple file systems into a single virtual file system.
+///
+/// Layers that are added later take priority over earlier layers, the same
+/// way archives later in the load order override earlier ones. Loose files
+/// added with [`Vfs::push_loose`] take priority over every archive, like in
+/// the games. BSA and BA2 archives can be mixed freely, and
+/// [`Vfs::resolve`] tells which layer provides a file.
+///
+/// Long-running tools can call [`Vfs::refresh`] periodically to pick up
+/// archives that were replaced on disk, and [`Vfs::subscribe`] to find out
+/// which layers were reloaded. With the `notify` feature, [`Vfs::watch`]
+/// avoids checking every archive on each refresh.
+#[derive(Default)]
+pub struct Vfs {
+    layers: Vec<Box<dyn FileSystem + Send>>,
+    loose: Vec<bool>,
+    subscribers: Vec<mpsc::Sender<LayerReloaded>>,
+    #[cfg(feature = "notify")]
+    watcher: Option<(notify::RecommendedWatcher, mpsc::Receiver<()>)>,
+}
+
+impl Vfs {
+    /// Creates a new, empty virtual file system
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file system layer with a higher priority than all existing layers
+    pub fn push(&mut self, layer: impl FileSystem + Send + 'static) -> &mut Self {
+        self.layers.push(Box::new(layer));
+        self.loose.push(false);
+        self
+    }
+
+    /// Adds a layer of loose files, which takes priority over all archives and
+    /// over loose file layers added before it
+    pub fn push_loose(&mut self, layer: impl FileSystem + Send + 'static) -> &mut Self {
+        self.layers.push(Box::new(layer));
+        self.loose.push(true);
+        self
+    }
+
+    /// Opens a BSA or BA2 archive, depending on its header, and adds it as a
+    /// layer with [`Vfs::push`]
+    pub fn push_archive<P: AsRef<path::Path>>(&mut self, path: P) -> Result<&mut Self, ReadError> {
+        let path = path.as_ref();
+        let mut magic = [0; 4];
+        io::Read::read_exact(&mut fs::File::open(path)?, &mut magic)?;
+        if &magic == b"BTDX" {
+            Ok(self.push(Ba2Fs::open(path)?))
+        } else {
+            Ok(self.push(BsaFs::new(crate::bsa::open(path)?)))
+        }
+    }
+
+    /// Returns the indices of the layers, from lowest to highest priority
+    fn by_priority(&self) -> Vec<usize> {
+        let archives = (0..self.layers.len()).filter(|&layer| !self.loose[layer]);
+        let loose = (0..self.layers.len()).filter(|&layer| self.loose[layer]);
+        archives.chain(loose).collect()
+    }
+
+    /// Returns the index of the layer that provides the file at the given
+    /// path, in the order the layers were added
+    pub fn resolve(&self, path: &str) -> Option<usize> {
+        self.by_priority()
+            .into_iter()
+            .rev()
+            .find(|&layer| self.layers[layer].exists(path))
+    }
+
+    /// Adds a file system layer like [`Vfs::push`], but only exposes the files
+    /// for which `rule` returns `true`. The rule is given lowercase paths
+    /// separated by backslashes. This can be used to emulate engine settings
+    /// that restrict what an archive may provide:
+    ///
+    /// ```no_run
+    /// use bsa::FileType;
+    /// use std::error::Error;
+    ///
+    /// fn main() -> Result<(), Box<dyn Error>> {
+    ///     let mut vfs = bsa::Vfs::new();
+    ///     // only use textures from this archive
+    ///     vfs.push_routed(bsa::BsaFs::new(bsa::open("textures.bsa")?), |path| {
+    ///         FileType::from_path(path) == FileType::Textures
+    ///     });
+    ///     // ignore voices from this one
+    ///     vfs.push_routed(bsa::BsaFs::new(bsa::open("mod.bsa")?), |path| {
+    ///         FileType::from_path(path) != FileType::Voices
+    ///     });
+    ///     Ok(())
+    /// }
+    /// ```
+    pub fn push_routed(
+        &mut self,
+        layer: impl FileSystem + Send + 'static,
+        rule: impl Fn(&str) -> bool + Send + 'static,
+    ) -> &mut Self {
+        self.push(Routed {
+            layer: Box::new(layer),
+            rule: Box::new(rule),
+        })
+    }
+
+    /// Returns every file that is provided by more than one layer, sorted by
+    /// path
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut providers: BTreeMap<String, Conflict> = BTreeMap::new();
+        for layer in self.by_priority() {
+            for path in self.layers[layer].list("") {
+                providers
+                    .entry(index_key(&path))
+                    .or_insert_with(|| Conflict {
+                        path,
+                        layers: vec![],
+                    })
+                    .layers
+                    .push(layer);
+            }
+        }
+        providers
+            .into_values()
+            .filter(|conflict| conflict.layers.len() > 1)
+            .collect()
+    }
+
+    /// Returns every compiled script (`scripts\*.pex`) without a source
+    /// (`scripts\source\*.psc`, or `source\scripts\*.psc` in Skyrim SE) and
+    /// every source without a compiled script, sorted by path. Scripts are
+    /// paired across all layers, so a source that is packaged in a separate
+    /// archive from its compiled script is not reported.
+    pub fn unpaired_scripts(&self) -> Vec<UnpairedScript> {
+        // the winning source and compiled script of each name
+        let mut scripts: BTreeMap<String, [Option<UnpairedScript>; 2]> = BTreeMap::new();
+        for layer in self.by_priority() {
+            for path in self.layers[layer].list("") {
+                let key = index_key(&path);
+                if let Some((name, compiled)) = script_key(&key) {
+                    scripts.entry(name.to_string()).or_default()[usize::from(compiled)] =
+                        Some(UnpairedScript { path, layer });
+                }
+            }
+        }
+        let mut unpaired: Vec<_> = scripts
+            .into_values()
+            .filter_map(|pair| match pair {
+                [Some(script), None] | [None, Some(script)] => Some(script),
+                _ => None,
+            })
+            .collect();
+        unpaired.sort_by_key(|script| index_key(&script.path));
+        unpaired
+    }
+
+    /// Returns a receiver that gets a [`LayerReloaded`] event whenever
+    /// [`Vfs::refresh`] reloads a layer
+    pub fn subscribe(&mut self) -> mpsc::Receiver<LayerReloaded> {
+        let (sender, receiver) = mpsc::channel();
+        self.subscribers.push(sender);
+        receiver
+    }
+
+    /// Reloads every layer whose files have changed on disk and notifies the
+    /// subscribers, returning the number of reloaded layers. Each layer is
+    /// replaced as a whole once it has been parsed, so files are never read
+    /// from a partially reloaded layer. If [`Vfs::watch`] has been called,
+    /// this does nothing until the watcher has seen a change.
+    pub fn refresh(&mut self) -> io::Result<usize> {
+        #[cfg(feature = "notify")]
+        if let Some((_, changes)) = &self.watcher {
+            if changes.try_iter().count() == 0 {
+                return Ok(0);
+            }
+        }
+        let mut reloaded = 0;
+        for (layer, fs) in self.layers.iter_mut().enumerate() {
+            if !fs.reload()? {
+                continue;
+            }
+            reloaded += 1;
+            let event = LayerReloaded {
+                layer,
+                path: fs.source_path().map(path::Path::to_path_buf),
+            };
+            self.subscribers
+                .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        }
+        Ok(reloaded)
+    }
+
+    /// Watches the files that the layers read from, so that [`Vfs::refresh`]
+    /// only checks the layers once something has changed. The folders
+    /// containing the files are watched, because mod managers usually replace
+    /// archives instead of modifying them. Folders of loose files are watched
+    /// recursively.
+    #[cfg(feature = "notify")]
+    pub fn watch(&mut self) -> notify::Result<()> {
+        use notify::Watcher;
+
+        let (sender, changes) = mpsc::channel();
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                if event.map_or(true, |event| !event.kind.is_access()) {
+                    let _ = sender.send(());
+                }
+            })?;
+        for layer in &self.layers {
+            // loose file folders are watched as a whole
+            if let Some(folder) = layer.source_path().filter(|path| path.is_dir()) {
+                watcher.watch(folder, notify::RecursiveMode::Recursive)?;
+                continue;
+            }
+            let folder = match layer.source_path().and_then(path::Path::parent) {
+                Some(folder) if folder.as_os_str().is_empty() => path::Path::new("."),
+                Some(folder) => folder,
+                None => continue,
+            };
+            watcher.watch(folder, notify::RecursiveMode::NonRecursive)?;
+        }
+        self.watcher = Some((watcher, changes));
+        Ok(())
+    }
+}
+
+impl FileSystem for Vfs {
+    fn open(&mut self, path: &str) -> io::Result<Box<dyn io::Read + '_>> {
+        match self.resolve(path) {
+            Some(layer) => self.layers[layer].open(path),
+            None => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} not found", path),
+            )),
+        }
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        self.layers.iter().any(|layer| layer.exists(path))
+    }
+
+    fn list(&self, prefix: &str) -> Vec<String> {
+        let mut res = BTreeMap::new();
+        for layer in self.by_priority() {
+            for path in self.layers[layer].list(prefix) {
+                res.insert(index_key(&path), path);
+            }
+        }
+        res.into_values().collect()
+    }
+
+    fn reload(&mut self) -> io::Result<bool> {
+        Ok(self.refresh()? > 0)
+    }
+}
+
+impl fmt::Debug for Vfs {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Vfs ({} layers)", self.layers.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BsaFs, FileSystem, LooseFs, Vfs};
+    use crate::bsa::Version;
+    use crate::write::BsaBuilder;
+    use std::io::Read;
+
+    fn bsa_fs(files: &[(&str, Vec<u8>)]) -> BsaFs {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        for (path, data) in files {
+            builder.add_file(path, data.clone()).unwrap();
+        }
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        BsaFs::new(crate::bsa::read(std::io::Cursor::new(data)).unwrap())
+    }
+
+    fn read(fs: &mut impl FileSystem, path: &str) -> Vec<u8> {
+        let mut contents = vec![];
+        fs.open(path).unwrap().read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn test_bsa_fs() {
+        let mut fs = bsa_fs(&[
+            (r"textures\foo\a.dds", vec![1, 2]),
+            (r"textures\foobar\b.dds", vec![3]),
+            (r"meshes\c.nif", vec![4]),
+        ]);
+
+        assert!(fs.exists("Textures/Foo/A.dds"));
+        assert!(!fs.exists("textures/foo"));
+        assert_eq!(fs.list("textures/foo"), vec![r"textures\foo\a.dds"]);
+        assert_eq!(fs.list("").len(), 3);
+
+        assert_eq!(read(&mut fs, "textures/foo/a.dds"), vec![1, 2]);
+        assert!(fs.open("textures/foo/missing.dds").is_err());
+    }
+
+    #[test]
+    fn test_vfs_priority() {
+        let mut vfs = Vfs::new();
+        vfs.push(bsa_fs(&[
+            (r"meshes\a.nif", vec![1]),
+            (r"meshes\b.nif", vec![2]),
+        ]));
+        vfs.push(bsa_fs(&[(r"meshes\b.nif", vec![3])]));
+        assert_eq!(vfs.list("meshes").len(), 2);
+        assert_eq!(read(&mut vfs, "meshes/a.nif"), vec![1]);
+        assert_eq!(read(&mut vfs, "meshes/b.nif"), vec![3]);
+    }
+
+    #[test]
+    fn test_push_routed() {
+        let mut vfs = Vfs::new();
+        vfs.push(bsa_fs(&[(r"meshes\a.nif", vec![1])]));
+        vfs.push_routed(
+            bsa_fs(&[(r"meshes\a.nif", vec![2]), (r"textures\b.dds", vec![3])]),
+            |path| path.starts_with(r"textures\"),
+        );
+        assert_eq!(read(&mut vfs, "Meshes/A.nif"), vec![1]);
+        assert_eq!(read(&mut vfs, "textures/b.dds"), vec![3]);
+        assert_eq!(vfs.list("").len(), 2);
+        assert!(vfs.conflicts().is_empty());
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let mut vfs = Vfs::new();
+        vfs.push(bsa_fs(&[
+            (r"meshes\a.nif", vec![1]),
+            (r"meshes\b.nif", vec![2]),
+        ]));
+        vfs.push(bsa_fs(&[(r"meshes\c.nif", vec![3])]));
+        vfs.push(bsa_fs(&[(r"Meshes\A.nif", vec![4])]));
+        let conflicts = vfs.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].path(), r"meshes\a.nif");
+        assert_eq!(conflicts[0].layers(), &[0, 2]);
+        assert_eq!(conflicts[0].winner(), 2);
+    }
+
+    #[test]
+    fn test_unpaired_scripts() {
+        let mut vfs = Vfs::new();
+        vfs.push(bsa_fs(&[
+            (r"scripts\paired.pex", vec![1]),
+            (r"scripts\compiledonly.pex", vec![2]),
+            (r"scripts\split.pex", vec![3]),
+            (r"scripts\source\paired.psc", vec![4]),
+            (r"source\scripts\sourceonly.psc", vec![5]),
+        ]));
+        vfs.push(bsa_fs(&[
+            (r"Scripts\Source\Split.psc", vec![6]),
+            (r"scripts\source\user\ns\fo4only.psc", vec![7]),
+            (r"scripts\source\readme.txt", vec![8]),
+        ]));
+        let unpaired = vfs.unpaired_scripts();
+        let paths: Vec<_> = unpaired
+            .iter()
+            .map(|script| (script.path(), script.layer(), script.is_compiled()))
+            .collect();
+        assert_eq!(
+            paths,
+            [
+                (r"scripts\compiledonly.pex", 0, true),
+                (r"scripts\source\user\ns\fo4only.psc", 1, false),
+                (r"source\scripts\sourceonly.psc", 0, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_refresh() {
+        let write = |path: &std::path::Path, files: &[(&str, Vec<u8>)]| {
+            let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+            for (path, data) in files {
+                builder.add_file(path, data.clone()).unwrap();
+            }
+            builder
+                .write(&mut std::fs::File::create(path).unwrap())
+                .unwrap();
+        };
+        let path = std::env::temp_dir().join(format!("bsa-refresh-{}.bsa", std::process::id()));
+        write(&path, &[(r"meshes\a.nif", vec![1])]);
+        let mut vfs = Vfs::new();
+        vfs.push(bsa_fs(&[(r"meshes\a.nif", vec![0])]));
+        vfs.push(BsaFs::new(crate::bsa::open(&path).unwrap()));
+        let events = vfs.subscribe();
+        assert_eq!(vfs.refresh().unwrap(), 0);
+
+        write(
+            &path,
+            &[(r"meshes\a.nif", vec![2]), (r"meshes\b.nif", vec![3])],
+        );
+        assert_eq!(vfs.refresh().unwrap(), 1);
+        let event = events.try_recv().unwrap();
+        assert_eq!((event.layer(), event.path()), (1, Some(path.as_path())));
+        assert!(events.try_recv().is_err());
+        assert_eq!(read(&mut vfs, "meshes/a.nif"), vec![2]);
+        assert_eq!(read(&mut vfs, "meshes/b.nif"), vec![3]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_loose_priority() {
+        let root = std::env::temp_dir().join(format!("bsa-loose-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("Meshes")).unwrap();
+        std::fs::write(root.join("Meshes").join("A.nif"), [1]).unwrap();
+        let mut vfs = Vfs::new();
+        vfs.push_loose(LooseFs::new(&root).unwrap());
+        vfs.push(bsa_fs(&[
+            (r"meshes\a.nif", vec![2]),
+            (r"meshes\b.nif", vec![3]),
+        ]));
+        assert_eq!(vfs.resolve("meshes/a.nif"), Some(0));
+        assert_eq!(vfs.resolve("meshes/b.nif"), Some(1));
+        assert_eq!(vfs.resolve("meshes/c.nif"), None);
+        assert_eq!(read(&mut vfs, "meshes/a.nif"), vec![1]);
+        assert_eq!(vfs.list("meshes"), vec![r"Meshes\A.nif", r"meshes\b.nif"]);
+        let conflicts = vfs.conflicts();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].layers(), &[1, 0]);
+        assert_eq!(conflicts[0].winner(), 0);
+
+        std::fs::write(root.join("Meshes").join("c.nif"), [4]).unwrap();
+        assert_eq!(vfs.refresh().unwrap(), 1);
+        assert_eq!(read(&mut vfs, "meshes/c.nif"), vec![4]);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,992 @@
+use crate::bsa::{
+    serialize_bstring, ArchiveFlags, Codec, FileFlags, FileType, RawFile, Version, WriteError,
+};
+use crate::game::Game;
+use crate::hash;
+use crate::plan::{BuildEntry, BuildPlan};
+use crate::profile::event;
+use crate::spec::{ArchiveSpec, FileSpec, FolderSpec};
+use log::trace;
+use std::{
+    collections::{btree_map, BTreeMap},
+    convert::TryFrom,
+    io,
+};
+
+/// Builds a new BSA file from in-memory file contents.
+///
+/// Folder names are normalized before they are added: forward slashes are
+/// converted to backslashes, and duplicate or trailing separators are removed.
+/// Folders whose names only differ in case (e.g. `Textures/Foo` and
+/// `textures\foo`) are merged into a single folder record, like Archive.exe does.
+///
+/// ```no_run
+/// use std::error::Error;
+///
+/// fn main() -> Result<(), Box<dyn Error>> {
+///     let mut builder = bsa::BsaBuilder::new(bsa::Version::SKYRIM_SPECIAL_EDITION);
+///     builder.compress(true);
+///     builder.add_file("Textures/Foo/bar.dds", vec![0; 128])?;
+///     builder.add_file(r"textures\foo\baz.dds", vec![0; 128])?;
+///     builder.write(&mut std::fs::File::create("out.bsa")?)?;
+///     Ok(())
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct BsaBuilder {
+    version: Version,
+    game: Game,
+    ignore_game_limits: bool,
+    empty_folders: bool,
+    compress: bool,
+    lz4_block: bool,
+    zstd: bool,
+    embed_file_names: bool,
+    keep_original_case: bool,
+    alignment: u32,
+    folders: BTreeMap<String, FolderEntry>,
+}
+
+#[derive(Debug, Clone)]
+struct FolderEntry {
+    name: String,
+    files: BTreeMap<String, FileEntry>,
+}
+
+#[derive(Debug, Clone)]
+struct FileEntry {
+    name: String,
+    data: FileData,
+}
+
+#[derive(Debug, Clone)]
+enum FileData {
+    /// Uncompressed contents, optionally overriding whether they should be compressed
+    Uncompressed(Vec<u8>, Option<bool>),
+    /// Contents that are already compressed with the codec for this BSA version
+    Compressed(Vec<u8>, u32),
+}
+
+/// Converts a path into the form used inside BSA files
+pub(crate) fn normalize_path(name: &str) -> String {
+    name.split(['/', '\\'])
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect::<Vec<_>>()
+        .join("\\")
+}
+
+fn write_u32(v: &mut Vec<u8>, value: u32) {
+    v.extend_from_slice(&value.to_le_bytes());
+}
+
+fn write_u64(v: &mut Vec<u8>, value: u64) {
+    v.extend_from_slice(&value.to_le_bytes());
+}
+
+fn to_u32(value: usize) -> Result<u32, WriteError> {
+    u32::try_from(value).map_err(|_| WriteError::ArchiveTooLarge)
+}
+
+struct SerializedFolder {
+    hash: u64,
+    name: String,
+    files: Vec<SerializedFile>,
+}
+
+struct SerializedFile {
+    hash: u64,
+    name: String,
+    data: Vec<u8>,
+    override_compressed: bool,
+}
+
+impl Codec {
+    /// Compresses `data` with this codec, using the given compression level
+    /// (e.g. 0-9 for zlib) or the default level of the codec. The result
+    /// doesn't include the uncompressed size that precedes compressed file
+    /// contents in a BSA.
+    #[cfg_attr(
+        not(any(feature = "zlib", feature = "lz4", feature = "zstd")),
+        allow(unused_variables)
+    )]
+    pub fn compress(self, data: &[u8], level: Option<u32>) -> Result<Vec<u8>, WriteError> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            #[cfg(feature = "zlib")]
+            Self::Zlib => {
+                let level = level.map_or_else(flate2::Compression::default, |level| {
+                    flate2::Compression::new(level)
+                });
+                let mut encoder = flate2::write::ZlibEncoder::new(vec![], level);
+                io::Write::write_all(&mut encoder, data)?;
+                Ok(encoder.finish()?)
+            }
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => {
+                let mut encoder = lz4::EncoderBuilder::new()
+                    .level(level.unwrap_or(0))
+                    .build(vec![])?;
+                io::Write::write_all(&mut encoder, data)?;
+                let (compressed, result) = encoder.finish();
+                result?;
+                Ok(compressed)
+            }
+            #[cfg(feature = "lz4")]
+            Self::Lz4Block => {
+                let mode = level.map(|level| {
+                    lz4::block::CompressionMode::HIGHCOMPRESSION(i32::try_from(level).unwrap_or(12))
+                });
+                Ok(lz4::block::compress(data, mode, false)?)
+            }
+            #[cfg(feature = "zstd")]
+            Self::Zstd => Ok(zstd::stream::encode_all(
+                data,
+                level.map_or(0, |level| i32::try_from(level).unwrap_or(22)),
+            )?),
+            #[allow(unreachable_patterns)]
+            Self::Zlib => Err(WriteError::CompressionFeatureDisabled("zlib")),
+            #[allow(unreachable_patterns)]
+            Self::Lz4 | Self::Lz4Block => Err(WriteError::CompressionFeatureDisabled("lz4")),
+            #[allow(unreachable_patterns)]
+            Self::Zstd => Err(WriteError::CompressionFeatureDisabled("zstd")),
+        }
+    }
+}
+
+impl BsaBuilder {
+    /// Creates a new, empty builder for the given BSA version. The limits of
+    /// the game guessed from the version are checked (see [`Game`]).
+    pub fn new(version: Version) -> Self {
+        Self {
+            version,
+            game: Game::from_version(version),
+            ignore_game_limits: false,
+            empty_folders: false,
+            compress: false,
+            lz4_block: false,
+            zstd: false,
+            embed_file_names: false,
+            keep_original_case: false,
+            alignment: 1,
+            folders: BTreeMap::new(),
+        }
+    }
+
+    /// Creates a new, empty builder for archives used by the given game
+    pub fn for_game(game: Game) -> Self {
+        Self {
+            game,
+            ..Self::new(game.version())
+        }
+    }
+
+    /// Sets whether archives that would break the game (e.g. because they
+    /// are too large) are written anyway, only logging a warning, instead of
+    /// failing with `WriteError::GameLimitExceeded`
+    pub fn ignore_game_limits(&mut self, ignore_game_limits: bool) -> &mut Self {
+        self.ignore_game_limits = ignore_game_limits;
+        self
+    }
+
+    /// Sets whether folder records are written for folders without files,
+    /// added with [`BsaBuilder::add_folder`]. Defaults to `false`, since some
+    /// tools choke on empty folders.
+    pub fn empty_folders(&mut self, empty_folders: bool) -> &mut Self {
+        self.empty_folders = empty_folders;
+        self
+    }
+
+    /// Sets whether file contents should be compressed
+    pub fn compress(&mut self, compress: bool) -> &mut Self {
+        self.compress = compress;
+        self
+    }
+
+    /// Sets whether files in Skyrim Special Edition archives are compressed as
+    /// LZ4 blocks without frame headers, like some third-party tools do,
+    /// instead of LZ4 frames. The game only reads LZ4 frames.
+    pub fn lz4_block(&mut self, lz4_block: bool) -> &mut Self {
+        self.lz4_block = lz4_block;
+        self
+    }
+
+    /// Sets whether compressed files use zstd instead of the codec of the game,
+    /// for pipelines that use BSA files to exchange data between tools. This
+    /// sets an extra archive flag, and the resulting archives can only be read
+    /// by this library, so writing them requires
+    /// [`BsaBuilder::ignore_game_limits`].
+    pub fn zstd(&mut self, zstd: bool) -> &mut Self {
+        self.zstd = zstd;
+        self
+    }
+
+    /// Sets whether full file paths should be embedded in the file data
+    /// (ignored for Oblivion archives)
+    pub fn embed_file_names(&mut self, embed_file_names: bool) -> &mut Self {
+        self.embed_file_names = embed_file_names;
+        self
+    }
+
+    /// Sets whether names should be written with the case they were first
+    /// added with, instead of being converted to lowercase
+    pub fn keep_original_case(&mut self, keep_original_case: bool) -> &mut Self {
+        self.keep_original_case = keep_original_case;
+        self
+    }
+
+    /// Sets the alignment (in bytes) of the data of each file within the
+    /// archive, e.g. 4096 to align files to page boundaries. Files are padded
+    /// with zero bytes as needed. Defaults to 1 (no padding).
+    pub fn alignment(&mut self, alignment: u32) -> &mut Self {
+        self.alignment = alignment.max(1);
+        self
+    }
+
+    /// Adds a file to the archive. The path must contain at least one folder,
+    /// and can use either forward slashes or backslashes as separators.
+    ///
+    /// Adding a file with the same path (ignoring case) as an existing file
+    /// replaces the existing file.
+    pub fn add_file(&mut self, path: &str, data: Vec<u8>) -> Result<&mut Self, WriteError> {
+        self.insert_file(path, FileData::Uncompressed(data, None))
+    }
+
+    /// Adds a file to the archive like `add_file`, but overrides whether this
+    /// particular file is compressed
+    pub fn add_file_with_compression(
+        &mut self,
+        path: &str,
+        data: Vec<u8>,
+        compress: bool,
+    ) -> Result<&mut Self, WriteError> {
+        self.insert_file(path, FileData::Uncompressed(data, Some(compress)))
+    }
+
+    /// Adds a file whose contents are already compressed with the codec used by
+    /// this BSA version (zlib for Oblivion and Skyrim, LZ4 for Skyrim Special
+    /// Edition), so they can be copied without being recompressed
+    pub fn add_precompressed_file(
+        &mut self,
+        path: &str,
+        data: Vec<u8>,
+        uncompressed_size: u32,
+    ) -> Result<&mut Self, WriteError> {
+        self.insert_file(path, FileData::Compressed(data, uncompressed_size))
+    }
+
+    /// Adds a file read with [`File::read_raw`](crate::File::read_raw),
+    /// copying its stored bytes verbatim instead of recompressing them.
+    /// Uncompressed files stay uncompressed. Returns
+    /// `WriteError::CodecMismatch` if the file is compressed with a codec that
+    /// this BSA version doesn't use.
+    pub fn add_raw_file(&mut self, path: &str, raw: RawFile) -> Result<&mut Self, WriteError> {
+        let codec = raw.codec();
+        if codec == Codec::None {
+            return self.add_file_with_compression(path, raw.into_data(), false);
+        }
+        let supported = if self.zstd {
+            codec == Codec::Zstd
+        } else {
+            codec.is_supported_by(self.version)
+        };
+        if !supported {
+            return Err(WriteError::CodecMismatch(codec, self.version));
+        }
+        let uncompressed_size =
+            u32::try_from(raw.uncompressed_size()).map_err(|_| WriteError::ArchiveTooLarge)?;
+        self.add_precompressed_file(path, raw.into_data(), uncompressed_size)
+    }
+
+    /// Returns the codec that files added with [`BsaBuilder::add_file`] are
+    /// compressed with
+    pub fn codec(&self) -> Codec {
+        if self.compress {
+            self.compressed_codec()
+        } else {
+            Codec::None
+        }
+    }
+
+    /// Returns the codec used for compressed files, even if files aren't
+    /// compressed by default
+    fn compressed_codec(&self) -> Codec {
+        if self.zstd {
+            Codec::Zstd
+        } else if self.lz4_block && self.version == Version::SKYRIM_SPECIAL_EDITION {
+            Codec::Lz4Block
+        } else {
+            Codec::for_version(self.version).unwrap_or(Codec::None)
+        }
+    }
+
+    /// Adds a folder to the archive, which is only written if it contains
+    /// files or [`BsaBuilder::empty_folders`] is enabled
+    pub fn add_folder(&mut self, path: &str) -> Result<&mut Self, WriteError> {
+        let path = normalize_path(path);
+        if path.is_empty() {
+            return Err(WriteError::MissingFolderName);
+        }
+        self.folders
+            .entry(path.to_ascii_lowercase())
+            .or_insert_with(|| FolderEntry {
+                name: path.to_string(),
+                files: BTreeMap::new(),
+            });
+        Ok(self)
+    }
+
+    fn insert_file(&mut self, path: &str, data: FileData) -> Result<&mut Self, WriteError> {
+        let path = normalize_path(path);
+        let (folder_name, file_name) = match path.rfind('\\') {
+            Some(idx) => (&path[..idx], &path[idx + 1..]),
+            None if path.is_empty() => return Err(WriteError::MissingFileName),
+            None => return Err(WriteError::MissingFolderName),
+        };
+        if file_name.len() > 255 {
+            return Err(WriteError::FileNameMoreThan255Characters);
+        }
+        let folder = self
+            .folders
+            .entry(folder_name.to_ascii_lowercase())
+            .or_insert_with(|| FolderEntry {
+                name: folder_name.to_string(),
+                files: BTreeMap::new(),
+            });
+        match folder.files.entry(file_name.to_ascii_lowercase()) {
+            btree_map::Entry::Occupied(mut entry) => entry.get_mut().data = data,
+            btree_map::Entry::Vacant(entry) => {
+                entry.insert(FileEntry {
+                    name: file_name.to_string(),
+                    data,
+                });
+            }
+        }
+        Ok(self)
+    }
+
+    /// Returns whether a file with the given path (ignoring case) has been added
+    pub fn contains(&self, path: &str) -> bool {
+        let path = normalize_path(path).to_ascii_lowercase();
+        match path.rfind('\\') {
+            Some(idx) => self
+                .folders
+                .get(&path[..idx])
+                .is_some_and(|folder| folder.files.contains_key(&path[idx + 1..])),
+            None => false,
+        }
+    }
+
+    fn archive_flags(&self) -> ArchiveFlags {
+        ArchiveFlags {
+            include_directory_names: true,
+            include_file_names: true,
+            compressed_archive: self.compress,
+            retain_directory_names: false,
+            retain_file_names: false,
+            retain_file_name_offsets: false,
+            xbox360_archive: false,
+            retain_strings: false,
+            embed_file_names: self.embed_file_names && self.game.embeds_file_names(),
+            xmem_codec: false,
+            zstd_codec: self.zstd,
+            unknown_bits: 0,
+        }
+    }
+
+    fn file_flags(&self) -> FileFlags {
+        let mut flags = FileFlags::deserialize(0);
+        for folder in self.folders.values() {
+            for file in folder.files.values() {
+                flags.insert(FileType::from_path(&format!(
+                    "{}\\{}",
+                    folder.name, file.name
+                )));
+            }
+        }
+        flags
+    }
+
+    fn display_name(&self, name: &str) -> String {
+        if self.keep_original_case {
+            name.to_string()
+        } else {
+            name.to_ascii_lowercase()
+        }
+    }
+
+    fn compress_data(&self, data: &[u8]) -> Result<Vec<u8>, WriteError> {
+        let mut res = vec![];
+        write_u32(&mut res, to_u32(data.len())?);
+        res.extend(self.compressed_codec().compress(data, None)?);
+        Ok(res)
+    }
+
+    fn serialize_folders(
+        &self,
+        archive_flags: ArchiveFlags,
+    ) -> Result<Vec<SerializedFolder>, WriteError> {
+        let mut folders = vec![];
+        for folder in self.folders.values() {
+            if folder.files.is_empty() && !self.empty_folders {
+                continue;
+            }
+            let folder_name = self.display_name(&folder.name);
+            let mut files = vec![];
+            for file in folder.files.values() {
+                let file_name = self.display_name(&file.name);
+                let mut data = vec![];
+                if archive_flags.embed_file_names {
+                    serialize_bstring(
+                        &format!("{}\\{}", folder_name, file_name),
+                        false,
+                        &mut data,
+                    )?;
+                }
+                let compressed = match &file.data {
+                    FileData::Uncompressed(contents, compress) => {
+                        let compress = compress.unwrap_or(self.compress);
+                        if compress {
+                            data.extend(self.compress_data(contents)?);
+                        } else {
+                            data.extend_from_slice(contents);
+                        }
+                        compress
+                    }
+                    FileData::Compressed(contents, uncompressed_size) => {
+                        write_u32(&mut data, *uncompressed_size);
+                        data.extend_from_slice(contents);
+                        true
+                    }
+                };
+                files.push(SerializedFile {
+                    hash: hash::compute_hash(&file_name, hash::Type::File)?,
+                    name: file_name,
+                    data,
+                    override_compressed: compressed != self.compress,
+                });
+            }
+            files.sort_by_key(|file| file.hash);
+            folders.push(SerializedFolder {
+                hash: hash::compute_hash(&folder_name, hash::Type::Directory)?,
+                name: folder_name,
+                files,
+            });
+        }
+        folders.sort_by_key(|folder| folder.hash);
+        Ok(folders)
+    }
+
+    /// Returns the settings, folders and files of this builder as plain data,
+    /// with folders and files sorted by their lowercase names
+    pub fn spec(&self) -> ArchiveSpec {
+        ArchiveSpec {
+            version: self.version,
+            game: self.game,
+            compress: self.compress,
+            lz4_block: self.lz4_block,
+            zstd: self.zstd,
+            embed_file_names: self.embed_file_names,
+            keep_original_case: self.keep_original_case,
+            alignment: self.alignment,
+            empty_folders: self.empty_folders,
+            ignore_game_limits: self.ignore_game_limits,
+            folders: self
+                .folders
+                .values()
+                .map(|folder| FolderSpec {
+                    name: folder.name.clone(),
+                    files: folder
+                        .files
+                        .values()
+                        .map(|file| {
+                            let (data, compress, uncompressed_size) = match &file.data {
+                                FileData::Uncompressed(data, compress) => {
+                                    (data.clone(), *compress, None)
+                                }
+                                FileData::Compressed(data, uncompressed_size) => {
+                                    (data.clone(), None, Some(*uncompressed_size))
+                                }
+                            };
+                            FileSpec {
+                                name: file.name.clone(),
+                                data,
+                                compress,
+                                uncompressed_size,
+                            }
+                        })
+                        .collect(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Creates a builder with the settings, folders and files of the given
+    /// spec. Folders and files are added in order, so later files replace
+    /// earlier files with the same path.
+    pub fn from_spec(spec: &ArchiveSpec) -> Result<Self, WriteError> {
+        let mut res = Self {
+            game: spec.game,
+            ..Self::new(spec.version)
+        };
+        res.compress(spec.compress)
+            .lz4_block(spec.lz4_block)
+            .zstd(spec.zstd)
+            .embed_file_names(spec.embed_file_names)
+            .keep_original_case(spec.keep_original_case)
+            .alignment(spec.alignment)
+            .empty_folders(spec.empty_folders)
+            .ignore_game_limits(spec.ignore_game_limits);
+        for folder in &spec.folders {
+            res.add_folder(&folder.name)?;
+            for file in &folder.files {
+                let data = match file.uncompressed_size {
+                    Some(uncompressed_size) => {
+                        FileData::Compressed(file.data.clone(), uncompressed_size)
+                    }
+                    None => FileData::Uncompressed(file.data.clone(), file.compress),
+                };
+                res.insert_file(&format!("{}\\{}", folder.name, file.name), data)?;
+            }
+        }
+        Ok(res)
+    }
+
+    /// Describes the files that [`write`](Self::write) would write, in order,
+    /// without compressing or writing anything
+    pub fn plan(&self) -> Result<BuildPlan, WriteError> {
+        let mut folders = vec![];
+        for folder in self.folders.values() {
+            let folder_name = self.display_name(&folder.name);
+            let mut files = vec![];
+            for file in folder.files.values() {
+                let file_name = self.display_name(&file.name);
+                let (size, compressed) = match &file.data {
+                    FileData::Uncompressed(contents, compress) => {
+                        (contents.len() as u64, compress.unwrap_or(self.compress))
+                    }
+                    FileData::Compressed(_, uncompressed_size) => {
+                        (u64::from(*uncompressed_size), true)
+                    }
+                };
+                let entry = BuildEntry {
+                    path: format!("{}\\{}", folder_name, file_name),
+                    size,
+                    compressed,
+                };
+                files.push((hash::compute_hash(&file_name, hash::Type::File)?, entry));
+            }
+            files.sort_by_key(|(hash, _)| *hash);
+            folders.push((
+                hash::compute_hash(&folder_name, hash::Type::Directory)?,
+                files,
+            ));
+        }
+        folders.sort_by_key(|(hash, _)| *hash);
+        Ok(BuildPlan {
+            entries: folders
+                .into_iter()
+                .flat_map(|(_, files)| files.into_iter().map(|(_, entry)| entry))
+                .collect(),
+        })
+    }
+
+    /// Returns the ways in which an archive of the given size would break the
+    /// game
+    fn game_limit_problems(&self, archive_size: u64) -> Vec<String> {
+        let mut problems = vec![];
+        if archive_size > self.game.max_archive_size() {
+            problems.push(format!(
+                "the archive is {} bytes, but at most {} bytes are supported",
+                archive_size,
+                self.game.max_archive_size()
+            ));
+        }
+        if self.zstd {
+            problems.push("files are compressed with zstd".to_string());
+        }
+        if self.lz4_block && self.game == Game::SkyrimSE {
+            problems.push("files are compressed as LZ4 blocks instead of LZ4 frames".to_string());
+        }
+        for folder in self.folders.values() {
+            for file in folder.files.values() {
+                if let FileData::Compressed(data, _) = &file.data {
+                    if !self.game.uses_codec(data) {
+                        problems.push(format!(
+                            "{}\\{} is not compressed with the codec of the game",
+                            folder.name, file.name
+                        ));
+                    }
+                }
+            }
+        }
+        problems
+    }
+
+    /// Writes the BSA file to the given writer
+    pub fn write(&self, writer: &mut impl io::Write) -> Result<(), WriteError> {
+        let archive_flags = self.archive_flags();
+        let folders = self.serialize_folders(archive_flags)?;
+        let file_count: usize = folders.iter().map(|folder| folder.files.len()).sum();
+        // names are encoded as cp1252, which uses one byte per character
+        let encoded_len = |name: &str| name.chars().count();
+        let total_folder_name_length: usize = folders
+            .iter()
+            .map(|folder| encoded_len(&folder.name) + 1)
+            .sum();
+        let total_file_name_length: usize = folders
+            .iter()
+            .flat_map(|folder| folder.files.iter())
+            .map(|file| encoded_len(&file.name) + 1)
+            .sum();
+
+        let folder_record_size = if self.version == Version::SKYRIM_SPECIAL_EDITION {
+            24
+        } else {
+            16
+        };
+        let file_record_blocks_offset = 36 + folders.len() * folder_record_size;
+        let data_offset = file_record_blocks_offset
+            + folders
+                .iter()
+                .map(|f| encoded_len(&f.name) + 2)
+                .sum::<usize>()
+            + file_count * 16
+            + total_file_name_length;
+
+        let mut res = b"BSA\0".to_vec();
+        write_u32(&mut res, self.version.serialize());
+        write_u32(&mut res, 36);
+        write_u32(&mut res, archive_flags.serialize());
+        write_u32(&mut res, to_u32(folders.len())?);
+        write_u32(&mut res, to_u32(file_count)?);
+        write_u32(&mut res, to_u32(total_folder_name_length)?);
+        write_u32(&mut res, to_u32(total_file_name_length)?);
+        write_u32(&mut res, self.file_flags().serialize());
+
+        // folder records
+        let mut block_offset = file_record_blocks_offset;
+        for folder in &folders {
+            let offset = to_u32(block_offset + total_file_name_length)?;
+            write_u64(&mut res, folder.hash);
+            write_u32(&mut res, to_u32(folder.files.len())?);
+            if self.version == Version::SKYRIM_SPECIAL_EDITION {
+                write_u32(&mut res, 0);
+                write_u64(&mut res, u64::from(offset));
+            } else {
+                write_u32(&mut res, offset);
+            }
+            block_offset += encoded_len(&folder.name) + 2 + folder.files.len() * 16;
+        }
+
+        // file record blocks
+        let alignment = self.alignment as usize;
+        let align = |offset: usize| offset.div_ceil(alignment) * alignment;
+        let mut file_offset = data_offset;
+        for folder in &folders {
+            serialize_bstring(&folder.name, true, &mut res)?;
+            for file in &folder.files {
+                let size = to_u32(file.data.len())?;
+                if size > 0x3fff_ffff {
+                    return Err(WriteError::ArchiveTooLarge);
+                }
+                write_u64(&mut res, file.hash);
+                if file.override_compressed {
+                    write_u32(&mut res, size | 0x4000_0000);
+                } else {
+                    write_u32(&mut res, size);
+                }
+                file_offset = align(file_offset);
+                write_u32(&mut res, to_u32(file_offset)?);
+                file_offset += file.data.len();
+            }
+        }
+
+        // file name block
+        for file in folders.iter().flat_map(|folder| folder.files.iter()) {
+            res.extend(crate::cp1252::encode_str(&file.name)?);
+            res.push(0);
+        }
+
+        for problem in self.game_limit_problems(file_offset as u64) {
+            if !self.ignore_game_limits {
+                return Err(WriteError::GameLimitExceeded(self.game, problem));
+            }
+            event!(
+                warn,
+                { game = %self.game, problem = %problem },
+                "archive would not work in {}: {}",
+                self.game,
+                problem
+            );
+        }
+
+        trace!("Writing {} bytes of BSA headers", res.len());
+        writer.write_all(&res)?;
+        let mut offset = res.len();
+        for file in folders.iter().flat_map(|folder| folder.files.iter()) {
+            let padding = align(offset) - offset;
+            writer.write_all(&vec![0; padding])?;
+            writer.write_all(&file.data)?;
+            offset += padding + file.data.len();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BsaBuilder;
+    use crate::bsa::{Version, WriteError};
+    use crate::game::Game;
+
+    fn round_trip(builder: &BsaBuilder) -> Vec<(String, String, Vec<u8>)> {
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = crate::bsa::read(std::io::Cursor::new(data)).unwrap();
+        let mut res = vec![];
+        for folder in bsa.folders() {
+            for file in folder.files() {
+                res.push((
+                    folder.name().unwrap().to_string(),
+                    file.name().unwrap().to_string(),
+                    file.read_to_vec(&mut bsa).unwrap(),
+                ));
+            }
+        }
+        res
+    }
+
+    #[test]
+    #[cfg(all(feature = "zlib", feature = "lz4"))]
+    fn test_folder_merging() {
+        for &version in &[
+            Version::OBLIVION,
+            Version::SKYRIM,
+            Version::SKYRIM_SPECIAL_EDITION,
+        ] {
+            for &compress in &[false, true] {
+                let mut builder = BsaBuilder::new(version);
+                builder.compress(compress).embed_file_names(true);
+                builder
+                    .add_file("Textures/Foo/a.dds", vec![1, 2, 3])
+                    .unwrap();
+                builder
+                    .add_file(r"textures\foo\\B.dds", vec![4; 100])
+                    .unwrap();
+                let files = round_trip(&builder);
+                assert_eq!(files.len(), 2);
+                assert!(files.iter().all(|(folder, _, _)| folder == r"textures\foo"));
+                assert!(files.contains(&(r"textures\foo".into(), "a.dds".into(), vec![1, 2, 3])));
+                assert!(files.contains(&(r"textures\foo".into(), "b.dds".into(), vec![4; 100])));
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_compression_override() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.compress(true);
+        builder
+            .add_file_with_compression("a/b.txt", vec![1; 64], false)
+            .unwrap();
+        builder.add_file("a/c.txt", vec![2; 64]).unwrap();
+        let files = round_trip(&builder);
+        assert!(files.contains(&("a".into(), "b.txt".into(), vec![1; 64])));
+        assert!(files.contains(&("a".into(), "c.txt".into(), vec![2; 64])));
+    }
+
+    #[test]
+    fn test_alignment() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.alignment(512);
+        builder.add_file("a/b.txt", vec![1; 3]).unwrap();
+        builder.add_file("a/c.txt", vec![2; 5]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        // the padding before each file isn't an offset mismatch
+        let bsa = crate::OpenOptions::new()
+            .offset_mismatch(crate::OffsetMismatch::Error)
+            .read(std::io::Cursor::new(data.clone()))
+            .unwrap();
+        assert_eq!(bsa.data_alignment(), 512);
+        assert_eq!(data.len(), 1024 + 5);
+        assert_eq!(round_trip(&builder).len(), 2);
+    }
+
+    #[test]
+    fn test_plan() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.compress(true);
+        builder.add_file("a/b.txt", vec![1; 3]).unwrap();
+        builder
+            .add_file_with_compression("a/c.txt", vec![2; 5], false)
+            .unwrap();
+        let plan = builder.plan().unwrap();
+        let entries: Vec<_> = plan
+            .entries()
+            .iter()
+            .map(|entry| (entry.path(), entry.size(), entry.is_compressed()))
+            .collect();
+        assert_eq!(entries, vec![(r"a\b.txt", 3, true), (r"a\c.txt", 5, false)]);
+        assert_eq!(plan.total_size(), 8);
+    }
+
+    #[test]
+    fn test_keep_original_case() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.keep_original_case(true);
+        builder.add_file("Textures/Foo/A.dds", vec![]).unwrap();
+        builder.add_file("textures/foo/b.dds", vec![]).unwrap();
+        let files = round_trip(&builder);
+        assert!(files.iter().all(|(folder, _, _)| folder == r"Textures\Foo"));
+        assert!(files.iter().any(|(_, file, _)| file == "A.dds"));
+    }
+
+    #[test]
+    fn test_game_limits() {
+        // a zlib stream in an archive for a game that uses LZ4
+        let mut builder = BsaBuilder::for_game(Game::SkyrimSE);
+        builder
+            .add_precompressed_file("a/b.txt", vec![0x78, 0x9c, 3, 0], 0)
+            .unwrap();
+        assert!(matches!(
+            builder.write(&mut vec![]),
+            Err(WriteError::GameLimitExceeded(Game::SkyrimSE, _))
+        ));
+        builder.ignore_game_limits(true);
+        assert!(builder.write(&mut vec![]).is_ok());
+
+        let mut builder = BsaBuilder::for_game(Game::Fallout3);
+        builder
+            .add_precompressed_file("a/b.txt", vec![0x78, 0x9c, 3, 0], 0)
+            .unwrap();
+        assert!(builder.write(&mut vec![]).is_ok());
+    }
+
+    #[test]
+    fn test_empty_folders() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.add_folder("a").unwrap();
+        builder.add_file("b/x.txt", vec![]).unwrap();
+        let read = |builder: &BsaBuilder| {
+            let mut data = vec![];
+            builder.write(&mut data).unwrap();
+            let bsa = crate::read(std::io::Cursor::new(data)).unwrap();
+            let mut folders: Vec<_> = bsa
+                .folders()
+                .map(|folder| (folder.name().unwrap().to_string(), folder.is_empty()))
+                .collect();
+            folders.sort();
+            folders
+        };
+        assert_eq!(read(&builder), vec![("b".to_string(), false)]);
+        builder.empty_folders(true);
+        assert_eq!(
+            read(&builder),
+            vec![("a".to_string(), true), ("b".to_string(), false)]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "zlib")]
+    fn test_add_raw_file() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM);
+        builder.compress(true);
+        builder.add_file("a/b.txt", vec![b'b'; 100]).unwrap();
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = crate::bsa::read(std::io::Cursor::new(data)).unwrap();
+        let file = bsa.folders_slice()[0].files_slice()[0].clone();
+
+        let mut copy = BsaBuilder::new(Version::SKYRIM);
+        copy.compress(true);
+        assert_eq!(copy.codec(), file.read_raw(&mut bsa).unwrap().codec());
+        copy.add_raw_file("a/b.txt", file.read_raw(&mut bsa).unwrap())
+            .unwrap();
+        assert_eq!(
+            round_trip(&copy),
+            vec![("a".to_string(), "b.txt".to_string(), vec![b'b'; 100])]
+        );
+
+        let mut other = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        assert!(matches!(
+            other.add_raw_file("a/b.txt", file.read_raw(&mut bsa).unwrap()),
+            Err(WriteError::CodecMismatch(_, _))
+        ));
+    }
+
+    #[test]
+    #[cfg(feature = "lz4")]
+    fn test_lz4_block() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.compress(true).lz4_block(true);
+        builder.add_file("a/b.txt", vec![b'b'; 100]).unwrap();
+        assert!(matches!(
+            builder.write(&mut vec![]),
+            Err(WriteError::GameLimitExceeded(_, _))
+        ));
+        builder.ignore_game_limits(true);
+        assert_eq!(
+            round_trip(&builder),
+            vec![("a".to_string(), "b.txt".to_string(), vec![b'b'; 100])]
+        );
+
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = crate::bsa::read(std::io::Cursor::new(data)).unwrap();
+        let file = bsa.folders_slice()[0].files_slice()[0].clone();
+        let raw = file.read_raw(&mut bsa).unwrap();
+        assert_eq!(raw.codec(), crate::bsa::Codec::Lz4Block);
+        assert_eq!(
+            file.verify(&mut bsa).unwrap(),
+            crate::bsa::FileVerification::Ok
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn test_zstd() {
+        let mut builder = BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION);
+        builder.compress(true).zstd(true);
+        builder.add_file("a/b.txt", vec![b'b'; 100]).unwrap();
+        assert!(matches!(
+            builder.write(&mut vec![]),
+            Err(WriteError::GameLimitExceeded(_, _))
+        ));
+        builder.ignore_game_limits(true);
+        assert_eq!(
+            round_trip(&builder),
+            vec![("a".to_string(), "b.txt".to_string(), vec![b'b'; 100])]
+        );
+
+        let mut data = vec![];
+        builder.write(&mut data).unwrap();
+        let mut bsa = crate::bsa::read(std::io::Cursor::new(data)).unwrap();
+        assert!(bsa.archive_flags().zstd_codec());
+        assert_eq!(bsa.archive_flags().unknown_bits(), 0);
+        let file = bsa.folders_slice()[0].files_slice()[0].clone();
+        let raw = file.read_raw(&mut bsa).unwrap();
+        assert_eq!(raw.codec(), crate::bsa::Codec::Zstd);
+        assert!(matches!(
+            BsaBuilder::new(Version::SKYRIM_SPECIAL_EDITION).add_raw_file("a/b.txt", raw),
+            Err(WriteError::CodecMismatch(_, _))
+        ));
+    }
+
+    #[test]
+    #[cfg(all(feature = "zlib", feature = "lz4"))]
+    fn test_codec_compress() {
+        let data = b"abcabcabcabcabcabcabcabcabcabcabcabc".to_vec();
+        use crate::bsa::Codec;
+        for &codec in &[Codec::None, Codec::Zlib, Codec::Lz4, Codec::Lz4Block] {
+            for &level in &[None, Some(9)] {
+                let compressed = codec.compress(&data, level).unwrap();
+                assert_eq!(
+                    crate::reader::try_decode(codec, &compressed, data.len() as u64),
+                    Some(data.clone())
+                );
+            }
+        }
+    }
+}